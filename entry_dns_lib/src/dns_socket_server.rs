@@ -10,12 +10,19 @@ use sub_lib::udp_socket_wrapper::UdpSocketWrapperTrait;
 use sub_lib::udp_socket_wrapper::UdpSocketWrapperReal;
 use sub_lib::limiter::Limiter;
 use sub_lib::logger::Logger;
+use sub_lib::blocklist::Blocklist;
+use sub_lib::hostname_map::HostnameMap;
+use processor::BlockedAnswer;
 use processor::ProcessorReal;
 use packet_server::PacketServerTrait;
 use packet_server::PacketServerReal;
 
 pub struct DnsSocketServer<S> where S: UdpSocketWrapperTrait {
     dns_target: Option<IpAddr>,
+    hostname_map: HostnameMap,
+    map_hostnames: bool,
+    blocklist: Blocklist,
+    blocked_answer: BlockedAnswer,
     socket_wrapper: S,
     pub limiter: Limiter
 }
@@ -27,13 +34,17 @@ impl<S> SocketServer for DnsSocketServer<S> where S: UdpSocketWrapperTrait {
 
     fn initialize_as_root (&mut self, args: &Vec<String>, _streams: &mut StdStreams) {
         self.dns_target = Some (get_dns_target (args));
+        self.map_hostnames = get_map_hostnames (args);
+        self.blocked_answer = get_blocked_answer (args);
         let socket_addr = SocketAddr::new (V4 (Ipv4Addr::from (0)), get_dns_port (args));
         // The following expect() will cause an appropriate panic if the port can't be opened
         self.socket_wrapper.bind (socket_addr).expect (&format! ("Cannot bind socket to {:?}", socket_addr));
     }
 
     fn serve_without_root (&mut self) {
-        let processor = ProcessorReal::new (self.dns_target.expect("Missing dns_target - was initialize_as_root called?"));
+        let hostname_map = if self.map_hostnames {Some (self.hostname_map.clone ())} else {None};
+        let processor = ProcessorReal::new (self.dns_target.expect("Missing dns_target - was initialize_as_root called?"), hostname_map,
+            self.blocklist.clone (), self.blocked_answer);
         let mut packet_server = PacketServerReal {logger: Logger::new ("EntryDnsServer"),
             socket: &mut self.socket_wrapper, processor: &processor};
         let mut buf: [u8; 65536] = [0; 65536];
@@ -44,8 +55,11 @@ impl<S> SocketServer for DnsSocketServer<S> where S: UdpSocketWrapperTrait {
 }
 
 // TODO: why not use the `::new` convention?
-pub fn new_dns_socket_server() -> DnsSocketServer<UdpSocketWrapperReal> {
-    DnsSocketServer {dns_target: None, socket_wrapper: UdpSocketWrapperReal::new (), limiter: Limiter::new()}
+// The HostnameMap is shared with whoever needs to know which hostname a loopback address stands for,
+// and the Blocklist with whoever loads it
+pub fn new_dns_socket_server(hostname_map: HostnameMap, blocklist: Blocklist) -> DnsSocketServer<UdpSocketWrapperReal> {
+    DnsSocketServer {dns_target: None, hostname_map, map_hostnames: false, blocklist, blocked_answer: BlockedAnswer::NxDomain,
+        socket_wrapper: UdpSocketWrapperReal::new (), limiter: Limiter::new()}
 }
 
 fn get_dns_target (args: &Vec<String>) -> IpAddr {
@@ -60,6 +74,22 @@ fn get_dns_target (args: &Vec<String>) -> IpAddr {
     }
 }
 
+// Forwarded TCP streams can only be traced back to their hostnames if every hostname gets its own address
+fn get_map_hostnames (args: &Vec<String>) -> bool {
+    let finder = ParameterFinder::new (args);
+    finder.find_value_after ("--tcp_forward_ports", "must be followed by a comma-separated list of port numbers").is_some ()
+}
+
+fn get_blocked_answer (args: &Vec<String>) -> BlockedAnswer {
+    let finder = ParameterFinder::new (args);
+    match finder.find_value_after ("--blocked_answer", "must be followed by nxdomain or 0.0.0.0 (default nxdomain)") {
+        None => BlockedAnswer::NxDomain,
+        Some (ref s) if s == "nxdomain" => BlockedAnswer::NxDomain,
+        Some (ref s) if s == "0.0.0.0" => BlockedAnswer::Unroutable,
+        Some (s) => panic! ("Invalid answer for --blocked_answer: {}", s)
+    }
+}
+
 fn get_dns_port (args: &Vec<String>) -> u16 {
     let finder = ParameterFinder::new (args);
     let port_str = match finder.find_value_after("--dns_port", "must be followed by port number on which DNS server listens (default 53)") {
@@ -183,7 +213,7 @@ mod tests {
 
     #[test]
     fn knows_its_name () {
-        let subject = new_dns_socket_server ();
+        let subject = new_dns_socket_server (HostnameMap::new (), Blocklist::new ());
 
         let result = subject.name ();
 
@@ -341,6 +371,59 @@ mod tests {
         assert_eq! (log[0], "bind ('V4(0.0.0.0:53)')")
     }
 
+    #[test]
+    fn does_not_map_hostnames_unless_tcp_forwarding_is_requested () {
+        let mut holder = FakeStreamHolder::new ();
+        let mut subject = make_instrumented_subject ();
+
+        subject.initialize_as_root(&vec!(), &mut holder.streams ());
+
+        assert_eq! (subject.map_hostnames, false);
+    }
+
+    #[test]
+    fn maps_hostnames_when_tcp_forwarding_is_requested () {
+        let mut holder = FakeStreamHolder::new ();
+        let mut subject = make_instrumented_subject ();
+
+        subject.initialize_as_root(&vec!(String::from ("--tcp_forward_ports"), String::from ("22,993")),
+                                   &mut holder.streams ());
+
+        assert_eq! (subject.map_hostnames, true);
+    }
+
+    #[test]
+    fn answers_blocked_names_with_nxdomain_by_default () {
+        let mut holder = FakeStreamHolder::new ();
+        let mut subject = make_instrumented_subject ();
+        subject.blocked_answer = BlockedAnswer::Unroutable;
+
+        subject.initialize_as_root(&vec!(), &mut holder.streams ());
+
+        assert_eq! (subject.blocked_answer, BlockedAnswer::NxDomain);
+    }
+
+    #[test]
+    fn answers_blocked_names_with_unroutable_address_when_asked () {
+        let mut holder = FakeStreamHolder::new ();
+        let mut subject = make_instrumented_subject ();
+
+        subject.initialize_as_root(&vec!(String::from ("--blocked_answer"), String::from ("0.0.0.0")),
+                                   &mut holder.streams ());
+
+        assert_eq! (subject.blocked_answer, BlockedAnswer::Unroutable);
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid answer for --blocked_answer: 127.0.0.1")]
+    fn complains_about_unknown_blocked_answer () {
+        let mut holder = FakeStreamHolder::new ();
+        let mut subject = make_instrumented_subject ();
+
+        subject.initialize_as_root(&vec!(String::from ("--blocked_answer"), String::from ("127.0.0.1")),
+                                   &mut holder.streams ());
+    }
+
     #[test]
     fn serves_a_single_request () {
         let (log, mut buf) = {
@@ -370,6 +453,7 @@ mod tests {
         let socket_wrapper = UdpSocketWrapperMock::new (&[
             0x12, 0x34, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
         ]);
-        DnsSocketServer {dns_target: None, socket_wrapper, limiter: Limiter::with_only (1)}
+        DnsSocketServer {dns_target: None, hostname_map: HostnameMap::new (), map_hostnames: false, blocklist: Blocklist::new (),
+            blocked_answer: BlockedAnswer::NxDomain, socket_wrapper, limiter: Limiter::with_only (1)}
     }
 }
\ No newline at end of file
@@ -5,19 +5,37 @@ use std::net::IpAddr;
 use packet_facade::PacketFacade;
 use packet_facade::Query;
 use packet_facade::ResourceRecord;
+use sub_lib::blocklist::BlockSource;
+use sub_lib::blocklist::Blocklist;
+use sub_lib::hostname_map::HostnameMap;
 use sub_lib::logger::Logger;
 
+// Short, so that taking a name off the blocklist takes effect soon
+const BLOCKED_TIME_TO_LIVE: u32 = 60;
+
 pub trait ProcessorTrait {
     fn process (&self, buf: &mut[u8], length: usize, addr: &SocketAddr, logger: &Logger) -> usize;
 }
 
+/// What the Entry DNS server says about a hostname on the blocklist
+#[derive (Clone, Copy, Debug, PartialEq)]
+pub enum BlockedAnswer {
+    NxDomain,
+    // 0.0.0.0, for clients that retry elsewhere when told a name doesn't exist
+    Unroutable
+}
+
 pub struct ProcessorReal {
-    target_ip: IpAddr
+    target_ip: IpAddr,
+    // If present, each hostname gets its own address from here instead of target_ip
+    hostname_map: Option<HostnameMap>,
+    blocklist: Blocklist,
+    blocked_answer: BlockedAnswer
 }
 
 impl ProcessorReal {
-    pub fn new (target_ip: IpAddr) -> ProcessorReal {
-        ProcessorReal {target_ip}
+    pub fn new (target_ip: IpAddr, hostname_map: Option<HostnameMap>, blocklist: Blocklist, blocked_answer: BlockedAnswer) -> ProcessorReal {
+        ProcessorReal {target_ip, hostname_map, blocklist, blocked_answer}
     }
 }
 
@@ -50,10 +68,18 @@ impl ProcessorTrait for ProcessorReal {
             for query in queries {
                 if query.get_query_type() != 0x0001 { return ProcessorReal::make_not_implemented_error(&mut facade) }
                 if query.get_query_class() != 0x0001 { return ProcessorReal::make_not_implemented_error(&mut facade) }
-                let octets = match self.target_ip {
-                    IpAddr::V4 (ipv4) => ipv4.octets (),
+                if self.blocklist.blocks (&query.get_query_name (), BlockSource::Dns) {
+                    match self.blocked_answer {
+                        BlockedAnswer::NxDomain => {facade.set_rcode (0x3);},
+                        BlockedAnswer::Unroutable => {facade.add_answer (&query.get_query_name (), 0x0001, 0x0001, BLOCKED_TIME_TO_LIVE, &[0, 0, 0, 0]);}
+                    }
+                    continue
+                }
+                let octets = match (&self.hostname_map, self.target_ip) {
+                    (&Some (ref hostname_map), _) => hostname_map.address_for (&query.get_query_name ()).octets (),
+                    (&None, IpAddr::V4 (ipv4)) => ipv4.octets (),
                     // crashpoint - make a card
-                    IpAddr::V6 (_ipv6) => unimplemented!()
+                    (&None, IpAddr::V6 (_ipv6)) => unimplemented!()
                 };
                 facade.add_answer(&query.get_query_name(), 0x0001, 0x0001, 3600, &octets);
             }
@@ -129,6 +155,11 @@ struct ResponseRecord {
 
 #[cfg(test)]
 mod tests {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
     use std::time::Instant;
     use std::net::SocketAddr;
     use std::net::SocketAddrV4;
@@ -140,7 +171,10 @@ mod tests {
     use packet_facade::ResourceRecord;
     use test_utils::test_utils::init_test_logging;
     use test_utils::test_utils::TestLogHandler;
+    use sub_lib::blocklist::Blocklist;
+    use sub_lib::hostname_map::HostnameMap;
     use sub_lib::logger::Logger;
+    use processor::BlockedAnswer;
     use processor::ProcessorTrait;
     use processor::ProcessorReal;
     use processor::RequestRecord;
@@ -163,7 +197,7 @@ mod tests {
         let truncated_length = correct_length - 1;
         let truncated_buf = &mut correct_buf[0..truncated_length];
         let addr = SocketAddr::from (SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
-        let subject = ProcessorReal::new (IpAddr::from_str ("123.124.125.126").unwrap ());
+        let subject = ProcessorReal::new (IpAddr::from_str ("123.124.125.126").unwrap (), None, Blocklist::new (), BlockedAnswer::NxDomain);
 
         let result = subject.process(truncated_buf, truncated_length, &addr,
             &Logger::new (""));
@@ -186,7 +220,7 @@ mod tests {
             facade.get_length ()
         };
         let addr = SocketAddr::from (SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
-        let subject = ProcessorReal::new (IpAddr::from_str ("18.52.86.120").unwrap ());
+        let subject = ProcessorReal::new (IpAddr::from_str ("18.52.86.120").unwrap (), None, Blocklist::new (), BlockedAnswer::NxDomain);
 
         let rsp_length = subject.process (&mut buf, req_length, &addr,
             &Logger::new (""));
@@ -209,7 +243,7 @@ mod tests {
             facade.get_length ()
         };
         let addr = SocketAddr::from (SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
-        let subject = ProcessorReal::new (IpAddr::from_str ("18.52.86.120").unwrap ());
+        let subject = ProcessorReal::new (IpAddr::from_str ("18.52.86.120").unwrap (), None, Blocklist::new (), BlockedAnswer::NxDomain);
 
         let rsp_length = subject.process (&mut buf, req_length, &addr,
             &Logger::new (""));
@@ -232,7 +266,7 @@ mod tests {
             facade.get_length ()
         };
         let addr = SocketAddr::from (SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
-        let subject = ProcessorReal::new (IpAddr::from_str ("18.52.86.120").unwrap ());
+        let subject = ProcessorReal::new (IpAddr::from_str ("18.52.86.120").unwrap (), None, Blocklist::new (), BlockedAnswer::NxDomain);
 
         let rsp_length = subject.process (&mut buf, req_length, &addr, &Logger::new (""));
 
@@ -258,7 +292,7 @@ mod tests {
         };
         let addr = SocketAddr::from (SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
         let rsp_length = {
-            let subject = ProcessorReal::new (IpAddr::from_str ("18.52.86.120").unwrap ());
+            let subject = ProcessorReal::new (IpAddr::from_str ("18.52.86.120").unwrap (), None, Blocklist::new (), BlockedAnswer::NxDomain);
 
             subject.process(&mut buf, req_length, &addr, &Logger::new ("two_queries_are_answered"))
         };
@@ -305,6 +339,107 @@ mod tests {
         tlh.exists_log_containing ("101.102.103.104:53 RQ0 (1/1/ooga.com, 1/1/booga.com) -> RS0 (18.52.86.120, 18.52.86.120)");
     }
 
+    #[test]
+    fn queries_get_their_own_addresses_when_hostname_map_is_present () {
+        init_test_logging();
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = {
+            let mut request = PacketFacade::new(&mut buf, 500);
+            assert_eq! (request.set_transaction_id(0x4321), true);
+            assert_eq! (request.set_query(true), true);
+            assert_eq! (request.set_opcode(0x0), true);
+            assert_eq! (request.add_query("ooga.com", 0x0001, 0x0001), true);
+            assert_eq! (request.add_query("booga.com", 0x0001, 0x0001), true);
+            request.get_length ()
+        };
+        let addr = SocketAddr::from (SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+        let hostname_map = HostnameMap::new ();
+        let rsp_length = {
+            let subject = ProcessorReal::new (IpAddr::from_str ("18.52.86.120").unwrap (), Some (hostname_map.clone ()), Blocklist::new (), BlockedAnswer::NxDomain);
+
+            subject.process(&mut buf, req_length, &addr, &Logger::new ("queries_get_their_own_addresses_when_hostname_map_is_present"))
+        };
+
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_rcode (), Some (0x0000));
+        let answers = response.get_answers().unwrap();
+        assert_eq!(answers[0].get_name(), "ooga.com");
+        assert_eq!(answers[0].get_rdata(), vec![127 as u8, 1 as u8, 0 as u8, 1 as u8].as_slice ());
+        assert_eq!(answers[1].get_name(), "booga.com");
+        assert_eq!(answers[1].get_rdata(), vec![127 as u8, 1 as u8, 0 as u8, 2 as u8].as_slice ());
+        assert_eq!(answers.len(), 2);
+        assert_eq!(hostname_map.hostname_for (&IpAddr::from_str ("127.1.0.2").unwrap ()), Some (String::from ("booga.com")));
+    }
+
+    fn make_blocklist (test_name: &str, contents: &str) -> Blocklist {
+        let dir = env::current_dir ().unwrap ().join (Path::new ("generated")).join (Path::new ("Processor"));
+        fs::create_dir_all (dir.clone ()).unwrap ();
+        let path = dir.join (Path::new (test_name));
+        write! (File::create (path.clone ()).unwrap (), "{}", contents).unwrap ();
+        let blocklist = Blocklist::new ();
+        blocklist.load (vec! (path), None).unwrap ();
+        blocklist
+    }
+
+    fn make_two_query_request (buf: &mut [u8]) -> usize {
+        let mut request = PacketFacade::new(buf, 500);
+        request.set_transaction_id(0x4321);
+        request.set_query(true);
+        request.set_opcode(0x0);
+        request.add_query("ooga.com", 0x0001, 0x0001);
+        request.add_query("ads.booga.com", 0x0001, 0x0001);
+        request.get_length ()
+    }
+
+    #[test]
+    fn blocked_queries_get_nxdomain_and_no_address () {
+        init_test_logging();
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_two_query_request (&mut buf);
+        let addr = SocketAddr::from (SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+        let hostname_map = HostnameMap::new ();
+        let blocklist = make_blocklist ("blocked_queries_get_nxdomain_and_no_address", "0.0.0.0 booga.com\n");
+        let rsp_length = {
+            let subject = ProcessorReal::new (IpAddr::from_str ("18.52.86.120").unwrap (), Some (hostname_map.clone ()),
+                blocklist.clone (), BlockedAnswer::NxDomain);
+
+            subject.process(&mut buf, req_length, &addr, &Logger::new ("blocked_queries_get_nxdomain_and_no_address"))
+        };
+
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_rcode (), Some (0x3));
+        let answers = response.get_answers().unwrap();
+        assert_eq!(answers[0].get_name(), "ooga.com");
+        assert_eq!(answers[0].get_rdata(), vec![127 as u8, 1 as u8, 0 as u8, 1 as u8].as_slice ());
+        assert_eq!(answers.len(), 1);
+        assert_eq!(hostname_map.hostname_for (&IpAddr::from_str ("127.1.0.2").unwrap ()), None);
+        assert_eq!(blocklist.stats ().dns_blocks, 1);
+        TestLogHandler::new ().exists_log_containing ("101.102.103.104:53 RQ0 (1/1/ooga.com, 1/1/ads.booga.com) -> RS3 (127.1.0.1)");
+    }
+
+    #[test]
+    fn blocked_queries_can_get_unroutable_address_instead () {
+        let mut buf: [u8; 500] = [0; 500];
+        let req_length = make_two_query_request (&mut buf);
+        let addr = SocketAddr::from (SocketAddrV4::new(Ipv4Addr::new(101, 102, 103, 104), 53));
+        let blocklist = make_blocklist ("blocked_queries_can_get_unroutable_address_instead", "booga.com\n");
+        let rsp_length = {
+            let subject = ProcessorReal::new (IpAddr::from_str ("18.52.86.120").unwrap (), None, blocklist, BlockedAnswer::Unroutable);
+
+            subject.process(&mut buf, req_length, &addr, &Logger::new ("blocked_queries_can_get_unroutable_address_instead"))
+        };
+
+        let response = PacketFacade::new(&mut buf, rsp_length);
+        assert_eq!(response.get_rcode (), Some (0x0));
+        let answers = response.get_answers().unwrap();
+        assert_eq!(answers[0].get_name(), "ooga.com");
+        assert_eq!(answers[0].get_rdata(), vec![0x12 as u8, 0x34 as u8, 0x56 as u8, 0x78 as u8].as_slice ());
+        assert_eq!(answers[1].get_name(), "ads.booga.com");
+        assert_eq!(answers[1].get_time_to_live(), 60);
+        assert_eq!(answers[1].get_rdata(), vec![0 as u8, 0 as u8, 0 as u8, 0 as u8].as_slice ());
+        assert_eq!(answers.len(), 2);
+    }
+
     #[test]
     fn write_log_produces_correct_text () {
         init_test_logging();
@@ -0,0 +1,169 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+
+// Measures how many IncipientCoresPackages per second the Hopper can turn into transmissions with
+// different numbers of workers. CryptDENull does almost no work, so this benchmark substitutes a
+// CryptDE that burns a fixed amount of CPU on every encode and decode, roughly the way real
+// cryptography will. Run it with `cargo bench -p hopper_lib`.
+extern crate actix;
+extern crate hopper_lib;
+extern crate sub_lib;
+extern crate test_utils;
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use actix::Actor;
+use actix::Addr;
+use actix::Context;
+use actix::Handler;
+use actix::Syn;
+use actix::System;
+use hopper_lib::hopper::Hopper;
+use sub_lib::cryptde::CryptData;
+use sub_lib::cryptde::CryptDE;
+use sub_lib::cryptde::CryptdecError;
+use sub_lib::cryptde::Key;
+use sub_lib::cryptde::PlainData;
+use sub_lib::cryptde_null::CryptDENull;
+use sub_lib::dispatcher::Component;
+use sub_lib::hopper::HopperConfig;
+use sub_lib::hopper::HopperTemporaryTransmitDataMsg;
+use sub_lib::hopper::IncipientCoresPackage;
+use sub_lib::peer_actors::BindMessage;
+use sub_lib::route::Route;
+use sub_lib::route::RouteSegment;
+use test_utils::test_utils::make_peer_actors;
+
+const PACKAGE_COUNT: usize = 400;
+const PAYLOAD_SIZE: usize = 256;
+const WORK_ROUNDS: u64 = 2000;
+const WORKER_COUNTS: [usize; 5] = [0, 1, 2, 4, 8];
+
+// Keeps the optimizer from discarding the busywork
+static SINK: AtomicUsize = AtomicUsize::new (0);
+
+struct SlowCryptDE {
+    inner: CryptDENull
+}
+
+impl CryptDE for SlowCryptDE {
+    fn generate_key_pair (&mut self) {
+        self.inner.generate_key_pair ()
+    }
+
+    fn encode(&self, key: &Key, data: &PlainData) -> Result<CryptData, CryptdecError> {
+        SlowCryptDE::burn (&data.data[..]);
+        self.inner.encode (key, data)
+    }
+
+    fn decode(&self, key: &Key, data: &CryptData) -> Result<PlainData, CryptdecError> {
+        SlowCryptDE::burn (&data.data[..]);
+        self.inner.decode (key, data)
+    }
+
+    fn random(&self, dest: &mut [u8]) {
+        self.inner.random (dest)
+    }
+
+    fn private_key(&self) -> Key {
+        self.inner.private_key ()
+    }
+
+    fn public_key(&self) -> Key {
+        self.inner.public_key ()
+    }
+}
+
+impl SlowCryptDE {
+    fn burn (data: &[u8]) {
+        let mut hash: u64 = 0;
+        for round in 0..WORK_ROUNDS {
+            for byte in data {
+                hash = hash.rotate_left (5) ^ (*byte as u64) ^ round;
+            }
+        }
+        SINK.fetch_add (hash as usize, Ordering::Relaxed);
+    }
+}
+
+// Recorder's mailbox is too small for a flood like this one
+struct CountingDispatcher {
+    count: Arc<AtomicUsize>
+}
+
+impl Actor for CountingDispatcher {
+    type Context = Context<Self>;
+
+    fn started (&mut self, ctx: &mut Self::Context) {
+        ctx.set_mailbox_capacity (0);
+    }
+}
+
+impl Handler<HopperTemporaryTransmitDataMsg> for CountingDispatcher {
+    type Result = ();
+
+    fn handle (&mut self, _msg: HopperTemporaryTransmitDataMsg, _ctx: &mut Self::Context) -> Self::Result {
+        self.count.fetch_add (1, Ordering::Relaxed);
+        ()
+    }
+}
+
+fn main () {
+    let cryptde: &'static SlowCryptDE = Box::leak (Box::new (SlowCryptDE {inner: CryptDENull::new ()}));
+    println! ("{} packages with {}-byte payloads:", PACKAGE_COUNT, PAYLOAD_SIZE);
+    let baseline = measure (cryptde, WORKER_COUNTS[0]);
+    report (WORKER_COUNTS[0], baseline, baseline);
+    for worker_count in WORKER_COUNTS[1..].iter () {
+        report (*worker_count, measure (cryptde, *worker_count), baseline);
+    }
+}
+
+fn measure (cryptde: &'static SlowCryptDE, worker_count: usize) -> Duration {
+    let transmitted = Arc::new (AtomicUsize::new (0));
+    let transmitted_inner = transmitted.clone ();
+    let destination_key = Key::new (&[65, 65, 65]);
+    let route = Route::new (
+        vec! (RouteSegment::new (vec! (&cryptde.public_key (), &destination_key), Component::Neighborhood)),
+        cryptde
+    ).unwrap ();
+    let packages: Vec<IncipientCoresPackage> = (0..PACKAGE_COUNT).map (|_| IncipientCoresPackage {
+        route: route.clone (),
+        payload: PlainData::new (&[0x55; PAYLOAD_SIZE]),
+        payload_destination_key: destination_key.clone ()
+    }).collect ();
+    let (tx, rx) = mpsc::channel ();
+    thread::spawn (move || {
+        let system = System::new ("hopper_workers");
+        let mut config = HopperConfig::new ();
+        config.worker_count = worker_count;
+        let dispatcher_addr: Addr<Syn, CountingDispatcher> = CountingDispatcher {count: transmitted_inner}.start ();
+        let mut peer_actors = make_peer_actors ();
+        peer_actors.dispatcher.from_hopper = dispatcher_addr.recipient::<HopperTemporaryTransmitDataMsg> ();
+        let subject_addr: Addr<Syn, Hopper> = Hopper::new (cryptde, config).start ();
+        subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+        tx.send (subject_addr).unwrap ();
+        system.run ();
+    });
+    let subject_addr = rx.recv ().unwrap ();
+    thread::sleep (Duration::from_millis (100)); // let the workers get started
+
+    let begin = Instant::now ();
+    // The whole batch goes in at once, past the Hopper's mailbox limit
+    for package in packages {
+        subject_addr.do_send (package);
+    }
+    while transmitted.load (Ordering::Relaxed) < PACKAGE_COUNT {
+        thread::sleep (Duration::from_millis (1));
+    }
+    Instant::now ().duration_since (begin)
+}
+
+fn report (worker_count: usize, elapsed: Duration, baseline: Duration) {
+    let seconds = elapsed.as_secs () as f64 + (elapsed.subsec_nanos () as f64 / 1_000_000_000.0);
+    let baseline_seconds = baseline.as_secs () as f64 + (baseline.subsec_nanos () as f64 / 1_000_000_000.0);
+    println! ("{:>3} workers: {:>9.1} packages/second ({:.2}x)", worker_count, PACKAGE_COUNT as f64 / seconds, baseline_seconds / seconds);
+}
@@ -0,0 +1,185 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::time::Duration;
+use std::time::Instant;
+use rand::thread_rng;
+use rand::Rng;
+use sub_lib::cryptde::CryptDE;
+use sub_lib::cryptde::Key;
+use sub_lib::cryptde::PlainData;
+use sub_lib::dispatcher::Component;
+use sub_lib::hopper::CoverTrafficConfig;
+use sub_lib::hopper::IncipientCoresPackage;
+use sub_lib::route::Route;
+use sub_lib::route::RouteSegment;
+
+const MAX_COVER_ROUTE_LENGTH: usize = 3;
+
+/// Keeps a running count of the bytes the Hopper puts on the wire during the current second.
+pub struct BandwidthMeter {
+    limit: Option<usize>,
+    window_start: Instant,
+    window_bytes: usize
+}
+
+impl BandwidthMeter {
+    pub fn new (limit: Option<usize>, now: Instant) -> BandwidthMeter {
+        BandwidthMeter {
+            limit,
+            window_start: now,
+            window_bytes: 0
+        }
+    }
+
+    pub fn record (&mut self, bytes: usize, now: Instant) {
+        self.roll_window (now);
+        self.window_bytes += bytes;
+    }
+
+    pub fn has_room_for (&mut self, bytes: usize, now: Instant) -> bool {
+        self.roll_window (now);
+        match self.limit {
+            None => true,
+            Some (limit) => self.window_bytes + bytes <= limit
+        }
+    }
+
+    fn roll_window (&mut self, now: Instant) {
+        if now.duration_since (self.window_start) >= Duration::from_secs (1) {
+            self.window_start = now;
+            self.window_bytes = 0;
+        }
+    }
+}
+
+pub struct CoverTrafficGenerator {
+    config: CoverTrafficConfig
+}
+
+impl CoverTrafficGenerator {
+    pub fn new (config: CoverTrafficConfig) -> CoverTrafficGenerator {
+        CoverTrafficGenerator {config}
+    }
+
+    /// Picks the wait before the next dummy package: somewhere between half and one and a half
+    /// times the configured interval, so that emissions don't tick like a clock.
+    pub fn next_interval (&self) -> Duration {
+        let millis = (self.config.interval.as_secs () * 1000) + (self.config.interval.subsec_nanos () / 1_000_000) as u64;
+        if millis < 2 {
+            return self.config.interval
+        }
+        Duration::from_millis (thread_rng ().gen_range (millis / 2, millis + (millis / 2) + 1))
+    }
+
+    /// Makes a dummy package carrying random bytes from this Node along a random route through the
+    /// relays, or back to this Node if there are none. The last hop of the route names the Hopper as
+    /// its recipient, which tells the destination Hopper to drop the package.
+    pub fn make_package (&self, origin_key: &Key, relay_keys: &Vec<Key>, cryptde: &CryptDE) -> IncipientCoresPackage {
+        let mut rng = thread_rng ();
+        let route_length = rng.gen_range (2, MAX_COVER_ROUTE_LENGTH + 1);
+        let keys: Vec<&Key> = (0..route_length).map (|index| {
+            if (index == 0) || relay_keys.is_empty () {origin_key} else {&relay_keys[rng.gen_range (0, relay_keys.len ())]}
+        }).collect ();
+        let destination_key = keys[keys.len () - 1].clone ();
+        // crashpoint - all the keys came from the caller, so this route can't be invalid
+        let route = Route::new (vec! (RouteSegment::new (keys, Component::Hopper)), cryptde).expect ("Couldn't create cover route");
+        let mut payload: Vec<u8> = vec! (0; self.config.payload_size);
+        cryptde.random (&mut payload[..]);
+        IncipientCoresPackage {
+            route,
+            payload: PlainData::new (&payload[..]),
+            payload_destination_key: destination_key
+        }
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use sub_lib::hop::Hop;
+    use test_utils::test_utils::cryptde;
+
+    fn make_config (interval: u64, payload_size: usize) -> CoverTrafficConfig {
+        CoverTrafficConfig {
+            interval: Duration::from_millis (interval),
+            payload_size
+        }
+    }
+
+    #[test]
+    fn unlimited_bandwidth_meter_always_has_room () {
+        let now = Instant::now ();
+        let mut subject = BandwidthMeter::new (None, now);
+
+        subject.record (1_000_000_000, now);
+
+        assert_eq! (subject.has_room_for (1_000_000_000, now), true);
+    }
+
+    #[test]
+    fn limited_bandwidth_meter_runs_out_of_room_within_a_second () {
+        let now = Instant::now ();
+        let mut subject = BandwidthMeter::new (Some (1000), now);
+
+        subject.record (600, now);
+
+        assert_eq! (subject.has_room_for (400, now + Duration::from_millis (500)), true);
+        assert_eq! (subject.has_room_for (401, now + Duration::from_millis (500)), false);
+    }
+
+    #[test]
+    fn limited_bandwidth_meter_regains_room_after_a_second () {
+        let now = Instant::now ();
+        let mut subject = BandwidthMeter::new (Some (1000), now);
+
+        subject.record (1000, now);
+
+        assert_eq! (subject.has_room_for (1, now + Duration::from_millis (999)), false);
+        assert_eq! (subject.has_room_for (1000, now + Duration::from_millis (1000)), true);
+    }
+
+    #[test]
+    fn next_interval_is_within_half_of_configured_interval () {
+        let subject = CoverTrafficGenerator::new (make_config (100, 10));
+
+        for _ in 0..100 {
+            let interval = subject.next_interval ();
+            assert! ((interval >= Duration::from_millis (50)) && (interval <= Duration::from_millis (150)), "{:?}", interval);
+        }
+    }
+
+    #[test]
+    fn made_package_ends_at_a_hopper_with_a_payload_of_configured_size () {
+        let cryptde = cryptde ();
+        let key = cryptde.public_key ();
+        let subject = CoverTrafficGenerator::new (make_config (100, 37));
+
+        let mut package = subject.make_package (&key, &vec! (), cryptde);
+
+        assert_eq! (package.payload.data.len (), 37);
+        assert_eq! (package.payload_destination_key, key);
+        let mut last_hop = None;
+        while let Some (hop) = package.route.shift (&cryptde.private_key (), cryptde) {
+            if hop.public_key.data.is_empty () {
+                last_hop = Some (hop);
+                break
+            }
+        }
+        assert_eq! (last_hop, Some (Hop::new (&Key::new (b""), Component::Hopper)));
+    }
+
+    #[test]
+    fn made_package_leaves_this_node_for_one_of_the_relays () {
+        let cryptde = cryptde ();
+        let key = cryptde.public_key ();
+        let relay_keys = vec! (Key::new (b"Bill"), Key::new (b"Ted"));
+        let subject = CoverTrafficGenerator::new (make_config (100, 10));
+
+        for _ in 0..20 {
+            let mut package = subject.make_package (&key, &relay_keys, cryptde);
+
+            let first_hop = package.route.shift (&cryptde.private_key (), cryptde).unwrap ();
+            assert! (relay_keys.contains (&first_hop.public_key), "{:?}", first_hop);
+            assert! (relay_keys.contains (&package.payload_destination_key), "{:?}", package.payload_destination_key);
+        }
+    }
+}
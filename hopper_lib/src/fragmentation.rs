@@ -0,0 +1,266 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+use rand::random;
+use sub_lib::cryptde::PlainData;
+
+/// Reassembly bookkeeping charged against the memory limit for every buffered fragment, so that
+/// floods of empty fragments can't grow the buffer without bound
+const FRAGMENT_OVERHEAD: usize = 64;
+
+/// One numbered piece of a payload that was too big to travel in a single CORES package
+#[derive (Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PayloadFragment {
+    pub message_id: u64,
+    pub index: u32,
+    pub count: u32,
+    pub data: Vec<u8>
+}
+
+/// Splits a payload into fragments carrying at most max_fragment_size bytes each. Every fragment
+/// of the payload shares the same randomly-chosen message ID.
+pub fn fragment (payload: &PlainData, max_fragment_size: usize) -> Vec<PayloadFragment> {
+    if max_fragment_size == 0 {panic! ("Fragments must be allowed to carry at least one byte")}
+    let message_id: u64 = random ();
+    let chunks: Vec<&[u8]> = payload.data.chunks (max_fragment_size).collect ();
+    let count = chunks.len () as u32;
+    chunks.into_iter ().enumerate ().map (|(index, chunk)| PayloadFragment {
+        message_id,
+        index: index as u32,
+        count,
+        data: chunk.to_vec ()
+    }).collect ()
+}
+
+struct PartialPayload {
+    started: Instant,
+    count: u32,
+    fragments: HashMap<u32, Vec<u8>>,
+    cost: usize
+}
+
+/// Collects fragments until every piece of a payload has arrived. Partial payloads are abandoned
+/// once they are older than the timeout, and the oldest ones are abandoned early whenever the
+/// fragments buffered for all of them would exceed max_bytes.
+pub struct Reassembler {
+    timeout: Duration,
+    max_bytes: usize,
+    buffered_bytes: usize,
+    partials: HashMap<u64, PartialPayload>
+}
+
+impl Reassembler {
+    pub fn new (timeout: Duration, max_bytes: usize) -> Reassembler {
+        Reassembler {
+            timeout,
+            max_bytes,
+            buffered_bytes: 0,
+            partials: HashMap::new ()
+        }
+    }
+
+    /// Returns the whole payload if this fragment completed it, None if more fragments are
+    /// still expected, or an explanation if the fragment had to be discarded.
+    pub fn add (&mut self, fragment: PayloadFragment, now: Instant) -> Result<Option<PlainData>, String> {
+        self.purge_expired (now);
+        if fragment.index >= fragment.count {
+            return Err (format! ("Fragment {} of message {} is out of range 0..{}", fragment.index, fragment.message_id, fragment.count))
+        }
+        if fragment.count == 1 {
+            return Ok (Some (PlainData::from (fragment.data)))
+        }
+        let mismatched = match self.partials.get (&fragment.message_id) {
+            Some (partial) if partial.count != fragment.count => true,
+            Some (partial) if partial.fragments.contains_key (&fragment.index) => return Ok (None),
+            _ => false
+        };
+        if mismatched {
+            self.abandon (fragment.message_id);
+            return Err (format! ("Fragment {} of message {} claims {} fragments; abandoning message", fragment.index, fragment.message_id, fragment.count))
+        }
+        let completes = match self.partials.get (&fragment.message_id) {
+            Some (partial) => partial.fragments.len () as u32 + 1 == partial.count,
+            None => false
+        };
+        if completes {
+            // crashpoint - we just checked that this message is present
+            let mut partial = self.partials.remove (&fragment.message_id).expect ("Partial payload disappeared");
+            self.buffered_bytes -= partial.cost;
+            partial.fragments.insert (fragment.index, fragment.data);
+            let mut data = vec! ();
+            for index in 0..partial.count {
+                // crashpoint - a complete partial payload has every index from 0 to count
+                data.extend (partial.fragments.remove (&index).expect ("Fragment disappeared"));
+            }
+            return Ok (Some (PlainData::from (data)))
+        }
+        let cost = fragment.data.len () + FRAGMENT_OVERHEAD;
+        if cost > self.max_bytes {
+            return Err (format! ("Fragment {} of message {} is too big to buffer: {} bytes", fragment.index, fragment.message_id, fragment.data.len ()))
+        }
+        while self.buffered_bytes + cost > self.max_bytes {
+            self.abandon_oldest ();
+        }
+        self.buffered_bytes += cost;
+        let partial = self.partials.entry (fragment.message_id).or_insert_with (|| PartialPayload {
+            started: now,
+            count: fragment.count,
+            fragments: HashMap::new (),
+            cost: 0
+        });
+        partial.cost += cost;
+        partial.fragments.insert (fragment.index, fragment.data);
+        Ok (None)
+    }
+
+    pub fn buffered_bytes (&self) -> usize {
+        self.buffered_bytes
+    }
+
+    fn purge_expired (&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let expired: Vec<u64> = self.partials.iter ()
+            .filter (|(_, partial)| now.duration_since (partial.started) >= timeout)
+            .map (|(message_id, _)| *message_id)
+            .collect ();
+        expired.into_iter ().for_each (|message_id| self.abandon (message_id));
+    }
+
+    fn abandon_oldest (&mut self) {
+        let oldest = self.partials.iter ()
+            .min_by_key (|(_, partial)| partial.started)
+            .map (|(message_id, _)| *message_id);
+        match oldest {
+            // crashpoint - buffered_bytes is only nonzero while some partial payload is buffered
+            None => panic! ("Reassembly buffer accounting is broken: {} bytes buffered with no partial payloads", self.buffered_bytes),
+            Some (message_id) => self.abandon (message_id)
+        }
+    }
+
+    fn abandon (&mut self, message_id: u64) {
+        if let Some (partial) = self.partials.remove (&message_id) {
+            self.buffered_bytes -= partial.cost;
+        }
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+
+    fn make_fragment (message_id: u64, index: u32, count: u32, data: &[u8]) -> PayloadFragment {
+        PayloadFragment {message_id, index, count, data: data.to_vec ()}
+    }
+
+    #[test]
+    fn fragment_splits_payload_into_numbered_pieces_with_common_message_id () {
+        let payload = PlainData::new (b"abcdefghij");
+
+        let result = fragment (&payload, 4);
+
+        assert_eq! (result.len (), 3);
+        let message_id = result[0].message_id;
+        assert_eq! (result, vec! (
+            make_fragment (message_id, 0, 3, b"abcd"),
+            make_fragment (message_id, 1, 3, b"efgh"),
+            make_fragment (message_id, 2, 3, b"ij"),
+        ));
+    }
+
+    #[test]
+    fn fragment_leaves_small_payload_in_one_piece () {
+        let payload = PlainData::new (b"abc");
+
+        let result = fragment (&payload, 4);
+
+        assert_eq! (result.len (), 1);
+        assert_eq! (result[0].count, 1);
+        assert_eq! (result[0].data, b"abc".to_vec ());
+    }
+
+    #[test]
+    fn fragments_reassemble_in_any_order () {
+        let mut subject = Reassembler::new (Duration::from_secs (10), 10000);
+        let now = Instant::now ();
+
+        assert_eq! (subject.add (make_fragment (7, 2, 3, b"ij"), now), Ok (None));
+        assert_eq! (subject.add (make_fragment (7, 0, 3, b"abcd"), now), Ok (None));
+        let result = subject.add (make_fragment (7, 1, 3, b"efgh"), now);
+
+        assert_eq! (result, Ok (Some (PlainData::new (b"abcdefghij"))));
+        assert_eq! (subject.buffered_bytes (), 0);
+    }
+
+    #[test]
+    fn duplicate_fragments_are_ignored () {
+        let mut subject = Reassembler::new (Duration::from_secs (10), 10000);
+        let now = Instant::now ();
+
+        subject.add (make_fragment (7, 0, 2, b"ab"), now).unwrap ();
+        let buffered_bytes = subject.buffered_bytes ();
+        let result = subject.add (make_fragment (7, 0, 2, b"ab"), now);
+
+        assert_eq! (result, Ok (None));
+        assert_eq! (subject.buffered_bytes (), buffered_bytes);
+        assert_eq! (subject.add (make_fragment (7, 1, 2, b"cd"), now), Ok (Some (PlainData::new (b"abcd"))));
+    }
+
+    #[test]
+    fn out_of_range_fragment_is_rejected () {
+        let mut subject = Reassembler::new (Duration::from_secs (10), 10000);
+
+        let result = subject.add (make_fragment (7, 3, 3, b"ab"), Instant::now ());
+
+        assert_eq! (result, Err (String::from ("Fragment 3 of message 7 is out of range 0..3")));
+    }
+
+    #[test]
+    fn fragment_with_inconsistent_count_abandons_message () {
+        let mut subject = Reassembler::new (Duration::from_secs (10), 10000);
+        let now = Instant::now ();
+        subject.add (make_fragment (7, 0, 3, b"ab"), now).unwrap ();
+
+        let result = subject.add (make_fragment (7, 1, 2, b"cd"), now);
+
+        assert_eq! (result, Err (String::from ("Fragment 1 of message 7 claims 2 fragments; abandoning message")));
+        assert_eq! (subject.buffered_bytes (), 0);
+    }
+
+    #[test]
+    fn partial_payloads_expire_after_timeout () {
+        let mut subject = Reassembler::new (Duration::from_millis (100), 10000);
+        let now = Instant::now ();
+        subject.add (make_fragment (7, 0, 2, b"ab"), now).unwrap ();
+
+        let result = subject.add (make_fragment (7, 1, 2, b"cd"), now + Duration::from_millis (100));
+
+        assert_eq! (result, Ok (None));
+        assert_eq! (subject.buffered_bytes (), 2 + FRAGMENT_OVERHEAD);
+    }
+
+    #[test]
+    fn oldest_partial_payload_is_abandoned_when_memory_runs_out () {
+        let mut subject = Reassembler::new (Duration::from_secs (10), 2 * (4 + FRAGMENT_OVERHEAD));
+        let now = Instant::now ();
+        subject.add (make_fragment (1, 0, 2, b"aaaa"), now).unwrap ();
+        subject.add (make_fragment (2, 0, 2, b"bbbb"), now + Duration::from_millis (1)).unwrap ();
+
+        subject.add (make_fragment (3, 0, 2, b"cccc"), now + Duration::from_millis (2)).unwrap ();
+
+        assert_eq! (subject.buffered_bytes (), 2 * (4 + FRAGMENT_OVERHEAD));
+        assert_eq! (subject.add (make_fragment (3, 1, 2, b"cccc"), now + Duration::from_millis (3)), Ok (Some (PlainData::new (b"cccccccc"))));
+        assert_eq! (subject.add (make_fragment (2, 1, 2, b"bbbb"), now + Duration::from_millis (4)), Ok (Some (PlainData::new (b"bbbbbbbb"))));
+        assert_eq! (subject.add (make_fragment (1, 1, 2, b"aaaa"), now + Duration::from_millis (5)), Ok (None));
+    }
+
+    #[test]
+    fn fragment_too_big_to_buffer_is_rejected () {
+        let mut subject = Reassembler::new (Duration::from_secs (10), 10 + FRAGMENT_OVERHEAD);
+
+        let result = subject.add (make_fragment (7, 0, 2, b"12345678901"), Instant::now ());
+
+        assert_eq! (result, Err (String::from ("Fragment 0 of message 7 is too big to buffer: 11 bytes")));
+        assert_eq! (subject.buffered_bytes (), 0);
+    }
+}
@@ -1,36 +1,71 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
-use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 use actix::Actor;
 use actix::Addr;
+use actix::Arbiter;
+use actix::AsyncContext;
 use actix::Context;
 use actix::Handler;
+use actix::Message;
 use actix::Recipient;
 use actix::Syn;
-use serde_cbor;
+use actix::prelude::SendError;
+use cover_traffic::BandwidthMeter;
+use cover_traffic::CoverTrafficGenerator;
+use fragmentation::PayloadFragment;
+use fragmentation::Reassembler;
+use hopper_worker::HopperWorker;
+use hopper_worker::WorkOrder;
+use hopper_worker::WorkResult;
+use package_processor::HopperOutcome;
+use package_processor::HopperWork;
+use package_processor::PackageProcessor;
 use sub_lib::cryptde::CryptData;
 use sub_lib::cryptde::CryptDE;
 use sub_lib::cryptde::CryptdecError;
 use sub_lib::cryptde::Key;
-use sub_lib::cryptde::PlainData;
 use sub_lib::dispatcher::Component;
-use sub_lib::dispatcher::Endpoint;
 use sub_lib::dispatcher::InboundClientData;
 use sub_lib::hop::Hop;
+use sub_lib::hopper::BULK_TRAFFIC_THRESHOLD;
 use sub_lib::hopper::ExpiredCoresPackage;
+use sub_lib::hopper::HopperConfig;
 use sub_lib::hopper::HopperSubs;
 use sub_lib::hopper::HopperTemporaryTransmitDataMsg;
 use sub_lib::hopper::IncipientCoresPackage;
 use sub_lib::logger::Logger;
+use sub_lib::outbox::Outbox;
 use sub_lib::peer_actors::BindMessage;
 use sub_lib::route::Route;
 use sub_lib::utils::NODE_MAILBOX_CAPACITY;
+use mixer::Mixer;
 
 pub struct Hopper {
     cryptde: &'static CryptDE,
     to_proxy_server: Option<Recipient<Syn, ExpiredCoresPackage>>,
-    to_proxy_client: Option<Recipient<Syn, ExpiredCoresPackage>>,
+    to_proxy_client: Option<Outbox<ExpiredCoresPackage>>,
     // TODO when we are decentralized, change this to a TransmitDataMsg
     to_dispatcher: Option<Recipient<Syn, HopperTemporaryTransmitDataMsg>>,
+    interactive_mixer: Option<Mixer<HopperTemporaryTransmitDataMsg>>,
+    bulk_mixer: Option<Mixer<HopperTemporaryTransmitDataMsg>>,
+    cover_traffic_generator: Option<CoverTrafficGenerator>,
+    cover_relays: Vec<Key>,
+    bandwidth_meter: BandwidthMeter,
+    max_fragment_size: Option<usize>,
+    reassembler: Reassembler,
+    processor: PackageProcessor,
+    worker_count: usize,
+    workers: Vec<Outbox<WorkOrder>>,
+    next_sequence: u64,
+    next_sequence_to_release: u64,
+    pending_outcomes: BTreeMap<u64, HopperOutcome>,
+    outcome_missing_since: Option<Instant>,
+    outcome_timeout: Duration,
+    max_pending_outcomes: usize,
     logger: Logger,
 }
 
@@ -44,8 +79,10 @@ impl Handler<BindMessage> for Hopper {
     fn handle(&mut self, msg: BindMessage, ctx: &mut Self::Context) -> Self::Result {
         ctx.set_mailbox_capacity(NODE_MAILBOX_CAPACITY);
         self.to_proxy_server = Some(msg.peer_actors.proxy_server.from_hopper);
-        self.to_proxy_client = Some(msg.peer_actors.proxy_client.from_hopper);
+        self.to_proxy_client = Some(Outbox::new (msg.peer_actors.proxy_client.from_hopper, "Proxy Client"));
         self.to_dispatcher = Some(msg.peer_actors.dispatcher.from_hopper);
+        self.start_workers (ctx);
+        self.schedule_cover_package (ctx);
         ()
     }
 }
@@ -53,37 +90,9 @@ impl Handler<BindMessage> for Hopper {
 impl Handler<IncipientCoresPackage> for Hopper {
     type Result = ();
 
-    fn handle(&mut self, msg: IncipientCoresPackage, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: IncipientCoresPackage, ctx: &mut Self::Context) -> Self::Result {
         self.logger.debug (format! ("Received IncipientCoresPackage with {}-byte payload", msg.payload.data.len ()));
-        let (live_package, key) = LiveCoresPackage::from_incipient(msg, self.cryptde.borrow());
-
-        let serialized_package = match serde_cbor::ser::to_vec (&live_package) {
-            Ok(package) => package,
-            Err(_) => {
-                self.logger.error(format! ("Couldn't serialize package"));
-                // TODO what should we do here? (nothing is unbound --so we don't need to blow up-- but we can't send this package)
-                return ()
-            }
-        };
-
-        let encrypted_package = match self.cryptde.encode(&key, &PlainData::new(&serialized_package[..])) {
-            Ok(package) => package,
-            Err (_) => {
-                self.logger.error(format! ("Couldn't encode package"));
-                // TODO what should we do here? (nothing is unbound --so we don't need to blow up-- but we can't send this package)
-                return ()
-            }
-        };
-
-        // TODO when we are decentralized, change this to a TransmitDataMsg
-        let transmit_msg = HopperTemporaryTransmitDataMsg {
-            endpoint: Endpoint::Key(key),
-            last_data: false, // Hopper-to-Hopper streams are never remotely killed
-            data: encrypted_package.data,
-        };
-
-        self.logger.debug (format! ("Sending TransmitDataMsg with {}-byte payload to Dispatcher", transmit_msg.data.len ()));
-        self.to_dispatcher.as_ref().expect("Dispatcher unbound in Hopper").try_send(transmit_msg).expect("Dispatcher is dead");
+        self.dispatch (HopperWork::Outbound (msg), ctx);
         ()
     }
 }
@@ -91,61 +100,50 @@ impl Handler<IncipientCoresPackage> for Hopper {
 impl Handler<InboundClientData> for Hopper {
     type Result = ();
 
-    fn handle(&mut self, msg: InboundClientData, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: InboundClientData, ctx: &mut Self::Context) -> Self::Result {
         self.logger.debug (format! ("Received {} bytes of InboundClientData from Dispatcher", msg.data.len ()));
-        let decrypted_package = match self.cryptde.decode(&self.cryptde.private_key(), &CryptData::new(&msg.data[..])) {
-            Ok(package) => package,
-            Err (e) => {
-                self.logger.error(format! ("{:?}", e));
-                // TODO what should we do here? (nothing is unbound --so we don't need to blow up-- but we can't send this package)
-                return ()
-            }
-        };
-        let live_package = match serde_cbor::de::from_slice::<LiveCoresPackage>(&decrypted_package.data[..]) {
-            Ok(package) => package,
-            Err(_) => {
-                self.logger.error(format!("Couldn't deserialize package"));
-                // TODO what should we do here? (nothing is unbound --so we don't need to blow up-- but we can't send this package)
-                return ()
-            }
-
-        };
+        self.dispatch (HopperWork::Inbound (msg), ctx);
+        ()
+    }
+}
 
-        let next_hop = live_package.next_hop(self.cryptde.borrow());
+impl Handler<WorkResult> for Hopper {
+    type Result = ();
 
-        match next_hop.component {
-            Component::ProxyServer => {
-                let expired_package = live_package.to_expired(self.cryptde.borrow());
-                self.logger.debug (format! ("Forwarding ExpiredCoresPackage to Proxy Server: {:?}", expired_package));
-                self.to_proxy_server.as_ref().expect("ProxyServer unbound in Hopper").try_send(expired_package).expect("Proxy Server is dead")
-            },
-            Component::ProxyClient => {
-                let expired_package = live_package.to_expired(self.cryptde.borrow());
-                self.logger.debug (format! ("Forwarding ExpiredCoresPackage to Proxy Client: {:?}", expired_package));
-                self.to_proxy_client.as_ref ().expect ("ProxyClient unbound in Hopper").try_send (expired_package ).expect ("Proxy Client is dead")
-            },
-            Component::Neighborhood => unimplemented!(),
-            Component::Hopper => {
-                let transmit_msg = match self.to_transmit_msg (live_package, msg.last_data) {
-                    // crashpoint - need to figure out how to bubble up different kinds of errors, or just log and return
-                    Err (_) => unimplemented! (),
-                    Ok (m) => m
-                };
-                self.logger.debug (format! ("Relaying {}-byte LiveCoresPackage Dispatcher inside a TransmitDataMsg", transmit_msg.data.len ()));
-                self.to_dispatcher.as_ref().expect("Dispatcher unbound in Hopper").try_send(transmit_msg).expect("Dispatcher is dead");
-            }
-        };
+    fn handle(&mut self, msg: WorkResult, ctx: &mut Self::Context) -> Self::Result {
+        if msg.sequence < self.next_sequence_to_release {
+            self.logger.warning (format! ("Dropping outcome {} that arrived after it was given up on", msg.sequence));
+            return
+        }
+        self.pending_outcomes.insert (msg.sequence, msg.outcome);
+        self.release_outcomes (Instant::now (), ctx);
         ()
     }
 }
 
 impl Hopper {
-    pub fn new (cryptde: &'static CryptDE) -> Hopper {
+    pub fn new (cryptde: &'static CryptDE, config: HopperConfig) -> Hopper {
         Hopper {
             cryptde,
             to_proxy_server: None,
             to_proxy_client: None,
             to_dispatcher: None,
+            interactive_mixer: config.interactive_mixing.map (|mixing| Mixer::new (mixing)),
+            bulk_mixer: config.bulk_mixing.map (|mixing| Mixer::new (mixing)),
+            cover_traffic_generator: config.cover_traffic.map (|cover_traffic| CoverTrafficGenerator::new (cover_traffic)),
+            cover_relays: config.cover_relays,
+            bandwidth_meter: BandwidthMeter::new (config.bandwidth_limit, Instant::now ()),
+            max_fragment_size: config.fragmentation.max_fragment_size,
+            reassembler: Reassembler::new (config.fragmentation.reassembly_timeout, config.fragmentation.max_reassembly_bytes),
+            processor: PackageProcessor::new (cryptde, config.fragmentation.max_fragment_size),
+            worker_count: config.worker_count,
+            workers: vec! (),
+            next_sequence: 0,
+            next_sequence_to_release: 0,
+            pending_outcomes: BTreeMap::new (),
+            outcome_missing_since: None,
+            outcome_timeout: Duration::from_millis (OUTCOME_TIMEOUT_MS),
+            max_pending_outcomes: MAX_PENDING_OUTCOMES,
             logger: Logger::new ("Hopper"),
         }
     }
@@ -158,41 +156,245 @@ impl Hopper {
         }
     }
 
-    // TODO when we are decentralized, change this type to a TransmitDataMsg
-    pub fn to_transmit_msg (&self, live_package: LiveCoresPackage, last_data: bool) -> Result<HopperTemporaryTransmitDataMsg, CryptdecError> {
-        let (next_key, next_live_package) = match live_package.to_next_live (self.cryptde.borrow ()) {
-            // crashpoint - log error and return None?
-            Err (_) => unimplemented! (),
-            Ok (p) => p
+    fn start_workers (&mut self, ctx: &mut Context<Hopper>) {
+        let hopper_addr: Addr<Syn, Hopper> = ctx.address ();
+        let cryptde = self.cryptde;
+        let max_fragment_size = self.max_fragment_size;
+        self.workers = (0..self.worker_count).map (|_| {
+            let to_hopper = hopper_addr.clone ().recipient::<WorkResult> ();
+            let worker_addr: Addr<Syn, HopperWorker> = Arbiter::start (move |worker_ctx: &mut Context<HopperWorker>| {
+                worker_ctx.set_mailbox_capacity(NODE_MAILBOX_CAPACITY);
+                HopperWorker::new (PackageProcessor::new (cryptde, max_fragment_size), to_hopper)
+            });
+            Outbox::new (worker_addr.recipient::<WorkOrder> (), "Hopper worker")
+        }).collect ();
+    }
+
+    /// Without workers, packages are processed right here on the Hopper's own thread. With them,
+    /// packages are handed out in turn, and their outcomes are acted on in the order the packages
+    /// arrived no matter which worker finishes first, so no stream's data gets reordered.
+    fn dispatch (&mut self, work: HopperWork, ctx: &mut Context<Hopper>) {
+        if self.workers.is_empty () {
+            let outcome = self.processor.process (work);
+            self.act_on (outcome, ctx);
+            return
+        }
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        let index = (sequence % self.workers.len () as u64) as usize;
+        self.workers[index].send_from (WorkOrder {sequence, work}, ctx, move |hopper: &mut Hopper| &mut hopper.workers[index]);
+    }
+
+    /// Acts on outcomes in sequence. If one goes missing, the outcomes behind it wait for it only so
+    /// long, and only so many of them wait, before it's given up on.
+    fn release_outcomes (&mut self, now: Instant, ctx: &mut Context<Hopper>) {
+        loop {
+            while let Some (outcome) = self.pending_outcomes.remove (&self.next_sequence_to_release) {
+                self.next_sequence_to_release += 1;
+                self.act_on (outcome, ctx);
+            }
+            let next_available = match self.pending_outcomes.keys ().next () {
+                None => {self.outcome_missing_since = None; return},
+                Some (sequence) => *sequence
+            };
+            let missing_since = match self.outcome_missing_since {
+                Some (since) => since,
+                None => {
+                    self.outcome_missing_since = Some (now);
+                    let timeout = self.outcome_timeout;
+                    ctx.run_later (timeout, |hopper, ctx| hopper.release_outcomes (Instant::now (), ctx));
+                    now
+                }
+            };
+            if (now.duration_since (missing_since) < self.outcome_timeout) && (self.pending_outcomes.len () <= self.max_pending_outcomes) {
+                return
+            }
+            self.logger.warning (format! ("Giving up on outcomes {} through {} from the workers", self.next_sequence_to_release, next_available - 1));
+            self.next_sequence_to_release = next_available;
+            self.outcome_missing_since = None;
+        }
+    }
+
+    fn act_on (&mut self, outcome: HopperOutcome, ctx: &mut Context<Hopper>) {
+        match outcome {
+            HopperOutcome::Transmit (transmit_msgs) => {
+                for transmit_msg in transmit_msgs {
+                    self.logger.debug (format! ("Sending TransmitDataMsg with {}-byte payload to Dispatcher", transmit_msg.data.len ()));
+                    self.transmit (transmit_msg, ctx);
+                }
+            },
+            HopperOutcome::Deliver {component, package, fragmented} => {
+                let expired_package = match self.reassemble (package, fragmented) {
+                    Some (p) => p,
+                    None => return
+                };
+                match component {
+                    Component::ProxyServer => {
+                        self.logger.debug (format! ("Forwarding ExpiredCoresPackage to Proxy Server: {:?}", expired_package));
+                        Hopper::offer (self.to_proxy_server.as_ref().expect("ProxyServer unbound in Hopper"), expired_package, "Proxy Server", &self.logger);
+                    },
+                    Component::ProxyClient => {
+                        self.logger.debug (format! ("Forwarding ExpiredCoresPackage to Proxy Client: {:?}", expired_package));
+                        self.to_proxy_client.as_mut ().expect ("ProxyClient unbound in Hopper").send_from (expired_package, ctx,
+                            |hopper: &mut Hopper| hopper.to_proxy_client.as_mut ().expect ("ProxyClient unbound in Hopper"))
+                    },
+                    // crashpoint - the PackageProcessor delivers only to the Proxy Server and Proxy Client
+                    other => panic! ("Hopper can't deliver packages to the {:?}", other)
+                }
+            },
+            HopperOutcome::Drop => ()
+        }
+    }
+
+    fn reassemble (&mut self, expired_package: ExpiredCoresPackage, fragmented: bool) -> Option<ExpiredCoresPackage> {
+        if !fragmented {return Some (expired_package)}
+        let fragment = match expired_package.payload::<PayloadFragment> () {
+            Ok (fragment) => fragment,
+            Err (_) => {
+                self.logger.error (format! ("Couldn't deserialize payload fragment"));
+                return None
+            }
         };
-        let next_live_package_ser = match serde_cbor::ser::to_vec (&next_live_package) {
-            // crashpoint - log error and return None?
-            Err (_) => unimplemented! (),
-            Ok (p) => p
+        self.logger.debug (format! ("Received fragment {} of {} for message {}", fragment.index, fragment.count, fragment.message_id));
+        match self.reassembler.add (fragment, Instant::now ()) {
+            Err (e) => {
+                self.logger.error (e);
+                None
+            },
+            Ok (None) => None,
+            Ok (Some (payload)) => Some (ExpiredCoresPackage::new (expired_package.remaining_route, payload))
+        }
+    }
+
+    fn schedule_cover_package (&self, ctx: &mut Context<Hopper>) {
+        let interval = match self.cover_traffic_generator {
+            None => return,
+            Some (ref generator) => generator.next_interval ()
+        };
+        ctx.run_later (interval, |hopper, ctx| {
+            hopper.emit_cover_package (ctx);
+            hopper.schedule_cover_package (ctx);
+        });
+    }
+
+    fn emit_cover_package (&mut self, ctx: &mut Context<Hopper>) {
+        let incipient = match self.cover_traffic_generator {
+            None => return,
+            Some (ref generator) => generator.make_package (&self.cryptde.public_key (), &self.cover_relays, self.cryptde)
         };
-        let next_live_package_enc = match self.cryptde.encode (&next_key, &PlainData::new (&next_live_package_ser[..])) {
-            // crashpoint - log error and return None?
-            Err (_) => unimplemented! (),
-            Ok (p) => p
+        let transmit_msg = match self.processor.incipient_to_transmit_msg (incipient, false) {
+            Some (m) => m,
+            None => return
         };
-        // TODO when we are decentralized, change this to a TransmitDataMsg
-        Ok (HopperTemporaryTransmitDataMsg {
-            endpoint: Endpoint::Key(next_key),
-            last_data,
-            data: next_live_package_enc.data
-        })
+        if !self.bandwidth_meter.has_room_for (transmit_msg.data.len (), Instant::now ()) {
+            self.logger.trace (format! ("Skipping {}-byte cover package: bandwidth limit reached", transmit_msg.data.len ()));
+            return
+        }
+        self.transmit (transmit_msg, ctx);
+    }
+
+    // TODO when we are decentralized, change this type to a TransmitDataMsg
+    fn transmit (&mut self, transmit_msg: HopperTemporaryTransmitDataMsg, ctx: &mut Context<Hopper>) {
+        let bulk = transmit_msg.data.len () >= BULK_TRAFFIC_THRESHOLD;
+        let released = match self.mixer_for (bulk) {
+            None => vec! (transmit_msg),
+            Some (mixer) => {
+                let (delay, released) = mixer.add (transmit_msg, Instant::now ());
+                ctx.run_later (delay, move |hopper, _ctx| hopper.release_due_packages (bulk));
+                released
+            }
+        };
+        self.send_to_dispatcher (released);
+    }
+
+    fn release_due_packages (&mut self, bulk: bool) {
+        let released = match self.mixer_for (bulk) {
+            None => vec! (),
+            Some (mixer) => mixer.release_due (Instant::now ())
+        };
+        self.send_to_dispatcher (released);
+    }
+
+    fn mixer_for (&mut self, bulk: bool) -> Option<&mut Mixer<HopperTemporaryTransmitDataMsg>> {
+        if bulk {self.bulk_mixer.as_mut ()} else {self.interactive_mixer.as_mut ()}
+    }
+
+    fn send_to_dispatcher (&mut self, transmit_msgs: Vec<HopperTemporaryTransmitDataMsg>) {
+        if transmit_msgs.is_empty () {return}
+        let to_dispatcher = self.to_dispatcher.as_ref().expect("Dispatcher unbound in Hopper");
+        for transmit_msg in transmit_msgs {
+            let size = transmit_msg.data.len ();
+            if Hopper::offer (to_dispatcher, transmit_msg, "Dispatcher", &self.logger) {
+                self.bandwidth_meter.record (size, Instant::now ());
+            }
+        }
+    }
+
+    /// The Dispatcher and the Proxy Server wait on the Hopper when it's full, so the Hopper can't wait
+    /// on them. A package they have no room for is dropped, the way a router drops what won't fit in
+    /// its queue: nothing acknowledges it, so whoever sent it sends it again.
+    fn offer<M> (recipient: &Recipient<Syn, M>, msg: M, name: &str, logger: &Logger) -> bool
+            where M: Message + Send + 'static, M::Result: Send {
+        match recipient.try_send (msg) {
+            Ok (()) => true,
+            Err (SendError::Full (_)) => {
+                logger.warning (format! ("{} is full; dropping package", name));
+                false
+            },
+            // crashpoint - actors don't die while the Node is running
+            Err (SendError::Closed (_)) => panic! ("{} is dead", name)
+        }
     }
 }
 
+/// How long the Hopper waits for a worker's outcome before acting on the ones that came after it
+pub const OUTCOME_TIMEOUT_MS: u64 = 5000;
+
+/// How many outcomes may wait behind a missing one before the Hopper stops waiting for it
+pub const MAX_PENDING_OUTCOMES: usize = 1024;
+
+/// The most times a package may be relayed from one Hopper to another. Honest routes are far
+/// shorter than this; a package that runs out is assumed to be caught in a loop.
+pub const DEFAULT_HOP_BUDGET: u8 = 32;
+
+/// How long a package may spend on the Substratum Network before Hoppers start dropping it
+pub const PACKAGE_LIFETIME_MS: u64 = 60000;
+
+/// How far a Hopper's clock may lag behind the originating Node's before it drops packages that
+/// haven't really expired yet
+pub const CLOCK_SKEW_TOLERANCE_MS: u64 = 30000;
+
+// hops_remaining and expires_at travel in the clear, so any relay can reset them. They keep
+// packages from circling forever by accident, not on purpose. Packages from Nodes that don't send
+// them get a full hop budget and never expire.
 #[derive (Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct LiveCoresPackage {
     pub route: Route,
-    pub payload: CryptData
+    pub payload: CryptData,
+    #[serde (default)]
+    pub fragmented: bool,
+    #[serde (default = "default_hops_remaining")]
+    pub hops_remaining: u8,
+    #[serde (default = "default_expires_at")]
+    pub expires_at: u64 // milliseconds since the UNIX epoch
+}
+
+fn default_hops_remaining () -> u8 {
+    DEFAULT_HOP_BUDGET
+}
+
+fn default_expires_at () -> u64 {
+    u64::max_value ()
 }
 
 impl LiveCoresPackage {
     pub fn new (route: Route, payload: CryptData) -> LiveCoresPackage {
-        LiveCoresPackage { route, payload}
+        let expires_at = LiveCoresPackage::epoch_millis (SystemTime::now () + Duration::from_millis (PACKAGE_LIFETIME_MS));
+        LiveCoresPackage { route, payload, fragmented: false, hops_remaining: DEFAULT_HOP_BUDGET, expires_at }
+    }
+
+    pub fn is_expired (&self, now: SystemTime) -> bool {
+        LiveCoresPackage::epoch_millis (now) > self.expires_at.saturating_add (CLOCK_SKEW_TOLERANCE_MS)
     }
 
     pub fn from_incipient (incipient: IncipientCoresPackage, cryptde: &CryptDE) -> (LiveCoresPackage, Key) {
@@ -224,7 +426,13 @@ impl LiveCoresPackage {
             Some (h) => h
         };
         let next_key = next_hop.public_key;
-        let next_live = LiveCoresPackage::new (self.route, self.payload);
+        let next_live = LiveCoresPackage {
+            route: self.route,
+            payload: self.payload,
+            fragmented: self.fragmented,
+            hops_remaining: self.hops_remaining.saturating_sub (1),
+            expires_at: self.expires_at
+        };
         Ok ((next_key, next_live))
     }
 
@@ -235,33 +443,78 @@ impl LiveCoresPackage {
             Some (h) => h
         }
     }
+
+    fn epoch_millis (time: SystemTime) -> u64 {
+        let since_epoch = time.duration_since (UNIX_EPOCH).expect ("SystemTime before UNIX EPOCH!");
+        (since_epoch.as_secs () * 1000) + (since_epoch.subsec_nanos () / 1_000_000) as u64
+    }
 }
 
 #[cfg (test)]
 mod tests {
     use super::*;
+    use serde_cbor;
+    use fragmentation::fragment;
     use std::net::SocketAddr;
     use std::str::FromStr;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::sync::mpsc;
     use std::thread;
+    use std::time::Duration;
     use actix::Actor;
     use actix::Arbiter;
     use actix::msgs;
     use actix::System;
     use sub_lib::cryptde::PlainData;
+    use sub_lib::cryptde_null::CryptDENull;
     use sub_lib::dispatcher::Component;
+    use sub_lib::dispatcher::Endpoint;
     use sub_lib::hopper::ExpiredCoresPackage;
     use sub_lib::hopper::HopperTemporaryTransmitDataMsg;
     use sub_lib::hopper::IncipientCoresPackage;
+    use sub_lib::hopper::CoverTrafficConfig;
+    use sub_lib::hopper::MixingConfig;
     use sub_lib::route::Route;
     use sub_lib::route::RouteSegment;
     use test_utils::test_utils::PayloadMock;
     use test_utils::test_utils::Recorder;
     use test_utils::test_utils::cryptde;
+    use test_utils::test_utils::init_test_logging;
+    use test_utils::test_utils::TestLogHandler;
     use test_utils::test_utils::make_peer_actors_from;
     use test_utils::test_utils::route_to_proxy_client;
     use test_utils::test_utils::route_to_proxy_server;
+    use test_utils::test_utils::route_from_proxy_server;
     use test_utils::test_utils::make_meaningless_route;
 
+    struct LoopbackDispatcher {
+        to_hopper: Recipient<Syn, InboundClientData>,
+        relay_count: Arc<Mutex<usize>>
+    }
+
+    impl Actor for LoopbackDispatcher {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<HopperTemporaryTransmitDataMsg> for LoopbackDispatcher {
+        type Result = ();
+
+        fn handle(&mut self, msg: HopperTemporaryTransmitDataMsg, _ctx: &mut Self::Context) -> Self::Result {
+            *self.relay_count.lock ().unwrap () += 1;
+            self.to_hopper.try_send (InboundClientData {
+                socket_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                origin_port: None,
+                target_hostname: None,
+                proxy_protocol: None,
+                last_data: msg.last_data,
+                stopped_reading: false,
+                data: msg.data
+            }).unwrap ();
+            ()
+        }
+    }
+
     #[test]
     fn live_cores_package_can_be_constructed_from_scratch () {
         let payload = CryptData::new (&[5, 6]);
@@ -269,10 +522,42 @@ mod tests {
         let route = Route::new(vec!(RouteSegment::new(vec!(&Key::new(&[1, 2]), &Key::new(&[3, 4])),
                       Component::Neighborhood)), cryptde).unwrap();
 
+        let before = SystemTime::now ();
+
         let subject = LiveCoresPackage::new (route.clone(), payload.clone ());
 
+        let after = SystemTime::now ();
         assert_eq! (subject.route, route);
         assert_eq! (subject.payload, payload);
+        assert_eq! (subject.fragmented, false);
+        assert_eq! (subject.hops_remaining, DEFAULT_HOP_BUDGET);
+        let lifetime = Duration::from_millis (PACKAGE_LIFETIME_MS);
+        assert! (subject.expires_at >= LiveCoresPackage::epoch_millis (before + lifetime));
+        assert! (subject.expires_at <= LiveCoresPackage::epoch_millis (after + lifetime));
+    }
+
+    #[test]
+    fn live_cores_package_expires_once_its_expiry_time_is_past_the_clock_skew_tolerance () {
+        let mut subject = LiveCoresPackage::new (make_meaningless_route (), CryptData::new (&[5, 6]));
+        subject.expires_at = 1000000;
+
+        assert_eq! (subject.is_expired (UNIX_EPOCH + Duration::from_millis (1000000 + CLOCK_SKEW_TOLERANCE_MS)), false);
+        assert_eq! (subject.is_expired (UNIX_EPOCH + Duration::from_millis (1000001 + CLOCK_SKEW_TOLERANCE_MS)), true);
+    }
+
+    #[test]
+    fn next_live_cores_package_has_one_fewer_hop_remaining () {
+        let cryptde = cryptde();
+        let key = cryptde.public_key ();
+        let route = Route::new (vec! (RouteSegment::new (vec! (&key, &key, &key), Component::ProxyServer)), cryptde).unwrap ();
+        let mut subject = LiveCoresPackage::new (route, CryptData::new (&[5, 6]));
+        subject.hops_remaining = 5;
+        subject.expires_at = 1234567890123;
+
+        let (_, result) = subject.to_next_live (cryptde).unwrap ();
+
+        assert_eq! (result.hops_remaining, 4);
+        assert_eq! (result.expires_at, 1234567890123);
     }
 
     #[test]
@@ -317,7 +602,7 @@ mod tests {
         thread::spawn (move || {
             let system = System::new ("converts_incipient_message_to_live_and_sends_to_dispatcher");
             let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
-            let subject = Hopper::new (cryptde);
+            let subject = Hopper::new (cryptde, HopperConfig::new ());
             let subject_addr: Addr<Syn, Hopper> = subject.start ();
             subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
 
@@ -328,7 +613,11 @@ mod tests {
         dispatcher_awaiter.await_message_count(1);
         let dispatcher_recording = dispatcher_recording_arc.lock().unwrap();
         let record = dispatcher_recording.get_record::<HopperTemporaryTransmitDataMsg>(0);
-        let expected_lcp = LiveCoresPackage::from_incipient (incipient_cores_package_a, cryptde).0;
+        let actual_lcp_ser = cryptde.decode (&CryptDENull::other_key (&destination_key), &CryptData::new (&record.data[..])).unwrap ();
+        let actual_lcp = serde_cbor::de::from_slice::<LiveCoresPackage> (&actual_lcp_ser.data[..]).unwrap ();
+        let mut expected_lcp = LiveCoresPackage::from_incipient (incipient_cores_package_a, cryptde).0;
+        assert! (expected_lcp.expires_at >= actual_lcp.expires_at);
+        expected_lcp.expires_at = actual_lcp.expires_at;
         let expected_lcp_ser = PlainData::new (&serde_cbor::ser::to_vec (&expected_lcp).unwrap ());
         let expected_lcp_enc = cryptde.encode (&destination_key, &expected_lcp_ser).unwrap ();
         assert_eq! (*record, HopperTemporaryTransmitDataMsg {
@@ -353,13 +642,16 @@ mod tests {
         let inbound_client_data = InboundClientData {
             socket_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
             origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
+            stopped_reading: false,
             data: data_enc.data
         };
         thread::spawn(move || {
             let system = System::new("converts_live_message_to_expired_for_proxy_client");
             let peer_actors = make_peer_actors_from(None, None, None, Some(component), None);
-            let subject = Hopper::new (cryptde);
+            let subject = Hopper::new (cryptde, HopperConfig::new ());
             let subject_addr: Addr<Syn, Hopper> = subject.start();
             subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
 
@@ -389,13 +681,16 @@ mod tests {
         let inbound_client_data = InboundClientData {
             socket_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
             origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
+            stopped_reading: false,
             data: data_enc.data
         };
         thread::spawn(move || {
             let system = System::new("converts_live_message_to_expired_for_proxy_server");
             let peer_actors = make_peer_actors_from(Some (component), None, None, None, None);
-            let subject = Hopper::new (cryptde);
+            let subject = Hopper::new (cryptde, HopperConfig::new ());
             let subject_addr: Addr<Syn, Hopper> = subject.start();
             subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
 
@@ -410,6 +705,612 @@ mod tests {
         assert_eq! (*record, expected_ecp);
     }
 
+    // Takes one message and then sits on it, leaving the rest in its mailbox, until the gate opens
+    struct StuckComponent {
+        gate: Option<mpsc::Receiver<()>>,
+        received: Arc<Mutex<usize>>
+    }
+
+    impl Actor for StuckComponent {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<HopperTemporaryTransmitDataMsg> for StuckComponent {
+        type Result = ();
+
+        fn handle(&mut self, _msg: HopperTemporaryTransmitDataMsg, _ctx: &mut Self::Context) -> Self::Result {
+            self.take ()
+        }
+    }
+
+    impl Handler<ExpiredCoresPackage> for StuckComponent {
+        type Result = ();
+
+        fn handle(&mut self, _msg: ExpiredCoresPackage, _ctx: &mut Self::Context) -> Self::Result {
+            self.take ()
+        }
+    }
+
+    impl StuckComponent {
+        // Returns once the mailbox is down to size
+        fn start (capacity: usize, gate: mpsc::Receiver<()>, received: Arc<Mutex<usize>>) -> Addr<Syn, StuckComponent> {
+            let (ready_tx, ready_rx) = mpsc::channel ();
+            let addr = Arbiter::start (move |ctx: &mut Context<StuckComponent>| {
+                ctx.set_mailbox_capacity (capacity);
+                ready_tx.send (()).unwrap ();
+                StuckComponent {gate: Some (gate), received}
+            });
+            ready_rx.recv ().unwrap ();
+            addr
+        }
+
+        fn take (&mut self) {
+            *self.received.lock ().unwrap () += 1;
+            if let Some (gate) = self.gate.take () {gate.recv_timeout (Duration::from_secs (5)).is_ok ();}
+        }
+    }
+
+    #[test]
+    fn a_stuck_dispatcher_is_offered_no_more_packages_than_its_mailbox_holds () {
+        init_test_logging ();
+        let cryptde = cryptde();
+        let received = Arc::new (Mutex::new (0));
+        let received_a = received.clone ();
+        let (gate_tx, gate_rx) = mpsc::channel ();
+        let destination_key = Key::new (&[65, 65, 65]);
+        let route = Route::new (
+            vec! (RouteSegment::new (vec! (&cryptde.public_key (), &destination_key.clone ()), Component::Neighborhood)),
+            cryptde
+        ).unwrap ();
+        thread::spawn (move || {
+            let system = System::new ("a_stuck_dispatcher_is_offered_no_more_packages_than_its_mailbox_holds");
+            let mut peer_actors = make_peer_actors_from(None, None, None, None, None);
+            peer_actors.dispatcher.from_hopper = StuckComponent::start (4, gate_rx, received_a).recipient ();
+            let subject = Hopper::new (cryptde, HopperConfig::new ());
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            for _ in 0..20 {
+                subject_addr.do_send (IncipientCoresPackage::new (route.clone (), PlainData::new (&b"abcd"[..]), &destination_key));
+            }
+
+            system.run ();
+        });
+        TestLogHandler::new ().await_log_containing ("Dispatcher is full; dropping package", 5000);
+        thread::sleep (Duration::from_millis (200));
+        gate_tx.send (()).unwrap ();
+        thread::sleep (Duration::from_millis (200));
+        let received = *received.lock ().unwrap ();
+        assert! ((received > 0) && (received <= 5), "Dispatcher received {} packages", received);
+    }
+
+    #[test]
+    fn a_stuck_proxy_server_is_offered_no_more_packages_than_its_mailbox_holds () {
+        init_test_logging ();
+        let cryptde = cryptde();
+        let received = Arc::new (Mutex::new (0));
+        let received_a = received.clone ();
+        let (gate_tx, gate_rx) = mpsc::channel ();
+        let route = route_to_proxy_server(&cryptde.public_key (), cryptde);
+        let lcp = LiveCoresPackage::new (route, cryptde.encode (&cryptde.public_key (), &PlainData::new (&b"abcd"[..])).unwrap ());
+        let data_ser = PlainData::new (&serde_cbor::ser::to_vec (&lcp).unwrap ()[..]);
+        let data_enc = cryptde.encode (&cryptde.public_key (), &data_ser).unwrap ();
+        thread::spawn (move || {
+            let system = System::new ("a_stuck_proxy_server_is_offered_no_more_packages_than_its_mailbox_holds");
+            let mut peer_actors = make_peer_actors_from(None, None, None, None, None);
+            peer_actors.proxy_server.from_hopper = StuckComponent::start (4, gate_rx, received_a).recipient ();
+            let subject = Hopper::new (cryptde, HopperConfig::new ());
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            for _ in 0..20 {
+                subject_addr.do_send (InboundClientData {
+                    socket_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                    origin_port: None,
+                    target_hostname: None,
+                    proxy_protocol: None,
+                    last_data: false,
+                    stopped_reading: false,
+                    data: data_enc.data.clone ()
+                });
+            }
+
+            system.run ();
+        });
+        TestLogHandler::new ().await_log_containing ("Proxy Server is full; dropping package", 5000);
+        thread::sleep (Duration::from_millis (200));
+        gate_tx.send (()).unwrap ();
+        thread::sleep (Duration::from_millis (200));
+        let received = *received.lock ().unwrap ();
+        assert! ((received > 0) && (received <= 5), "Proxy Server received {} packages", received);
+    }
+
+    #[test]
+    fn mixing_hopper_holds_outbound_package_until_its_delay_expires () {
+        let cryptde = cryptde();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let dispatcher_awaiter = dispatcher.get_awaiter();
+        let destination_key = Key::new (&[65, 65, 65]);
+        let route = Route::new (
+            vec! (RouteSegment::new (vec! (&cryptde.public_key (), &destination_key.clone ()), Component::Neighborhood)),
+            cryptde
+        ).unwrap ();
+        let incipient_cores_package = IncipientCoresPackage::new (route, PlainData::new (&b"abcd"[..]), &destination_key);
+        let mut config = HopperConfig::new ();
+        config.interactive_mixing = Some (MixingConfig {batch_size: 0, min_delay: Duration::from_millis (300), max_delay: Duration::from_millis (300)});
+        let begin = Instant::now ();
+        thread::spawn (move || {
+            let system = System::new ("mixing_hopper_holds_outbound_package_until_its_delay_expires");
+            let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
+            let subject = Hopper::new (cryptde, config);
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            subject_addr.try_send (incipient_cores_package).unwrap ();
+
+            system.run ();
+        });
+        dispatcher_awaiter.await_message_count(1);
+        assert! (Instant::now ().duration_since (begin) >= Duration::from_millis (300));
+        let dispatcher_recording = dispatcher_recording_arc.lock().unwrap();
+        let record = dispatcher_recording.get_record::<HopperTemporaryTransmitDataMsg>(0);
+        assert_eq! (record.endpoint, Endpoint::Key (destination_key));
+    }
+
+    #[test]
+    fn mixing_hopper_releases_full_batch_at_once () {
+        let cryptde = cryptde();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let dispatcher_awaiter = dispatcher.get_awaiter();
+        let destination_key = Key::new (&[65, 65, 65]);
+        let route = Route::new (
+            vec! (RouteSegment::new (vec! (&cryptde.public_key (), &destination_key.clone ()), Component::Neighborhood)),
+            cryptde
+        ).unwrap ();
+        let first_package = IncipientCoresPackage::new (route.clone (), PlainData::new (&b"abcd"[..]), &destination_key);
+        let second_package = IncipientCoresPackage::new (route, PlainData::new (&b"efgh"[..]), &destination_key);
+        let mut config = HopperConfig::new ();
+        config.interactive_mixing = Some (MixingConfig {batch_size: 2, min_delay: Duration::from_secs (60), max_delay: Duration::from_secs (60)});
+        thread::spawn (move || {
+            let system = System::new ("mixing_hopper_releases_full_batch_at_once");
+            let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
+            let subject = Hopper::new (cryptde, config);
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            subject_addr.try_send (first_package).unwrap ();
+            subject_addr.try_send (second_package).unwrap ();
+
+            system.run ();
+        });
+        dispatcher_awaiter.await_message_count(2);
+        let dispatcher_recording = dispatcher_recording_arc.lock().unwrap();
+        assert_eq! (dispatcher_recording.len (), 2);
+    }
+
+    #[test]
+    fn hopper_with_cover_traffic_emits_dummy_packages_to_itself () {
+        let cryptde = cryptde();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let dispatcher_awaiter = dispatcher.get_awaiter();
+        let mut config = HopperConfig::new ();
+        config.cover_traffic = Some (CoverTrafficConfig {interval: Duration::from_millis (20), payload_size: 100});
+        thread::spawn (move || {
+            let system = System::new ("hopper_with_cover_traffic_emits_dummy_packages_to_itself");
+            let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
+            let subject = Hopper::new (cryptde, config);
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            system.run ();
+        });
+        dispatcher_awaiter.await_message_count(3);
+        let dispatcher_recording = dispatcher_recording_arc.lock().unwrap();
+        let record = dispatcher_recording.get_record::<HopperTemporaryTransmitDataMsg>(0);
+        assert_eq! (record.endpoint, Endpoint::Key (cryptde.public_key ()));
+        assert_eq! (record.last_data, false);
+    }
+
+    #[test]
+    fn hopper_with_cover_traffic_and_relays_sends_dummy_packages_through_the_relays () {
+        let cryptde = cryptde();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let dispatcher_awaiter = dispatcher.get_awaiter();
+        let relay_keys = vec! (Key::new (b"Bill"), Key::new (b"Ted"));
+        let mut config = HopperConfig::new ();
+        config.cover_traffic = Some (CoverTrafficConfig {interval: Duration::from_millis (20), payload_size: 100});
+        config.cover_relays = relay_keys.clone ();
+        thread::spawn (move || {
+            let system = System::new ("hopper_with_cover_traffic_and_relays_sends_dummy_packages_through_the_relays");
+            let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
+            let subject = Hopper::new (cryptde, config);
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            system.run ();
+        });
+        dispatcher_awaiter.await_message_count(5);
+        let dispatcher_recording = dispatcher_recording_arc.lock().unwrap();
+        (0..5).for_each (|index| {
+            let record = dispatcher_recording.get_record::<HopperTemporaryTransmitDataMsg>(index);
+            match record.endpoint {
+                Endpoint::Key (ref key) => assert! (relay_keys.contains (key), "{:?}", key),
+                ref endpoint => panic! ("Expected a relay key, but found {:?}", endpoint)
+            }
+        });
+    }
+
+    #[test]
+    fn hopper_with_cover_traffic_respects_bandwidth_limit () {
+        let cryptde = cryptde();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let mut config = HopperConfig::new ();
+        config.cover_traffic = Some (CoverTrafficConfig {interval: Duration::from_millis (10), payload_size: 100});
+        config.bandwidth_limit = Some (10);
+        thread::spawn (move || {
+            let system = System::new ("hopper_with_cover_traffic_respects_bandwidth_limit");
+            let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
+            let subject = Hopper::new (cryptde, config);
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            system.run ();
+        });
+        thread::sleep (Duration::from_millis (200));
+        assert_eq! (dispatcher_recording_arc.lock().unwrap().len (), 0);
+    }
+
+    #[test]
+    fn cover_traffic_is_silently_dropped_at_its_destination () {
+        init_test_logging();
+        let cryptde = cryptde();
+        let proxy_server = Recorder::new ();
+        let proxy_server_recording_arc = proxy_server.get_recording ();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let proxy_client = Recorder::new ();
+        let proxy_client_recording_arc = proxy_client.get_recording ();
+        let generator = CoverTrafficGenerator::new (CoverTrafficConfig {interval: Duration::from_millis (1000), payload_size: 10});
+        let mut route = generator.make_package (&cryptde.public_key (), &vec! (), cryptde).route;
+        while !route.next_hop (&cryptde.private_key (), cryptde).unwrap ().public_key.data.is_empty () {
+            route.shift (&cryptde.private_key (), cryptde).unwrap ();
+        }
+        let lcp = LiveCoresPackage::new (route, cryptde.encode (&cryptde.public_key (), &PlainData::new (b"cover")).unwrap ());
+        let data_ser = PlainData::new (&serde_cbor::ser::to_vec (&lcp).unwrap ()[..]);
+        let data_enc = cryptde.encode (&cryptde.public_key (), &data_ser).unwrap ();
+        let inbound_client_data = InboundClientData {
+            socket_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: data_enc.data
+        };
+        thread::spawn(move || {
+            let system = System::new("cover_traffic_is_silently_dropped_at_its_destination");
+            let peer_actors = make_peer_actors_from(Some (proxy_server), Some (dispatcher), None, Some (proxy_client), None);
+            let subject = Hopper::new (cryptde, HopperConfig::new ());
+            let subject_addr: Addr<Syn, Hopper> = subject.start();
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+            subject_addr.try_send(inbound_client_data ).unwrap ();
+
+            system.run();
+        });
+        TestLogHandler::new ().await_log_containing ("TRACE: Hopper: Dropping cover traffic", 1000);
+        assert_eq! (proxy_server_recording_arc.lock ().unwrap ().len (), 0);
+        assert_eq! (dispatcher_recording_arc.lock ().unwrap ().len (), 0);
+        assert_eq! (proxy_client_recording_arc.lock ().unwrap ().len (), 0);
+    }
+
+    #[test]
+    fn hopper_fragments_payload_larger_than_maximum () {
+        let cryptde = cryptde();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let dispatcher_awaiter = dispatcher.get_awaiter();
+        let route = route_from_proxy_server (&cryptde.public_key (), cryptde);
+        let incipient_cores_package = IncipientCoresPackage {
+            route,
+            payload: PlainData::new (b"abcdefghij"),
+            payload_destination_key: cryptde.public_key ()
+        };
+        let mut config = HopperConfig::new ();
+        config.fragmentation.max_fragment_size = Some (4);
+        thread::spawn (move || {
+            let system = System::new ("hopper_fragments_payload_larger_than_maximum");
+            let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
+            let subject = Hopper::new (cryptde, config);
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            subject_addr.try_send (incipient_cores_package).unwrap ();
+
+            system.run ();
+        });
+        dispatcher_awaiter.await_message_count(3);
+        let dispatcher_recording = dispatcher_recording_arc.lock().unwrap();
+        let fragments: Vec<PayloadFragment> = (0..3).map (|index| {
+            let record = dispatcher_recording.get_record::<HopperTemporaryTransmitDataMsg>(index);
+            let lcp_ser = cryptde.decode (&cryptde.private_key (), &CryptData::new (&record.data[..])).unwrap ();
+            let lcp = serde_cbor::de::from_slice::<LiveCoresPackage> (&lcp_ser.data[..]).unwrap ();
+            assert_eq! (lcp.fragmented, true);
+            let fragment_ser = cryptde.decode (&cryptde.private_key (), &lcp.payload).unwrap ();
+            serde_cbor::de::from_slice::<PayloadFragment> (&fragment_ser.data[..]).unwrap ()
+        }).collect ();
+        assert_eq! (fragments.iter ().map (|f| f.index).collect::<Vec<u32>> (), vec! (0, 1, 2));
+        assert_eq! (fragments.iter ().all (|f| (f.count == 3) && (f.message_id == fragments[0].message_id)), true);
+        assert_eq! (fragments.into_iter ().flat_map (|f| f.data).collect::<Vec<u8>> (), b"abcdefghij".to_vec ());
+    }
+
+    #[test]
+    fn hopper_does_not_fragment_payload_no_larger_than_maximum () {
+        let cryptde = cryptde();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let route = route_from_proxy_server (&cryptde.public_key (), cryptde);
+        let incipient_cores_package = IncipientCoresPackage {
+            route,
+            payload: PlainData::new (b"abcd"),
+            payload_destination_key: cryptde.public_key ()
+        };
+        let mut config = HopperConfig::new ();
+        config.fragmentation.max_fragment_size = Some (4);
+        thread::spawn (move || {
+            let system = System::new ("hopper_does_not_fragment_payload_no_larger_than_maximum");
+            let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
+            let subject = Hopper::new (cryptde, config);
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            subject_addr.try_send (incipient_cores_package).unwrap ();
+
+            system.run ();
+        });
+        thread::sleep (Duration::from_millis (200));
+        let dispatcher_recording = dispatcher_recording_arc.lock().unwrap();
+        assert_eq! (dispatcher_recording.len (), 1);
+        let record = dispatcher_recording.get_record::<HopperTemporaryTransmitDataMsg>(0);
+        let lcp_ser = cryptde.decode (&cryptde.private_key (), &CryptData::new (&record.data[..])).unwrap ();
+        let lcp = serde_cbor::de::from_slice::<LiveCoresPackage> (&lcp_ser.data[..]).unwrap ();
+        assert_eq! (lcp.fragmented, false);
+        assert_eq! (cryptde.decode (&cryptde.private_key (), &lcp.payload).unwrap (), PlainData::new (b"abcd"));
+    }
+
+    #[test]
+    fn hopper_reassembles_fragments_before_expiring_package () {
+        let cryptde = cryptde();
+        let proxy_server = Recorder::new ();
+        let proxy_server_recording_arc = proxy_server.get_recording ();
+        let proxy_server_awaiter = proxy_server.get_awaiter ();
+        let route = route_to_proxy_server (&cryptde.public_key (), cryptde);
+        let mut inbound_client_data_msgs: Vec<InboundClientData> = fragment (&PlainData::new (b"abcdefghij"), 4).into_iter ().map (|fragment| {
+            let fragment_ser = PlainData::new (&serde_cbor::ser::to_vec (&fragment).unwrap ()[..]);
+            let mut lcp = LiveCoresPackage::new (route.clone (), cryptde.encode (&cryptde.public_key (), &fragment_ser).unwrap ());
+            lcp.fragmented = true;
+            let data_ser = PlainData::new (&serde_cbor::ser::to_vec (&lcp).unwrap ()[..]);
+            InboundClientData {
+                socket_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                origin_port: None,
+                target_hostname: None,
+                proxy_protocol: None,
+                last_data: false,
+                stopped_reading: false,
+                data: cryptde.encode (&cryptde.public_key (), &data_ser).unwrap ().data
+            }
+        }).collect ();
+        inbound_client_data_msgs.reverse ();
+        let expected_route = route.clone ();
+        thread::spawn(move || {
+            let system = System::new("hopper_reassembles_fragments_before_expiring_package");
+            let peer_actors = make_peer_actors_from(Some (proxy_server), None, None, None, None);
+            let subject = Hopper::new (cryptde, HopperConfig::new ());
+            let subject_addr: Addr<Syn, Hopper> = subject.start();
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+            for inbound_client_data in inbound_client_data_msgs {
+                subject_addr.try_send(inbound_client_data).unwrap ();
+            }
+
+            system.run();
+        });
+        proxy_server_awaiter.await_message_count(1);
+        thread::sleep (Duration::from_millis (100));
+        let proxy_server_recording = proxy_server_recording_arc.lock().unwrap();
+        assert_eq! (proxy_server_recording.len (), 1);
+        let record = proxy_server_recording.get_record::<ExpiredCoresPackage>(0);
+        assert_eq! (*record, ExpiredCoresPackage::new (expected_route, PlainData::new (b"abcdefghij")));
+    }
+
+    #[test]
+    fn expired_package_is_dropped_and_logged () {
+        init_test_logging();
+        let cryptde = cryptde();
+        let proxy_client = Recorder::new ();
+        let proxy_client_recording_arc = proxy_client.get_recording ();
+        let route = route_to_proxy_client(&cryptde.public_key (), cryptde);
+        let payload = PlainData::new (&b"stale"[..]);
+        let mut lcp = LiveCoresPackage::new (route, cryptde.encode (&cryptde.public_key (), &payload).unwrap ());
+        lcp.expires_at = LiveCoresPackage::epoch_millis (SystemTime::now ()) - CLOCK_SKEW_TOLERANCE_MS - 1;
+        let data_ser = PlainData::new (&serde_cbor::ser::to_vec (&lcp).unwrap ()[..]);
+        let data_enc = cryptde.encode (&cryptde.public_key (), &data_ser).unwrap ();
+        let inbound_client_data = InboundClientData {
+            socket_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: data_enc.data
+        };
+        thread::spawn(move || {
+            let system = System::new("expired_package_is_dropped_and_logged");
+            let peer_actors = make_peer_actors_from(None, None, None, Some (proxy_client), None);
+            let subject = Hopper::new (cryptde, HopperConfig::new ());
+            let subject_addr: Addr<Syn, Hopper> = subject.start();
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+            subject_addr.try_send(inbound_client_data ).unwrap ();
+
+            system.run();
+        });
+        TestLogHandler::new ().await_log_containing ("WARN: Hopper: Dropping package: it expired before it reached its destination", 1000);
+        assert_eq! (proxy_client_recording_arc.lock ().unwrap ().len (), 0);
+    }
+
+    #[test]
+    fn package_on_looping_route_is_dropped_when_its_hop_budget_runs_out () {
+        init_test_logging();
+        let cryptde = cryptde();
+        let proxy_server = Recorder::new ();
+        let proxy_server_recording_arc = proxy_server.get_recording ();
+        let key = cryptde.public_key ();
+        let keys: Vec<&Key> = (0..100).map (|_| &key).collect ();
+        let route = Route::new (vec! (RouteSegment::new (keys, Component::ProxyServer)), cryptde).unwrap ();
+        let incipient_cores_package = IncipientCoresPackage {
+            route,
+            payload: PlainData::new (b"around and around"),
+            payload_destination_key: key.clone ()
+        };
+        let relay_count = Arc::new (Mutex::new (0));
+        let relay_count_inner = relay_count.clone ();
+        thread::spawn (move || {
+            let system = System::new ("package_on_looping_route_is_dropped_when_its_hop_budget_runs_out");
+            let subject = Hopper::new (cryptde, HopperConfig::new ());
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+            let loopback = LoopbackDispatcher {
+                to_hopper: subject_addr.clone ().recipient::<InboundClientData> (),
+                relay_count: relay_count_inner
+            };
+            let mut peer_actors = make_peer_actors_from(Some (proxy_server), None, None, None, None);
+            let loopback_addr: Addr<Syn, LoopbackDispatcher> = loopback.start ();
+            peer_actors.dispatcher.from_hopper = loopback_addr.recipient::<HopperTemporaryTransmitDataMsg> ();
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            subject_addr.try_send (incipient_cores_package).unwrap ();
+
+            system.run ();
+        });
+        TestLogHandler::new ().await_log_containing ("WARN: Hopper: Dropping package: its hop budget is exhausted", 5000);
+        assert_eq! (*relay_count.lock ().unwrap (), DEFAULT_HOP_BUDGET as usize + 1);
+        assert_eq! (proxy_server_recording_arc.lock ().unwrap ().len (), 0);
+    }
+
+    #[test]
+    fn hopper_with_workers_keeps_packages_in_arrival_order () {
+        let cryptde = cryptde();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let dispatcher_awaiter = dispatcher.get_awaiter();
+        let destination_key = Key::new (&[65, 65, 65]);
+        let route = Route::new (
+            vec! (RouteSegment::new (vec! (&cryptde.public_key (), &destination_key.clone ()), Component::Neighborhood)),
+            cryptde
+        ).unwrap ();
+        let packages: Vec<IncipientCoresPackage> = (0..12u8).map (|index| IncipientCoresPackage {
+            route: route.clone (),
+            payload: PlainData::new (&[index]),
+            payload_destination_key: destination_key.clone ()
+        }).collect ();
+        let mut config = HopperConfig::new ();
+        config.worker_count = 4;
+        thread::spawn (move || {
+            let system = System::new ("hopper_with_workers_keeps_packages_in_arrival_order");
+            let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
+            let subject = Hopper::new (cryptde, config);
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            for package in packages {
+                subject_addr.try_send (package).unwrap ();
+            }
+
+            system.run ();
+        });
+        dispatcher_awaiter.await_message_count(12);
+        let dispatcher_recording = dispatcher_recording_arc.lock().unwrap();
+        let payloads: Vec<u8> = (0..12).map (|index| {
+            let record = dispatcher_recording.get_record::<HopperTemporaryTransmitDataMsg>(index);
+            let lcp_ser = cryptde.decode (&CryptDENull::other_key (&destination_key), &CryptData::new (&record.data[..])).unwrap ();
+            let lcp = serde_cbor::de::from_slice::<LiveCoresPackage> (&lcp_ser.data[..]).unwrap ();
+            cryptde.decode (&CryptDENull::other_key (&destination_key), &lcp.payload).unwrap ().data[0]
+        }).collect ();
+        assert_eq! (payloads, (0..12u8).collect::<Vec<u8>> ());
+    }
+
+    fn make_transmit_outcome (data: &[u8]) -> HopperOutcome {
+        HopperOutcome::Transmit (vec! (HopperTemporaryTransmitDataMsg {
+            endpoint: Endpoint::Key (Key::new (b"next")),
+            last_data: false,
+            data: CryptData::new (data).data
+        }))
+    }
+
+    #[test]
+    fn hopper_acts_on_later_outcomes_once_a_missing_one_times_out () {
+        init_test_logging();
+        let cryptde = cryptde();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let dispatcher_awaiter = dispatcher.get_awaiter();
+        thread::spawn (move || {
+            let system = System::new ("hopper_acts_on_later_outcomes_once_a_missing_one_times_out");
+            let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
+            let mut subject = Hopper::new (cryptde, HopperConfig::new ());
+            subject.outcome_timeout = Duration::from_millis (50);
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            subject_addr.try_send (WorkResult {sequence: 1, outcome: make_transmit_outcome (b"one")}).unwrap ();
+            subject_addr.try_send (WorkResult {sequence: 2, outcome: make_transmit_outcome (b"two")}).unwrap ();
+
+            system.run ();
+        });
+        dispatcher_awaiter.await_message_count(2);
+        let dispatcher_recording = dispatcher_recording_arc.lock().unwrap();
+        assert_eq! (&dispatcher_recording.get_record::<HopperTemporaryTransmitDataMsg>(0).data[..], b"one");
+        assert_eq! (&dispatcher_recording.get_record::<HopperTemporaryTransmitDataMsg>(1).data[..], b"two");
+        TestLogHandler::new ().exists_log_containing ("WARN: Hopper: Giving up on outcomes 0 through 0 from the workers");
+    }
+
+    #[test]
+    fn hopper_stops_waiting_for_a_missing_outcome_when_too_many_pile_up_behind_it () {
+        let cryptde = cryptde();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let dispatcher_awaiter = dispatcher.get_awaiter();
+        thread::spawn (move || {
+            let system = System::new ("hopper_stops_waiting_for_a_missing_outcome_when_too_many_pile_up_behind_it");
+            let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
+            let mut subject = Hopper::new (cryptde, HopperConfig::new ());
+            subject.outcome_timeout = Duration::from_secs (3600);
+            subject.max_pending_outcomes = 2;
+            let subject_addr: Addr<Syn, Hopper> = subject.start ();
+            subject_addr.try_send (BindMessage {peer_actors}).unwrap ();
+
+            subject_addr.try_send (WorkResult {sequence: 1, outcome: make_transmit_outcome (b"one")}).unwrap ();
+            subject_addr.try_send (WorkResult {sequence: 2, outcome: make_transmit_outcome (b"two")}).unwrap ();
+            subject_addr.try_send (WorkResult {sequence: 3, outcome: make_transmit_outcome (b"three")}).unwrap ();
+            subject_addr.try_send (WorkResult {sequence: 0, outcome: make_transmit_outcome (b"zero")}).unwrap ();
+            subject_addr.try_send (WorkResult {sequence: 4, outcome: make_transmit_outcome (b"four")}).unwrap ();
+
+            system.run ();
+        });
+        dispatcher_awaiter.await_message_count(4);
+        let dispatcher_recording = dispatcher_recording_arc.lock().unwrap();
+        let data: Vec<Vec<u8>> = (0..4).map (|index| dispatcher_recording.get_record::<HopperTemporaryTransmitDataMsg>(index).data.to_vec ()).collect ();
+        assert_eq! (data, vec! (b"one".to_vec (), b"two".to_vec (), b"three".to_vec (), b"four".to_vec ()));
+    }
+
     #[test]
     fn passes_on_inbound_client_data_not_meant_for_this_node () {
         let cryptde = cryptde();
@@ -428,13 +1329,16 @@ mod tests {
         let inbound_client_data = InboundClientData {
             socket_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
             origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: true,
+            stopped_reading: false,
             data: data_enc.data
         };
         thread::spawn(move || {
             let system = System::new("converts_live_message_to_expired_for_proxy_server");
             let peer_actors = make_peer_actors_from(None, Some (dispatcher), None, None, None);
-            let subject = Hopper::new (cryptde);
+            let subject = Hopper::new (cryptde, HopperConfig::new ());
             let subject_addr: Addr<Syn, Hopper> = subject.start();
             subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
 
@@ -470,11 +1374,14 @@ mod tests {
         let inbound_client_data = InboundClientData {
             socket_addr,
             origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
+            stopped_reading: false,
             data: encrypted_package,
         };
         let system = System::new("panics_if_proxy_server_is_unbound");
-        let subject = Hopper::new (cryptde);
+        let subject = Hopper::new (cryptde, HopperConfig::new ());
         let subject_addr: Addr<Syn, Hopper> = subject.start();
 
         subject_addr.try_send(inbound_client_data ).unwrap ();
@@ -498,11 +1405,14 @@ mod tests {
         let inbound_client_data = InboundClientData {
             socket_addr,
             origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
+            stopped_reading: false,
             data: encrypted_package,
         };
         let system = System::new("panics_if_proxy_client_is_unbound");
-        let subject = Hopper::new (cryptde);
+        let subject = Hopper::new (cryptde, HopperConfig::new ());
         let subject_addr: Addr<Syn, Hopper> = subject.start();
 
         subject_addr.try_send(inbound_client_data ).unwrap ();
@@ -524,7 +1434,7 @@ mod tests {
             PayloadMock::new (), &cryptde.public_key ()
         );
         let system = System::new("panics_if_dispatcher_is_unbound");
-        let subject = Hopper::new (cryptde);
+        let subject = Hopper::new (cryptde, HopperConfig::new ());
         let subject_addr: Addr<Syn, Hopper> = subject.start();
 
         subject_addr.try_send(incipient_package ).unwrap ();
@@ -535,7 +1445,7 @@ mod tests {
 
     #[test]
     fn live_cores_package_serialization_deserialization () {
-        let original = LiveCoresPackage {route: make_meaningless_route(), payload: CryptData::new (&[1, 2, 3, 4])};
+        let original = LiveCoresPackage {route: make_meaningless_route(), payload: CryptData::new (&[1, 2, 3, 4]), fragmented: true, hops_remaining: 7, expires_at: 1234567890123};
 
         let serialized = serde_cbor::ser::to_vec (&original).unwrap ();
 
@@ -543,4 +1453,21 @@ mod tests {
 
         assert_eq! (deserialized, original);
     }
+
+    #[test]
+    fn live_cores_package_from_a_node_without_hop_budgets_or_expiry_times_gets_a_full_budget_and_no_expiry () {
+        #[derive (Serialize)]
+        struct OlderLiveCoresPackage {
+            route: Route,
+            payload: CryptData
+        }
+        let older = OlderLiveCoresPackage {route: make_meaningless_route(), payload: CryptData::new (&[1, 2, 3, 4])};
+        let serialized = serde_cbor::ser::to_vec (&older).unwrap ();
+
+        let result = serde_cbor::de::from_slice::<LiveCoresPackage> (&serialized[..]).unwrap ();
+
+        assert_eq! (result.fragmented, false);
+        assert_eq! (result.hops_remaining, DEFAULT_HOP_BUDGET);
+        assert_eq! (result.is_expired (SystemTime::now () + Duration::from_secs (86400 * 365)), false);
+    }
 }
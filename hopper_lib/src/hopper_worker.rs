@@ -0,0 +1,157 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use actix::Actor;
+use actix::Context;
+use actix::Handler;
+use actix::Recipient;
+use actix::Syn;
+use package_processor::HopperOutcome;
+use package_processor::HopperWork;
+use package_processor::PackageProcessor;
+
+/// Work handed from the Hopper to one of its workers. The sequence number lets the Hopper put the
+/// outcomes back in the order the packages arrived.
+#[derive (Debug, Message)]
+pub struct WorkOrder {
+    pub sequence: u64,
+    pub work: HopperWork
+}
+
+#[derive (Debug, Message)]
+pub struct WorkResult {
+    pub sequence: u64,
+    pub outcome: HopperOutcome
+}
+
+pub struct HopperWorker {
+    processor: PackageProcessor,
+    to_hopper: Recipient<Syn, WorkResult>
+}
+
+impl Actor for HopperWorker {
+    type Context = Context<Self>;
+}
+
+impl Handler<WorkOrder> for HopperWorker {
+    type Result = ();
+
+    fn handle(&mut self, msg: WorkOrder, _ctx: &mut Self::Context) -> Self::Result {
+        // A package that makes the processor panic is dropped; the Hopper still needs an outcome for it
+        let (sequence, work, processor) = (msg.sequence, msg.work, &self.processor);
+        let outcome = panic::catch_unwind (AssertUnwindSafe (|| processor.process (work))).unwrap_or (HopperOutcome::Drop);
+        // The Hopper may be waiting on this worker's mailbox, so it mustn't have to wait on the Hopper's
+        self.to_hopper.do_send (WorkResult {sequence, outcome}).expect ("Hopper is dead");
+        ()
+    }
+}
+
+impl HopperWorker {
+    pub fn new (processor: PackageProcessor, to_hopper: Recipient<Syn, WorkResult>) -> HopperWorker {
+        HopperWorker {processor, to_hopper}
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use std::thread;
+    use actix::Addr;
+    use actix::System;
+    use serde_cbor;
+    use sub_lib::cryptde::CryptDE;
+    use sub_lib::cryptde::Key;
+    use sub_lib::cryptde::PlainData;
+    use sub_lib::dispatcher::Component;
+    use sub_lib::dispatcher::InboundClientData;
+    use sub_lib::route::Route;
+    use sub_lib::route::RouteSegment;
+    use hopper::LiveCoresPackage;
+    use test_utils::test_utils::Recorder;
+    use test_utils::test_utils::cryptde;
+    use test_utils::test_utils::route_to_proxy_client;
+
+    impl Handler<WorkResult> for Recorder {
+        type Result = ();
+
+        fn handle(&mut self, msg: WorkResult, _ctx: &mut Context<Self>) -> () {
+            self.record (msg)
+        }
+    }
+
+    #[test]
+    fn worker_processes_order_and_reports_outcome_with_same_sequence_number () {
+        let cryptde = cryptde ();
+        let hopper = Recorder::new ();
+        let hopper_recording_arc = hopper.get_recording ();
+        let hopper_awaiter = hopper.get_awaiter ();
+        // Outbound packages get a fresh expiration time, so only inbound work has a predictable outcome
+        let lcp = LiveCoresPackage::new (route_to_proxy_client (&cryptde.public_key (), cryptde),
+            cryptde.encode (&cryptde.public_key (), &PlainData::new (b"abcd")).unwrap ());
+        let lcp_ser = PlainData::new (&serde_cbor::ser::to_vec (&lcp).unwrap ()[..]);
+        let inbound = InboundClientData {
+            socket_addr: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
+            origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: cryptde.encode (&cryptde.public_key (), &lcp_ser).unwrap ().data
+        };
+        let expected_outcome = PackageProcessor::new (cryptde, None).process (HopperWork::Inbound (inbound.clone ()));
+        thread::spawn (move || {
+            let system = System::new ("worker_processes_order_and_reports_outcome_with_same_sequence_number");
+            let hopper_addr: Addr<Syn, Recorder> = hopper.start ();
+            let subject = HopperWorker::new (PackageProcessor::new (cryptde, None), hopper_addr.recipient::<WorkResult> ());
+            let subject_addr: Addr<Syn, HopperWorker> = subject.start ();
+
+            subject_addr.try_send (WorkOrder {sequence: 42, work: HopperWork::Inbound (inbound)}).unwrap ();
+
+            system.run ();
+        });
+        hopper_awaiter.await_message_count (1);
+        let hopper_recording = hopper_recording_arc.lock ().unwrap ();
+        let record = hopper_recording.get_record::<WorkResult> (0);
+        assert_eq! (record.sequence, 42);
+        assert_eq! (record.outcome, expected_outcome);
+    }
+
+    #[test]
+    fn worker_reports_a_dropped_outcome_for_a_package_that_makes_the_processor_panic () {
+        let cryptde = cryptde ();
+        let hopper = Recorder::new ();
+        let hopper_recording_arc = hopper.get_recording ();
+        let hopper_awaiter = hopper.get_awaiter ();
+        // This Node can't read the next hop of a route meant for someone else
+        let stranger_key = Key::new (b"stranger");
+        let route = Route::new (vec! (RouteSegment::new (vec! (&stranger_key, &stranger_key), Component::ProxyClient)), cryptde).unwrap ();
+        let lcp = LiveCoresPackage::new (route, cryptde.encode (&cryptde.public_key (), &PlainData::new (b"abcd")).unwrap ());
+        let lcp_ser = PlainData::new (&serde_cbor::ser::to_vec (&lcp).unwrap ()[..]);
+        let inbound = InboundClientData {
+            socket_addr: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
+            origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: cryptde.encode (&cryptde.public_key (), &lcp_ser).unwrap ().data
+        };
+        thread::spawn (move || {
+            let system = System::new ("worker_reports_a_dropped_outcome_for_a_package_that_makes_the_processor_panic");
+            let hopper_addr: Addr<Syn, Recorder> = hopper.start ();
+            let subject = HopperWorker::new (PackageProcessor::new (cryptde, None), hopper_addr.recipient::<WorkResult> ());
+            let subject_addr: Addr<Syn, HopperWorker> = subject.start ();
+
+            subject_addr.try_send (WorkOrder {sequence: 43, work: HopperWork::Inbound (inbound)}).unwrap ();
+
+            system.run ();
+        });
+        hopper_awaiter.await_message_count (1);
+        let hopper_recording = hopper_recording_arc.lock ().unwrap ();
+        let record = hopper_recording.get_record::<WorkResult> (0);
+        assert_eq! (record.sequence, 43);
+        assert_eq! (record.outcome, HopperOutcome::Drop);
+    }
+}
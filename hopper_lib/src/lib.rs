@@ -4,9 +4,16 @@ extern crate serde;
 extern crate serde_derive;
 extern crate serde_cbor;
 extern crate sub_lib;
+#[macro_use]
 extern crate actix;
+extern crate rand;
 
 #[cfg (test)]
 extern crate test_utils;
 
+pub mod cover_traffic;
+pub mod fragmentation;
 pub mod hopper;
+pub mod hopper_worker;
+pub mod mixer;
+pub mod package_processor;
@@ -0,0 +1,148 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::time::Duration;
+use std::time::Instant;
+use rand::thread_rng;
+use rand::Rng;
+use sub_lib::hopper::MixingConfig;
+
+pub struct Mixer<T> {
+    config: MixingConfig,
+    pool: Vec<(Instant, T)>
+}
+
+impl<T> Mixer<T> {
+    pub fn new (config: MixingConfig) -> Mixer<T> {
+        if config.min_delay > config.max_delay {
+            panic! ("Mixing delay bounds are reversed: minimum {:?} exceeds maximum {:?}", config.min_delay, config.max_delay)
+        }
+        Mixer {
+            config,
+            pool: vec! ()
+        }
+    }
+
+    /// Puts an item into the pool. Returns the delay after which the item will be due for release,
+    /// along with the entire pool in shuffled order if this item filled the batch.
+    pub fn add (&mut self, item: T, now: Instant) -> (Duration, Vec<T>) {
+        let delay = self.random_delay ();
+        self.pool.push ((now + delay, item));
+        if (self.config.batch_size > 0) && (self.pool.len () >= self.config.batch_size) {
+            let released = self.pool.drain (..).map (|(_, item)| item).collect ();
+            (delay, Mixer::shuffle (released))
+        }
+        else {
+            (delay, vec! ())
+        }
+    }
+
+    /// Removes and returns, in shuffled order, every item whose delay has expired.
+    pub fn release_due (&mut self, now: Instant) -> Vec<T> {
+        let mut due = vec! ();
+        let mut index = 0;
+        while index < self.pool.len () {
+            if self.pool[index].0 <= now {
+                due.push (self.pool.remove (index).1);
+            }
+            else {
+                index += 1;
+            }
+        }
+        Mixer::shuffle (due)
+    }
+
+    pub fn len (&self) -> usize {
+        self.pool.len ()
+    }
+
+    fn random_delay (&self) -> Duration {
+        let min_millis = Mixer::<T>::to_millis (&self.config.min_delay);
+        let max_millis = Mixer::<T>::to_millis (&self.config.max_delay);
+        if min_millis == max_millis {
+            return Duration::from_millis (min_millis)
+        }
+        Duration::from_millis (thread_rng ().gen_range (min_millis, max_millis + 1))
+    }
+
+    fn shuffle (mut items: Vec<T>) -> Vec<T> {
+        thread_rng ().shuffle (&mut items[..]);
+        items
+    }
+
+    fn to_millis (duration: &Duration) -> u64 {
+        (duration.as_secs () * 1000) + (duration.subsec_nanos () / 1_000_000) as u64
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+
+    fn make_config (batch_size: usize, min_delay: u64, max_delay: u64) -> MixingConfig {
+        MixingConfig {
+            batch_size,
+            min_delay: Duration::from_millis (min_delay),
+            max_delay: Duration::from_millis (max_delay)
+        }
+    }
+
+    #[test]
+    #[should_panic (expected = "Mixing delay bounds are reversed: minimum 200ms exceeds maximum 100ms")]
+    fn reversed_delay_bounds_are_rejected () {
+        let _: Mixer<u32> = Mixer::new (make_config (0, 200, 100));
+    }
+
+    #[test]
+    fn items_are_held_until_their_delay_expires () {
+        let mut subject = Mixer::new (make_config (0, 100, 100));
+        let now = Instant::now ();
+
+        let (delay, released) = subject.add (1, now);
+
+        assert_eq! (delay, Duration::from_millis (100));
+        assert_eq! (released, vec! ());
+        assert_eq! (subject.release_due (now + Duration::from_millis (99)), vec! ());
+        assert_eq! (subject.len (), 1);
+        assert_eq! (subject.release_due (now + Duration::from_millis (100)), vec! (1));
+        assert_eq! (subject.len (), 0);
+    }
+
+    #[test]
+    fn only_expired_items_are_released () {
+        let mut subject = Mixer::new (make_config (0, 100, 100));
+        let now = Instant::now ();
+        subject.add (1, now);
+        subject.add (2, now + Duration::from_millis (50));
+
+        let released = subject.release_due (now + Duration::from_millis (120));
+
+        assert_eq! (released, vec! (1));
+        assert_eq! (subject.len (), 1);
+    }
+
+    #[test]
+    fn random_delays_stay_within_bounds () {
+        let mut subject = Mixer::new (make_config (0, 10, 20));
+        let now = Instant::now ();
+
+        for item in 0..100 {
+            let (delay, _) = subject.add (item, now);
+            assert! ((delay >= Duration::from_millis (10)) && (delay <= Duration::from_millis (20)), "{:?}", delay);
+        }
+        assert_eq! (subject.release_due (now + Duration::from_millis (9)).len (), 0);
+        assert_eq! (subject.release_due (now + Duration::from_millis (20)).len (), 100);
+    }
+
+    #[test]
+    fn full_batch_releases_the_whole_pool () {
+        let mut subject = Mixer::new (make_config (3, 1000, 1000));
+        let now = Instant::now ();
+
+        assert_eq! (subject.add (1, now).1, vec! ());
+        assert_eq! (subject.add (2, now).1, vec! ());
+        let mut released = subject.add (3, now).1;
+
+        released.sort ();
+        assert_eq! (released, vec! (1, 2, 3));
+        assert_eq! (subject.len (), 0);
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::borrow::Borrow;
+use std::time::SystemTime;
+use serde_cbor;
+use fragmentation::fragment;
+use hopper::LiveCoresPackage;
+use sub_lib::cryptde::CryptData;
+use sub_lib::cryptde::CryptDE;
+use sub_lib::cryptde::CryptdecError;
+use sub_lib::cryptde::PlainData;
+use sub_lib::dispatcher::Component;
+use sub_lib::dispatcher::Endpoint;
+use sub_lib::dispatcher::InboundClientData;
+use sub_lib::hopper::ExpiredCoresPackage;
+use sub_lib::hopper::HopperTemporaryTransmitDataMsg;
+use sub_lib::hopper::IncipientCoresPackage;
+use sub_lib::logger::Logger;
+
+/// A package that needs the Hopper's cryptographic attention
+#[derive (Debug, PartialEq)]
+pub enum HopperWork {
+    Outbound (IncipientCoresPackage),
+    Inbound (InboundClientData)
+}
+
+/// What the Hopper should do once a package has been decrypted, deserialized, re-encrypted, or
+/// whatever else it needed
+#[derive (Debug, PartialEq)]
+pub enum HopperOutcome {
+    // TODO when we are decentralized, change this to a TransmitDataMsg
+    Transmit (Vec<HopperTemporaryTransmitDataMsg>),
+    Deliver {component: Component, package: ExpiredCoresPackage, fragmented: bool},
+    Drop
+}
+
+/// Does the CPU-heavy part of the Hopper's job. It keeps no state between packages, so any number
+/// of them can work side by side.
+pub struct PackageProcessor {
+    cryptde: &'static CryptDE,
+    max_fragment_size: Option<usize>,
+    logger: Logger
+}
+
+impl PackageProcessor {
+    pub fn new (cryptde: &'static CryptDE, max_fragment_size: Option<usize>) -> PackageProcessor {
+        PackageProcessor {
+            cryptde,
+            max_fragment_size,
+            logger: Logger::new ("Hopper")
+        }
+    }
+
+    pub fn process (&self, work: HopperWork) -> HopperOutcome {
+        match work {
+            HopperWork::Outbound (incipient) => HopperOutcome::Transmit (self.incipient_to_transmit_msgs (incipient)),
+            HopperWork::Inbound (inbound_client_data) => self.process_inbound (inbound_client_data)
+        }
+    }
+
+    // TODO when we are decentralized, change this to a TransmitDataMsg
+    pub fn incipient_to_transmit_msg (&self, incipient: IncipientCoresPackage, fragmented: bool) -> Option<HopperTemporaryTransmitDataMsg> {
+        let (mut live_package, key) = LiveCoresPackage::from_incipient(incipient, self.cryptde.borrow());
+        live_package.fragmented = fragmented;
+
+        let serialized_package = match serde_cbor::ser::to_vec (&live_package) {
+            Ok(package) => package,
+            Err(_) => {
+                self.logger.error(format! ("Couldn't serialize package"));
+                // TODO what should we do here? (nothing is unbound --so we don't need to blow up-- but we can't send this package)
+                return None
+            }
+        };
+
+        let encrypted_package = match self.cryptde.encode(&key, &PlainData::from (serialized_package)) {
+            Ok(package) => package,
+            Err (_) => {
+                self.logger.error(format! ("Couldn't encode package"));
+                // TODO what should we do here? (nothing is unbound --so we don't need to blow up-- but we can't send this package)
+                return None
+            }
+        };
+
+        Some (HopperTemporaryTransmitDataMsg {
+            endpoint: Endpoint::Key(key),
+            last_data: false, // Hopper-to-Hopper streams are never remotely killed
+            data: encrypted_package.data,
+        })
+    }
+
+    // TODO when we are decentralized, change this type to a TransmitDataMsg
+    pub fn to_transmit_msg (&self, live_package: LiveCoresPackage, last_data: bool) -> Result<HopperTemporaryTransmitDataMsg, CryptdecError> {
+        let (next_key, next_live_package) = match live_package.to_next_live (self.cryptde.borrow ()) {
+            // crashpoint - log error and return None?
+            Err (_) => unimplemented! (),
+            Ok (p) => p
+        };
+        let next_live_package_ser = match serde_cbor::ser::to_vec (&next_live_package) {
+            // crashpoint - log error and return None?
+            Err (_) => unimplemented! (),
+            Ok (p) => p
+        };
+        let next_live_package_enc = match self.cryptde.encode (&next_key, &PlainData::from (next_live_package_ser)) {
+            // crashpoint - log error and return None?
+            Err (_) => unimplemented! (),
+            Ok (p) => p
+        };
+        // TODO when we are decentralized, change this to a TransmitDataMsg
+        Ok (HopperTemporaryTransmitDataMsg {
+            endpoint: Endpoint::Key(next_key),
+            last_data,
+            data: next_live_package_enc.data
+        })
+    }
+
+    // TODO when we are decentralized, change this to a TransmitDataMsg
+    fn incipient_to_transmit_msgs (&self, incipient: IncipientCoresPackage) -> Vec<HopperTemporaryTransmitDataMsg> {
+        let max_fragment_size = match self.max_fragment_size {
+            Some (max) if incipient.payload.data.len () > max => max,
+            _ => return self.incipient_to_transmit_msg (incipient, false).into_iter ().collect ()
+        };
+        let fragments = fragment (&incipient.payload, max_fragment_size);
+        self.logger.debug (format! ("Splitting {}-byte payload into {} fragments", incipient.payload.data.len (), fragments.len ()));
+        fragments.into_iter ().filter_map (|fragment| {
+            let fragment_package = IncipientCoresPackage::new (incipient.route.clone (), fragment, &incipient.payload_destination_key);
+            self.incipient_to_transmit_msg (fragment_package, true)
+        }).collect ()
+    }
+
+    fn process_inbound (&self, msg: InboundClientData) -> HopperOutcome {
+        let decrypted_package = match self.cryptde.decode(&self.cryptde.private_key(), &CryptData::from (msg.data.clone ())) {
+            Ok(package) => package,
+            Err (e) => {
+                self.logger.error(format! ("{:?}", e));
+                // TODO what should we do here? (nothing is unbound --so we don't need to blow up-- but we can't send this package)
+                return HopperOutcome::Drop
+            }
+        };
+        let live_package = match serde_cbor::de::from_slice::<LiveCoresPackage>(&decrypted_package.data[..]) {
+            Ok(package) => package,
+            Err(_) => {
+                self.logger.error(format!("Couldn't deserialize package"));
+                // TODO what should we do here? (nothing is unbound --so we don't need to blow up-- but we can't send this package)
+                return HopperOutcome::Drop
+            }
+
+        };
+
+        if live_package.is_expired (SystemTime::now ()) {
+            self.logger.warning (format! ("Dropping package: it expired before it reached its destination"));
+            return HopperOutcome::Drop
+        }
+
+        let next_hop = live_package.next_hop(self.cryptde.borrow());
+
+        match next_hop.component {
+            Component::ProxyServer | Component::ProxyClient => {
+                let fragmented = live_package.fragmented;
+                HopperOutcome::Deliver {
+                    component: next_hop.component,
+                    package: live_package.to_expired (self.cryptde.borrow ()),
+                    fragmented
+                }
+            },
+            Component::Neighborhood => unimplemented!(),
+            Component::Hopper if next_hop.public_key.data.is_empty () => {
+                self.logger.trace (format! ("Dropping cover traffic"));
+                HopperOutcome::Drop
+            },
+            Component::Hopper if live_package.hops_remaining == 0 => {
+                self.logger.warning (format! ("Dropping package: its hop budget is exhausted"));
+                HopperOutcome::Drop
+            },
+            Component::Hopper => {
+                let transmit_msg = match self.to_transmit_msg (live_package, msg.last_data) {
+                    // crashpoint - need to figure out how to bubble up different kinds of errors, or just log and return
+                    Err (_) => unimplemented! (),
+                    Ok (m) => m
+                };
+                self.logger.debug (format! ("Relaying {}-byte LiveCoresPackage Dispatcher inside a TransmitDataMsg", transmit_msg.data.len ()));
+                HopperOutcome::Transmit (vec! (transmit_msg))
+            }
+        }
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use sub_lib::cryptde::Key;
+    use sub_lib::route::Route;
+    use sub_lib::route::RouteSegment;
+    use test_utils::test_utils::cryptde;
+    use test_utils::test_utils::route_to_proxy_client;
+
+    fn make_inbound_client_data (lcp: &LiveCoresPackage, cryptde: &CryptDE) -> InboundClientData {
+        let data_ser = PlainData::new (&serde_cbor::ser::to_vec (lcp).unwrap ()[..]);
+        InboundClientData {
+            socket_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: cryptde.encode (&cryptde.public_key (), &data_ser).unwrap ().data
+        }
+    }
+
+    #[test]
+    fn outbound_work_becomes_transmit_outcome () {
+        let cryptde = cryptde ();
+        let destination_key = Key::new (&[65, 65, 65]);
+        let route = Route::new (
+            vec! (RouteSegment::new (vec! (&cryptde.public_key (), &destination_key), Component::Neighborhood)),
+            cryptde
+        ).unwrap ();
+        let incipient = IncipientCoresPackage::new (route, PlainData::new (b"abcd"), &destination_key);
+        let subject = PackageProcessor::new (cryptde, None);
+
+        let result = subject.process (HopperWork::Outbound (incipient));
+
+        match result {
+            HopperOutcome::Transmit (transmit_msgs) => {
+                assert_eq! (transmit_msgs.len (), 1);
+                assert_eq! (transmit_msgs[0].endpoint, Endpoint::Key (destination_key));
+            },
+            other => panic! ("Expected Transmit, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn inbound_work_for_local_component_becomes_deliver_outcome () {
+        let cryptde = cryptde ();
+        let route = route_to_proxy_client (&cryptde.public_key (), cryptde);
+        let payload = PlainData::new (b"abcd");
+        let lcp = LiveCoresPackage::new (route.clone (), cryptde.encode (&cryptde.public_key (), &payload).unwrap ());
+        let subject = PackageProcessor::new (cryptde, None);
+
+        let result = subject.process (HopperWork::Inbound (make_inbound_client_data (&lcp, cryptde)));
+
+        assert_eq! (result, HopperOutcome::Deliver {
+            component: Component::ProxyClient,
+            package: ExpiredCoresPackage::new (route, payload),
+            fragmented: false
+        });
+    }
+
+    #[test]
+    fn undecryptable_inbound_work_becomes_drop_outcome () {
+        let cryptde = cryptde ();
+        let subject = PackageProcessor::new (cryptde, None);
+
+        let result = subject.process (HopperWork::Inbound (InboundClientData {
+            socket_addr: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+            origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: b"garbage".to_vec ().into ()
+        }));
+
+        assert_eq! (result, HopperOutcome::Drop);
+    }
+}
@@ -7,16 +7,20 @@ use actix::Syn;
 use sub_lib::dispatcher::Component;
 use sub_lib::node_addr::NodeAddr;
 use sub_lib::route::Route;
+use sub_lib::route::RouteSegment;
 use sub_lib::cryptde::Key;
 use sub_lib::neighborhood::NeighborhoodSubs;
 use sub_lib::peer_actors::BindMessage;
 use sub_lib::cryptde::CryptDE;
 use sub_lib::neighborhood::NodeQueryMessage;
 use sub_lib::neighborhood::NodeDescriptor;
+use sub_lib::neighborhood::RouteQueryMessage;
+use sub_lib::neighborhood::RouteQueryResponse;
 use actix::MessageResult;
+use std::iter;
 
 pub struct Neighborhood {
-    _cryptde: &'static CryptDE,
+    cryptde: &'static CryptDE,
     neighboring_nodes: Vec<NodeDescriptor>,
 }
 
@@ -46,10 +50,18 @@ impl Handler<NodeQueryMessage> for Neighborhood {
     }
 }
 
+impl Handler<RouteQueryMessage> for Neighborhood {
+    type Result = MessageResult<RouteQueryMessage>;
+
+    fn handle(&mut self, msg: RouteQueryMessage, _ctx: &mut Self::Context) -> <Self as Handler<RouteQueryMessage>>::Result {
+        MessageResult(self.route_round_trip(&msg.excluded_exits, msg.exit_key.as_ref (), msg.route_count))
+    }
+}
+
 impl Neighborhood {
     pub fn new(cryptde: &'static CryptDE, config: Vec<(Key, NodeAddr)>) -> Self {
         Neighborhood {
-            _cryptde: cryptde,
+            cryptde,
             neighboring_nodes: config.into_iter().map(|(key, node_addr)| {
                 NodeDescriptor::new (key, Some (node_addr))
             }).collect ()
@@ -59,6 +71,7 @@ impl Neighborhood {
     pub fn make_subs_from(addr: &Addr<Syn, Neighborhood>) -> NeighborhoodSubs {
         NeighborhoodSubs {
             bind: addr.clone ().recipient::<BindMessage>(),
+            route_query: addr.clone ().recipient::<RouteQueryMessage>(),
         }
     }
 
@@ -69,10 +82,33 @@ impl Neighborhood {
         unimplemented!()
     }
 
-    // crashpoint - unused so far
-    #[allow (dead_code)]
-    fn route_round_trip(&self, _remote_recipient: Component, _local_recipient: Component) -> Result<(Route, Key), ()> {
-        unimplemented!()
+    // Until we know more about the network, this Node is its own first choice of exit and its
+    // neighbors follow in the order they were configured, each one hop away. The first route goes
+    // straight to the exit; any others go by way of a different neighbor each.
+    fn route_round_trip(&self, excluded_exits: &Vec<Key>, exit_key: Option<&Key>, route_count: usize) -> Option<RouteQueryResponse> {
+        let own_key = self.cryptde.public_key ();
+        let exit_key = iter::once (&own_key)
+            .chain (self.neighboring_nodes.iter ().map (|node| &node.public_key))
+            .find (|key| match exit_key {
+                Some (exit_key) => *key == exit_key,
+                None => !excluded_exits.contains (key)
+            })?
+            .clone ();
+        let mut routes = vec! (Route::new (vec! (
+            RouteSegment::new (vec! (&own_key, &exit_key), Component::ProxyClient),
+            RouteSegment::new (vec! (&exit_key, &own_key), Component::ProxyServer)
+        ), self.cryptde).ok ()?);
+        let relays = self.neighboring_nodes.iter ()
+            .map (|node| &node.public_key)
+            .filter (|key| (**key != exit_key) && (**key != own_key))
+            .take (route_count.saturating_sub (1));
+        for relay_key in relays {
+            routes.push (Route::new (vec! (
+                RouteSegment::new (vec! (&own_key, relay_key, &exit_key), Component::ProxyClient),
+                RouteSegment::new (vec! (&exit_key, relay_key, &own_key), Component::ProxyServer)
+            ), self.cryptde).ok ()?);
+        }
+        Some (RouteQueryResponse {routes, exit_key})
     }
 
     fn matches (&self, node_ref_ref: &&NodeDescriptor, query: &NodeQueryMessage) -> bool {
@@ -97,6 +133,7 @@ mod tests {
     use actix::msgs;
     use futures::future::Future;
     use test_utils::test_utils::cryptde;
+    use test_utils::test_utils::route_from_proxy_server;
 
 
     #[test]
@@ -194,4 +231,117 @@ mod tests {
         let result = future.wait ().unwrap ();
         assert_eq! (result.unwrap (), NodeDescriptor::new (public_key, Some (node_addr)));
     }
+
+    #[test]
+    fn route_query_offers_this_node_as_exit_before_any_neighbor () {
+        let cryptde = cryptde ();
+        let system = System::new ("route_query_offers_this_node_as_exit_before_any_neighbor");
+        let subject = Neighborhood::new (cryptde, vec! (
+            (Key::new (&b"booga"[..]), NodeAddr::new (&IpAddr::from_str ("1.2.3.4").unwrap(), &vec! (1234))),
+        ));
+        let addr: Addr<Syn, Neighborhood> = subject.start ();
+        let sub: Recipient<Syn, RouteQueryMessage> = Neighborhood::make_subs_from (&addr).route_query;
+
+        let future = sub.send(RouteQueryMessage {excluded_exits: vec! (), exit_key: None, route_count: 1});
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run ();
+        let result = future.wait ().unwrap ().unwrap ();
+        assert_eq! (result.exit_key, cryptde.public_key ());
+        assert_eq! (result.routes, vec! (route_from_proxy_server (&cryptde.public_key (), cryptde)));
+    }
+
+    #[test]
+    fn route_query_skips_excluded_exits () {
+        let cryptde = cryptde ();
+        let system = System::new ("route_query_skips_excluded_exits");
+        let first_key = Key::new (&b"booga"[..]);
+        let second_key = Key::new (&b"gooba"[..]);
+        let subject = Neighborhood::new (cryptde, vec! (
+            (first_key.clone (), NodeAddr::new (&IpAddr::from_str ("1.2.3.4").unwrap(), &vec! (1234))),
+            (second_key.clone (), NodeAddr::new (&IpAddr::from_str ("2.3.4.5").unwrap(), &vec! (1234))),
+        ));
+        let addr: Addr<Syn, Neighborhood> = subject.start ();
+        let sub: Recipient<Syn, RouteQueryMessage> = addr.recipient::<RouteQueryMessage> ();
+
+        let future = sub.send(RouteQueryMessage {excluded_exits: vec! (cryptde.public_key (), first_key), exit_key: None, route_count: 1});
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run ();
+        let result = future.wait ().unwrap ().unwrap ();
+        let own_key = cryptde.public_key ();
+        assert_eq! (result.exit_key, second_key);
+        assert_eq! (result.routes, vec! (Route::new (vec! (
+            RouteSegment::new (vec! (&own_key, &second_key), Component::ProxyClient),
+            RouteSegment::new (vec! (&second_key, &own_key), Component::ProxyServer)
+        ), cryptde).unwrap ()));
+    }
+
+    #[test]
+    fn route_query_answers_none_when_every_exit_is_excluded () {
+        let cryptde = cryptde ();
+        let system = System::new ("route_query_answers_none_when_every_exit_is_excluded");
+        let subject = Neighborhood::new (cryptde, vec! ());
+        let addr: Addr<Syn, Neighborhood> = subject.start ();
+        let sub: Recipient<Syn, RouteQueryMessage> = addr.recipient::<RouteQueryMessage> ();
+
+        let future = sub.send(RouteQueryMessage {excluded_exits: vec! (cryptde.public_key ()), exit_key: None, route_count: 1});
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run ();
+        assert_eq! (future.wait ().unwrap (), None);
+    }
+
+    #[test]
+    fn route_query_offers_disjoint_routes_to_the_named_exit () {
+        let cryptde = cryptde ();
+        let system = System::new ("route_query_offers_disjoint_routes_to_the_named_exit");
+        let first_key = Key::new (&b"booga"[..]);
+        let second_key = Key::new (&b"gooba"[..]);
+        let third_key = Key::new (&b"oogab"[..]);
+        let subject = Neighborhood::new (cryptde, vec! (
+            (first_key.clone (), NodeAddr::new (&IpAddr::from_str ("1.2.3.4").unwrap(), &vec! (1234))),
+            (second_key.clone (), NodeAddr::new (&IpAddr::from_str ("2.3.4.5").unwrap(), &vec! (1234))),
+            (third_key.clone (), NodeAddr::new (&IpAddr::from_str ("3.4.5.6").unwrap(), &vec! (1234))),
+        ));
+        let addr: Addr<Syn, Neighborhood> = subject.start ();
+        let sub: Recipient<Syn, RouteQueryMessage> = addr.recipient::<RouteQueryMessage> ();
+
+        let future = sub.send(RouteQueryMessage {excluded_exits: vec! (), exit_key: Some (second_key.clone ()), route_count: 3});
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run ();
+        let result = future.wait ().unwrap ().unwrap ();
+        let own_key = cryptde.public_key ();
+        assert_eq! (result.exit_key, second_key);
+        assert_eq! (result.routes, vec! (
+            Route::new (vec! (
+                RouteSegment::new (vec! (&own_key, &second_key), Component::ProxyClient),
+                RouteSegment::new (vec! (&second_key, &own_key), Component::ProxyServer)
+            ), cryptde).unwrap (),
+            Route::new (vec! (
+                RouteSegment::new (vec! (&own_key, &first_key, &second_key), Component::ProxyClient),
+                RouteSegment::new (vec! (&second_key, &first_key, &own_key), Component::ProxyServer)
+            ), cryptde).unwrap (),
+            Route::new (vec! (
+                RouteSegment::new (vec! (&own_key, &third_key, &second_key), Component::ProxyClient),
+                RouteSegment::new (vec! (&second_key, &third_key, &own_key), Component::ProxyServer)
+            ), cryptde).unwrap (),
+        ));
+    }
+
+    #[test]
+    fn route_query_answers_none_when_the_named_exit_is_unknown () {
+        let cryptde = cryptde ();
+        let system = System::new ("route_query_answers_none_when_the_named_exit_is_unknown");
+        let subject = Neighborhood::new (cryptde, vec! ());
+        let addr: Addr<Syn, Neighborhood> = subject.start ();
+        let sub: Recipient<Syn, RouteQueryMessage> = addr.recipient::<RouteQueryMessage> ();
+
+        let future = sub.send(RouteQueryMessage {excluded_exits: vec! (), exit_key: Some (Key::new (&b"booga"[..])), route_count: 2});
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run ();
+        assert_eq! (future.wait ().unwrap (), None);
+    }
 }
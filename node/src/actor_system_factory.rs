@@ -20,13 +20,18 @@ use sub_lib::cryptde::CryptDE;
 use sub_lib::cryptde::Key;
 use sub_lib::cryptde_null::CryptDENull;
 use sub_lib::dispatcher::DispatcherSubs;
+use sub_lib::blocklist::Blocklist;
+use sub_lib::hostname_map::HostnameMap;
+use sub_lib::hopper::HopperConfig;
 use sub_lib::hopper::HopperSubs;
 use sub_lib::neighborhood::NeighborhoodSubs;
 use sub_lib::node_addr::NodeAddr;
 use sub_lib::peer_actors::BindMessage;
 use sub_lib::peer_actors::PeerActors;
 use sub_lib::proxy_client::ProxyClientSubs;
+use sub_lib::proxy_server::ProxyServerConfig;
 use sub_lib::proxy_server::ProxyServerSubs;
+use sub_lib::stream_timeouts::StreamTimeouts;
 use bootstrapper;
 
 pub trait ActorSystemFactory: Send {
@@ -48,11 +53,11 @@ impl ActorSystemFactory for ActorSystemFactoryReal {
 
             // make all the actors
             let (dispatcher_subs, pool_bind_sub) = ActorSystemFactoryReal::make_and_start_dispatcher();
-            let proxy_server_subs = ActorSystemFactoryReal::make_and_start_proxy_server(cryptde);
-            let proxy_client_subs = ActorSystemFactoryReal::make_and_start_proxy_client(cryptde, config.dns_servers);
-            let hopper_subs = ActorSystemFactoryReal::make_and_start_hopper(cryptde);
+            let proxy_server_subs = ActorSystemFactoryReal::make_and_start_proxy_server(cryptde, config.proxy_server_config, config.blocklist);
+            let proxy_client_subs = ActorSystemFactoryReal::make_and_start_proxy_client(cryptde, config.dns_servers, config.stream_timeouts);
+            let hopper_subs = ActorSystemFactoryReal::make_and_start_hopper(cryptde, config.hopper_config);
             let neighborhood_subs = ActorSystemFactoryReal::make_and_start_neighborhood(cryptde, config.neighbor_configs);
-            let stream_handler_pool_subs = ActorSystemFactoryReal::make_and_start_stream_handler_pool();
+            let stream_handler_pool_subs = ActorSystemFactoryReal::make_and_start_stream_handler_pool(config.hostname_map, config.stream_timeouts);
 
             // collect all the subs
             let peer_actors = PeerActors {
@@ -90,14 +95,14 @@ impl ActorSystemFactoryReal {
         (Dispatcher::make_subs_from(&addr), addr.recipient::<PoolBindMessage> ())
     }
 
-    fn make_and_start_proxy_server(cryptde: &'static CryptDE) -> ProxyServerSubs {
-        let proxy_server = ProxyServer::new(cryptde);
+    fn make_and_start_proxy_server(cryptde: &'static CryptDE, config: ProxyServerConfig, blocklist: Blocklist) -> ProxyServerSubs {
+        let proxy_server = ProxyServer::new(cryptde, config, blocklist);
         let addr: Addr<Syn, ProxyServer> = proxy_server.start();
         ProxyServer::make_subs_from(&addr)
     }
 
-    fn make_and_start_hopper(cryptde: &'static CryptDE) -> HopperSubs {
-        let hopper = Hopper::new(cryptde);
+    fn make_and_start_hopper(cryptde: &'static CryptDE, config: HopperConfig) -> HopperSubs {
+        let hopper = Hopper::new(cryptde, config);
         let addr: Addr<Syn, Hopper> = hopper.start();
         Hopper::make_subs_from(&addr)
     }
@@ -108,14 +113,14 @@ impl ActorSystemFactoryReal {
         Neighborhood::make_subs_from (&addr)
     }
 
-    fn make_and_start_stream_handler_pool() -> StreamHandlerPoolSubs {
-        let pool = StreamHandlerPool::new();
+    fn make_and_start_stream_handler_pool(hostname_map: HostnameMap, stream_timeouts: StreamTimeouts) -> StreamHandlerPoolSubs {
+        let pool = StreamHandlerPool::new(hostname_map, stream_timeouts);
         let addr: Addr<Syn, StreamHandlerPool> = pool.start();
         StreamHandlerPool::make_subs_from(&addr)
     }
 
-    fn make_and_start_proxy_client(cryptde: &'static CryptDE, dns_servers: Vec<SocketAddr>) -> ProxyClientSubs {
-        let proxy_client = ProxyClient::new(cryptde, dns_servers);
+    fn make_and_start_proxy_client(cryptde: &'static CryptDE, dns_servers: Vec<SocketAddr>, stream_timeouts: StreamTimeouts) -> ProxyClientSubs {
+        let proxy_client = ProxyClient::new(cryptde, dns_servers, stream_timeouts);
         let addr: Addr<Syn, ProxyClient> = proxy_client.start();
         ProxyClient::make_subs_from(&addr)
     }
@@ -1,8 +1,10 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::thread;
+use std::time::Duration;
 use actor_system_factory::ActorSystemFactory;
 use actor_system_factory::ActorSystemFactoryReal;
 use base64;
@@ -11,20 +13,43 @@ use listener_handler::ListenerHandler;
 use listener_handler::ListenerHandlerFactory;
 use listener_handler::ListenerHandlerFactoryReal;
 use stream_handler_pool::StreamHandlerPoolSubs;
+use sub_lib::blocklist::Blocklist;
 use sub_lib::cryptde::Key;
+use sub_lib::hostname_map::HostnameMap;
+use sub_lib::hopper::CoverTrafficConfig;
+use sub_lib::hopper::FragmentationConfig;
+use sub_lib::hopper::HopperConfig;
+use sub_lib::hopper::MixingConfig;
 use sub_lib::main_tools::StdStreams;
 use sub_lib::node_addr::NodeAddr;
 use sub_lib::parameter_finder::ParameterFinder;
+use sub_lib::proxy_server::HeaderRule;
+use sub_lib::proxy_server::PROTECTED_HEADERS;
+use sub_lib::proxy_server::ProxyServerConfig;
+use sub_lib::proxy_server::DEFAULT_ROUTE_RETRIES;
+use sub_lib::proxy_server::DEFAULT_CIRCUIT_IDLE_TIMEOUT_MS;
+use sub_lib::proxy_server::DEFAULT_STREAM_PATHS;
+use sub_lib::multipath::MAX_STREAM_PATHS;
+use sub_lib::stream_timeouts::DEFAULT_STREAM_IDLE_TIMEOUT_MS;
+use sub_lib::stream_timeouts::DEFAULT_STREAM_LIFETIME_MS;
+use sub_lib::stream_timeouts::StreamTimeouts;
 use sub_lib::socket_server::SocketServer;
 use sub_lib::cryptde::CryptDE;
 use sub_lib::cryptde_null::CryptDENull;
 
+const DEFAULT_BLOCKLIST_RELOAD_SECS: u64 = 3600;
+
 pub static mut CRYPT_DE_OPT: Option<CryptDENull> = None;
 
 #[derive (Clone)]
 pub struct BootstrapperConfig {
     pub dns_servers: Vec<SocketAddr>,
-    pub neighbor_configs: Vec<(Key, NodeAddr)>
+    pub neighbor_configs: Vec<(Key, NodeAddr)>,
+    pub hopper_config: HopperConfig,
+    pub proxy_server_config: ProxyServerConfig,
+    pub stream_timeouts: StreamTimeouts,
+    pub hostname_map: HostnameMap,
+    pub blocklist: Blocklist
 }
 
 // TODO: Consider splitting this into a piece that's meant for being root and a piece that's not.
@@ -35,6 +60,8 @@ pub struct Bootstrapper {
     #[allow (dead_code)]
     stream_handler_pool_subs: Option<StreamHandlerPoolSubs>,
     config: Option<BootstrapperConfig>,
+    hostname_map: HostnameMap,
+    blocklist: Blocklist,
 }
 
 impl SocketServer for Bootstrapper {
@@ -55,7 +82,7 @@ impl SocketServer for Bootstrapper {
             }
             listener_handler
         }).collect ();
-        self.config = Some(Bootstrapper::parse_args (args));
+        self.config = Some(Bootstrapper::parse_args (args, self.hostname_map.clone (), self.blocklist.clone ()));
         Bootstrapper::initialize_and_report_cryptde (streams);
     }
 
@@ -74,7 +101,8 @@ impl SocketServer for Bootstrapper {
 }
 
 impl Bootstrapper {
-    pub fn new () -> Bootstrapper {
+    // The HostnameMap must be the one the Entry DNS server fills in, and the Blocklist the one it consults
+    pub fn new (hostname_map: HostnameMap, blocklist: Blocklist) -> Bootstrapper {
         Bootstrapper {
             listener_handler_factory: Box::new (ListenerHandlerFactoryReal::new ()),
             listener_handlers: vec! (),
@@ -82,6 +110,8 @@ impl Bootstrapper {
             actor_system_factory: Box::new (ActorSystemFactoryReal {}),
             stream_handler_pool_subs: None,
             config: None,
+            hostname_map,
+            blocklist,
         }
     }
 
@@ -91,14 +121,41 @@ impl Bootstrapper {
         });
     }
 
-    fn parse_args (args: &Vec<String>) -> BootstrapperConfig {
+    fn parse_args (args: &Vec<String>, hostname_map: HostnameMap, blocklist: Blocklist) -> BootstrapperConfig {
         let finder = ParameterFinder::new(args.clone ());
+        Bootstrapper::load_blocklist (&finder, &blocklist);
+        let neighbor_configs = Bootstrapper::parse_neighbor_configs (&finder);
+        let mut hopper_config = Bootstrapper::parse_hopper_config (&finder);
+        hopper_config.cover_relays = neighbor_configs.iter ().map (|&(ref key, _)| key.clone ()).collect ();
         BootstrapperConfig {
             dns_servers: Bootstrapper::parse_dns_servers (&finder),
-            neighbor_configs: Bootstrapper::parse_neighbor_configs (&finder),
+            neighbor_configs,
+            hopper_config,
+            proxy_server_config: Bootstrapper::parse_proxy_server_config (&finder),
+            stream_timeouts: Bootstrapper::parse_stream_timeouts (&finder),
+            hostname_map,
+            blocklist,
         }
     }
 
+    // We read the files now, while we can still see everything root can; later reloads happen
+    // without root, so files only root can read will keep their first contents.
+    fn load_blocklist (finder: &ParameterFinder, blocklist: &Blocklist) {
+        let usage = "--blocklist <file>,<file>,...";
+        let files: Vec<PathBuf> = match finder.find_value_for ("--blocklist", usage) {
+            Some (files_string) => files_string.split (",").map (PathBuf::from).collect (),
+            None => return
+        };
+        let reload_usage = "--blocklist_reload_secs <seconds> where 0 means never";
+        let reload_secs = match finder.find_value_for ("--blocklist_reload_secs", reload_usage) {
+            Some (secs_string) => secs_string.parse::<u64> ()
+                .unwrap_or_else (|_| panic! ("Invalid number for {}: '{}'", reload_usage, secs_string)),
+            None => DEFAULT_BLOCKLIST_RELOAD_SECS
+        };
+        let reload_interval = if reload_secs == 0 {None} else {Some (Duration::from_secs (reload_secs))};
+        if let Err (msg) = blocklist.load (files, reload_interval) {panic! ("{}", msg)}
+    }
+
     fn parse_dns_servers (finder: &ParameterFinder) -> Vec<SocketAddr> {
         let parameter_tag = "--dns_servers";
         let usage = "--dns_servers <servers> where 'servers' is a comma-separated list of IP addresses";
@@ -135,6 +192,186 @@ impl Bootstrapper {
         (public_key, NodeAddr::new (&ip_addr, &ports))
     }
 
+    fn parse_proxy_server_config (finder: &ParameterFinder) -> ProxyServerConfig {
+        let usage = "--socks_credentials <username>:<password>";
+        ProxyServerConfig {
+            port_protocols: Configuration::parse_port_protocols (finder),
+            http_proxy_port: Configuration::parse_http_proxy_port (finder),
+            socks_port: Configuration::parse_socks_port (finder),
+            socks_credentials: finder.find_value_for ("--socks_credentials", usage).map (|s| {
+                match s.find (':') {
+                    Some (index) => (String::from (&s[..index]), String::from (&s[index + 1..])),
+                    None => panic! (usage)
+                }
+            }),
+            tcp_forward_ports: Configuration::parse_tcp_forward_ports (finder),
+            header_rules: Bootstrapper::parse_header_rules (finder),
+            route_retries: Bootstrapper::parse_route_retries (finder),
+            circuit_idle_timeout: Bootstrapper::parse_circuit_idle_timeout (finder),
+            stream_paths: Bootstrapper::parse_stream_paths (finder)
+        }
+    }
+
+    fn parse_route_retries (finder: &ParameterFinder) -> usize {
+        let usage = "--route_retries <other exits to try when a new stream fails>";
+        match finder.find_value_for ("--route_retries", usage) {
+            None => DEFAULT_ROUTE_RETRIES,
+            Some (s) => s.parse::<usize> ().unwrap_or_else (|_| panic! ("Invalid number for {}: '{}'", usage, s))
+        }
+    }
+
+    fn parse_circuit_idle_timeout (finder: &ParameterFinder) -> Duration {
+        let usage = "--circuit_idle_timeout <ms>";
+        match finder.find_value_for ("--circuit_idle_timeout", usage) {
+            None => Duration::from_millis (DEFAULT_CIRCUIT_IDLE_TIMEOUT_MS),
+            Some (s) => Duration::from_millis (s.parse::<u64> ().unwrap_or_else (|_| panic! ("Invalid number for {}: '{}'", usage, s)))
+        }
+    }
+
+    fn parse_stream_timeouts (finder: &ParameterFinder) -> StreamTimeouts {
+        let idle_usage = "--stream_idle_timeout <ms> where 0 means never";
+        let lifetime_usage = "--stream_lifetime <ms> where 0 means forever";
+        let parse = |tag: &str, usage: &str, default: u64| match finder.find_value_for (tag, usage) {
+            None => default,
+            Some (s) => s.parse::<u64> ().unwrap_or_else (|_| panic! ("Invalid number for {}: '{}'", usage, s))
+        };
+        StreamTimeouts {
+            idle: StreamTimeouts::limit (parse ("--stream_idle_timeout", idle_usage, DEFAULT_STREAM_IDLE_TIMEOUT_MS)),
+            lifetime: StreamTimeouts::limit (parse ("--stream_lifetime", lifetime_usage, DEFAULT_STREAM_LIFETIME_MS))
+        }
+    }
+
+    fn parse_stream_paths (finder: &ParameterFinder) -> usize {
+        let usage = "--stream_paths <routes to spread each stream across, 1-16>";
+        match finder.find_value_for ("--stream_paths", usage) {
+            None => DEFAULT_STREAM_PATHS,
+            Some (s) => match s.parse::<usize> () {
+                Ok (count) if (count > 0) && (count <= MAX_STREAM_PATHS) => count,
+                _ => panic! ("Invalid number for {}: '{}'", usage, s)
+            }
+        }
+    }
+
+    // Removals come first, then rewrites, then the Referer policy
+    fn parse_header_rules (finder: &ParameterFinder) -> Vec<HeaderRule> {
+        let remove_usage = "--remove_headers <header>,<header>,...";
+        let rewrite_usage = "--rewrite_header <header>:<value>";
+        let referer_usage = "--cross_origin_referer <keep|strip>";
+        let mut rules: Vec<HeaderRule> = match finder.find_value_for ("--remove_headers", remove_usage) {
+            Some (names) => names.split (",").map (|name| HeaderRule::Remove {
+                name: Bootstrapper::validate_header_name (name.trim (), remove_usage)
+            }).collect (),
+            None => vec! ()
+        };
+        rules.extend (finder.find_values_for ("--rewrite_header", rewrite_usage).into_iter ().map (|s| {
+            let index = s.find (':').unwrap_or_else (|| panic! ("Invalid rewrite for {}: '{}'", rewrite_usage, s));
+            let value = s[index + 1..].trim ();
+            if value.contains ('\r') || value.contains ('\n') {panic! ("Invalid value for {}: '{}'", rewrite_usage, value)}
+            HeaderRule::Rewrite {
+                name: Bootstrapper::validate_header_name (s[..index].trim (), rewrite_usage),
+                value: String::from (value)
+            }
+        }));
+        match finder.find_value_for ("--cross_origin_referer", referer_usage) {
+            None => (),
+            Some (ref policy) if policy == "keep" => (),
+            Some (ref policy) if policy == "strip" => rules.push (HeaderRule::StripCrossOriginReferer),
+            Some (policy) => panic! ("Unknown policy for {}: '{}'", referer_usage, policy)
+        }
+        rules
+    }
+
+    fn validate_header_name (name: &str, usage: &str) -> String {
+        if name.is_empty () || name.contains (|c: char| c.is_whitespace () || (c == ':')) {
+            panic! ("Invalid header name for {}: '{}'", usage, name)
+        }
+        if PROTECTED_HEADERS.iter ().any (|protected| protected.eq_ignore_ascii_case (name)) {
+            panic! ("Header {} frames the request and can't be changed by {}", name, usage)
+        }
+        String::from (name)
+    }
+
+    fn parse_hopper_config (finder: &ParameterFinder) -> HopperConfig {
+        HopperConfig {
+            interactive_mixing: Bootstrapper::parse_mixing_config (finder, "--mix_interactive"),
+            bulk_mixing: Bootstrapper::parse_mixing_config (finder, "--mix_bulk"),
+            cover_traffic: Bootstrapper::parse_cover_traffic_config (finder),
+            // Filled in from the neighbor configurations
+            cover_relays: vec! (),
+            bandwidth_limit: Bootstrapper::parse_bandwidth_limit (finder),
+            fragmentation: Bootstrapper::parse_fragmentation_config (finder),
+            worker_count: Bootstrapper::parse_hopper_worker_count (finder),
+        }
+    }
+
+    fn parse_mixing_config (finder: &ParameterFinder, parameter_tag: &str) -> Option<MixingConfig> {
+        let usage = format! ("{} <batch size>,<minimum delay ms>,<maximum delay ms>", parameter_tag);
+        let string = finder.find_value_for (parameter_tag, &usage)?;
+        let pieces: Vec<&str> = string.split (",").collect ();
+        if pieces.len () != 3 {panic! ("{}", usage)}
+        let numbers: Vec<u64> = pieces.iter ().map (|s| s.parse::<u64> ()
+            .expect (format! ("Invalid number for {}: '{}'", usage, s).as_str ())).collect ();
+        if numbers[1] > numbers[2] {panic! ("Minimum delay must not exceed maximum delay for {}", usage)}
+        Some (MixingConfig {
+            batch_size: numbers[0] as usize,
+            min_delay: Duration::from_millis (numbers[1]),
+            max_delay: Duration::from_millis (numbers[2])
+        })
+    }
+
+    fn parse_cover_traffic_config (finder: &ParameterFinder) -> Option<CoverTrafficConfig> {
+        let usage = "--cover_traffic <average interval ms>,<payload bytes>";
+        let string = finder.find_value_for ("--cover_traffic", usage)?;
+        let pieces: Vec<&str> = string.split (",").collect ();
+        if pieces.len () != 2 {panic! (usage)}
+        let interval = pieces[0].parse::<u64> ()
+            .expect (format! ("Invalid interval for {}: '{}'", usage, pieces[0]).as_str ());
+        if interval == 0 {panic! ("Cover traffic interval must be positive for {}", usage)}
+        let payload_size = pieces[1].parse::<usize> ()
+            .expect (format! ("Invalid payload size for {}: '{}'", usage, pieces[1]).as_str ());
+        Some (CoverTrafficConfig {
+            interval: Duration::from_millis (interval),
+            payload_size
+        })
+    }
+
+    fn parse_bandwidth_limit (finder: &ParameterFinder) -> Option<usize> {
+        let usage = "--bandwidth_limit <outbound bytes per second>";
+        finder.find_value_for ("--bandwidth_limit", usage).map (|s| s.parse::<usize> ()
+            .expect (format! ("Invalid number for {}: '{}'", usage, s).as_str ()))
+    }
+
+    fn parse_fragmentation_config (finder: &ParameterFinder) -> FragmentationConfig {
+        let mut config = FragmentationConfig::new ();
+        let size_usage = "--max_fragment_size <payload bytes>";
+        config.max_fragment_size = finder.find_value_for ("--max_fragment_size", size_usage).map (|s| {
+            let size = s.parse::<usize> ()
+                .expect (format! ("Invalid number for {}: '{}'", size_usage, s).as_str ());
+            if size == 0 {panic! ("Maximum fragment size must be positive for {}", size_usage)}
+            size
+        });
+        let limits_usage = "--reassembly_limits <timeout ms>,<buffered bytes>";
+        if let Some (string) = finder.find_value_for ("--reassembly_limits", limits_usage) {
+            let pieces: Vec<&str> = string.split (",").collect ();
+            if pieces.len () != 2 {panic! (limits_usage)}
+            let timeout = pieces[0].parse::<u64> ()
+                .expect (format! ("Invalid timeout for {}: '{}'", limits_usage, pieces[0]).as_str ());
+            config.reassembly_timeout = Duration::from_millis (timeout);
+            config.max_reassembly_bytes = pieces[1].parse::<usize> ()
+                .expect (format! ("Invalid byte count for {}: '{}'", limits_usage, pieces[1]).as_str ());
+        }
+        config
+    }
+
+    fn parse_hopper_worker_count (finder: &ParameterFinder) -> usize {
+        let usage = "--hopper_workers <number of worker threads>";
+        match finder.find_value_for ("--hopper_workers", usage) {
+            None => 0,
+            Some (s) => s.parse::<usize> ()
+                .expect (format! ("Invalid number for {}: '{}'", usage, s).as_str ())
+        }
+    }
+
     fn initialize_and_report_cryptde (streams: &mut StdStreams) {
         let mut exemplar = CryptDENull::new ();
         exemplar.generate_key_pair();
@@ -151,12 +388,18 @@ impl Bootstrapper {
 mod tests {
     use super::*;
     use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::fs::File;
     use std::io;
+    use std::io::Write;
     use std::io::Error;
     use std::io::ErrorKind;
     use std::marker::Sync;
     use std::net::SocketAddr;
     use std::ops::DerefMut;
+    use std::path::Path;
     use std::str::FromStr;
     use std::sync::Arc;
     use std::sync::mpsc;
@@ -170,6 +413,8 @@ mod tests {
     use node_test_utils::TcpStreamWrapperMock;
     use node_test_utils::TestLogOwner;
     use stream_handler_pool::AddStreamMsg;
+    use sub_lib::blocklist::BlockSource;
+    use sub_lib::proxy_server::ProxyProtocol;
     use test_utils::test_utils::FakeStreamHolder;
     use test_utils::test_utils::RecordAwaiter;
     use test_utils::test_utils::Recorder;
@@ -352,13 +597,309 @@ mod tests {
             "--irrelevant", "irrelevant"
         ).into_iter ().map (String::from).collect ();
 
-        let config = Bootstrapper::parse_args (&args);
+        let config = Bootstrapper::parse_args (&args, HostnameMap::new (), Blocklist::new ());
 
         assert_eq! (config.dns_servers, vec! (SocketAddr::from_str ("12.34.56.78:53").unwrap (), SocketAddr::from_str ("23.45.67.89:53").unwrap ()));
         assert_eq! (config.neighbor_configs, vec! (
             (Key::new (b"Bill"), NodeAddr::new (&IpAddr::from_str ("1.2.3.4").unwrap (), &vec! (1234, 2345))),
             (Key::new (b"Ted"), NodeAddr::new (&IpAddr::from_str ("2.3.4.5").unwrap (), &vec! (3456, 4567))),
         ));
+        let mut expected_hopper_config = HopperConfig::new ();
+        expected_hopper_config.cover_relays = vec! (Key::new (b"Bill"), Key::new (b"Ted"));
+        assert_eq! (config.hopper_config, expected_hopper_config);
+        assert_eq! (config.proxy_server_config, ProxyServerConfig::new ());
+        assert_eq! (config.stream_timeouts, StreamTimeouts::new ());
+    }
+
+    #[test]
+    fn parse_args_reads_stream_timeouts () {
+        let args: Vec<String> = vec! (
+            "--dns_servers", "12.34.56.78",
+            "--stream_idle_timeout", "0",
+            "--stream_lifetime", "3600000",
+        ).into_iter ().map (String::from).collect ();
+
+        let config = Bootstrapper::parse_args (&args, HostnameMap::new (), Blocklist::new ());
+
+        assert_eq! (config.stream_timeouts, StreamTimeouts {idle: None, lifetime: Some (Duration::from_millis (3600000))});
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid number for --stream_lifetime <ms> where 0 means forever: '1h'")]
+    fn parse_args_rejects_bad_stream_lifetime () {
+        let args: Vec<String> = vec! (
+            "--dns_servers", "12.34.56.78",
+            "--stream_lifetime", "1h",
+        ).into_iter ().map (String::from).collect ();
+
+        Bootstrapper::parse_args (&args, HostnameMap::new (), Blocklist::new ());
+    }
+
+    #[test]
+    fn parse_args_reads_proxy_server_config () {
+        let args: Vec<String> = vec! (
+            "--dns_servers", "12.34.56.78",
+            "--http_proxy_port", "8080",
+            "--socks_port", "1080",
+            "--socks_credentials", "booga:wooga:tooga",
+            "--tcp_forward_ports", "22,993",
+            "--port_protocols", "8443:TLS",
+            "--remove_headers", "X-Forwarded-For, Via",
+            "--rewrite_header", "User-Agent: Mozilla/5.0 (X11; Linux x86_64)",
+            "--rewrite_header", "Accept-Language:en",
+            "--cross_origin_referer", "strip",
+            "--route_retries", "5",
+            "--circuit_idle_timeout", "60000",
+            "--stream_paths", "3",
+        ).into_iter ().map (String::from).collect ();
+
+        let config = Bootstrapper::parse_args (&args, HostnameMap::new (), Blocklist::new ());
+
+        let mut port_protocols = HashMap::new ();
+        port_protocols.insert (80, ProxyProtocol::HTTP);
+        port_protocols.insert (443, ProxyProtocol::TLS);
+        port_protocols.insert (8443, ProxyProtocol::TLS);
+        assert_eq! (config.proxy_server_config, ProxyServerConfig {
+            port_protocols,
+            http_proxy_port: Some (8080),
+            socks_port: Some (1080),
+            socks_credentials: Some ((String::from ("booga"), String::from ("wooga:tooga"))),
+            tcp_forward_ports: vec! (22, 993),
+            header_rules: vec! (
+                HeaderRule::Remove {name: String::from ("X-Forwarded-For")},
+                HeaderRule::Remove {name: String::from ("Via")},
+                HeaderRule::Rewrite {name: String::from ("User-Agent"), value: String::from ("Mozilla/5.0 (X11; Linux x86_64)")},
+                HeaderRule::Rewrite {name: String::from ("Accept-Language"), value: String::from ("en")},
+                HeaderRule::StripCrossOriginReferer
+            ),
+            route_retries: 5,
+            circuit_idle_timeout: Duration::from_millis (60000),
+            stream_paths: 3
+        });
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid number for --route_retries <other exits to try when a new stream fails>: 'many'")]
+    fn parse_args_rejects_bad_route_retries () {
+        let args: Vec<String> = vec! (
+            "--dns_servers", "12.34.56.78",
+            "--route_retries", "many",
+        ).into_iter ().map (String::from).collect ();
+
+        Bootstrapper::parse_args (&args, HostnameMap::new (), Blocklist::new ());
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid number for --circuit_idle_timeout <ms>: '5m'")]
+    fn parse_args_rejects_bad_circuit_idle_timeout () {
+        let args: Vec<String> = vec! (
+            "--dns_servers", "12.34.56.78",
+            "--circuit_idle_timeout", "5m",
+        ).into_iter ().map (String::from).collect ();
+
+        Bootstrapper::parse_args (&args, HostnameMap::new (), Blocklist::new ());
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid number for --stream_paths <routes to spread each stream across, 1-16>: '0'")]
+    fn parse_args_rejects_zero_stream_paths () {
+        let args: Vec<String> = vec! (
+            "--dns_servers", "12.34.56.78",
+            "--stream_paths", "0",
+        ).into_iter ().map (String::from).collect ();
+
+        Bootstrapper::parse_args (&args, HostnameMap::new (), Blocklist::new ());
+    }
+
+    #[test]
+    fn parse_header_rules_keeps_referer_when_asked () {
+        let finder = ParameterFinder::new (vec! ("--cross_origin_referer", "keep").into_iter ().map (String::from).collect ());
+
+        let result = Bootstrapper::parse_header_rules (&finder);
+
+        assert_eq! (result, vec! ());
+    }
+
+    #[test]
+    #[should_panic (expected = "Header content-length frames the request and can't be changed by --remove_headers <header>,<header>,...")]
+    fn parse_header_rules_refuses_to_remove_framing_headers () {
+        let finder = ParameterFinder::new (vec! ("--remove_headers", "Via,content-length").into_iter ().map (String::from).collect ());
+
+        Bootstrapper::parse_header_rules (&finder);
+    }
+
+    #[test]
+    #[should_panic (expected = "Header Host frames the request and can't be changed by --rewrite_header <header>:<value>")]
+    fn parse_header_rules_refuses_to_rewrite_framing_headers () {
+        let finder = ParameterFinder::new (vec! ("--rewrite_header", "Host:elsewhere.com").into_iter ().map (String::from).collect ());
+
+        Bootstrapper::parse_header_rules (&finder);
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid rewrite for --rewrite_header <header>:<value>: 'User-Agent'")]
+    fn parse_header_rules_rejects_rewrite_without_value () {
+        let finder = ParameterFinder::new (vec! ("--rewrite_header", "User-Agent").into_iter ().map (String::from).collect ());
+
+        Bootstrapper::parse_header_rules (&finder);
+    }
+
+    #[test]
+    #[should_panic (expected = "Unknown policy for --cross_origin_referer <keep|strip>: 'sometimes'")]
+    fn parse_header_rules_rejects_unknown_referer_policy () {
+        let finder = ParameterFinder::new (vec! ("--cross_origin_referer", "sometimes").into_iter ().map (String::from).collect ());
+
+        Bootstrapper::parse_header_rules (&finder);
+    }
+
+    #[test]
+    #[should_panic (expected = "--socks_credentials <username>:<password>")]
+    fn parse_args_rejects_socks_credentials_without_password () {
+        let args: Vec<String> = vec! (
+            "--dns_servers", "12.34.56.78",
+            "--socks_credentials", "booga",
+        ).into_iter ().map (String::from).collect ();
+
+        Bootstrapper::parse_args (&args, HostnameMap::new (), Blocklist::new ());
+    }
+
+    fn make_blocklist_file (test_name: &str, contents: &str) -> String {
+        let dir = env::current_dir ().unwrap ().join (Path::new ("generated")).join (Path::new ("Bootstrapper"));
+        fs::create_dir_all (dir.clone ()).unwrap ();
+        let path = dir.join (Path::new (test_name));
+        write! (File::create (path.clone ()).unwrap (), "{}", contents).unwrap ();
+        String::from (path.to_str ().unwrap ())
+    }
+
+    #[test]
+    fn parse_args_loads_blocklist_files_into_shared_blocklist () {
+        let first = make_blocklist_file ("parse_args_loads_blocklist_files_into_shared_blocklist_1", "0.0.0.0 ads.com\n");
+        let second = make_blocklist_file ("parse_args_loads_blocklist_files_into_shared_blocklist_2", "tracker.com\n");
+        let args: Vec<String> = vec! (
+            String::from ("--dns_servers"), String::from ("12.34.56.78"),
+            String::from ("--blocklist"), format! ("{},{}", first, second),
+            String::from ("--blocklist_reload_secs"), String::from ("0"),
+        );
+        let blocklist = Blocklist::new ();
+
+        let config = Bootstrapper::parse_args (&args, HostnameMap::new (), blocklist.clone ());
+
+        assert_eq! (blocklist.stats ().domain_count, 2);
+        assert_eq! (config.blocklist.blocks ("www.tracker.com", BlockSource::ProxyServer), true);
+        assert_eq! (blocklist.stats ().proxy_server_blocks, 1);
+    }
+
+    #[test]
+    #[should_panic (expected = "Can't read blocklist file \"/nonexistent/blocklist\"")]
+    fn parse_args_complains_about_unreadable_blocklist_file () {
+        let args: Vec<String> = vec! (
+            "--dns_servers", "12.34.56.78",
+            "--blocklist", "/nonexistent/blocklist",
+        ).into_iter ().map (String::from).collect ();
+
+        Bootstrapper::parse_args (&args, HostnameMap::new (), Blocklist::new ());
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid number for --blocklist_reload_secs <seconds> where 0 means never: 'often'")]
+    fn parse_args_complains_about_bad_blocklist_reload_interval () {
+        let file = make_blocklist_file ("parse_args_complains_about_bad_blocklist_reload_interval", "tracker.com\n");
+        let args: Vec<String> = vec! (
+            String::from ("--dns_servers"), String::from ("12.34.56.78"),
+            String::from ("--blocklist"), file,
+            String::from ("--blocklist_reload_secs"), String::from ("often"),
+        );
+
+        Bootstrapper::parse_args (&args, HostnameMap::new (), Blocklist::new ());
+    }
+
+    #[test]
+    fn parse_hopper_config_reads_mixing_configurations () {
+        let finder = ParameterFinder::new (vec! (
+            "--mix_interactive", "0,10,50",
+            "--mix_bulk", "16,100,2000",
+            "--cover_traffic", "500,1024",
+            "--bandwidth_limit", "100000",
+            "--max_fragment_size", "8192",
+            "--reassembly_limits", "5000,1000000",
+            "--hopper_workers", "4",
+        ).into_iter ().map (String::from).collect ());
+
+        let config = Bootstrapper::parse_hopper_config (&finder);
+
+        assert_eq! (config, HopperConfig {
+            interactive_mixing: Some (MixingConfig {batch_size: 0, min_delay: Duration::from_millis (10), max_delay: Duration::from_millis (50)}),
+            bulk_mixing: Some (MixingConfig {batch_size: 16, min_delay: Duration::from_millis (100), max_delay: Duration::from_millis (2000)}),
+            cover_traffic: Some (CoverTrafficConfig {interval: Duration::from_millis (500), payload_size: 1024}),
+            cover_relays: vec! (),
+            bandwidth_limit: Some (100000),
+            fragmentation: FragmentationConfig {
+                max_fragment_size: Some (8192),
+                reassembly_timeout: Duration::from_millis (5000),
+                max_reassembly_bytes: 1000000
+            },
+            worker_count: 4,
+        });
+    }
+
+    #[test]
+    #[should_panic (expected = "Maximum fragment size must be positive for --max_fragment_size <payload bytes>")]
+    fn parse_hopper_config_complains_about_zero_fragment_size () {
+        let finder = ParameterFinder::new (vec! (
+            "--max_fragment_size", "0",
+        ).into_iter ().map (String::from).collect ());
+
+        Bootstrapper::parse_hopper_config (&finder);
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid byte count for --reassembly_limits <timeout ms>,<buffered bytes>: 'plenty'")]
+    fn parse_hopper_config_complains_about_bad_reassembly_limits () {
+        let finder = ParameterFinder::new (vec! (
+            "--reassembly_limits", "5000,plenty",
+        ).into_iter ().map (String::from).collect ());
+
+        Bootstrapper::parse_hopper_config (&finder);
+    }
+
+    #[test]
+    #[should_panic (expected = "Cover traffic interval must be positive for --cover_traffic <average interval ms>,<payload bytes>")]
+    fn parse_hopper_config_complains_about_zero_cover_traffic_interval () {
+        let finder = ParameterFinder::new (vec! (
+            "--cover_traffic", "0,1024",
+        ).into_iter ().map (String::from).collect ());
+
+        Bootstrapper::parse_hopper_config (&finder);
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid number for --bandwidth_limit <outbound bytes per second>: 'lots'")]
+    fn parse_hopper_config_complains_about_bad_bandwidth_limit () {
+        let finder = ParameterFinder::new (vec! (
+            "--bandwidth_limit", "lots",
+        ).into_iter ().map (String::from).collect ());
+
+        Bootstrapper::parse_hopper_config (&finder);
+    }
+
+    #[test]
+    #[should_panic (expected = "--mix_bulk <batch size>,<minimum delay ms>,<maximum delay ms>")]
+    fn parse_hopper_config_requires_three_pieces_to_a_mixing_configuration () {
+        let finder = ParameterFinder::new (vec! (
+            "--mix_bulk", "16,100",
+        ).into_iter ().map (String::from).collect ());
+
+        Bootstrapper::parse_hopper_config (&finder);
+    }
+
+    #[test]
+    #[should_panic (expected = "Minimum delay must not exceed maximum delay for --mix_interactive <batch size>,<minimum delay ms>,<maximum delay ms>")]
+    fn parse_hopper_config_complains_about_reversed_delay_bounds () {
+        let finder = ParameterFinder::new (vec! (
+            "--mix_interactive", "0,50,10",
+        ).into_iter ().map (String::from).collect ());
+
+        Bootstrapper::parse_hopper_config (&finder);
     }
 
     #[test]
@@ -600,6 +1141,8 @@ mod tests {
                 listener_handler_factory: Box::new (self.listener_handler_factory),
                 listener_handlers: vec! (),
                 config: None,
+                hostname_map: HostnameMap::new (),
+                blocklist: Blocklist::new (),
             }
         }
     }
@@ -1,7 +1,13 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 use std::collections::HashMap;
 use discriminator::DiscriminatorFactory;
+use http_proxy_discriminator_factory::HttpProxyDiscriminatorFactory;
 use http_request_start_finder::HttpRequestDiscriminatorFactory;
+use passthrough_discriminator_factory::PassthroughDiscriminatorFactory;
+use socks_discriminator_factory::SocksDiscriminatorFactory;
+use sub_lib::parameter_finder::ParameterFinder;
+use sub_lib::proxy_server::ProxyProtocol;
+use sub_lib::proxy_server::default_port_protocols;
 use tls_discriminator_factory::TlsDiscriminatorFactory;
 
 pub struct Configuration {
@@ -15,11 +21,99 @@ impl Configuration {
         }
     }
 
-    pub fn establish (&mut self, _args: &Vec<String>) {
-        self.port_discriminator_factories.insert (80,
-            vec! (Box::new (HttpRequestDiscriminatorFactory::new ())));
-        self.port_discriminator_factories.insert (443,
-            vec! (Box::new (TlsDiscriminatorFactory::new ())));
+    pub fn establish (&mut self, args: &Vec<String>) {
+        let finder = ParameterFinder::new (args.clone ());
+        Configuration::parse_port_protocols (&finder).into_iter ().for_each (|(port, protocol)| {
+            self.port_discriminator_factories.insert (port,
+                Configuration::discriminator_factories_for (protocol));
+        });
+        let http_proxy_port = Configuration::parse_http_proxy_port (&finder);
+        let socks_port = Configuration::parse_socks_port (&finder);
+        if http_proxy_port.is_some () && (http_proxy_port == socks_port) {
+            panic! ("--http_proxy_port and --socks_port must be different")
+        }
+        if let Some (port) = http_proxy_port {
+            self.port_discriminator_factories.insert (port,
+                vec! (Box::new (HttpProxyDiscriminatorFactory::new ())));
+        }
+        if let Some (port) = socks_port {
+            self.port_discriminator_factories.insert (port,
+                vec! (Box::new (SocksDiscriminatorFactory::new ())));
+        }
+        Configuration::parse_tcp_forward_ports (&finder).into_iter ().for_each (|port| {
+            self.port_discriminator_factories.insert (port,
+                vec! (Box::new (PassthroughDiscriminatorFactory::new ())));
+        });
+    }
+
+    // The ProxyServer picks its ProtocolPacks from the same list, so listeners and payloads always agree
+    pub fn parse_port_protocols (finder: &ParameterFinder) -> HashMap<u16, ProxyProtocol> {
+        let usage = "--port_protocols <port>:<protocol>,<port>:<protocol>,... where 'protocol' is HTTP or TLS";
+        let mut port_protocols = default_port_protocols ();
+        let bindings_string = match finder.find_value_for ("--port_protocols", usage) {
+            Some (bindings_string) => bindings_string,
+            None => return port_protocols
+        };
+        bindings_string.split (",").for_each (|binding| {
+            let pieces: Vec<&str> = binding.split (":").collect ();
+            if pieces.len () != 2 {panic! ("Invalid binding for {}: '{}'", usage, binding)}
+            let port = pieces[0].parse::<u16> ()
+                .unwrap_or_else (|_| panic! ("Invalid port number for {}: '{}'", usage, pieces[0]));
+            if port == 0 {panic! ("Port 0 is not available for {}", usage)}
+            let protocol = match pieces[1].to_uppercase ().as_str () {
+                "HTTP" => ProxyProtocol::HTTP,
+                "TLS" => ProxyProtocol::TLS,
+                _ => panic! ("Unknown protocol for {}: '{}'", usage, pieces[1])
+            };
+            port_protocols.insert (port, protocol);
+        });
+        port_protocols
+    }
+
+    pub fn parse_http_proxy_port (finder: &ParameterFinder) -> Option<u16> {
+        Configuration::parse_listener_port (finder, "--http_proxy_port")
+    }
+
+    pub fn parse_socks_port (finder: &ParameterFinder) -> Option<u16> {
+        Configuration::parse_listener_port (finder, "--socks_port")
+    }
+
+    pub fn parse_tcp_forward_ports (finder: &ParameterFinder) -> Vec<u16> {
+        let usage = "--tcp_forward_ports <port>,<port>,...";
+        let ports_string = match finder.find_value_for ("--tcp_forward_ports", usage) {
+            Some (ports_string) => ports_string,
+            None => return vec! ()
+        };
+        let mut unavailable_ports: Vec<u16> = vec! (Some (0),
+            Configuration::parse_http_proxy_port (finder), Configuration::parse_socks_port (finder))
+            .into_iter ().flat_map (|port_opt| port_opt).collect ();
+        unavailable_ports.extend (Configuration::parse_port_protocols (finder).keys ());
+        ports_string.split (",").map (|port_string| {
+            let port = port_string.parse::<u16> ()
+                .unwrap_or_else (|_| panic! ("Invalid port number for {}: '{}'", usage, port_string));
+            if unavailable_ports.contains (&port) {panic! ("Port {} is not available for {}", port, usage)}
+            port
+        }).collect ()
+    }
+
+    fn parse_listener_port (finder: &ParameterFinder, parameter_tag: &str) -> Option<u16> {
+        let usage = format! ("{} <port>", parameter_tag);
+        let port = finder.find_value_for (parameter_tag, &usage)?.parse::<u16> ()
+            .unwrap_or_else (|_| panic! ("Invalid port number for {}", usage));
+        if (port == 0) || Configuration::parse_port_protocols (finder).contains_key (&port) {
+            panic! ("Port {} is not available for {}", port, usage)
+        }
+        Some (port)
+    }
+
+    // Clients don't always send what the port suggests (TLS to port 80, for instance), so each port can
+    // sniff out either protocol; the one it was declared for comes first and wins when in doubt.
+    fn discriminator_factories_for (protocol: ProxyProtocol) -> Vec<Box<DiscriminatorFactory>> {
+        match protocol {
+            ProxyProtocol::HTTP => vec! (Box::new (HttpRequestDiscriminatorFactory::new ()), Box::new (TlsDiscriminatorFactory::new ())),
+            ProxyProtocol::TLS => vec! (Box::new (TlsDiscriminatorFactory::new ()), Box::new (HttpRequestDiscriminatorFactory::new ())),
+            ProxyProtocol::Tunnel => vec! (Box::new (PassthroughDiscriminatorFactory::new ()))
+        }
     }
 
     pub fn ports (&self) -> Vec<u16> {
@@ -48,7 +142,8 @@ mod tests {
         subject.establish (&args);
 
         let mut port_80_factories = subject.port_discriminator_factories.remove (&80).unwrap ();
-        assert_eq! (port_80_factories.len (), 1);
+        assert_eq! (port_80_factories.len (), 2);
+        assert_eq! (port_80_factories[1].proxy_protocol (), Some (ProxyProtocol::TLS));
         let http_factory = port_80_factories.remove (0);
         let mut http_discriminator = http_factory.make ();
         http_discriminator.add_data ("GET http://url.com HTTP/1.1\r\n\r\n".as_bytes ());
@@ -64,7 +159,8 @@ mod tests {
         subject.establish (&args);
 
         let mut port_443_factories = subject.port_discriminator_factories.remove (&443).unwrap ();
-        assert_eq! (port_443_factories.len (), 1);
+        assert_eq! (port_443_factories.len (), 2);
+        assert_eq! (port_443_factories[1].proxy_protocol (), Some (ProxyProtocol::HTTP));
         let tls_factory = port_443_factories.remove (0);
         let mut tls_discriminator = tls_factory.make ();
         tls_discriminator.add_data (&vec! (0x16, 0x03, 0x01, 0x00, 0x03, 0x01, 0x02, 0x03)[..]);
@@ -72,6 +168,160 @@ mod tests {
         assert_eq! (tls_chunk, UnmaskedChunk::new (vec! (0x16, 0x03, 0x01, 0x00, 0x03, 0x01, 0x02, 0x03), true));
     }
 
+    #[test]
+    fn no_parameters_produces_no_http_proxy_port () {
+        let args = vec! (String::from ("command"));
+        let mut subject = Configuration::new ();
+
+        subject.establish (&args);
+
+        let mut ports = subject.ports ();
+        ports.sort ();
+        assert_eq! (ports, vec! (80, 443));
+    }
+
+    #[test]
+    fn port_protocols_parameter_adds_listeners_and_can_override_defaults () {
+        let args = vec! ("command", "--port_protocols", "8080:HTTP,8443:tls,80:TLS")
+            .into_iter ().map (String::from).collect ();
+        let mut subject = Configuration::new ();
+
+        subject.establish (&args);
+
+        let mut ports = subject.ports ();
+        ports.sort ();
+        assert_eq! (ports, vec! (80, 443, 8080, 8443));
+        let tls_data = vec! (0x16, 0x03, 0x01, 0x00, 0x03, 0x01, 0x02, 0x03);
+        let mut port_8443_discriminator = subject.port_discriminator_factories.remove (&8443).unwrap ().remove (0).make ();
+        port_8443_discriminator.add_data (&tls_data[..]);
+        assert_eq! (port_8443_discriminator.take_chunk (), Some (UnmaskedChunk::new (tls_data.clone (), true)));
+        let mut port_80_discriminator = subject.port_discriminator_factories.remove (&80).unwrap ().remove (0).make ();
+        port_80_discriminator.add_data (&tls_data[..]);
+        assert_eq! (port_80_discriminator.take_chunk (), Some (UnmaskedChunk::new (tls_data.clone (), true)));
+        let mut port_8080_discriminator = subject.port_discriminator_factories.remove (&8080).unwrap ().remove (0).make ();
+        port_8080_discriminator.add_data (b"GET http://url.com HTTP/1.1\r\n\r\n");
+        assert_eq! (port_8080_discriminator.take_chunk (), Some (UnmaskedChunk::new (b"GET http://url.com HTTP/1.1\r\n\r\n".to_vec (), true)));
+    }
+
+    #[test]
+    #[should_panic (expected = "Unknown protocol for --port_protocols <port>:<protocol>,<port>:<protocol>,... where 'protocol' is HTTP or TLS: 'SMTP'")]
+    fn unknown_port_protocol_is_rejected () {
+        let args = vec! ("command", "--port_protocols", "8080:HTTP,25:SMTP")
+            .into_iter ().map (String::from).collect ();
+
+        Configuration::new ().establish (&args);
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid binding for --port_protocols <port>:<protocol>,<port>:<protocol>,... where 'protocol' is HTTP or TLS: '8080'")]
+    fn port_protocol_binding_without_protocol_is_rejected () {
+        let args = vec! ("command", "--port_protocols", "8080")
+            .into_iter ().map (String::from).collect ();
+
+        Configuration::new ().establish (&args);
+    }
+
+    #[test]
+    #[should_panic (expected = "Port 8080 is not available for --http_proxy_port <port>")]
+    fn http_proxy_port_may_not_collide_with_port_protocols () {
+        let args = vec! ("command", "--port_protocols", "8080:HTTP", "--http_proxy_port", "8080")
+            .into_iter ().map (String::from).collect ();
+
+        Configuration::new ().establish (&args);
+    }
+
+    #[test]
+    fn http_proxy_port_parameter_produces_configuration_for_proxy_port () {
+        let args = vec! (String::from ("command"), String::from ("--http_proxy_port"), String::from ("8080"));
+        let mut subject = Configuration::new ();
+
+        subject.establish (&args);
+
+        let mut port_8080_factories = subject.port_discriminator_factories.remove (&8080).unwrap ();
+        assert_eq! (port_8080_factories.len (), 1);
+        let mut proxy_discriminator = port_8080_factories.remove (0).make ();
+        proxy_discriminator.add_data ("CONNECT url.com:443 HTTP/1.1\r\n\r\n".as_bytes ());
+        let connect_chunk = proxy_discriminator.take_chunk ().unwrap ();
+        assert_eq! (connect_chunk, UnmaskedChunk::new (Vec::from ("CONNECT url.com:443 HTTP/1.1\r\n\r\n".as_bytes ()), true));
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid port number for --http_proxy_port <port>")]
+    fn bad_http_proxy_port_is_rejected () {
+        let args = vec! (String::from ("command"), String::from ("--http_proxy_port"), String::from ("booga"));
+
+        Configuration::new ().establish (&args);
+    }
+
+    #[test]
+    #[should_panic (expected = "Port 443 is not available for --http_proxy_port <port>")]
+    fn http_proxy_port_may_not_collide_with_standard_ports () {
+        let args = vec! (String::from ("command"), String::from ("--http_proxy_port"), String::from ("443"));
+
+        Configuration::new ().establish (&args);
+    }
+
+    #[test]
+    fn socks_port_parameter_produces_configuration_for_socks_port () {
+        let args = vec! (String::from ("command"), String::from ("--socks_port"), String::from ("1080"));
+        let mut subject = Configuration::new ();
+
+        subject.establish (&args);
+
+        let mut port_1080_factories = subject.port_discriminator_factories.remove (&1080).unwrap ();
+        assert_eq! (port_1080_factories.len (), 1);
+        let mut socks_discriminator = port_1080_factories.remove (0).make ();
+        socks_discriminator.add_data (&[0x05, 0x01, 0x00]);
+        let greeting_chunk = socks_discriminator.take_chunk ().unwrap ();
+        assert_eq! (greeting_chunk, UnmaskedChunk::new (vec! (0x05, 0x01, 0x00), true));
+    }
+
+    #[test]
+    #[should_panic (expected = "--http_proxy_port and --socks_port must be different")]
+    fn http_proxy_port_and_socks_port_may_not_collide () {
+        let args = vec! ("command", "--http_proxy_port", "8080", "--socks_port", "8080")
+            .into_iter ().map (String::from).collect ();
+
+        Configuration::new ().establish (&args);
+    }
+
+    #[test]
+    fn tcp_forward_ports_parameter_produces_passthrough_configuration_for_each_port () {
+        let args = vec! ("command", "--tcp_forward_ports", "22,993")
+            .into_iter ().map (String::from).collect ();
+        let mut subject = Configuration::new ();
+
+        subject.establish (&args);
+
+        let mut ports = subject.ports ();
+        ports.sort ();
+        assert_eq! (ports, vec! (22, 80, 443, 993));
+        let mut port_22_factories = subject.port_discriminator_factories.remove (&22).unwrap ();
+        assert_eq! (port_22_factories.len (), 1);
+        let mut ssh_discriminator = port_22_factories.remove (0).make ();
+        ssh_discriminator.add_data (b"SSH-2.0-OpenSSH_7.6\r\n");
+        let ssh_chunk = ssh_discriminator.take_chunk ().unwrap ();
+        assert_eq! (ssh_chunk, UnmaskedChunk::new (Vec::from (&b"SSH-2.0-OpenSSH_7.6\r\n"[..]), true));
+    }
+
+    #[test]
+    #[should_panic (expected = "Invalid port number for --tcp_forward_ports <port>,<port>,...: 'booga'")]
+    fn bad_tcp_forward_port_is_rejected () {
+        let args = vec! ("command", "--tcp_forward_ports", "22,booga")
+            .into_iter ().map (String::from).collect ();
+
+        Configuration::new ().establish (&args);
+    }
+
+    #[test]
+    #[should_panic (expected = "Port 1080 is not available for --tcp_forward_ports <port>,<port>,...")]
+    fn tcp_forward_ports_may_not_collide_with_proxy_ports () {
+        let args = vec! ("command", "--socks_port", "1080", "--tcp_forward_ports", "22,1080")
+            .into_iter ().map (String::from).collect ();
+
+        Configuration::new ().establish (&args);
+    }
+
     #[test]
     fn ports_returns_list_of_ports () {
         let mut subject = Configuration::new ();
@@ -1,6 +1,7 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 use sub_lib::logger::Logger;
 use sub_lib::framer::Framer;
+use sub_lib::proxy_server::ProxyProtocol;
 use masquerader::Masquerader;
 
 #[derive (Debug, PartialEq, Clone)]
@@ -21,6 +22,18 @@ impl UnmaskedChunk {
 pub trait DiscriminatorFactory: Send {
     fn make (&self) -> Discriminator;
     fn duplicate (&self) -> Box<DiscriminatorFactory>;
+
+    // When a port has several factories, the first one that recognizes the start of a new stream gets it.
+    // The data may be only a few bytes long, so anything that could be the start of a match should count.
+    fn recognizes (&self, _data: &[u8]) -> bool {
+        true
+    }
+
+    // The protocol of the traffic this factory's Discriminators frame, for factories whose protocol the
+    // ProxyServer can't tell from the port
+    fn proxy_protocol (&self) -> Option<ProxyProtocol> {
+        None
+    }
 }
 
 pub struct Discriminator {
@@ -11,15 +11,20 @@ use sub_lib::dispatcher::InboundClientData;
 use sub_lib::dispatcher::DispatcherSubs;
 use sub_lib::hopper::HopperTemporaryTransmitDataMsg;
 use sub_lib::logger::Logger;
+use sub_lib::outbox::Outbox;
 use sub_lib::peer_actors::BindMessage;
+use sub_lib::stream_handler_pool::PauseReadingMsg;
 use sub_lib::stream_handler_pool::TransmitDataMsg;
+use sub_lib::stream_handler_pool::WriteBacklogMsg;
 use sub_lib::utils::NODE_MAILBOX_CAPACITY;
 use stream_handler_pool::PoolBindMessage;
 
 pub struct Dispatcher {
-    to_proxy_server: Option<Recipient<Syn, InboundClientData>>,
-    to_hopper: Option<Recipient<Syn, InboundClientData>>,
+    to_proxy_server: Option<Outbox<InboundClientData>>,
+    to_hopper: Option<Outbox<InboundClientData>>,
     to_stream: Option<Recipient<Syn, TransmitDataMsg>>,
+    pause_reading: Option<Recipient<Syn, PauseReadingMsg>>,
+    write_backlog: Option<Recipient<Syn, WriteBacklogMsg>>,
     logger: Logger,
 }
 
@@ -32,8 +37,9 @@ impl Handler<BindMessage> for Dispatcher {
 
     fn handle(&mut self, msg: BindMessage, ctx: &mut Self::Context) {
         ctx.set_mailbox_capacity(NODE_MAILBOX_CAPACITY);
-        self.to_proxy_server = Some(msg.peer_actors.proxy_server.from_dispatcher);
-        self.to_hopper = Some(msg.peer_actors.hopper.from_dispatcher);
+        self.to_proxy_server = Some(Outbox::new (msg.peer_actors.proxy_server.from_dispatcher, "ProxyServer"));
+        self.to_hopper = Some(Outbox::new (msg.peer_actors.hopper.from_dispatcher, "Hopper"));
+        self.write_backlog = Some(msg.peer_actors.proxy_server.write_backlog);
     }
 }
 
@@ -42,14 +48,16 @@ impl Handler<PoolBindMessage> for Dispatcher {
 
     fn handle(&mut self, msg: PoolBindMessage, _ctx: &mut Self::Context) {
         self.to_stream = Some(msg.stream_handler_pool_subs.transmit_sub);
+        self.pause_reading = Some(msg.stream_handler_pool_subs.pause_reading_sub);
     }
 }
 
 impl Handler<InboundClientData> for Dispatcher {
     type Result = ();
 
-    fn handle(&mut self, msg: InboundClientData, _ctx: &mut Self::Context) {
-        self.to_proxy_server.as_ref().expect("ProxyServer unbound in Dispatcher").try_send(msg).expect("ProxyServer is dead");
+    fn handle(&mut self, msg: InboundClientData, ctx: &mut Self::Context) {
+        self.to_proxy_server.as_mut().expect("ProxyServer unbound in Dispatcher")
+            .send_from (msg, ctx, |dispatcher: &mut Dispatcher| dispatcher.to_proxy_server.as_mut ().expect ("ProxyServer unbound in Dispatcher"));
     }
 }
 
@@ -57,15 +65,19 @@ impl Handler<InboundClientData> for Dispatcher {
 impl Handler<HopperTemporaryTransmitDataMsg> for Dispatcher {
     type Result = ();
 
-    fn handle(&mut self, msg: HopperTemporaryTransmitDataMsg, _ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: HopperTemporaryTransmitDataMsg, ctx: &mut Self::Context) {
         self.logger.debug (format! ("Echoing {} bytes from Hopper to Hopper", msg.data.len ()));
         let ibcd = InboundClientData {
             last_data: msg.last_data,
+            stopped_reading: false,
             data: msg.data,
             socket_addr: SocketAddr::from_str("1.2.3.4:5678").expect("Couldn't create SocketAddr from 1.2.3.4:5678"),
             origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
         };
-        self.to_hopper.as_ref().expect("Hopper unbound in Dispatcher").try_send(ibcd).expect("Hopper is dead");
+        self.to_hopper.as_mut().expect("Hopper unbound in Dispatcher")
+            .send_from (ibcd, ctx, |dispatcher: &mut Dispatcher| dispatcher.to_hopper.as_mut ().expect ("Hopper unbound in Dispatcher"));
     }
 }
 
@@ -74,7 +86,24 @@ impl Handler<TransmitDataMsg> for Dispatcher {
 
     fn handle(&mut self, msg: TransmitDataMsg, _ctx: &mut Self::Context) {
         self.logger.debug (format! ("Relaying {} bytes from ProxyServer to StreamHandlerPool", msg.data.len ()));
-        self.to_stream.as_ref().expect("StreamHandlerPool unbound in Dispatcher").try_send(msg).expect("StreamHandlerPool is dead");
+        // Data on its way out to a stream never waits; the WriteQueue takes it
+        self.to_stream.as_ref().expect("StreamHandlerPool unbound in Dispatcher").do_send(msg).expect("StreamHandlerPool is dead");
+    }
+}
+
+impl Handler<PauseReadingMsg> for Dispatcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: PauseReadingMsg, _ctx: &mut Self::Context) {
+        self.pause_reading.as_ref().expect("StreamHandlerPool unbound in Dispatcher").do_send(msg).expect("StreamHandlerPool is dead");
+    }
+}
+
+impl Handler<WriteBacklogMsg> for Dispatcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: WriteBacklogMsg, _ctx: &mut Self::Context) {
+        self.write_backlog.as_ref().expect("ProxyServer unbound in Dispatcher").do_send(msg).expect("ProxyServer is dead");
     }
 }
 
@@ -83,6 +112,8 @@ impl Dispatcher {
         Dispatcher {
             to_proxy_server: None,
             to_stream: None,
+            pause_reading: None,
+            write_backlog: None,
             to_hopper: None,
             logger: Logger::new ("Dispatcher"),
         }
@@ -93,6 +124,8 @@ impl Dispatcher {
             ibcd_sub: addr.clone ().recipient::<InboundClientData>(),
             bind: addr.clone ().recipient::<BindMessage>(),
             from_proxy_server: addr.clone ().recipient::<TransmitDataMsg>(),
+            pause_reading: addr.clone ().recipient::<PauseReadingMsg>(),
+            write_backlog: addr.clone ().recipient::<WriteBacklogMsg>(),
             from_hopper: addr.clone ().recipient::<HopperTemporaryTransmitDataMsg>(),
         }
     }
@@ -101,6 +134,7 @@ impl Dispatcher {
 #[cfg (test)]
 mod tests {
     use super::*;
+    use bytes::Bytes;
     use std::str::FromStr;
     use std::net::SocketAddr;
     use actix::Arbiter;
@@ -127,8 +161,11 @@ mod tests {
         let ibcd_in = InboundClientData {
             socket_addr,
             origin_port,
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
-            data: data.clone ()
+            stopped_reading: false,
+            data: Bytes::from (data.clone ())
         };
         let mut peer_actors = make_peer_actors_from(Some(proxy_server), None, None, None, None);
         peer_actors.dispatcher = Dispatcher::make_subs_from(&subject_addr);
@@ -167,8 +204,11 @@ mod tests {
         let ibcd_in = InboundClientData {
             socket_addr,
             origin_port,
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
-            data: data.clone ()
+            stopped_reading: false,
+            data: Bytes::from (data.clone ())
         };
 
         subject_ibcd.try_send (ibcd_in).unwrap ();
@@ -189,7 +229,8 @@ mod tests {
         let obcd = TransmitDataMsg {
             endpoint: Endpoint::Socket(socket_addr),
             last_data: false,
-            data: data.clone ()
+            stopped_reading: false,
+            data: Bytes::from (data.clone ())
         };
 
         subject_obcd.try_send (obcd).unwrap ();
@@ -209,7 +250,7 @@ mod tests {
         let transmit_msg = HopperTemporaryTransmitDataMsg {
             endpoint: Endpoint::Socket(socket_addr),
             last_data: false,
-            data: data.clone ()
+            data: Bytes::from (data.clone ())
         };
 
         subject_addr.try_send (transmit_msg).unwrap ();
@@ -232,7 +273,8 @@ mod tests {
         let obcd = TransmitDataMsg {
             endpoint: Endpoint::Socket(socket_addr),
             last_data: false,
-            data: data.clone ()
+            stopped_reading: false,
+            data: Bytes::from (data.clone ())
         };
         let mut peer_actors = make_peer_actors_from(None, None, None, None, None);
         peer_actors.dispatcher = Dispatcher::make_subs_from(&subject_addr);
@@ -260,6 +302,56 @@ mod tests {
         assert_eq! (recording.len (), 1);
     }
 
+    #[test]
+    fn forwards_pause_reading_messages_to_stream_handler_pool() {
+        let system = System::new ("test");
+        let subject = Dispatcher::new ();
+        let subject_addr: Addr<Syn, Dispatcher> = subject.start ();
+        let stream_handler_pool = Recorder::new();
+        let recording_arc = stream_handler_pool.get_recording();
+        let awaiter = stream_handler_pool.get_awaiter();
+        let socket_addr = SocketAddr::from_str ("1.2.3.4:5678").unwrap ();
+        let mut peer_actors = make_peer_actors_from(None, None, None, None, None);
+        peer_actors.dispatcher = Dispatcher::make_subs_from(&subject_addr);
+        let stream_handler_pool_subs = make_stream_handler_pool_subs_from (Some (stream_handler_pool));
+        subject_addr.try_send( PoolBindMessage { dispatcher_subs: peer_actors.dispatcher.clone (), stream_handler_pool_subs}).unwrap ();
+        subject_addr.try_send( BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send (PauseReadingMsg {socket_addr, paused: true}).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run ();
+
+        awaiter.await_message_count (1);
+        let recording = recording_arc.lock ().unwrap ();
+        assert_eq! (recording.get_record::<PauseReadingMsg> (0), &PauseReadingMsg {socket_addr, paused: true});
+        assert_eq! (recording.len (), 1);
+    }
+
+    #[test]
+    fn forwards_write_backlog_messages_to_proxy_server() {
+        let system = System::new ("test");
+        let subject = Dispatcher::new ();
+        let subject_addr: Addr<Syn, Dispatcher> = subject.start ();
+        let proxy_server = Recorder::new();
+        let recording_arc = proxy_server.get_recording();
+        let awaiter = proxy_server.get_awaiter();
+        let socket_addr = SocketAddr::from_str ("1.2.3.4:5678").unwrap ();
+        let mut peer_actors = make_peer_actors_from(Some (proxy_server), None, None, None, None);
+        peer_actors.dispatcher = Dispatcher::make_subs_from(&subject_addr);
+        subject_addr.try_send( BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send (WriteBacklogMsg {socket_addr, backed_up: true}).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run ();
+
+        awaiter.await_message_count (1);
+        let recording = recording_arc.lock ().unwrap ();
+        assert_eq! (recording.get_record::<WriteBacklogMsg> (0), &WriteBacklogMsg {socket_addr, backed_up: true});
+        assert_eq! (recording.len (), 1);
+    }
+
     #[test]
     fn converts_nonterminal_hopper_temporary_transmit_data_msg_to_inbound_client_data_for_hopper() {
         let system = System::new ("test");
@@ -274,7 +366,7 @@ mod tests {
         let transmit_msg = HopperTemporaryTransmitDataMsg {
             endpoint: Endpoint::Socket(socket_addr),
             last_data: false,
-            data: data.clone ()
+            data: Bytes::from (data.clone ())
         };
         let mut peer_actors = make_peer_actors_from(None, None, Some(hopper), None, None);
         peer_actors.dispatcher = Dispatcher::make_subs_from(&subject_addr);
@@ -315,7 +407,7 @@ mod tests {
         let transmit_msg = HopperTemporaryTransmitDataMsg {
             endpoint: Endpoint::Socket(socket_addr),
             last_data: true,
-            data: data.clone ()
+            data: Bytes::from (data.clone ())
         };
         let mut peer_actors = make_peer_actors_from(None, None, Some(hopper), None, None);
         peer_actors.dispatcher = Dispatcher::make_subs_from(&subject_addr);
@@ -0,0 +1,118 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use sub_lib::framer::FramedChunk;
+use sub_lib::framer::Framer;
+use sub_lib::http_packet_framer::HttpPacketFramer;
+use sub_lib::passthrough_framer::PassthroughFramer;
+use discriminator::Discriminator;
+use discriminator::DiscriminatorFactory;
+use http_request_start_finder::HttpRequestStartFinder;
+use null_masquerader::NullMasquerader;
+
+/// Frames HTTP requests arriving on the forward-proxy port until it sees a CONNECT request; after
+/// that the stream is a tunnel, and whatever arrives is passed along exactly as it came.
+pub struct HttpProxyFramer {
+    http_framer: HttpPacketFramer,
+    tunnel_framer: Option<PassthroughFramer>
+}
+
+impl Framer for HttpProxyFramer {
+    fn add_data(&mut self, data: &[u8]) {
+        match self.tunnel_framer {
+            Some (ref mut tunnel_framer) => tunnel_framer.add_data (data),
+            None => self.http_framer.add_data (data)
+        }
+    }
+
+    fn take_frame(&mut self) -> Option<FramedChunk> {
+        if let Some (ref mut tunnel_framer) = self.tunnel_framer {
+            return tunnel_framer.take_frame ()
+        }
+        let frame = self.http_framer.take_frame ()?;
+        // A client may start tunneling without waiting for our 200
+        if frame.chunk.starts_with (b"CONNECT ") {
+            let mut tunnel_framer = PassthroughFramer::new ();
+            tunnel_framer.add_data (&self.http_framer.take_leftover ()[..]);
+            self.tunnel_framer = Some (tunnel_framer);
+        }
+        Some (frame)
+    }
+}
+
+impl HttpProxyFramer {
+    pub fn new () -> HttpProxyFramer {
+        HttpProxyFramer {
+            http_framer: HttpPacketFramer::new (Box::new (HttpRequestStartFinder {})),
+            tunnel_framer: None
+        }
+    }
+}
+
+pub struct HttpProxyDiscriminatorFactory {}
+
+impl DiscriminatorFactory for HttpProxyDiscriminatorFactory {
+    fn make(&self) -> Discriminator {
+        Discriminator::new (Box::new (HttpProxyFramer::new ()), vec! (Box::new (NullMasquerader::new ())))
+    }
+
+    fn duplicate(&self) -> Box<DiscriminatorFactory> {
+        Box::new (HttpProxyDiscriminatorFactory{})
+    }
+}
+
+impl HttpProxyDiscriminatorFactory {
+    pub fn new () -> HttpProxyDiscriminatorFactory {
+        HttpProxyDiscriminatorFactory {}
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use discriminator::UnmaskedChunk;
+
+    #[test]
+    fn discriminator_factory_duplicate_works () {
+        let subject = HttpProxyDiscriminatorFactory::new ();
+
+        subject.duplicate ();
+
+        // no panic; test passes
+    }
+
+    #[test]
+    fn frames_absolute_form_requests_as_http () {
+        let mut subject = HttpProxyDiscriminatorFactory::new ().make ();
+
+        subject.add_data (b"GET http://url.com/ HTTP/1.1\r\n\r\nGET http://url.com/other HTTP/1.1\r\n\r\n");
+
+        assert_eq! (subject.take_chunk (), Some (UnmaskedChunk::new (b"GET http://url.com/ HTTP/1.1\r\n\r\n".to_vec (), true)));
+        assert_eq! (subject.take_chunk (), Some (UnmaskedChunk::new (b"GET http://url.com/other HTTP/1.1\r\n\r\n".to_vec (), true)));
+        assert_eq! (subject.take_chunk (), None);
+    }
+
+    #[test]
+    fn passes_everything_after_connect_through_untouched () {
+        let mut subject = HttpProxyDiscriminatorFactory::new ().make ();
+        subject.add_data (b"CONNECT url.com:443 HTTP/1.1\r\n\r\n");
+        let connect_chunk = subject.take_chunk ();
+
+        subject.add_data (&[0x16, 0x03, 0x01]);
+        subject.add_data (b"GET ");
+        let tunneled_chunk = subject.take_chunk ();
+
+        assert_eq! (connect_chunk, Some (UnmaskedChunk::new (b"CONNECT url.com:443 HTTP/1.1\r\n\r\n".to_vec (), true)));
+        assert_eq! (tunneled_chunk, Some (UnmaskedChunk::new (vec! (0x16, 0x03, 0x01, 0x47, 0x45, 0x54, 0x20), true)));
+        assert_eq! (subject.take_chunk (), None);
+    }
+
+    #[test]
+    fn passes_through_what_arrives_with_the_connect_request () {
+        let mut subject = HttpProxyDiscriminatorFactory::new ().make ();
+
+        subject.add_data (b"CONNECT url.com:443 HTTP/1.1\r\n\r\n\x16\x03\x01");
+
+        assert_eq! (subject.take_chunk (), Some (UnmaskedChunk::new (b"CONNECT url.com:443 HTTP/1.1\r\n\r\n".to_vec (), true)));
+        assert_eq! (subject.take_chunk (), Some (UnmaskedChunk::new (vec! (0x16, 0x03, 0x01), true)));
+        assert_eq! (subject.take_chunk (), None);
+    }
+}
@@ -1,8 +1,11 @@
+use std::cmp::min;
+use sub_lib::proxy_server::ProxyProtocol;
 use sub_lib::utils::index_of;
 use sub_lib::http_packet_framer::PacketProgressState;
 use sub_lib::http_packet_framer::HttpPacketStartFinder;
 use sub_lib::http_packet_framer::HttpFramerState;
 use sub_lib::http_packet_framer::HttpPacketFramer;
+use sub_lib::http_packet_framer::has_header;
 use discriminator::Discriminator;
 use discriminator::DiscriminatorFactory;
 use null_masquerader::NullMasquerader;
@@ -39,6 +42,12 @@ impl HttpPacketStartFinder for HttpRequestStartFinder {
             false
         }
     }
+
+    // The client won't send anything in the new protocol until the server agrees to switch, and if the
+    // server refuses, whatever the client sends next still reaches the same server stream unframed.
+    fn is_upgrade(&self, packet: &[u8]) -> bool {
+        has_header (packet, "Upgrade")
+    }
 }
 
 pub struct HttpRequestDiscriminatorFactory {}
@@ -52,6 +61,18 @@ impl DiscriminatorFactory for HttpRequestDiscriminatorFactory {
     fn duplicate(&self) -> Box<DiscriminatorFactory> {
         Box::new (HttpRequestDiscriminatorFactory{})
     }
+
+    // A short read might have cut the method off partway through
+    fn recognizes(&self, data: &[u8]) -> bool {
+        !data.is_empty () && METHODS.iter ().any (|method| {
+            let length = min (method.len (), data.len ());
+            method[..length] == data[..length]
+        })
+    }
+
+    fn proxy_protocol(&self) -> Option<ProxyProtocol> {
+        Some (ProxyProtocol::HTTP)
+    }
 }
 
 impl HttpRequestDiscriminatorFactory {
@@ -77,6 +98,44 @@ mod tests {
         // no panic; test passes
     }
 
+    #[test]
+    fn recognizes_http_requests_even_when_only_part_of_the_method_has_arrived () {
+        let subject = HttpRequestDiscriminatorFactory::new ();
+
+        assert_eq! (subject.recognizes (b"GET / HTTP/1.1\r\n"), true);
+        assert_eq! (subject.recognizes (b"OPT"), true);
+        assert_eq! (subject.recognizes (&[0x16, 0x03, 0x01]), false);
+        assert_eq! (subject.recognizes (&[]), false);
+        assert_eq! (subject.proxy_protocol (), Some (ProxyProtocol::HTTP));
+    }
+
+    #[test]
+    fn websocket_handshake_turns_discriminator_into_passthrough () {
+        let handshake = &b"GET /chat HTTP/1.1\r\nHost: server.example.com\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nOrigin: http://example.com\r\nSec-WebSocket-Protocol: chat, superchat\r\nSec-WebSocket-Version: 13\r\n\r\n"[..];
+        let masked_hello_frame = &[0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58][..];
+        let mut subject = HttpRequestDiscriminatorFactory::new ().make ();
+        subject.add_data (handshake);
+
+        let handshake_chunk = subject.take_chunk ();
+        subject.add_data (masked_hello_frame);
+        let websocket_chunk = subject.take_chunk ();
+
+        assert_eq! (handshake_chunk, Some (UnmaskedChunk::new (Vec::from (handshake), true)));
+        assert_eq! (websocket_chunk, Some (UnmaskedChunk::new (Vec::from (masked_hello_frame), true)));
+        assert_eq! (subject.take_chunk (), None);
+    }
+
+    #[test]
+    fn ordinary_request_does_not_turn_discriminator_into_passthrough () {
+        let mut subject = HttpRequestDiscriminatorFactory::new ().make ();
+        subject.add_data (b"GET /index.html HTTP/1.1\r\nHost: server.com\r\nConnection: keep-alive\r\n\r\n");
+        subject.take_chunk ();
+
+        subject.add_data (&[0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]);
+
+        assert_eq! (subject.take_chunk (), None);
+    }
+
     #[test]
     fn refuses_to_operate_in_state_other_than_seeking_request_start () {
         let mut framer_state = HttpFramerState {
@@ -2,9 +2,11 @@
 #[macro_use]
 extern crate actix;
 extern crate base64;
+extern crate bytes;
 extern crate chrono;
 extern crate entry_dns_lib;
 extern crate flexi_logger;
+extern crate futures;
 extern crate hopper_lib;
 extern crate log;
 extern crate neighborhood_lib;
@@ -16,6 +18,7 @@ extern crate serde_cbor;
 extern crate serde_derive;
 extern crate serde_json;
 extern crate sub_lib;
+extern crate tokio_core;
 
 #[cfg (test)]
 extern crate test_utils;
@@ -28,6 +31,7 @@ mod bootstrapper;
 mod configuration;
 pub mod discriminator;
 mod dispatcher;
+mod http_proxy_discriminator_factory;
 mod http_request_start_finder;
 pub mod json_discriminator_factory;
 pub mod json_framer;
@@ -35,8 +39,10 @@ pub mod json_masquerader;
 mod listener_handler;
 pub mod masquerader;
 mod null_masquerader;
+mod passthrough_discriminator_factory;
 mod privilege_drop;
 pub mod server_initializer;
+mod socks_discriminator_factory;
 mod stream_handler_pool;
 pub mod tls_discriminator_factory;
 
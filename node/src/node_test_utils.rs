@@ -18,9 +18,14 @@ use actix::Actor;
 use actix::Addr;
 use actix::Handler;
 use actix::Syn;
+use futures::Async;
+use futures::Poll;
+use futures::task;
+use tokio_core::reactor::Handle;
 use sub_lib::tcp_wrappers::TcpStreamWrapper;
 use sub_lib::framer::Framer;
 use sub_lib::framer::FramedChunk;
+use sub_lib::stream_handler_pool::PauseReadingMsg;
 use sub_lib::stream_handler_pool::TransmitDataMsg;
 use test_utils::test_utils::Recorder;
 use test_utils::test_utils::TestLog;
@@ -66,8 +71,7 @@ impl io::Read for TcpStreamWrapperMock {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.log.lock ().unwrap ().log (format! ("read ({}-byte buf)", buf.len ()));
         let (data, result) = self.read_results.remove (0);
-        let utf8 = String::from_utf8 (data.clone ());
-        if utf8.is_ok () && (utf8.expect ("Internal error") == String::from ("block")) {
+        if TcpStreamWrapperMock::blocks (&data) {
             let (_tx, rx) = mpsc::channel::<usize> ();
             rx.recv ().unwrap (); // block here; don't continue
             Ok (5) // compiler candy: never executed
@@ -143,6 +147,40 @@ impl TcpStreamWrapper for TcpStreamWrapperMock {
         self.log.lock ().unwrap ().log (format! ("try_clone ()"));
         self.try_clone_results.borrow_mut ().deref_mut ().remove (0)
     }
+
+    fn register (&mut self, _handle: &Handle) -> io::Result<()> {
+        // Skinny implementation
+        Ok (())
+    }
+
+    // A stream with no more read results, or whose next one is "block", never has anything more to read.
+    // WouldBlock means there's nothing to read yet, but there will be right away.
+    fn poll_read (&mut self, buf: &mut [u8]) -> Poll<usize, io::Error> {
+        match self.read_results.first () {
+            None => return Ok (Async::NotReady),
+            Some (&(ref data, _)) if TcpStreamWrapperMock::blocks (data) => {
+                self.log.lock ().unwrap ().log (format! ("read ({}-byte buf)", buf.len ()));
+                return Ok (Async::NotReady)
+            },
+            Some (_) => ()
+        }
+        match io::Read::read (self, buf) {
+            Ok (length) => Ok (Async::Ready (length)),
+            Err (ref e) if e.kind () == io::ErrorKind::WouldBlock => {
+                task::current ().notify ();
+                Ok (Async::NotReady)
+            },
+            Err (e) => Err (e)
+        }
+    }
+
+    fn poll_write (&mut self, buf: &[u8]) -> Poll<usize, io::Error> {
+        match io::Write::write (self, buf) {
+            Ok (length) => Ok (Async::Ready (length)),
+            Err (ref e) if e.kind () == io::ErrorKind::WouldBlock => Ok (Async::NotReady),
+            Err (e) => Err (e)
+        }
+    }
 }
 
 impl TestLogOwner for TcpStreamWrapperMock {
@@ -170,6 +208,10 @@ impl TcpStreamWrapperMock {
         self
     }
 
+    fn blocks (data: &[u8]) -> bool {
+        data == b"block"
+    }
+
     pub fn name (mut self, name: &str) -> TcpStreamWrapperMock {
         self.name = String::from (name);
         self
@@ -325,6 +367,7 @@ pub fn make_stream_handler_pool_subs_from(stream_handler_pool_opt: Option<Record
     StreamHandlerPoolSubs {
         add_sub: addr.clone ().recipient::<AddStreamMsg>(),
         transmit_sub: addr.clone ().recipient::<TransmitDataMsg>(),
+        pause_reading_sub: addr.clone ().recipient::<PauseReadingMsg>(),
         remove_sub: addr.clone ().recipient::<RemoveStreamMsg>(),
         bind: addr.clone ().recipient::<PoolBindMessage>(),
     }
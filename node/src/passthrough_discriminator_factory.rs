@@ -0,0 +1,52 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use sub_lib::passthrough_framer::PassthroughFramer;
+use discriminator::Discriminator;
+use discriminator::DiscriminatorFactory;
+use null_masquerader::NullMasquerader;
+
+pub struct PassthroughDiscriminatorFactory {}
+
+impl DiscriminatorFactory for PassthroughDiscriminatorFactory {
+    fn make(&self) -> Discriminator {
+        Discriminator::new (
+            Box::new (PassthroughFramer::new ()),
+            vec! (Box::new (NullMasquerader::new ()))
+        )
+    }
+
+    fn duplicate(&self) -> Box<DiscriminatorFactory> {
+        Box::new (PassthroughDiscriminatorFactory{})
+    }
+}
+
+impl PassthroughDiscriminatorFactory {
+    pub fn new () -> PassthroughDiscriminatorFactory {
+        PassthroughDiscriminatorFactory {}
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use discriminator::UnmaskedChunk;
+
+    #[test]
+    fn discriminator_factory_duplicate_works () {
+        let subject = PassthroughDiscriminatorFactory::new ();
+
+        subject.duplicate ();
+
+        // no panic; test passes
+    }
+
+    #[test]
+    fn factory_makes_discriminator_that_passes_anything_through () {
+        let data: &[u8] = b"SSH-2.0-OpenSSH_7.6\r\n";
+        let subject = PassthroughDiscriminatorFactory::new ();
+
+        let mut result = subject.make ();
+
+        result.add_data (data);
+        assert_eq! (result.take_chunk (), Some (UnmaskedChunk::new (Vec::from (data), true)));
+    }
+}
@@ -10,6 +10,8 @@ use sub_lib::main_tools::StdStreams;
 use sub_lib::main_tools::Command;
 use sub_lib::parameter_finder::ParameterFinder;
 use sub_lib::socket_server::SocketServer;
+use sub_lib::blocklist::Blocklist;
+use sub_lib::hostname_map::HostnameMap;
 use entry_dns_lib::dns_socket_server::new_dns_socket_server;
 use bootstrapper::Bootstrapper;
 use privilege_drop::PrivilegeDropper;
@@ -52,9 +54,11 @@ impl<P, D> Command for ServerInitializer<P, D> where P: PrivilegeDropper, D: Dae
 impl ServerInitializer<PrivilegeDropperReal, DaemonizerReal> {
     pub fn new ()
             -> ServerInitializer<PrivilegeDropperReal, DaemonizerReal> {
+        let hostname_map = HostnameMap::new ();
+        let blocklist = Blocklist::new ();
         ServerInitializer {
-            dns_socket_server: Some (Box::new (new_dns_socket_server())),
-            bootstrapper: Some (Box::new (Bootstrapper::new ())),
+            dns_socket_server: Some (Box::new (new_dns_socket_server(hostname_map.clone (), blocklist.clone ()))),
+            bootstrapper: Some (Box::new (Bootstrapper::new (hostname_map, blocklist))),
             privilege_dropper: PrivilegeDropperReal::new (),
             daemonizer: DaemonizerReal::new (),
             logger_initializer_wrapper: Box::new (LoggerInitializerWrapperReal {}),
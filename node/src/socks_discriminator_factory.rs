@@ -0,0 +1,181 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use sub_lib::framer::FramedChunk;
+use sub_lib::framer::Framer;
+use sub_lib::passthrough_framer::PassthroughFramer;
+use discriminator::Discriminator;
+use discriminator::DiscriminatorFactory;
+use null_masquerader::NullMasquerader;
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_VERSION: u8 = 0x01;
+
+/// Frames the messages of a SOCKS5 negotiation: the greeting, then any number of username/password
+/// authentication messages, then the request. Everything after the request belongs to the tunnel.
+/// The authentication messages and the request start with different version bytes, so the framer
+/// can tell them apart without knowing which authentication method the ProxyServer picked.
+pub struct SocksFramer {
+    data_so_far: Vec<u8>,
+    greeted: bool,
+    tunnel_framer: Option<PassthroughFramer>
+}
+
+impl Framer for SocksFramer {
+    fn add_data(&mut self, data: &[u8]) {
+        match self.tunnel_framer {
+            Some (ref mut tunnel_framer) => tunnel_framer.add_data (data),
+            None => self.data_so_far.extend (data)
+        }
+    }
+
+    fn take_frame(&mut self) -> Option<FramedChunk> {
+        if let Some (ref mut tunnel_framer) = self.tunnel_framer {
+            return tunnel_framer.take_frame ()
+        }
+        let length = match self.data_so_far.first () {
+            None => return None,
+            Some (&SOCKS_VERSION) if !self.greeted => SocksFramer::greeting_length (&self.data_so_far[..]),
+            Some (&AUTH_VERSION) if self.greeted => SocksFramer::credentials_length (&self.data_so_far[..]),
+            Some (&SOCKS_VERSION) => SocksFramer::request_length (&self.data_so_far[..]),
+            // Not SOCKS5; pass it along so that the ProxyServer can refuse it
+            Some (_) => Some (self.data_so_far.len ())
+        }?;
+        if self.data_so_far.len () < length {return None}
+        let leftovers = self.data_so_far.split_off (length);
+        let chunk = ::std::mem::replace (&mut self.data_so_far, leftovers);
+        if !self.greeted {
+            self.greeted = true
+        }
+        else if chunk[0] != AUTH_VERSION {
+            let mut tunnel_framer = PassthroughFramer::new ();
+            tunnel_framer.add_data (&self.data_so_far[..]);
+            self.data_so_far.clear ();
+            self.tunnel_framer = Some (tunnel_framer);
+        }
+        Some (FramedChunk {chunk, last_chunk: false})
+    }
+}
+
+impl SocksFramer {
+    pub fn new () -> SocksFramer {
+        SocksFramer {
+            data_so_far: vec! (),
+            greeted: false,
+            tunnel_framer: None
+        }
+    }
+
+    // VER NMETHODS METHODS...
+    fn greeting_length (data: &[u8]) -> Option<usize> {
+        data.get (1).map (|method_count| 2 + *method_count as usize)
+    }
+
+    // VER ULEN UNAME... PLEN PASSWD...
+    fn credentials_length (data: &[u8]) -> Option<usize> {
+        let password_length_offset = 2 + *data.get (1)? as usize;
+        data.get (password_length_offset).map (|password_length| password_length_offset + 1 + *password_length as usize)
+    }
+
+    // VER CMD RSV ATYP DST.ADDR DST.PORT
+    fn request_length (data: &[u8]) -> Option<usize> {
+        let address_length = match *data.get (3)? {
+            0x01 => 4,
+            0x03 => 1 + *data.get (4)? as usize,
+            0x04 => 16,
+            // Unknown address type; the ProxyServer will refuse whatever we frame
+            _ => return Some (data.len ())
+        };
+        Some (4 + address_length + 2)
+    }
+}
+
+pub struct SocksDiscriminatorFactory {}
+
+impl DiscriminatorFactory for SocksDiscriminatorFactory {
+    fn make(&self) -> Discriminator {
+        Discriminator::new (Box::new (SocksFramer::new ()), vec! (Box::new (NullMasquerader::new ())))
+    }
+
+    fn duplicate(&self) -> Box<DiscriminatorFactory> {
+        Box::new (SocksDiscriminatorFactory{})
+    }
+}
+
+impl SocksDiscriminatorFactory {
+    pub fn new () -> SocksDiscriminatorFactory {
+        SocksDiscriminatorFactory {}
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+
+    fn frame (chunk: &[u8]) -> Option<FramedChunk> {
+        Some (FramedChunk {chunk: chunk.to_vec (), last_chunk: false})
+    }
+
+    #[test]
+    fn discriminator_factory_duplicate_works () {
+        let subject = SocksDiscriminatorFactory::new ();
+
+        subject.duplicate ();
+
+        // no panic; test passes
+    }
+
+    #[test]
+    fn frames_negotiation_without_authentication_then_passes_tunnel_data_through () {
+        let mut subject = SocksFramer::new ();
+
+        subject.add_data (&[0x05, 0x01]);
+        let incomplete = subject.take_frame ();
+        subject.add_data (&[0x00, 0x05, 0x01, 0x00, 0x01, 1, 2, 3, 4, 0x00]);
+        let greeting = subject.take_frame ();
+        let incomplete_request = subject.take_frame ();
+        subject.add_data (&[0x50, 0x05, 0x01, 0x00]);
+        let request = subject.take_frame ();
+        let tunneled = subject.take_frame ();
+        subject.add_data (&[0x01]);
+        let more_tunneled = subject.take_frame ();
+
+        assert_eq! (incomplete, None);
+        assert_eq! (greeting, frame (&[0x05, 0x01, 0x00]));
+        assert_eq! (incomplete_request, None);
+        assert_eq! (request, frame (&[0x05, 0x01, 0x00, 0x01, 1, 2, 3, 4, 0x00, 0x50]));
+        assert_eq! (tunneled, frame (&[0x05, 0x01, 0x00]));
+        assert_eq! (more_tunneled, frame (&[0x01]));
+    }
+
+    #[test]
+    fn frames_negotiation_with_authentication_and_domain_name () {
+        let mut subject = SocksFramer::new ();
+
+        subject.add_data (b"\x05\x01\x02\x01\x05booga\x05wooga\x05\x01\x00\x03\x0Aserver.com\x00\x16");
+
+        assert_eq! (subject.take_frame (), frame (&[0x05, 0x01, 0x02]));
+        assert_eq! (subject.take_frame (), frame (b"\x01\x05booga\x05wooga"));
+        assert_eq! (subject.take_frame (), frame (b"\x05\x01\x00\x03\x0Aserver.com\x00\x16"));
+        assert_eq! (subject.take_frame (), None);
+    }
+
+    #[test]
+    fn frames_request_with_ipv6_address () {
+        let mut subject = SocksFramer::new ();
+        subject.add_data (&[0x05, 0x01, 0x00]);
+        subject.take_frame ();
+        let request = vec! (0x05, 0x01, 0x00, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x01, 0xBB);
+
+        subject.add_data (&request[..]);
+
+        assert_eq! (subject.take_frame (), frame (&request[..]));
+    }
+
+    #[test]
+    fn passes_non_socks5_data_along_whole () {
+        let mut subject = SocksFramer::new ();
+
+        subject.add_data (b"GET / HTTP/1.1\r\n\r\n");
+
+        assert_eq! (subject.take_frame (), frame (b"GET / HTTP/1.1\r\n\r\n"));
+    }
+}
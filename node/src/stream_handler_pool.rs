@@ -1,5 +1,7 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt;
 use std::fmt::Debug;
 use std::fmt::Formatter;
@@ -7,34 +9,59 @@ use std::io;
 use std::net::Shutdown;
 use std::net::SocketAddr;
 use std::string::ToString;
-use std::thread;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
 use actix::Actor;
 use actix::Addr;
+use actix::Arbiter;
+use actix::AsyncContext;
 use actix::Context;
 use actix::Handler;
 use actix::Recipient;
 use actix::Syn;
+use bytes::Bytes;
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use futures::task;
+use futures::task::Task;
+use tokio_core::reactor::Handle;
+use tokio_core::reactor::Remote;
 use discriminator::Discriminator;
 use discriminator::DiscriminatorFactory;
-use sub_lib::cryptde::StreamKey;
 use sub_lib::dispatcher;
 use sub_lib::dispatcher::DispatcherSubs;
 use sub_lib::dispatcher::Endpoint;
 use sub_lib::dispatcher::InboundClientData;
+use sub_lib::hostname_map::HostnameMap;
 use sub_lib::logger::Logger;
 use sub_lib::node_addr::NodeAddr;
+use sub_lib::outbox::Outbox;
+use sub_lib::proxy_server::ProxyProtocol;
+use sub_lib::send_window::RELIABILITY_TICK_MS;
+use sub_lib::stream_handler_pool::PauseReadingMsg;
 use sub_lib::stream_handler_pool::TransmitDataMsg;
+use sub_lib::stream_handler_pool::WriteBacklogMsg;
+use sub_lib::stream_timeouts::ExpiryTimer;
+use sub_lib::stream_timeouts::StreamClock;
+use sub_lib::stream_timeouts::StreamTimeouts;
 use sub_lib::tcp_wrappers::TcpStreamWrapper;
 use sub_lib::utils::indicates_dead_stream;
-use sub_lib::utils::NODE_MAILBOX_CAPACITY;
-
-trait StreamReader {
-    fn handle_traffic (&mut self);
-}
+use sub_lib::utils::MAX_READ_ERRORS_IN_A_ROW;
+use sub_lib::utils::MAX_READS_PER_POLL;
+use sub_lib::write_queue::WriteQueue;
 
 trait StreamWriter {
     fn transmit (&mut self, data: &[u8]) -> io::Result<usize>;
     fn shutdown (&mut self, how: Shutdown) -> io::Result<()>;
+    fn is_backed_up (&self) -> bool;
+}
+
+thread_local! {
+    // The StreamReaders on a thread take turns, so they can all read into the same buffer
+    static READ_BUFFER: RefCell<Vec<u8>> = RefCell::new (vec! [0; 0x10000]);
 }
 
 #[derive (Message)]
@@ -52,6 +79,7 @@ pub struct RemoveStreamMsg {
 pub struct StreamHandlerPoolSubs {
     pub add_sub: Recipient<Syn, AddStreamMsg>,
     pub transmit_sub: Recipient<Syn, TransmitDataMsg>,
+    pub pause_reading_sub: Recipient<Syn, PauseReadingMsg>,
     pub remove_sub: Recipient<Syn, RemoveStreamMsg>,
     pub bind: Recipient<Syn, PoolBindMessage>,
 }
@@ -61,90 +89,228 @@ impl Clone for StreamHandlerPoolSubs {
         StreamHandlerPoolSubs {
             add_sub: self.add_sub.clone (),
             transmit_sub: self.transmit_sub.clone (),
+            pause_reading_sub: self.pause_reading_sub.clone (),
             remove_sub: self.remove_sub.clone (),
             bind: self.bind.clone(),
         }
     }
 }
 
+// Lets the StreamHandlerPool stop a StreamReader from reading, and start it again
+#[derive (Clone)]
+struct ReadGate {
+    inner: Arc<Mutex<ReadGateInner>>
+}
+
+struct ReadGateInner {
+    closed: bool,
+    reader: Option<Task>
+}
+
+impl ReadGate {
+    fn new () -> ReadGate {
+        ReadGate {inner: Arc::new (Mutex::new (ReadGateInner {closed: false, reader: None}))}
+    }
+
+    fn set_closed (&self, closed: bool) {
+        let mut inner = self.inner.lock ().expect ("ReadGate poisoned");
+        inner.closed = closed;
+        if !closed {
+            if let Some (reader) = inner.reader.take () {reader.notify ()}
+        }
+    }
+
+    // Ready while the gate is open; otherwise the current task is woken when it opens
+    fn poll_open (&self) -> Async<()> {
+        let mut inner = self.inner.lock ().expect ("ReadGate poisoned");
+        if !inner.closed {return Async::Ready (())}
+        inner.reader = Some (task::current ());
+        Async::NotReady
+    }
+
+    // Once its StreamReader is gone, nobody's left to stop
+    fn is_abandoned (&self) -> bool {
+        Arc::strong_count (&self.inner) == 1
+    }
+}
+
 struct StreamReaderReal {
     stream: Box<TcpStreamWrapper>,
-    stream_key: StreamKey,
+    stream_key: SocketAddr,
+    port: u16,
     origin_port: Option<u16>,
-    ibcd_sub: Recipient<Syn, dispatcher::InboundClientData>,
+    target_hostname: Option<String>,
+    to_dispatcher: Outbox<dispatcher::InboundClientData>,
     remove_sub: Recipient<Syn, RemoveStreamMsg>,
-    discriminators: Vec<Discriminator>,
+    read_gate: ReadGate,
+    discriminator_factories: Vec<Box<DiscriminatorFactory>>,
+    // Chosen when the first data arrives
+    discriminator: Option<Discriminator>,
+    proxy_protocol: Option<ProxyProtocol>,
+    clock: StreamClock,
+    expiry_timer: ExpiryTimer,
+    read_errors: usize,
+    ended: bool,
     logger: Logger
 }
 
-impl StreamReader for StreamReaderReal {
-    fn handle_traffic(&mut self) {
-        let port = self.stream.local_addr().expect ("Internal error: no local address").port ();
-        self.logger.debug (format! ("StreamReader for port {} starting with no read timeout", port));
-        let mut buf: [u8; 0x10000] = [0; 0x10000];
-        loop {
-            match self.stream.read(&mut buf) {
-                Ok(0) => { // see RETURN VALUE section of recv man page (Unix)
-                    self.logger.debug (format! ("Stream on port {} has shut down (0-byte read)", port));
-                    self.shutdown();
-                    break;
+// The reactor polls a StreamReader when its stream has something to read, its ExpiryTimer goes off,
+// the Dispatcher has room for what it's been holding, or its ReadGate opens
+impl Future for StreamReaderReal {
+    type Item = ();
+    type Error = ();
+
+    fn poll (&mut self) -> Poll<(), ()> {
+        if self.ended {return self.finish ()}
+        if let Some (expiry) = self.expiry_timer.poll (&self.clock) {
+            self.logger.info (format! ("Closing stream on port {}: {}", self.port, expiry));
+            self.shutdown ();
+            self.end_of_stream (true);
+            return self.finish ()
+        }
+        if let Async::NotReady = self.to_dispatcher.poll_flush () {return Ok (Async::NotReady)}
+        // Until the network is ready for more of this stream, the client's data waits in the stream
+        if let Async::NotReady = self.read_gate.poll_open () {return Ok (Async::NotReady)}
+        for _ in 0..MAX_READS_PER_POLL {
+            match self.read () {
+                Ok (Async::NotReady) => return Ok (Async::NotReady),
+                Ok (Async::Ready (0)) => { // see RETURN VALUE section of recv man page (Unix)
+                    // The client may still be reading, so the stream stays open for writing
+                    self.logger.debug (format! ("Stream on port {} has shut down (0-byte read)", self.port));
+                    self.end_of_stream (false);
+                    return self.finish ()
                 },
-                Ok(length) => {
-                    self.logger.debug (format! ("Read {}-byte chunk from port {}", length, port));
-                    self.wrangle_discriminators(&buf, length)
+                // Until the Dispatcher has room, the client's data waits in the stream, and then the client waits
+                Ok (Async::Ready (_)) => {
+                    self.read_errors = 0;
+                    if !self.to_dispatcher.is_empty () {
+                        self.logger.debug (format! ("Dispatcher is full; pausing stream on port {}", self.port));
+                        return Ok (Async::NotReady)
+                    }
                 },
-                Err(e) => {
+                Err (e) => {
                     if indicates_dead_stream (e.kind ()) {
-                        self.logger.debug (format! ("Stream on port {} is dead: {}", port, e));
-                        self.shutdown();
-                        break;
+                        self.logger.debug (format! ("Stream on port {} is dead: {}", self.port, e));
+                        self.shutdown ();
+                        self.end_of_stream (true);
+                        return self.finish ()
                     }
-                    else {
-                        self.logger.warning (format! ("Continuing after read error on port {}: {}", port, e.to_string ()))
+                    self.read_errors += 1;
+                    if self.read_errors >= MAX_READ_ERRORS_IN_A_ROW {
+                        self.logger.warning (format! ("Closing stream on port {} after {} read errors in a row: {}", self.port, self.read_errors, e));
+                        self.shutdown ();
+                        self.end_of_stream (true);
+                        return self.finish ()
                     }
+                    self.logger.warning (format! ("Continuing after read error on port {}: {}", self.port, e.to_string ()));
+                    // The error may not have cleared by the next read, so the other streams on this thread go first
+                    task::current ().notify ();
+                    return Ok (Async::NotReady)
                 }
             }
         }
-        self.logger.debug (format! ("StreamReader for port {} shutting down", port));
+        // There may be more to read, but the other streams on this thread get their turns first
+        task::current ().notify ();
+        Ok (Async::NotReady)
     }
 }
 
 impl StreamReaderReal {
-    fn new (stream: Box<TcpStreamWrapper>, origin_port: Option<u16>, ibcd_sub: Recipient<Syn, dispatcher::InboundClientData>,
-            remove_sub: Recipient<Syn, RemoveStreamMsg>, discriminator_factories: Vec<Box<DiscriminatorFactory>>) -> StreamReaderReal {
+    fn new (stream: Box<TcpStreamWrapper>, origin_port: Option<u16>, target_hostname: Option<String>,
+            ibcd_sub: Recipient<Syn, dispatcher::InboundClientData>, remove_sub: Recipient<Syn, RemoveStreamMsg>,
+            read_gate: ReadGate, discriminator_factories: Vec<Box<DiscriminatorFactory>>, clock: StreamClock) -> StreamReaderReal {
         let socket_addr = stream.peer_addr ().expect ("Internal error: no peer address creating StreamReaderReal");
+        let port = stream.local_addr ().expect ("Internal error: no local address creating StreamReaderReal").port ();
         let name = format! ("Dispatcher for {:?}", socket_addr);
         if discriminator_factories.is_empty () {panic! ("Internal error: no Discriminator factories!")}
         StreamReaderReal {
             stream,
             stream_key: socket_addr,
+            port,
             origin_port,
-            ibcd_sub,
+            target_hostname,
+            to_dispatcher: Outbox::new (ibcd_sub, "Dispatcher"),
             remove_sub,
-            // Skinny implementation
-            discriminators: vec! (discriminator_factories[0].make ()),
+            read_gate,
+            discriminator_factories,
+            discriminator: None,
+            proxy_protocol: None,
+            clock,
+            expiry_timer: ExpiryTimer::none (),
+            read_errors: 0,
+            ended: false,
             logger: Logger::new (&name)
         }
     }
 
+    // The stream must already be registered with the reactor behind the Handle
+    fn start (mut self, handle: &Handle) {
+        self.expiry_timer = match ExpiryTimer::new (self.clock.timeouts (), handle) {
+            Ok (expiry_timer) => expiry_timer,
+            Err (e) => {
+                self.logger.warning (format! ("Stream on port {} won't expire: could not start timer: {}", self.port, e));
+                ExpiryTimer::none ()
+            }
+        };
+        self.logger.debug (format! ("StreamReader for port {} starting", self.port));
+        handle.spawn (self);
+    }
+
+    fn read (&mut self) -> Poll<usize, io::Error> {
+        READ_BUFFER.with (|buf| {
+            let mut buf = buf.borrow_mut ();
+            let result = self.stream.poll_read (&mut buf[..]);
+            match result {
+                Ok (Async::Ready (length)) if length > 0 => {
+                    self.logger.debug (format! ("Read {}-byte chunk from port {}", length, self.port));
+                    self.clock.traffic (Instant::now ());
+                    self.wrangle_discriminators (&buf, length)
+                },
+                _ => ()
+            }
+            result
+        })
+    }
+
+    // Whatever was read before the stream ended still has to reach the Dispatcher
+    fn finish (&mut self) -> Poll<(), ()> {
+        self.ended = true;
+        if let Async::NotReady = self.to_dispatcher.poll_flush () {return Ok (Async::NotReady)}
+        self.logger.debug (format! ("StreamReader for port {} shutting down", self.port));
+        Ok (Async::Ready (()))
+    }
+
+    // If nothing recognizes the data, the first factory is the port's best guess
+    fn choose_discriminator (&mut self, data: &[u8]) {
+        let factory = self.discriminator_factories.iter ()
+            .find (|factory| factory.recognizes (data))
+            .unwrap_or (&self.discriminator_factories[0]);
+        self.proxy_protocol = factory.proxy_protocol ();
+        self.discriminator = Some (factory.make ());
+    }
     fn wrangle_discriminators (&mut self, buf: &[u8], length: usize) {
-        // Skinny implementation
-        if self.discriminators.is_empty () {panic! ("Internal error: no Discriminator factories!")}
-        let discriminator = &mut self.discriminators[0];
+        if self.discriminator.is_none () {
+            self.choose_discriminator (&buf[..length]);
+            self.logger.debug (format! ("Stream looks like {:?}", self.proxy_protocol));
+        }
+        let discriminator = self.discriminator.as_mut ().expect ("Internal error: no Discriminator");
         self.logger.debug (format! ("Adding {} bytes to discriminator", length));
         discriminator.add_data (&buf[..length]);
         loop {
             match discriminator.take_chunk() {
                 Some(unmasked_chunk) => {
+                    self.logger.debug (format! ("Discriminator framed and unmasked {} bytes for {}; transmitting via Hopper",
+                                                 unmasked_chunk.chunk.len (), self.stream_key));
                     let msg = dispatcher::InboundClientData {
                         socket_addr: self.stream_key,
                         origin_port: self.origin_port,
+                        target_hostname: self.target_hostname.clone (),
+                        proxy_protocol: self.proxy_protocol,
                         last_data: false,
-                        data: unmasked_chunk.chunk.clone ()
+                        stopped_reading: false,
+                        data: Bytes::from (unmasked_chunk.chunk)
                     };
-                    self.logger.debug (format! ("Discriminator framed and unmasked {} bytes for {}; transmitting via Hopper",
-                                                 unmasked_chunk.chunk.len (), msg.socket_addr));
-                    self.ibcd_sub.try_send(msg).expect("Dispatcher is dead");
+                    self.to_dispatcher.send (msg);
                 }
                 None => {
                     self.logger.debug (format!("Discriminator has no more data framed"));
@@ -157,32 +323,40 @@ impl StreamReaderReal {
     fn shutdown(&mut self) {
         self.remove_sub.try_send(RemoveStreamMsg { socket_addr: self.stream_key }).expect("StreamHandlerPool is dead");
         self.stream.shutdown(Shutdown::Both).ok(); // can't do anything about failure
-        // TODO: Skinny implementation: wrong for decentralization. StreamReaders for clandestine and non-clandestine data should probably behave differently here.
-        self.ibcd_sub.try_send(InboundClientData {
+    }
+
+    // TODO: Skinny implementation: wrong for decentralization. StreamReaders for clandestine and non-clandestine data should probably behave differently here.
+    fn end_of_stream (&mut self, stopped_reading: bool) {
+        self.to_dispatcher.send (InboundClientData {
             socket_addr: self.stream_key,
             origin_port: self.origin_port,
+            target_hostname: self.target_hostname.clone (),
+            proxy_protocol: self.proxy_protocol,
             last_data: true,
-            data: Vec::new(),
-        }).expect("Dispatcher is dead");
+            stopped_reading,
+            data: Bytes::new (),
+        });
     }
 }
 
 struct StreamWriterReal {
-    stream: Box<TcpStreamWrapper>,
-    stream_key: StreamKey,
+    write_queue: WriteQueue,
+    stream_key: SocketAddr,
     remove_sub: Recipient<Syn, RemoveStreamMsg>,
+    clock: StreamClock,
     logger: Logger
 }
 
 impl StreamWriter for StreamWriterReal {
     fn transmit(&mut self, data: &[u8]) -> io::Result<usize> {
-        match self.stream.write (data) {
-            Ok (size) => Ok (size),
+        match self.write_queue.write (data) {
+            Ok (size) => {
+                self.clock.traffic (Instant::now ());
+                Ok (size)
+            },
             Err (e) => {
-                if indicates_dead_stream (e.kind ()) {
-                    self.stream.shutdown (Shutdown::Both).ok (); // can't do anything about failure
-                    self.remove_sub.try_send (RemoveStreamMsg {socket_addr: self.stream_key}).expect ("Internal error: StreamHandlerPool is dead");
-                }
+                // The WriteQueue has shut the stream down, so nothing more can be written to it
+                self.remove_sub.try_send (RemoveStreamMsg {socket_addr: self.stream_key}).expect ("Internal error: StreamHandlerPool is dead");
                 self.logger.log (format! ("Cannot transmit {} bytes: {}", data.len (), e.to_string ()));
                 Err(e)
             }
@@ -190,19 +364,24 @@ impl StreamWriter for StreamWriterReal {
     }
 
     fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
-        self.stream.shutdown (how)
+        self.write_queue.shutdown (how)
+    }
+
+    fn is_backed_up(&self) -> bool {
+        self.write_queue.is_backed_up ()
     }
 }
 
 impl StreamWriterReal {
-    fn new (stream: Box<TcpStreamWrapper>, remove_sub: Recipient<Syn, RemoveStreamMsg>) -> StreamWriterReal {
+    fn new (stream: Box<TcpStreamWrapper>, remote: Remote, remove_sub: Recipient<Syn, RemoveStreamMsg>, clock: StreamClock) -> StreamWriterReal {
         let socket_addr = stream.peer_addr ().expect ("Internal error: no peer address creating StreamWriterReal");
         let name = format! ("Dispatcher for {:?}", socket_addr);
         let logger = Logger::new (&name[..]);
         StreamWriterReal {
-            stream,
+            write_queue: WriteQueue::new (stream, remote),
             stream_key: socket_addr,
             remove_sub,
+            clock,
             logger
         }
     }
@@ -210,8 +389,12 @@ impl StreamWriterReal {
 
 pub struct StreamHandlerPool {
     stream_writers: HashMap<SocketAddr, Box<StreamWriter>>,
+    read_gates: HashMap<SocketAddr, ReadGate>,
+    backed_up: HashSet<SocketAddr>,
     dispatcher_subs: Option<DispatcherSubs>,
     self_subs: Option<StreamHandlerPoolSubs>,
+    hostname_map: HostnameMap,
+    stream_timeouts: StreamTimeouts,
     logger: Logger
 }
 
@@ -221,11 +404,15 @@ impl Actor for StreamHandlerPool {
 
 impl StreamHandlerPool {
 
-    pub fn new() -> StreamHandlerPool {
+    pub fn new(hostname_map: HostnameMap, stream_timeouts: StreamTimeouts) -> StreamHandlerPool {
         StreamHandlerPool {
             stream_writers: HashMap::new (),
+            read_gates: HashMap::new (),
+            backed_up: HashSet::new (),
             dispatcher_subs: None,
             self_subs: None,
+            hostname_map,
+            stream_timeouts,
             logger: Logger::new ("Dispatcher"),
         }
     }
@@ -234,34 +421,70 @@ impl StreamHandlerPool {
         StreamHandlerPoolSubs {
             add_sub: pool_addr.clone ().recipient::<AddStreamMsg>(),
             transmit_sub: pool_addr.clone ().recipient::<TransmitDataMsg>(),
+            pause_reading_sub: pool_addr.clone ().recipient::<PauseReadingMsg>(),
             remove_sub: pool_addr.clone ().recipient::<RemoveStreamMsg>(),
             bind: pool_addr.clone ().recipient::<PoolBindMessage>(),
         }
     }
 
     fn set_up_stream_reader (&mut self, read_stream: Box<TcpStreamWrapper>, origin_port: Option<u16>,
-            discriminator_factories: Vec<Box<DiscriminatorFactory>>) {
+            discriminator_factories: Vec<Box<DiscriminatorFactory>>, clock: StreamClock) {
         let ibcd_sub: Recipient<Syn, dispatcher::InboundClientData> =
             self.dispatcher_subs.as_ref().expect("StreamHandlerPool is unbound").ibcd_sub.clone ();
         let remove_sub: Recipient<Syn, RemoveStreamMsg> =
             self.self_subs.as_ref().expect("StreamHandlerPool is unbound").remove_sub.clone ();
-        thread::spawn(move || {
-            let ibcd_sub = ibcd_sub.clone ();
-            let remove_sub = remove_sub.clone();
-            let mut stream_reader = StreamReaderReal::new(read_stream, origin_port,
-                ibcd_sub, remove_sub, discriminator_factories);
-            stream_reader.handle_traffic();
-        });
+        // If the Entry DNS server gave the hostname its own address, the address the client connected to tells us the hostname
+        let target_hostname = match read_stream.local_addr () {
+            Ok (local_addr) => self.hostname_map.hostname_for (&local_addr.ip ()),
+            Err (_) => None
+        };
+        let read_gate = ReadGate::new ();
+        let stream_reader = StreamReaderReal::new(read_stream, origin_port, target_hostname,
+            ibcd_sub, remove_sub, read_gate.clone (), discriminator_factories, clock);
+        self.read_gates.retain (|_, read_gate| !read_gate.is_abandoned ());
+        self.read_gates.insert (stream_reader.stream_key, read_gate);
+        stream_reader.start (&Arbiter::handle ());
     }
 
-    fn set_up_stream_writer (&mut self, write_stream: Box<TcpStreamWrapper>) {
+    fn set_up_stream_writer (&mut self, write_stream: Box<TcpStreamWrapper>, clock: StreamClock) {
         let socket_addr = write_stream.peer_addr ().expect ("Internal error: no peer address preparing StreamWriter");
         let stream_writer = StreamWriterReal::new (
             write_stream,
+            Arbiter::handle ().remote ().clone (),
             self.self_subs.as_ref().expect("StreamHandlerPool is unbound").remove_sub.clone (),
+            clock
         );
         self.stream_writers.insert (socket_addr, Box::new (stream_writer));
     }
+
+    // While a client's WriteQueue is backed up, the ProxyServer stops acknowledging the responses for
+    // it, so the exit stops reading from the server once its send window fills
+    fn note_backlog (&mut self, socket_addr: SocketAddr, ctx: &mut Context<StreamHandlerPool>) {
+        if self.backed_up.contains (&socket_addr) || !self.is_backed_up (socket_addr) {return}
+        self.logger.debug (format! ("Client {:?} is behind on its responses", socket_addr));
+        self.backed_up.insert (socket_addr);
+        self.report_backlog (socket_addr, true);
+        self.watch_backlog (socket_addr, ctx)
+    }
+
+    fn watch_backlog (&self, socket_addr: SocketAddr, ctx: &mut Context<StreamHandlerPool>) {
+        ctx.run_later (Duration::from_millis (RELIABILITY_TICK_MS), move |pool, ctx| {
+            if pool.is_backed_up (socket_addr) {return pool.watch_backlog (socket_addr, ctx)}
+            pool.logger.debug (format! ("Client {:?} has caught up on its responses", socket_addr));
+            pool.backed_up.remove (&socket_addr);
+            pool.report_backlog (socket_addr, false)
+        });
+    }
+
+    // A stream that's gone has nothing waiting for it
+    fn is_backed_up (&self, socket_addr: SocketAddr) -> bool {
+        self.stream_writers.get (&socket_addr).map (|stream_writer| stream_writer.is_backed_up ()).unwrap_or (false)
+    }
+
+    fn report_backlog (&self, socket_addr: SocketAddr, backed_up: bool) {
+        self.dispatcher_subs.as_ref ().expect ("StreamHandlerPool is unbound").write_backlog
+            .do_send (WriteBacklogMsg {socket_addr, backed_up}).expect ("Dispatcher is dead");
+    }
 }
 
 impl Handler<AddStreamMsg> for StreamHandlerPool {
@@ -269,23 +492,33 @@ impl Handler<AddStreamMsg> for StreamHandlerPool {
 
     fn handle(&mut self, msg: AddStreamMsg, _ctx: &mut Self::Context) {
         let stream_ref = msg.stream.as_ref();
-        let read_stream = match stream_ref.try_clone() {
+        let mut read_stream = match stream_ref.try_clone() {
             Ok(stream) => stream,
             Err(e) => {
                 self.logger.error(format!("Could not clone read stream; giving up: {:?}", e));
                 return
             }
         };
-        let write_stream = match stream_ref.try_clone() {
+        let mut write_stream = match stream_ref.try_clone() {
             Ok(stream) => stream,
             Err(e) => {
                 self.logger.error (format! ("Could not clone write stream: giving up: {:?}", e));
                 return
             }
         };
+        let handle = Arbiter::handle ();
+        match read_stream.register (&handle).and_then (|_| write_stream.register (&handle)) {
+            Ok (()) => (),
+            Err (e) => {
+                self.logger.error (format! ("Could not register stream with the reactor; giving up: {:?}", e));
+                return
+            }
+        }
 
-        self.set_up_stream_writer(write_stream);
-        self.set_up_stream_reader(read_stream, msg.origin_port, msg.discriminator_factories);
+        // Traffic either way keeps the stream alive, so the reader and the writer share a clock
+        let clock = StreamClock::new (self.stream_timeouts);
+        self.set_up_stream_writer(write_stream, clock.clone ());
+        self.set_up_stream_reader(read_stream, msg.origin_port, msg.discriminator_factories, clock);
     }
 }
 
@@ -300,7 +533,7 @@ impl Handler<RemoveStreamMsg> for StreamHandlerPool {
 impl Handler<TransmitDataMsg> for StreamHandlerPool {
     type Result = ();
 
-    fn handle(&mut self, msg: TransmitDataMsg, _ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: TransmitDataMsg, ctx: &mut Self::Context) {
         let node_addr = match msg.endpoint {
             Endpoint::Key (_) => unimplemented!(),
             Endpoint::Ip (_) => unimplemented!(),
@@ -313,8 +546,16 @@ impl Handler<TransmitDataMsg> for StreamHandlerPool {
         match self.stream_writers.get_mut (&socket_addr) {
             Some (stream_writer_box) => {
                 stream_writer_box.transmit (&msg.data[..]).is_ok ();
-                if msg.last_data {
-                    stream_writer_box.shutdown (Shutdown::Both).is_ok ();
+                // Shutting down the reading side wakes the stream's StreamReader, which ends the stream
+                // from the client's side too
+                let how = match (msg.last_data, msg.stopped_reading) {
+                    (true, true) => Some (Shutdown::Both),
+                    (true, false) => Some (Shutdown::Write),
+                    (false, true) => Some (Shutdown::Read),
+                    (false, false) => None
+                };
+                if let Some (how) = how {
+                    stream_writer_box.shutdown (how).is_ok ();
                 }
             },
             None => {
@@ -322,6 +563,21 @@ impl Handler<TransmitDataMsg> for StreamHandlerPool {
                     msg.data.len (), socket_addr));
             }
         }
+        self.note_backlog (socket_addr, ctx);
+        // Nothing more will be written; the StreamReader's copy of the stream keeps it open until the client finishes
+        if msg.last_data {self.stream_writers.remove (&socket_addr);}
+    }
+}
+
+impl Handler<PauseReadingMsg> for StreamHandlerPool {
+    type Result = ();
+
+    fn handle(&mut self, msg: PauseReadingMsg, _ctx: &mut Self::Context) {
+        match self.read_gates.get (&msg.socket_addr) {
+            Some (read_gate) => read_gate.set_closed (msg.paused),
+            None => self.logger.debug (format! ("Cannot {} reading from {:?}: nonexistent stream",
+                if msg.paused {"pause"} else {"resume"}, msg.socket_addr))
+        }
     }
 }
 
@@ -341,7 +597,15 @@ impl Handler<PoolBindMessage> for StreamHandlerPool {
     type Result = ();
 
     fn handle(&mut self, msg: PoolBindMessage, ctx: &mut Self::Context) {
-        ctx.set_mailbox_capacity(NODE_MAILBOX_CAPACITY);
+        // Data for clients comes from the Proxy Server through the Dispatcher to here, and that's the
+        // one path through the Node whose mailboxes aren't bounded. Nothing waits on the
+        // StreamHandlerPool: it only hands data to WriteQueues and keeps track of streams, and making
+        // the Dispatcher wait here would let a slow client hold up every other stream and the Proxy
+        // Server with it. Nor can anything on the way be dropped, since it's already been
+        // acknowledged. What comes in is limited before it gets here instead: no more responses come
+        // for a client than the exit's send window holds, and the exit stops reading once a client's
+        // WriteQueue is backed up.
+        ctx.set_mailbox_capacity(0);
         self.dispatcher_subs = Some(msg.dispatcher_subs);
         self.self_subs = Some(msg.stream_handler_pool_subs);
     }
@@ -356,15 +620,22 @@ mod tests {
     use std::ops::Deref;
     use std::str::FromStr;
     use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
     use actix::Arbiter;
     use actix::msgs;
     use actix::System;
+    use futures::future;
+    use std::net::Ipv4Addr;
     use http_request_start_finder::HttpRequestDiscriminatorFactory;
+    use tls_discriminator_factory::TlsDiscriminatorFactory;
+    use passthrough_discriminator_factory::PassthroughDiscriminatorFactory;
     use node_test_utils::make_stream_handler_pool_subs_from;
     use node_test_utils::TcpStreamWrapperMock;
     use node_test_utils::TestLogOwner;
     use node_test_utils::wait_until;
     use sub_lib::dispatcher::InboundClientData;
+    use sub_lib::write_queue::MAX_PENDING_BYTES;
     use test_utils::test_utils::init_test_logging;
     use test_utils::test_utils::make_peer_actors;
     use test_utils::test_utils::make_peer_actors_from;
@@ -385,11 +656,53 @@ mod tests {
         let discriminator_factory = HttpRequestDiscriminatorFactory {};
 
         let subject = StreamReaderReal::new (Box::new (stream),
-                                             None, ibcd_sub, remove_sub, vec! (Box::new (discriminator_factory)));
+                                             None, None, ibcd_sub, remove_sub, ReadGate::new (), vec! (Box::new (discriminator_factory)), StreamClock::new (StreamTimeouts::none ()));
 
         assert_eq! (subject.stream_key, SocketAddr::from_str ("12.34.56.78:9101").unwrap ());
     }
 
+    #[test]
+    fn stream_reader_chooses_discriminator_that_recognizes_the_data () {
+        let stream = TcpStreamWrapperMock::new ()
+            .peer_addr_result (Ok (SocketAddr::from_str ("12.34.56.78:9101").unwrap ()));
+        let _system = System::new ("test");
+        let ibcd = Recorder::new ();
+        let ibcd_addr: Addr<Syn, Recorder> = ibcd.start ();
+        let remove = Recorder::new ();
+        let remove_addr: Addr<Syn, Recorder> = remove.start ();
+        let mut subject = StreamReaderReal::new (Box::new (stream), Some (80), None,
+            ibcd_addr.recipient (), remove_addr.recipient (), ReadGate::new (),
+            vec! (Box::new (HttpRequestDiscriminatorFactory::new ()), Box::new (TlsDiscriminatorFactory::new ())),
+            StreamClock::new (StreamTimeouts::none ()));
+        let tls_data = vec! (0x16, 0x03, 0x01, 0x00, 0x03, 0x01, 0x02, 0x03);
+
+        subject.choose_discriminator (&tls_data[..]);
+
+        assert_eq! (subject.proxy_protocol, Some (ProxyProtocol::TLS));
+        let discriminator = subject.discriminator.as_mut ().unwrap ();
+        discriminator.add_data (&tls_data[..]);
+        assert_eq! (discriminator.take_chunk ().unwrap ().chunk, tls_data);
+    }
+
+    #[test]
+    fn stream_reader_falls_back_to_first_discriminator_when_nothing_recognizes_the_data () {
+        let stream = TcpStreamWrapperMock::new ()
+            .peer_addr_result (Ok (SocketAddr::from_str ("12.34.56.78:9101").unwrap ()));
+        let _system = System::new ("test");
+        let ibcd = Recorder::new ();
+        let ibcd_addr: Addr<Syn, Recorder> = ibcd.start ();
+        let remove = Recorder::new ();
+        let remove_addr: Addr<Syn, Recorder> = remove.start ();
+        let mut subject = StreamReaderReal::new (Box::new (stream), Some (443), None,
+            ibcd_addr.recipient (), remove_addr.recipient (), ReadGate::new (),
+            vec! (Box::new (TlsDiscriminatorFactory::new ()), Box::new (HttpRequestDiscriminatorFactory::new ())),
+            StreamClock::new (StreamTimeouts::none ()));
+
+        subject.choose_discriminator (b"garbage");
+
+        assert_eq! (subject.proxy_protocol, Some (ProxyProtocol::TLS));
+    }
+
     #[test]
     fn stream_writer_constructor_assigns_peer_addr () {
         let stream = TcpStreamWrapperMock::new ()
@@ -399,11 +712,198 @@ mod tests {
         let remove_addr: Addr<Syn, Recorder> = remove.start ();
         let remove_sub: Recipient<Syn, RemoveStreamMsg> = remove_addr.recipient ();
 
-        let subject = StreamWriterReal::new (Box::new (stream), remove_sub);
+        let subject = StreamWriterReal::new (Box::new (stream), Arbiter::handle ().remote ().clone (), remove_sub,
+            StreamClock::new (StreamTimeouts::none ()));
 
         assert_eq! (subject.stream_key, SocketAddr::from_str ("12.34.56.78:9101").unwrap ());
     }
 
+    fn run_stream_reader (stream: TcpStreamWrapperMock, clock: StreamClock, dispatcher: Recorder, pool: Recorder) {
+        thread::spawn (move || {
+            let system = System::new ("test");
+            let dispatcher_addr: Addr<Syn, Recorder> = dispatcher.start ();
+            let pool_addr: Addr<Syn, Recorder> = pool.start ();
+            let subject = StreamReaderReal::new (Box::new (stream), Some (80), None,
+                dispatcher_addr.recipient (), pool_addr.recipient (), ReadGate::new (),
+                vec! (Box::new (HttpRequestDiscriminatorFactory::new ())), clock);
+            subject.start (&Arbiter::handle ());
+            system.run ();
+        });
+    }
+
+    #[test]
+    fn stream_reader_closes_an_expired_stream_and_tells_the_network_the_client_is_gone () {
+        init_test_logging ();
+        let socket_addr = SocketAddr::from_str ("1.2.3.4:5678").unwrap ();
+        let dispatcher = Recorder::new ();
+        let dispatcher_awaiter = dispatcher.get_awaiter ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let pool = Recorder::new ();
+        let pool_awaiter = pool.get_awaiter ();
+        let pool_recording_arc = pool.get_recording ();
+        let mut stream = TcpStreamWrapperMock::new ().peer_addr_result (Ok (socket_addr));
+        stream.read_results = vec! ((vec! (), Err (Error::from (ErrorKind::WouldBlock))));
+        stream.shutdown_results = RefCell::new (vec! (Ok (())));
+        let stream_log = stream.get_test_log ();
+        let timeouts = StreamTimeouts {idle: None, lifetime: Some (Duration::from_millis (1500))};
+        let clock = StreamClock::starting_at (timeouts, Instant::now () - Duration::from_millis (2000));
+
+        run_stream_reader (stream, clock, dispatcher, pool);
+
+        dispatcher_awaiter.await_message_count (1);
+        pool_awaiter.await_message_count (1);
+        assert_eq! (dispatcher_recording_arc.lock ().unwrap ().get_record::<InboundClientData> (0), &InboundClientData {
+            socket_addr,
+            origin_port: Some (80),
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: true,
+            stopped_reading: true,
+            data: Bytes::new ()
+        });
+        assert_eq! (pool_recording_arc.lock ().unwrap ().get_record::<RemoveStreamMsg> (0).socket_addr, socket_addr);
+        wait_until (|| stream_log.lock ().unwrap ().dump ().len () == 1);
+        assert_eq! (stream_log.lock ().unwrap ().dump (), vec! (String::from ("shutdown (Both)")));
+        TestLogHandler::new ().await_log_containing ("Closing stream on port 6789: open for more than 1500ms", 1000);
+    }
+
+    #[test]
+    fn stream_reader_keeps_waiting_for_data_on_a_stream_that_has_not_expired () {
+        let socket_addr = SocketAddr::from_str ("1.2.3.4:5679").unwrap ();
+        let http_req = Vec::from ("GET http://here.com HTTP/1.1\r\n\r\n".as_bytes ());
+        let dispatcher = Recorder::new ();
+        let dispatcher_awaiter = dispatcher.get_awaiter ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let mut stream = TcpStreamWrapperMock::new ().peer_addr_result (Ok (socket_addr));
+        stream.read_results = vec! (
+            (vec! (), Err (Error::from (ErrorKind::WouldBlock))),
+            (http_req.clone (), Ok (http_req.len ())),
+            (vec! (), Ok (0))
+        );
+        let clock = StreamClock::new (StreamTimeouts {idle: Some (Duration::from_millis (300000)), lifetime: None});
+
+        run_stream_reader (stream, clock, dispatcher, Recorder::new ());
+
+        dispatcher_awaiter.await_message_count (2);
+        let dispatcher_recording = dispatcher_recording_arc.lock ().unwrap ();
+        assert_eq! (dispatcher_recording.get_record::<InboundClientData> (0).data, http_req);
+        assert_eq! (dispatcher_recording.get_record::<InboundClientData> (1).last_data, true);
+        assert_eq! (dispatcher_recording.get_record::<InboundClientData> (1).stopped_reading, false);
+        assert_eq! (dispatcher_recording.len (), 2);
+    }
+
+    #[test]
+    fn stream_reader_stops_reading_while_the_dispatcher_has_no_room () {
+        let socket_addr = SocketAddr::from_str ("1.2.3.4:5680").unwrap ();
+        let http_req = Vec::from ("GET http://here.com HTTP/1.1\r\n\r\n".as_bytes ());
+        let mut stream = TcpStreamWrapperMock::new ().peer_addr_result (Ok (socket_addr));
+        stream.read_results = (0..20).map (|_| (http_req.clone (), Ok (http_req.len ()))).collect ();
+        let stream_log = stream.get_test_log ();
+        let _system = System::new ("test");
+        // The Dispatcher's mailbox holds 16 messages, and it isn't running to take any of them out
+        let dispatcher_addr: Addr<Syn, Recorder> = Recorder::new ().start ();
+        let pool_addr: Addr<Syn, Recorder> = Recorder::new ().start ();
+        let mut subject = StreamReaderReal::new (Box::new (stream), Some (80), None,
+            dispatcher_addr.recipient (), pool_addr.recipient (), ReadGate::new (),
+            vec! (Box::new (HttpRequestDiscriminatorFactory::new ())), StreamClock::new (StreamTimeouts::none ()));
+
+        let results: Vec<Poll<(), ()>> = (0..3).map (|_| future::lazy (|| Ok::<_, ()> (subject.poll ())).wait ().unwrap ()).collect ();
+
+        assert_eq! (results, vec! (Ok (Async::NotReady), Ok (Async::NotReady), Ok (Async::NotReady)));
+        assert_eq! (stream_log.lock ().unwrap ().dump ().len (), 17);
+        assert_eq! (subject.to_dispatcher.is_empty (), false);
+    }
+
+    #[test]
+    fn stream_reader_stops_reading_while_its_read_gate_is_closed () {
+        let socket_addr = SocketAddr::from_str ("1.2.3.4:5681").unwrap ();
+        let http_req = Vec::from ("GET http://here.com HTTP/1.1\r\n\r\n".as_bytes ());
+        let mut stream = TcpStreamWrapperMock::new ().peer_addr_result (Ok (socket_addr));
+        stream.read_results = vec! (
+            (http_req.clone (), Ok (http_req.len ())),
+            (vec! (), Err (Error::from (ErrorKind::WouldBlock)))
+        );
+        let stream_log = stream.get_test_log ();
+        let _system = System::new ("test");
+        let dispatcher_addr: Addr<Syn, Recorder> = Recorder::new ().start ();
+        let pool_addr: Addr<Syn, Recorder> = Recorder::new ().start ();
+        let read_gate = ReadGate::new ();
+        let mut subject = StreamReaderReal::new (Box::new (stream), Some (80), None,
+            dispatcher_addr.recipient (), pool_addr.recipient (), read_gate.clone (),
+            vec! (Box::new (HttpRequestDiscriminatorFactory::new ())), StreamClock::new (StreamTimeouts::none ()));
+        read_gate.set_closed (true);
+
+        let closed_result = future::lazy (|| Ok::<_, ()> (subject.poll ())).wait ().unwrap ();
+        let reads_while_closed = stream_log.lock ().unwrap ().dump ().len ();
+        let reader_waiting = read_gate.inner.lock ().unwrap ().reader.is_some ();
+        read_gate.set_closed (false);
+        let open_result = future::lazy (|| Ok::<_, ()> (subject.poll ())).wait ().unwrap ();
+
+        assert_eq! (closed_result, Ok (Async::NotReady));
+        assert_eq! (reads_while_closed, 0);
+        assert_eq! (reader_waiting, true);
+        assert_eq! (read_gate.inner.lock ().unwrap ().reader.is_none (), true);
+        assert_eq! (open_result, Ok (Async::NotReady));
+        assert_eq! (stream_log.lock ().unwrap ().dump ().len (), 2);
+    }
+
+    #[test]
+    fn pause_reading_message_closes_and_opens_the_streams_read_gate () {
+        let socket_addr = SocketAddr::from_str ("1.2.3.4:5682").unwrap ();
+        let read_gate = ReadGate::new ();
+        let pool_read_gate = read_gate.clone ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new ("test");
+            let mut subject = StreamHandlerPool::new (HostnameMap::new (), StreamTimeouts::none ());
+            subject.read_gates.insert (socket_addr, pool_read_gate);
+            let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start ();
+            tx.send (subject_addr.recipient::<PauseReadingMsg> ()).unwrap ();
+            system.run ();
+        });
+        let pause_reading_sub = rx.recv ().unwrap ();
+
+        pause_reading_sub.try_send (PauseReadingMsg {socket_addr, paused: true}).unwrap ();
+        wait_until (|| read_gate.inner.lock ().unwrap ().closed);
+        pause_reading_sub.try_send (PauseReadingMsg {socket_addr, paused: false}).unwrap ();
+        wait_until (|| !read_gate.inner.lock ().unwrap ().closed);
+    }
+
+    #[test]
+    fn stream_reader_yields_after_each_read_error_and_closes_the_stream_when_they_keep_coming () {
+        init_test_logging ();
+        let socket_addr = SocketAddr::from_str ("1.2.3.4:5683").unwrap ();
+        let mut stream = TcpStreamWrapperMock::new ().peer_addr_result (Ok (socket_addr));
+        stream.read_results = (0..10).map (|_| (vec! (), Err (Error::from (ErrorKind::Other)))).collect ();
+        stream.shutdown_results = RefCell::new (vec! (Ok (())));
+        let stream_log = stream.get_test_log ();
+        let _system = System::new ("test");
+        let dispatcher_addr: Addr<Syn, Recorder> = Recorder::new ().start ();
+        let pool_addr: Addr<Syn, Recorder> = Recorder::new ().start ();
+        let mut subject = StreamReaderReal::new (Box::new (stream), Some (80), None,
+            dispatcher_addr.recipient (), pool_addr.recipient (), ReadGate::new (),
+            vec! (Box::new (HttpRequestDiscriminatorFactory::new ())), StreamClock::new (StreamTimeouts::none ()));
+
+        let mut poll = || future::lazy (|| Ok::<_, ()> (subject.poll ())).wait ().unwrap ();
+        let first_result = poll ();
+        let reads_after_first_poll = stream_log.lock ().unwrap ().dump ().len ();
+        let second_result = poll ();
+        let last_result = poll ();
+
+        assert_eq! (first_result, Ok (Async::NotReady));
+        assert_eq! (reads_after_first_poll, 1);
+        assert_eq! (second_result, Ok (Async::NotReady));
+        assert_eq! (last_result, Ok (Async::Ready (())));
+        assert_eq! (stream_log.lock ().unwrap ().dump (), vec! (
+            String::from ("read (65536-byte buf)"),
+            String::from ("read (65536-byte buf)"),
+            String::from ("read (65536-byte buf)"),
+            String::from ("shutdown (Both)")
+        ));
+        TestLogHandler::new ().exists_log_containing (&format! ("Dispatcher for 1.2.3.4:5683: Closing stream on port 6789 after {} read errors in a row",
+            MAX_READ_ERRORS_IN_A_ROW));
+    }
+
     #[test]
     fn a_newly_added_stream_produces_stream_handler_that_sends_received_data_to_dispatcher () {
         let dispatcher = Recorder::new ();
@@ -436,7 +936,7 @@ mod tests {
                 .peer_addr_result (Ok (socket_addr));
             let mut stream = TcpStreamWrapperMock::new();
             stream.try_clone_results = RefCell::new(vec!(Ok(Box::new(read_stream)), Ok(Box::new(write_stream))));
-            let subject = StreamHandlerPool::new();
+            let subject = StreamHandlerPool::new(HostnameMap::new (), StreamTimeouts::none ());
             let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start();
             let subject_subs = StreamHandlerPool::make_subs_from(&subject_addr);
             let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
@@ -456,32 +956,106 @@ mod tests {
         assert_eq! (dispatcher_recording.get_record::<dispatcher::InboundClientData> (0), &dispatcher::InboundClientData {
             socket_addr,
             origin_port,
+            target_hostname: None,
+            proxy_protocol: Some (ProxyProtocol::HTTP),
             last_data: false,
-            data: one_http_req_a
+            stopped_reading: false,
+            data: Bytes::from (one_http_req_a)
         });
         assert_eq! (dispatcher_recording.get_record::<dispatcher::InboundClientData> (1), &dispatcher::InboundClientData {
             socket_addr,
             origin_port,
+            target_hostname: None,
+            proxy_protocol: Some (ProxyProtocol::HTTP),
             last_data: false,
-            data: another_http_req_a
+            stopped_reading: false,
+            data: Bytes::from (another_http_req_a)
         });
         assert_eq! (dispatcher_recording.get_record::<dispatcher::InboundClientData> (2), &dispatcher::InboundClientData {
             socket_addr,
             origin_port,
+            target_hostname: None,
+            proxy_protocol: Some (ProxyProtocol::HTTP),
             last_data: false,
-            data: a_third_http_req_a
+            stopped_reading: false,
+            data: Bytes::from (a_third_http_req_a)
         });
         assert_eq! (dispatcher_recording.get_record::<dispatcher::InboundClientData> (3), &dispatcher::InboundClientData {
             socket_addr,
             origin_port,
+            target_hostname: None,
+            proxy_protocol: Some (ProxyProtocol::HTTP),
             last_data: true,
-            data: Vec::new ()
+            stopped_reading: true,
+            data: Bytes::new ()
         });
         assert_eq! (dispatcher_recording.len (), 4);
     }
 
     #[test]
-    fn receiving_0_bytes_from_existing_stream_removes_writer_and_shuts_down_stream () {
+    fn stream_to_mapped_address_carries_hostname_to_dispatcher () {
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording();
+        let awaiter = dispatcher.get_awaiter ();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let origin_port = Some (22);
+        let ssh_banner = Vec::from("SSH-2.0-OpenSSH_7.6\r\n".as_bytes());
+        let expected_ssh_banner = ssh_banner.clone ();
+        // TcpStreamWrapperMock always claims to be connected to 2.3.4.5
+        let hostname_map = HostnameMap::with_pool (Ipv4Addr::new (2, 3, 4, 5), 1);
+        hostname_map.address_for ("server.com");
+        let mut read_stream = TcpStreamWrapperMock::new()
+            .peer_addr_result (Ok(socket_addr));
+        read_stream.read_results = vec! (
+            (ssh_banner.clone(), Ok(ssh_banner.len ())),
+            (Vec::new (), Err(Error::from(ErrorKind::BrokenPipe)))
+        );
+        read_stream.shutdown_results = RefCell::new (vec! (Ok (())));
+        let write_stream = TcpStreamWrapperMock::new()
+            .peer_addr_result (Ok (socket_addr));
+        let mut stream = TcpStreamWrapperMock::new();
+        stream.try_clone_results = RefCell::new(vec!(Ok(Box::new(read_stream)), Ok(Box::new(write_stream))));
+        thread::spawn (move || {
+            let system = System::new("test");
+            let subject = StreamHandlerPool::new(hostname_map, StreamTimeouts::none ());
+            let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start();
+            let subject_subs = StreamHandlerPool::make_subs_from(&subject_addr);
+            let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
+
+            subject_subs.bind.try_send(PoolBindMessage { dispatcher_subs: peer_actors.dispatcher, stream_handler_pool_subs: subject_subs.clone ()}).unwrap ();
+            subject_subs.add_sub.try_send(AddStreamMsg {
+                stream: Box::new(stream),
+                origin_port,
+                discriminator_factories: vec! (Box::new (PassthroughDiscriminatorFactory::new ()))
+            }).unwrap ();
+
+            system.run ();
+        });
+
+        awaiter.await_message_count (2);
+        let dispatcher_recording = dispatcher_recording_arc.lock ().unwrap ();
+        assert_eq! (dispatcher_recording.get_record::<dispatcher::InboundClientData> (0), &dispatcher::InboundClientData {
+            socket_addr,
+            origin_port,
+            target_hostname: Some (String::from ("server.com")),
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: Bytes::from (expected_ssh_banner)
+        });
+        assert_eq! (dispatcher_recording.get_record::<dispatcher::InboundClientData> (1), &dispatcher::InboundClientData {
+            socket_addr,
+            origin_port,
+            target_hostname: Some (String::from ("server.com")),
+            proxy_protocol: None,
+            last_data: true,
+            stopped_reading: true,
+            data: Bytes::new ()
+        });
+    }
+
+    #[test]
+    fn receiving_0_bytes_from_existing_stream_ends_it_but_leaves_it_open_for_responses () {
         init_test_logging();
         let dispatcher = Recorder::new ();
         let dispatcher_recording = dispatcher.get_recording();
@@ -504,7 +1078,7 @@ mod tests {
         stream.try_clone_results = RefCell::new(vec!(Ok(Box::new(read_stream)), Ok(Box::new(write_stream))));
         thread::spawn (move || {
             let system = System::new("test");
-            let subject = StreamHandlerPool::new();
+            let subject = StreamHandlerPool::new(HostnameMap::new (), StreamTimeouts::none ());
             let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start();
             let subject_subs = StreamHandlerPool::make_subs_from(&subject_addr);
             let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
@@ -525,22 +1099,27 @@ mod tests {
         assert_eq! (recording.get_record::<dispatcher::InboundClientData> (0), &dispatcher::InboundClientData {
             socket_addr,
             origin_port,
+            target_hostname: None,
+            proxy_protocol: Some (ProxyProtocol::HTTP),
             last_data: false,
-            data: expected_http_req
+            stopped_reading: false,
+            data: Bytes::from (expected_http_req)
         });
         assert_eq! (recording.get_record::<dispatcher::InboundClientData> (1), &dispatcher::InboundClientData {
             socket_addr,
             origin_port,
+            target_hostname: None,
+            proxy_protocol: Some (ProxyProtocol::HTTP),
             last_data: true,
-            data: Vec::new()
+            stopped_reading: false,
+            data: Bytes::new ()
         });
         wait_until (|| {
-            read_stream_log_arc.lock ().unwrap ().dump ().len () == 3
+            read_stream_log_arc.lock ().unwrap ().dump ().len () == 2
         });
         assert_eq! (read_stream_log_arc.lock ().unwrap ().dump (), vec! (
             "read (65536-byte buf)",
-            "read (65536-byte buf)",
-            "shutdown (Both)"
+            "read (65536-byte buf)"
         ));
         TestLogHandler::new().exists_log_containing("DEBUG: Dispatcher for V4(1.2.3.4:5678): Stream on port 6789 has shut down (0-byte read)");
     }
@@ -569,7 +1148,7 @@ mod tests {
         stream.try_clone_results = RefCell::new(vec!(Ok(Box::new(read_stream)), Ok(Box::new(write_stream))));
         thread::spawn (move || {
             let system = System::new("test");
-            let subject = StreamHandlerPool::new();
+            let subject = StreamHandlerPool::new(HostnameMap::new (), StreamTimeouts::none ());
             let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start();
             let subject_subs = StreamHandlerPool::make_subs_from(&subject_addr);
             let peer_actors = make_peer_actors_from(None, Some(dispatcher), None, None, None);
@@ -591,8 +1170,11 @@ mod tests {
         assert_eq! (recording.get_record::<dispatcher::InboundClientData> (0), &dispatcher::InboundClientData {
             socket_addr,
             origin_port,
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
-            data: http_req_a
+            stopped_reading: false,
+            data: Bytes::from (http_req_a)
         });
     }
 
@@ -613,7 +1195,7 @@ mod tests {
         let (sub_tx, sub_rx) = mpsc::channel ();
         thread::spawn (move || {
             let system = System::new("test");
-            let subject = StreamHandlerPool::new();
+            let subject = StreamHandlerPool::new(HostnameMap::new (), StreamTimeouts::none ());
             let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start();
             let subject_subs = StreamHandlerPool::make_subs_from(&subject_addr);
             let peer_actors = make_peer_actors();
@@ -636,7 +1218,8 @@ mod tests {
         subject_subs.transmit_sub.try_send(TransmitDataMsg {
             endpoint: Endpoint::Socket(socket_addr),
             last_data: false,
-            data: vec!(0x12, 0x34)
+            stopped_reading: false,
+            data: Bytes::from (vec!(0x12, 0x34))
         }).unwrap ();
         TestLogHandler::new ().exists_no_log_matching("WARN.*1\\.2\\.3\\.4:5676.*Continuing after read error");
 
@@ -659,7 +1242,7 @@ mod tests {
             .peer_addr_result (Ok(socket_addr));
         let mut stream = TcpStreamWrapperMock::new();
         stream.try_clone_results = RefCell::new(vec!(Ok(Box::new(read_stream)), Ok(Box::new(write_stream))));
-        let subject = StreamHandlerPool::new();
+        let subject = StreamHandlerPool::new(HostnameMap::new (), StreamTimeouts::none ());
         let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start();
         let subject_subs = StreamHandlerPool::make_subs_from(&subject_addr);
         let peer_actors = make_peer_actors();
@@ -668,13 +1251,99 @@ mod tests {
         subject_subs.add_sub.try_send(AddStreamMsg {
             stream: Box::new(stream),
             origin_port: None,
-            discriminator_factories: vec! ()
+            discriminator_factories: vec! (Box::new (HttpRequestDiscriminatorFactory::new ()))
         }).unwrap ();
 
         subject_subs.transmit_sub.try_send(TransmitDataMsg {
             endpoint: Endpoint::Socket(socket_addr),
             last_data: false,
-            data: vec!(0x12, 0x34)
+            stopped_reading: false,
+            data: Bytes::from (vec!(0x12, 0x34))
+        }).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run ();
+        let write_stream_params = write_stream_params_arc.lock ().unwrap ();
+        TestLogHandler::new ().exists_no_log_matching("ERROR:.*1\\.2\\.3\\.4:5673");
+        assert_eq! (write_stream_params.deref (), &vec! (vec! (0x12, 0x34)));
+    }
+
+    #[test]
+    fn transmitting_faster_than_the_client_reads_is_reported_until_the_client_catches_up () {
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5674").unwrap();
+        let dispatcher = Recorder::new ();
+        let dispatcher_recording_arc = dispatcher.get_recording ();
+        let dispatcher_awaiter = dispatcher.get_awaiter ();
+        thread::spawn (move || {
+            let mut write_stream = TcpStreamWrapperMock::new()
+                .peer_addr_result (Ok (socket_addr));
+            // The client takes nothing at first, then everything
+            write_stream.write_results = vec! (Err (Error::from (ErrorKind::WouldBlock)), Ok (MAX_PENDING_BYTES));
+            let system = System::new("test");
+            let read_stream = TcpStreamWrapperMock::new()
+                .peer_addr_result (Ok(socket_addr));
+            let mut stream = TcpStreamWrapperMock::new();
+            stream.try_clone_results = RefCell::new(vec!(Ok(Box::new(read_stream)), Ok(Box::new(write_stream))));
+            let subject = StreamHandlerPool::new(HostnameMap::new (), StreamTimeouts::none ());
+            let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start();
+            let subject_subs = StreamHandlerPool::make_subs_from(&subject_addr);
+            let peer_actors = make_peer_actors_from (None, Some (dispatcher), None, None, None);
+            subject_subs.bind.try_send(PoolBindMessage { dispatcher_subs: peer_actors.dispatcher, stream_handler_pool_subs: subject_subs.clone ()}).unwrap ();
+            subject_subs.add_sub.try_send(AddStreamMsg {
+                stream: Box::new(stream),
+                origin_port: None,
+                discriminator_factories: vec! (Box::new (HttpRequestDiscriminatorFactory::new ()))
+            }).unwrap ();
+
+            subject_subs.transmit_sub.try_send(TransmitDataMsg {
+                endpoint: Endpoint::Socket(socket_addr),
+                last_data: false,
+                stopped_reading: false,
+                data: Bytes::from (vec! (0x12; MAX_PENDING_BYTES))
+            }).unwrap ();
+
+            system.run ();
+        });
+
+        dispatcher_awaiter.await_message_count (2);
+        let dispatcher_recording = dispatcher_recording_arc.lock ().unwrap ();
+        assert_eq! (dispatcher_recording.get_record::<WriteBacklogMsg> (0), &WriteBacklogMsg {socket_addr, backed_up: true});
+        assert_eq! (dispatcher_recording.get_record::<WriteBacklogMsg> (1), &WriteBacklogMsg {socket_addr, backed_up: false});
+        assert_eq! (dispatcher_recording.len (), 2);
+    }
+
+    #[test]
+    fn terminal_packet_is_transmitted_and_then_stream_is_shut_down_for_writing () {
+        init_test_logging();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5673").unwrap();
+        let mut write_stream = TcpStreamWrapperMock::new()
+            .peer_addr_result (Ok (socket_addr));
+        write_stream.write_results = vec! (Ok (2));
+        write_stream.shutdown_results = RefCell::new (vec! (Ok (())));
+        let write_stream_params_arc = write_stream.write_params.clone ();
+        let write_stream_log_arc = write_stream.get_test_log ();
+        let system = System::new("test");
+        let read_stream = TcpStreamWrapperMock::new()
+            .peer_addr_result (Ok(socket_addr));
+        let mut stream = TcpStreamWrapperMock::new();
+        stream.try_clone_results = RefCell::new(vec!(Ok(Box::new(read_stream)), Ok(Box::new(write_stream))));
+        let subject = StreamHandlerPool::new(HostnameMap::new (), StreamTimeouts::none ());
+        let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start();
+        let subject_subs = StreamHandlerPool::make_subs_from(&subject_addr);
+        let peer_actors = make_peer_actors();
+        subject_subs.bind.try_send(PoolBindMessage { dispatcher_subs: peer_actors.dispatcher, stream_handler_pool_subs: subject_subs.clone ()}).unwrap ();
+
+        subject_subs.add_sub.try_send(AddStreamMsg {
+            stream: Box::new(stream),
+            origin_port: None,
+            discriminator_factories: vec! (Box::new (HttpRequestDiscriminatorFactory::new ()))
+        }).unwrap ();
+
+        subject_subs.transmit_sub.try_send(TransmitDataMsg {
+            endpoint: Endpoint::Socket(socket_addr),
+            last_data: true,
+            stopped_reading: false,
+            data: Bytes::from (vec!(0x12, 0x34))
         }).unwrap ();
 
         Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
@@ -682,10 +1351,12 @@ mod tests {
         let write_stream_params = write_stream_params_arc.lock ().unwrap ();
         TestLogHandler::new ().exists_no_log_matching("ERROR:.*1\\.2\\.3\\.4:5673");
         assert_eq! (write_stream_params.deref (), &vec! (vec! (0x12, 0x34)));
+        let write_stream_log = write_stream_log_arc.lock ().unwrap ();
+        assert_eq! (write_stream_log.dump ().contains (&String::from ("shutdown (Write)")), true, "{:?}", write_stream_log.dump ());
     }
 
     #[test]
-    fn terminal_packet_is_transmitted_and_then_stream_is_shut_down () {
+    fn terminal_packet_from_a_server_that_stopped_reading_shuts_stream_down_entirely () {
         init_test_logging();
         let socket_addr = SocketAddr::from_str("1.2.3.4:5673").unwrap();
         let mut write_stream = TcpStreamWrapperMock::new()
@@ -699,7 +1370,7 @@ mod tests {
             .peer_addr_result (Ok(socket_addr));
         let mut stream = TcpStreamWrapperMock::new();
         stream.try_clone_results = RefCell::new(vec!(Ok(Box::new(read_stream)), Ok(Box::new(write_stream))));
-        let subject = StreamHandlerPool::new();
+        let subject = StreamHandlerPool::new(HostnameMap::new (), StreamTimeouts::none ());
         let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start();
         let subject_subs = StreamHandlerPool::make_subs_from(&subject_addr);
         let peer_actors = make_peer_actors();
@@ -708,13 +1379,14 @@ mod tests {
         subject_subs.add_sub.try_send(AddStreamMsg {
             stream: Box::new(stream),
             origin_port: None,
-            discriminator_factories: vec! ()
+            discriminator_factories: vec! (Box::new (HttpRequestDiscriminatorFactory::new ()))
         }).unwrap ();
 
         subject_subs.transmit_sub.try_send(TransmitDataMsg {
             endpoint: Endpoint::Socket(socket_addr),
             last_data: true,
-            data: vec!(0x12, 0x34)
+            stopped_reading: true,
+            data: Bytes::from (vec!(0x12, 0x34))
         }).unwrap ();
 
         Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
@@ -746,7 +1418,7 @@ mod tests {
 
         thread::spawn (move || {
             let system = System::new("test");
-            let subject = StreamHandlerPool::new();
+            let subject = StreamHandlerPool::new(HostnameMap::new (), StreamTimeouts::none ());
             let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start();
             let subject_subs = StreamHandlerPool::make_subs_from(&subject_addr);
             let peer_actors = make_peer_actors();
@@ -761,20 +1433,22 @@ mod tests {
         subject_subs.add_sub.try_send(AddStreamMsg {
             stream: Box::new(stream),
             origin_port: None,
-            discriminator_factories: vec! ()
+            discriminator_factories: vec! (Box::new (HttpRequestDiscriminatorFactory::new ()))
         }).unwrap ();
 
         subject_subs.transmit_sub.try_send(TransmitDataMsg {
             endpoint: Endpoint::Socket(socket_addr),
             last_data: false,
-            data: vec!(0x12, 0x34)
+            stopped_reading: false,
+            data: Bytes::from (vec!(0x12, 0x34))
         }).unwrap ();
         tlh.await_log_containing ("ERROR: Dispatcher for V4(1.2.3.4:5679): Cannot transmit 2 bytes: broken pipe", 5000);
 
         subject_subs.transmit_sub.try_send(TransmitDataMsg {
             endpoint: Endpoint::Socket(socket_addr),
             last_data: false,
-            data: vec!(0x12, 0x34)
+            stopped_reading: false,
+            data: Bytes::from (vec!(0x12, 0x34))
         }).unwrap ();
         tlh.await_log_containing ("ERROR: Dispatcher: Cannot transmit 2 bytes to V4(1.2.3.4:5679): nonexistent stream", 5000);
 
@@ -789,7 +1463,7 @@ mod tests {
         thread::spawn (move || {
             let system = System::new("test");
             let socket_addr = SocketAddr::from_str("1.2.3.4:5677").unwrap();
-            let subject = StreamHandlerPool::new();
+            let subject = StreamHandlerPool::new(HostnameMap::new (), StreamTimeouts::none ());
             let subject_addr: Addr<Syn, StreamHandlerPool> = subject.start();
             let subject_subs = StreamHandlerPool::make_subs_from(&subject_addr);
             let peer_actors = make_peer_actors();
@@ -801,7 +1475,8 @@ mod tests {
             subject_subs.transmit_sub.try_send(TransmitDataMsg {
                 endpoint: Endpoint::Socket(socket_addr),
                 last_data: false,
-                data: vec!(0x12, 0x34)
+                stopped_reading: false,
+                data: Bytes::from (vec!(0x12, 0x34))
             }).unwrap ();
 
             system.run();
@@ -1,4 +1,5 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use sub_lib::proxy_server::ProxyProtocol;
 use sub_lib::tls_framer::TlsFramer;
 use discriminator::Discriminator;
 use discriminator::DiscriminatorFactory;
@@ -17,6 +18,15 @@ impl DiscriminatorFactory for TlsDiscriminatorFactory {
     fn duplicate(&self) -> Box<DiscriminatorFactory> {
         Box::new (TlsDiscriminatorFactory{})
     }
+
+    // Every TLS connection starts with a handshake record
+    fn recognizes(&self, data: &[u8]) -> bool {
+        data.first () == Some (&0x16)
+    }
+
+    fn proxy_protocol(&self) -> Option<ProxyProtocol> {
+        Some (ProxyProtocol::TLS)
+    }
 }
 
 impl TlsDiscriminatorFactory {
@@ -39,6 +49,15 @@ mod tests {
         // no panic; test passes
     }
 
+    #[test]
+    fn recognizes_tls_handshake_but_not_http () {
+        let subject = TlsDiscriminatorFactory::new ();
+
+        assert_eq! (subject.recognizes (&[0x16, 0x03, 0x01]), true);
+        assert_eq! (subject.recognizes (b"GET / HTTP/1.1\r\n"), false);
+        assert_eq! (subject.proxy_protocol (), Some (ProxyProtocol::TLS));
+    }
+
     #[test]
     fn factory_makes_discriminator_that_can_handle_null_masking_for_proxy_server () {
         let data: &[u8] = &[0x16, 0x03, 0x03, 0x00, 0x01, 0xCA];
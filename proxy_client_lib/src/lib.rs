@@ -12,6 +12,8 @@ extern crate test_utils;
 
 pub mod proxy_client;
 pub mod resolver_wrapper;
+pub mod response_sender;
+pub mod return_paths;
 pub mod stream_handler_establisher;
 pub mod stream_handler_pool;
 pub mod stream_reader;
@@ -8,7 +8,7 @@ use std::net::Shutdown;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::thread;
+use std::sync::mpsc::Receiver;
 use std::time::Duration;
 use resolver_wrapper::ResolverWrapper;
 use resolver_wrapper::ResolverWrapperFactory;
@@ -19,13 +19,23 @@ use sub_lib::tcp_wrappers::TcpStreamWrapperFactory;
 use std::net::IpAddr;
 use tokio_core::reactor::Handle;
 use futures::future;
+use futures::Async;
+use futures::Poll;
+use futures::task;
 use trust_dns_resolver::error::ResolveError;
+use tokio_core::reactor::Core;
 use tokio_core::reactor::CoreId;
+use tokio_core::reactor::Remote;
 use trust_dns_resolver::lookup::Lookup;
 use trust_dns_proto::rr::RData;
 use std::io::ErrorKind;
 use resolver_wrapper::WrappedLookupIpFuture;
 
+// Anything spawned on this Remote is dropped, so it suits writes the stream takes right away
+pub fn make_remote () -> Remote {
+    Core::new ().unwrap ().remote ()
+}
+
 pub struct TcpStreamWrapperFactoryMock {
     tcp_stream_wrappers: Arc<Mutex<Vec<TcpStreamWrapperMock>>>
 }
@@ -56,15 +66,16 @@ impl TcpStreamWrapperFactoryMock {
 }
 
 struct TcpStreamWrapperMockResults {
+    connect_gate: Option<Receiver<()>>,
     connect_results: Vec<io::Result<()>>,
     try_clone_results: Vec<io::Result<Box<TcpStreamWrapper>>>,
     peer_addr_result: io::Result<SocketAddr>,
     write_results: Vec<io::Result<usize>>,
     read_buffers: Vec<Vec<u8>>,
     read_results: Vec<io::Result<usize>>,
-    read_delay: u64,
+    read_blocks: bool,
     shutdown_results: Vec<io::Result<()>>,
-    set_read_timeout_results: Vec<io::Result<()>>,
+    register_results: Vec<io::Result<()>>,
 }
 
 pub struct TcpStreamWrapperMock {
@@ -72,7 +83,7 @@ pub struct TcpStreamWrapperMock {
     connect_parameters: Arc<Mutex<Vec<SocketAddr>>>,
     write_parameters: Arc<Mutex<Vec<Vec<u8>>>>,
     shutdown_parameters: Arc<Mutex<Vec<Shutdown>>>,
-    set_read_timeout_parameters: Arc<Mutex<Vec<Option<Duration>>>>,
+    register_parameters: Arc<Mutex<Vec<CoreId>>>,
     results: Arc<Mutex<TcpStreamWrapperMockResults>>,
 }
 
@@ -83,7 +94,7 @@ impl Clone for TcpStreamWrapperMock {
             connect_parameters: self.connect_parameters.clone (),
             write_parameters: self.write_parameters.clone (),
             shutdown_parameters: self.shutdown_parameters.clone (),
-            set_read_timeout_parameters: self.set_read_timeout_parameters.clone (),
+            register_parameters: self.register_parameters.clone (),
             results: self.results.clone (),
         }
     }
@@ -92,6 +103,8 @@ impl Clone for TcpStreamWrapperMock {
 impl TcpStreamWrapper for TcpStreamWrapperMock {
     fn connect(&mut self, addr: SocketAddr) -> io::Result<()> {
         self.connect_parameters.lock ().unwrap ().push (addr);
+        let gate = self.results.lock ().unwrap ().connect_gate.take ();
+        if let Some (gate) = gate {gate.recv_timeout (Duration::from_secs (2)).is_ok ();}
         self.results.lock ().unwrap ().connect_results.remove (0)
     }
 
@@ -100,11 +113,6 @@ impl TcpStreamWrapper for TcpStreamWrapperMock {
         self.results.lock ().unwrap ().shutdown_results.remove (0)
     }
 
-    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
-        self.set_read_timeout_parameters.lock ().unwrap ().push (dur);
-        self.results.lock ().unwrap ().set_read_timeout_results.remove (0)
-    }
-
     fn try_clone(&self) -> io::Result<Box<TcpStreamWrapper>> {
         if self.mocked_try_clone {
             let mut guts = self.results.lock().unwrap();
@@ -123,7 +131,34 @@ impl TcpStreamWrapper for TcpStreamWrapperMock {
         }
     }
 
+    fn register(&mut self, handle: &Handle) -> io::Result<()> {
+        self.register_parameters.lock ().unwrap ().push (handle.id ());
+        self.results.lock ().unwrap ().register_results.remove (0)
+    }
+
+    // WouldBlock means there's nothing to read yet, but there will be right away
+    fn poll_read(&mut self, buf: &mut [u8]) -> Poll<usize, io::Error> {
+        if self.results.lock ().unwrap ().read_blocks {return Ok (Async::NotReady)}
+        match self.read (buf) {
+            Ok (length) => Ok (Async::Ready (length)),
+            Err (ref e) if e.kind () == ErrorKind::WouldBlock => {
+                task::current ().notify ();
+                Ok (Async::NotReady)
+            },
+            Err (e) => Err (e)
+        }
+    }
+
+    fn poll_write(&mut self, buf: &[u8]) -> Poll<usize, io::Error> {
+        match self.write (buf) {
+            Ok (length) => Ok (Async::Ready (length)),
+            Err (ref e) if e.kind () == ErrorKind::WouldBlock => Ok (Async::NotReady),
+            Err (e) => Err (e)
+        }
+    }
+
     fn local_addr(&self) -> io::Result<SocketAddr> { unimplemented!() }
+    fn set_read_timeout(&self, _dur: Option<Duration>) -> io::Result<()> { unimplemented!() }
     fn set_write_timeout(&self, _dur: Option<Duration>) -> io::Result<()> { unimplemented!() }
     fn read_timeout(&self) -> io::Result<Option<Duration>> { unimplemented!() }
     fn write_timeout(&self) -> io::Result<Option<Duration>> { unimplemented!() }
@@ -139,7 +174,6 @@ impl TcpStreamWrapper for TcpStreamWrapperMock {
 impl Read for TcpStreamWrapperMock {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let mut results = self.results.lock ().unwrap ();
-        thread::sleep (Duration::from_millis (results.read_delay));
         if results.read_buffers.len () > 0 {
             let chunk = results.read_buffers.remove(0);
             for index in 0..chunk.len() { buf[index] = chunk[index] }
@@ -171,21 +205,28 @@ impl TcpStreamWrapperMock {
             connect_parameters: Arc::new (Mutex::new (vec! ())),
             write_parameters: Arc::new (Mutex::new (vec! ())),
             shutdown_parameters: Arc::new (Mutex::new (vec! ())),
-            set_read_timeout_parameters: Arc::new (Mutex::new (vec! ())),
+            register_parameters: Arc::new (Mutex::new (vec! ())),
             results: Arc::new (Mutex::new (TcpStreamWrapperMockResults {
+                connect_gate: None,
                 connect_results: vec!(),
                 try_clone_results: vec!(),
                 peer_addr_result: Err (io::Error::from (ErrorKind::Other)),
                 read_buffers: vec!(),
                 read_results: vec!(),
-                read_delay: 0,
+                read_blocks: false,
                 write_results: vec!(),
                 shutdown_results: vec!(),
-                set_read_timeout_results: vec!(),
+                register_results: vec!(),
             }))
         }
     }
 
+    // The connection isn't made until something comes through the gate, or a couple of seconds pass
+    pub fn connect_gate (self, gate: Receiver<()>) -> TcpStreamWrapperMock {
+        self.results.lock ().unwrap ().connect_gate = Some (gate);
+        self
+    }
+
     pub fn connect_result (self, result: io::Result<()>) -> TcpStreamWrapperMock {
         self.results.lock ().unwrap ().connect_results.push (result);
         self
@@ -216,8 +257,9 @@ impl TcpStreamWrapperMock {
         self
     }
 
-    pub fn read_delay (self, milliseconds: u64) -> TcpStreamWrapperMock {
-        self.results.lock ().unwrap ().read_delay = milliseconds;
+    // The stream never has anything to read
+    pub fn read_blocks (self) -> TcpStreamWrapperMock {
+        self.results.lock ().unwrap ().read_blocks = true;
         self
     }
 
@@ -231,13 +273,13 @@ impl TcpStreamWrapperMock {
         self
     }
 
-    pub fn set_read_timeout_result (self, result: io::Result<()>) -> TcpStreamWrapperMock {
-        self.results.lock ().unwrap ().set_read_timeout_results.push (result);
+    pub fn register_result (self, result: io::Result<()>) -> TcpStreamWrapperMock {
+        self.results.lock ().unwrap ().register_results.push (result);
         self
     }
 
-    pub fn set_read_timeout_parameters (mut self, parameters: &Arc<Mutex<Vec<Option<Duration>>>>) -> TcpStreamWrapperMock {
-        self.set_read_timeout_parameters = parameters.clone ();
+    pub fn register_parameters (mut self, parameters: &Arc<Mutex<Vec<CoreId>>>) -> TcpStreamWrapperMock {
+        self.register_parameters = parameters.clone ();
         self
     }
 
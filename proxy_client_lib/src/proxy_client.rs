@@ -1,8 +1,10 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 use std::net::SocketAddr;
+use std::time::Duration;
 use actix::Actor;
 use actix::Addr;
 use actix::Arbiter;
+use actix::AsyncContext;
 use actix::Context;
 use actix::Handler;
 use actix::Recipient;
@@ -18,6 +20,8 @@ use sub_lib::hopper::IncipientCoresPackage;
 use sub_lib::logger::Logger;
 use sub_lib::peer_actors::BindMessage;
 use sub_lib::proxy_client::ProxyClientSubs;
+use sub_lib::send_window::RELIABILITY_TICK_MS;
+use sub_lib::stream_timeouts::StreamTimeouts;
 use sub_lib::tcp_wrappers::TcpStreamWrapperFactory;
 use sub_lib::tcp_wrappers::TcpStreamWrapperFactoryReal;
 use sub_lib::utils::NODE_MAILBOX_CAPACITY;
@@ -28,6 +32,7 @@ use trust_dns_resolver::config::ResolverOpts;
 
 pub struct ProxyClient {
     dns_servers: Vec<SocketAddr>,
+    stream_timeouts: StreamTimeouts,
     tcp_stream_wrapper_factory: Box<TcpStreamWrapperFactory>,
     resolver_wrapper_factory: Box<ResolverWrapperFactory>,
     stream_handler_pool_factory: Box<StreamHandlerPoolFactory>,
@@ -59,7 +64,8 @@ impl Handler<BindMessage> for ProxyClient {
         let opts = ResolverOpts::default ();
         let resolver = self.resolver_wrapper_factory.make(config, opts, Arbiter::handle ());
         self.pool = Some (self.stream_handler_pool_factory.make (resolver,
-                                                                 self._cryptde, msg.peer_actors.hopper.from_hopper_client));
+                                                                 self._cryptde, msg.peer_actors.hopper.from_hopper_client, self.stream_timeouts));
+        self.schedule_tick (ctx);
         ()
     }
 }
@@ -76,12 +82,13 @@ impl Handler<ExpiredCoresPackage> for ProxyClient {
 }
 
 impl ProxyClient {
-    pub fn new(cryptde: &'static CryptDE, dns_servers: Vec<SocketAddr>) -> ProxyClient {
+    pub fn new(cryptde: &'static CryptDE, dns_servers: Vec<SocketAddr>, stream_timeouts: StreamTimeouts) -> ProxyClient {
         if dns_servers.is_empty () {
             panic! ("Proxy Client requires at least one DNS server IP address after the --dns_servers parameter")
         }
         ProxyClient {
             dns_servers,
+            stream_timeouts,
             tcp_stream_wrapper_factory: Box::new(TcpStreamWrapperFactoryReal {}),
             resolver_wrapper_factory: Box::new (ResolverWrapperFactoryReal {}),
             stream_handler_pool_factory: Box::new (StreamHandlerPoolFactoryReal {}),
@@ -92,6 +99,14 @@ impl ProxyClient {
         }
     }
 
+    // The StreamHandlerPool can't keep time by itself, so it's reminded to check its streams
+    fn schedule_tick (&self, ctx: &mut Context<ProxyClient>) {
+        ctx.run_later (Duration::from_millis (RELIABILITY_TICK_MS), |proxy_client, ctx| {
+            if let Some (pool) = proxy_client.pool.as_mut () {pool.tick ()}
+            proxy_client.schedule_tick (ctx)
+        });
+    }
+
     pub fn make_subs_from(addr: &Addr<Syn, ProxyClient>) -> ProxyClientSubs {
         ProxyClientSubs {
             bind: addr.clone ().recipient::<BindMessage>(),
@@ -103,6 +118,7 @@ impl ProxyClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use sub_lib::cryptde::StreamKey;
     use std::cell::RefCell;
     use std::net::IpAddr;
     use std::net::Shutdown;
@@ -110,7 +126,7 @@ mod tests {
     use std::str::FromStr;
     use std::sync::Arc;
     use std::sync::Mutex;
-    use std::time::Duration;
+    use std::thread;
     use actix::Arbiter;
     use actix::msgs;
     use actix::Recipient;
@@ -140,21 +156,32 @@ mod tests {
 
     pub struct StreamHandlerPoolMock {
         process_package_parameters: Arc<Mutex<Vec<ExpiredCoresPackage>>>,
+        tick_count: Arc<Mutex<usize>>,
     }
 
     impl StreamHandlerPool for StreamHandlerPoolMock {
         fn process_package(&mut self, package: ExpiredCoresPackage) {
             self.process_package_parameters.lock ().unwrap ().push (package);
         }
+
+        fn tick (&mut self) {
+            *self.tick_count.lock ().unwrap () += 1;
+        }
     }
 
     impl StreamHandlerPoolMock {
         pub fn new () -> StreamHandlerPoolMock {
             StreamHandlerPoolMock {
                 process_package_parameters: Arc::new (Mutex::new (vec! ())),
+                tick_count: Arc::new (Mutex::new (0)),
             }
         }
 
+        pub fn tick_count (self, tick_count: &mut Arc<Mutex<usize>>) -> StreamHandlerPoolMock {
+            *tick_count = self.tick_count.clone ();
+            self
+        }
+
         pub fn process_package_parameters (self, parameters: &mut Arc<Mutex<Vec<ExpiredCoresPackage>>>) -> StreamHandlerPoolMock {
             *parameters = self.process_package_parameters.clone ();
             self
@@ -162,14 +189,14 @@ mod tests {
     }
 
     pub struct StreamHandlerPoolFactoryMock {
-        make_parameters: Arc<Mutex<Vec<(Box<ResolverWrapper>, &'static CryptDE, Recipient<Syn, IncipientCoresPackage>)>>>,
+        make_parameters: Arc<Mutex<Vec<(Box<ResolverWrapper>, &'static CryptDE, Recipient<Syn, IncipientCoresPackage>, StreamTimeouts)>>>,
         make_results: RefCell<Vec<Box<StreamHandlerPool>>>
     }
 
     impl StreamHandlerPoolFactory for StreamHandlerPoolFactoryMock {
         fn make(&self, resolver: Box<ResolverWrapper>, cryptde: &'static CryptDE,
-                hopper_sub: Recipient<Syn, IncipientCoresPackage>) -> Box<StreamHandlerPool> {
-            self.make_parameters.lock ().unwrap ().push ((resolver, cryptde, hopper_sub));
+                hopper_sub: Recipient<Syn, IncipientCoresPackage>, stream_timeouts: StreamTimeouts) -> Box<StreamHandlerPool> {
+            self.make_parameters.lock ().unwrap ().push ((resolver, cryptde, hopper_sub, stream_timeouts));
             self.make_results.borrow_mut ().remove (0)
         }
     }
@@ -183,7 +210,7 @@ mod tests {
         }
 
         pub fn make_parameters (self, parameters: &mut Arc<Mutex<Vec<(Box<ResolverWrapper>, &'static CryptDE,
-                Recipient<Syn, IncipientCoresPackage>)>>>) -> StreamHandlerPoolFactoryMock {
+                Recipient<Syn, IncipientCoresPackage>, StreamTimeouts)>>>) -> StreamHandlerPoolFactoryMock {
             *parameters = self.make_parameters.clone ();
             self
         }
@@ -197,7 +224,7 @@ mod tests {
     #[test]
     #[should_panic (expected = "Proxy Client requires at least one DNS server IP address after the --dns_servers parameter")]
     fn at_least_one_dns_server_must_be_provided () {
-        ProxyClient::new (cryptde(), vec! ());
+        ProxyClient::new (cryptde(), vec! (), StreamTimeouts::none ());
     }
 
     #[test]
@@ -217,7 +244,7 @@ mod tests {
         let mut subject = ProxyClient::new (cryptde(), vec! (
             SocketAddr::from_str ("4.3.2.1:4321").unwrap (),
             SocketAddr::from_str ("5.4.3.2:5432").unwrap ()
-        ));
+        ), StreamTimeouts {idle: Some (Duration::from_millis (1000)), lifetime: None});
         subject.resolver_wrapper_factory = Box::new (resolver_wrapper_factory);
         subject.stream_handler_pool_factory = Box::new (pool_factory);
         let subject_addr: Addr<Syn, ProxyClient> = subject.start();
@@ -237,15 +264,23 @@ mod tests {
         ]);
         assert_eq! (opts, ResolverOpts::default ());
         assert_eq! (new_parameters_guard.is_empty (), true);
+        let (_, _, _, stream_timeouts) = pool_factory_make_parameters.lock ().unwrap ().remove (0);
+        assert_eq! (stream_timeouts, StreamTimeouts {idle: Some (Duration::from_millis (1000)), lifetime: None});
     }
 
     #[test]
     #[should_panic (expected = "StreamHandlerPool unbound")]
     fn panics_if_unbound() {
+        let stream_key = StreamKey::new ();
         let response_data = Vec::from (&b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 29\r\n\r\nUser-agent: *\nDisallow: /deny"[..]);
         let request = ClientRequestPayload {
-            stream_key: SocketAddr::from_str ("1.2.3.4:56789").unwrap (),
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
             last_data: false,
+            stopped_reading: false,
             data: PlainData::new (b"HEAD http://www.nyan.cat/ HTTP/1.1\r\n\r\n"),
             target_hostname: Some (String::from("target.hostname.com")),
             target_port: 1234,
@@ -259,15 +294,13 @@ mod tests {
         );
         let mut connect_parameters: Arc<Mutex<Vec<SocketAddr>>> = Arc::new (Mutex::new (vec! ()));
         let mut write_parameters: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new (Mutex::new (vec! ()));
-        let mut set_read_timeout_parameters: Arc<Mutex<Vec<Option<Duration>>>> = Arc::new (Mutex::new (vec! ()));
         let mut shutdown_parameters: Arc<Mutex<Vec<Shutdown>>> = Arc::new (Mutex::new (vec! ()));
         let stream = TcpStreamWrapperMock::new ()
             .connect_result (Ok (()))
             .connect_parameters (&mut connect_parameters)
             .write_result (Ok (request.data.data.len ()))
             .write_parameters (&mut write_parameters)
-            .set_read_timeout_result (Ok (()))
-            .set_read_timeout_parameters (&mut set_read_timeout_parameters)
+            .register_result (Ok (()))
             .read_buffer (Vec::from (&response_data[0..40]))
             .read_result (Ok (40))
             .read_buffer (Vec::from (&response_data[40..]))
@@ -277,7 +310,7 @@ mod tests {
         let tcp_stream_wrapper_factory = TcpStreamWrapperFactoryMock::new ()
             .tcp_stream_wrapper (stream);
         let system = System::new("panics_if_hopper_is_unbound");
-        let mut subject = ProxyClient::new(cryptde, dnss ());
+        let mut subject = ProxyClient::new(cryptde, dnss (), StreamTimeouts::none ());
         subject.tcp_stream_wrapper_factory = Box::new(tcp_stream_wrapper_factory);
         let subject_addr: Addr<Syn, ProxyClient> = subject.start();
 
@@ -289,9 +322,15 @@ mod tests {
 
     #[test]
     fn data_from_hopper_is_relayed_to_stream_handler_pool () {
+        let stream_key = StreamKey::new ();
         let request = ClientRequestPayload {
-            stream_key: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
             last_data: false,
+            stopped_reading: false,
             data: PlainData::new (&b"inbound data"[..]),
             target_hostname: None,
             target_port: 0,
@@ -315,7 +354,7 @@ mod tests {
             .lookup_ip_success (vec! (IpAddr::from_str ("4.3.2.1").unwrap ()));
         let resolver_factory = ResolverWrapperFactoryMock::new ()
             .new_result (Box::new (resolver));
-        let mut subject = ProxyClient::new(cryptde(), dnss());
+        let mut subject = ProxyClient::new(cryptde(), dnss(), StreamTimeouts::none ());
         subject.resolver_wrapper_factory = Box::new (resolver_factory);
         subject.stream_handler_pool_factory = Box::new (pool_factory);
         let subject_addr: Addr<Syn, ProxyClient> = subject.start();
@@ -331,4 +370,28 @@ mod tests {
             payload: PlainData::new(&serde_cbor::ser::to_vec(&request.clone()).unwrap()[..]),
         });
     }
+
+    #[test]
+    fn stream_handler_pool_is_reminded_to_check_its_streams_after_bind () {
+        let mut tick_count = Arc::new (Mutex::new (0));
+        let pool = StreamHandlerPoolMock::new ()
+            .tick_count (&mut tick_count);
+        thread::spawn (move || {
+            let system = System::new ("stream_handler_pool_is_reminded_to_check_its_streams_after_bind");
+            let pool_factory = StreamHandlerPoolFactoryMock::new ()
+                .make_result (Box::new (pool));
+            let resolver_factory = ResolverWrapperFactoryMock::new ()
+                .new_result (Box::new (ResolverWrapperMock::new ()));
+            let mut subject = ProxyClient::new (cryptde (), dnss (), StreamTimeouts::none ());
+            subject.resolver_wrapper_factory = Box::new (resolver_factory);
+            subject.stream_handler_pool_factory = Box::new (pool_factory);
+            let subject_addr: Addr<Syn, ProxyClient> = subject.start ();
+            subject_addr.try_send (BindMessage {peer_actors: make_peer_actors ()}).unwrap ();
+            system.run ();
+        });
+
+        thread::sleep (Duration::from_millis (RELIABILITY_TICK_MS * 3 + RELIABILITY_TICK_MS / 2));
+
+        assert! (*tick_count.lock ().unwrap () >= 2, "Pool was reminded only {} times", *tick_count.lock ().unwrap ());
+    }
 }
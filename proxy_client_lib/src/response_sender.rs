@@ -0,0 +1,360 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::cmp::max;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Instant;
+use actix::Recipient;
+use actix::Syn;
+use futures::Async;
+use futures::task;
+use futures::task::Task;
+use sub_lib::cryptde::Key;
+use sub_lib::cryptde::PlainData;
+use sub_lib::cryptde::StreamKey;
+use sub_lib::hopper::IncipientCoresPackage;
+use sub_lib::outbox::Outbox;
+use sub_lib::proxy_client::ClientResponsePayload;
+use sub_lib::proxy_client::StreamFailure;
+use sub_lib::proxy_server::ClientRequestPayload;
+use sub_lib::route::Route;
+use sub_lib::send_window::MAX_TRANSMISSIONS;
+use sub_lib::send_window::SendWindow;
+use return_paths::ReturnPaths;
+
+/// Sends one stream's responses back to the originating Node, and keeps them until they're
+/// acknowledged so the ones that go missing can be sent again. The stream's StreamReader sends
+/// through it; the StreamHandlerPool tells it what the stream's requests have acknowledged, how
+/// many of them have arrived, and whether the last one has, and has it check regularly for
+/// overdue responses. Responses the Hopper has no room for wait here, and the StreamReader reads
+/// no more from the server until they've gone, or while too many responses are waiting for the
+/// originating Node to acknowledge the ones before them.
+#[derive (Clone)]
+pub struct ResponseSender {
+    inner: Arc<Mutex<ResponseSenderInner>>
+}
+
+struct ResponseSenderInner {
+    stream_key: StreamKey,
+    return_paths: ReturnPaths,
+    to_hopper: Outbox<IncipientCoresPackage>,
+    originator_public_key: Key,
+    window: SendWindow<ClientResponsePayload>,
+    // The StreamReader, if it's waiting for room in the window
+    reader: Option<Task>,
+    next_sequence_number: u64,
+    // How many of the stream's requests have arrived in order, and whether the originating Node still needs telling
+    ack: u64,
+    ack_owed: bool,
+    last_response_sent: bool,
+    last_request_arrived: bool,
+    server_stopped_reading: bool
+}
+
+impl ResponseSender {
+    pub fn new (stream_key: StreamKey, return_paths: ReturnPaths, hopper_sub: Recipient<Syn, IncipientCoresPackage>,
+                originator_public_key: Key) -> ResponseSender {
+        ResponseSender {
+            inner: Arc::new (Mutex::new (ResponseSenderInner {
+                stream_key,
+                return_paths,
+                to_hopper: Outbox::new (hopper_sub, "Hopper"),
+                originator_public_key,
+                window: SendWindow::new (),
+                reader: None,
+                next_sequence_number: 0,
+                ack: 0,
+                ack_owed: false,
+                last_response_sent: false,
+                last_request_arrived: false,
+                server_stopped_reading: false
+            }))
+        }
+    }
+
+    /// Takes note of a request that's just come in: the responses it acknowledges, and the path it
+    /// came over. Returns the paths that are no longer used; see ReturnPaths.
+    pub fn arrived (&self, payload: &ClientRequestPayload, route: &Route) -> Vec<u8> {
+        let mut inner = self.inner.lock ().expect ("ResponseSender poisoned");
+        let closed = inner.return_paths.arrived (payload, route);
+        for response in inner.window.acknowledge (payload.ack, Instant::now ()) {
+            inner.transmit (response)
+        }
+        if !inner.window.is_full () {
+            if let Some (reader) = inner.reader.take () {reader.notify ()}
+        }
+        closed
+    }
+
+    /// Every request numbered below next_sequence_number has arrived. The originating Node hears so
+    /// with the next response, or on the next tick if there isn't one by then.
+    pub fn received (&self, next_sequence_number: u64) {
+        let mut inner = self.inner.lock ().expect ("ResponseSender poisoned");
+        inner.ack = max (inner.ack, next_sequence_number);
+        inner.ack_owed = true;
+    }
+
+    /// The originating Node has sent everything it's going to send
+    pub fn last_request_arrived (&self) {
+        self.inner.lock ().expect ("ResponseSender poisoned").last_request_arrived = true;
+    }
+
+    /// The server won't take any more data, or there never was one; the last response says so
+    pub fn server_stopped_reading (&self) {
+        self.inner.lock ().expect ("ResponseSender poisoned").server_stopped_reading = true;
+    }
+
+    pub fn send (&self, data: PlainData, last_response: bool, failure: Option<StreamFailure>) {
+        let mut inner = self.inner.lock ().expect ("ResponseSender poisoned");
+        // Empty responses that don't end the stream would look like bare acknowledgements
+        if inner.last_response_sent || (data.data.is_empty () && !last_response) {return}
+        let sequence_number = inner.next_sequence_number;
+        inner.next_sequence_number += 1;
+        inner.last_response_sent = last_response;
+        let response = ClientResponsePayload {
+            stream_key: inner.stream_key,
+            sequence_number,
+            ack: 0, // these two are filled in when the response goes out
+            path_id: 0,
+            last_response,
+            stopped_reading: last_response && inner.server_stopped_reading,
+            data,
+            failure
+        };
+        for response in inner.window.send (sequence_number, response, Instant::now ()) {
+            inner.transmit (response)
+        }
+    }
+
+    /// Ready once the Hopper has taken everything sent so far and the window has room for more;
+    /// otherwise, the current task will be woken when it does
+    pub fn poll_flush (&self) -> Async<()> {
+        let mut inner = self.inner.lock ().expect ("ResponseSender poisoned");
+        if let Async::NotReady = inner.to_hopper.poll_flush () {return Async::NotReady}
+        if inner.window.is_full () {
+            inner.reader = Some (task::current ());
+            return Async::NotReady
+        }
+        Async::Ready (())
+    }
+
+    /// Resends the oldest unacknowledged response if it's overdue, and sends a bare acknowledgement
+    /// if one is owed. Returns an explanation if the originating Node seems to have stopped listening.
+    pub fn tick (&self, now: Instant) -> Result<(), String> {
+        let mut inner = self.inner.lock ().expect ("ResponseSender poisoned");
+        // Responses still waiting for the Hopper go first, even if the StreamReader is gone
+        inner.to_hopper.poll_flush ();
+        if let Some ((response, transmissions)) = inner.window.due (now) {
+            if transmissions > MAX_TRANSMISSIONS {
+                return Err (format! ("Response {} went unacknowledged after {} tries", response.sequence_number, MAX_TRANSMISSIONS))
+            }
+            inner.transmit (response);
+        }
+        if inner.ack_owed {
+            // Bare acknowledgements aren't numbered; they carry the number of the next response instead
+            let ack_only = ClientResponsePayload {
+                stream_key: inner.stream_key,
+                sequence_number: inner.next_sequence_number,
+                ack: 0,
+                path_id: 0,
+                last_response: false,
+                stopped_reading: false,
+                data: PlainData::new (&[]),
+                failure: None
+            };
+            inner.transmit (ack_only);
+        }
+        Ok (())
+    }
+
+    /// The last response has been sent, the originating Node has acknowledged everything, and it
+    /// has nothing more to send either, or nothing it sends would be read
+    pub fn is_finished (&self) -> bool {
+        let inner = self.inner.lock ().expect ("ResponseSender poisoned");
+        inner.last_response_sent && inner.window.is_empty () && inner.to_hopper.is_empty ()
+            && (inner.last_request_arrived || inner.server_stopped_reading)
+    }
+}
+
+impl ResponseSenderInner {
+    fn transmit (&mut self, mut response: ClientResponsePayload) {
+        let (path_id, route) = self.return_paths.next ();
+        response.path_id = path_id;
+        response.ack = self.ack;
+        self.ack_owed = false;
+        let package = IncipientCoresPackage::new (route, response, &self.originator_public_key);
+        self.to_hopper.send (package);
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use actix::System;
+    use futures::future;
+    use futures::Future;
+    use sub_lib::send_window::MAX_WAITING;
+    use sub_lib::proxy_server::ProxyProtocol;
+    use test_utils::test_utils;
+    use test_utils::test_utils::Recorder;
+
+    fn request (sequence_number: u64, ack: u64) -> ClientRequestPayload {
+        ClientRequestPayload {
+            stream_key: StreamKey::new (),
+            sequence_number,
+            ack,
+            path_id: 0,
+            open_paths: vec! (),
+            last_data: false,
+            stopped_reading: false,
+            data: PlainData::new (&[]),
+            target_hostname: None,
+            target_port: 0,
+            protocol: ProxyProtocol::Tunnel,
+            originator_public_key: Key::new (b"originator")
+        }
+    }
+
+    fn response (stream_key: StreamKey, sequence_number: u64, ack: u64, last_response: bool, data: &[u8]) -> IncipientCoresPackage {
+        IncipientCoresPackage::new (test_utils::make_meaningless_route (), ClientResponsePayload {
+            stream_key,
+            sequence_number,
+            ack,
+            path_id: 0,
+            last_response,
+            stopped_reading: false,
+            data: PlainData::new (data),
+            failure: None
+        }, &Key::new (b"originator"))
+    }
+
+    // Runs the closure against a ResponseSender whose packages go to a recording Hopper, then
+    // waits for that many packages to be recorded
+    fn with_subject<F, R> (stream_key: StreamKey, package_count: usize, f: F) -> (R, Vec<IncipientCoresPackage>)
+            where F: FnOnce (&ResponseSender) -> R + Send + 'static, R: Send + 'static {
+        let hopper = Recorder::new ();
+        let recording = hopper.get_recording ();
+        let awaiter = hopper.get_awaiter ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new ("test");
+            let hopper_sub = test_utils::make_peer_actors_from (None, None, Some (hopper), None, None).hopper.from_hopper_client;
+            let subject = ResponseSender::new (stream_key, ReturnPaths::new (0, test_utils::make_meaningless_route ()),
+                hopper_sub, Key::new (b"originator"));
+            tx.send (f (&subject)).unwrap ();
+            system.run ();
+        });
+        let result = rx.recv ().unwrap ();
+        awaiter.await_message_count (package_count);
+        let recording = recording.lock ().unwrap ();
+        (result, (0..recording.len ()).map (|index| recording.get_record::<IncipientCoresPackage> (index).clone ()).collect ())
+    }
+
+    #[test]
+    fn responses_carry_the_latest_acknowledgement_and_owed_ones_go_out_bare () {
+        let stream_key = StreamKey::new ();
+
+        let (_, packages) = with_subject (stream_key, 2, |subject| {
+            subject.received (3);
+            subject.send (PlainData::new (b"first"), false, None);
+            subject.tick (Instant::now ()).unwrap ();
+            subject.received (5);
+            subject.tick (Instant::now ()).unwrap ();
+        });
+
+        assert_eq! (packages, vec! (
+            response (stream_key, 0, 3, false, b"first"),
+            response (stream_key, 1, 5, false, b"")
+        ));
+    }
+
+    #[test]
+    fn unacknowledged_responses_are_resent_until_the_originator_acknowledges_them_and_finishes () {
+        let stream_key = StreamKey::new ();
+
+        let (finished, packages) = with_subject (stream_key, 3, |subject| {
+            let start = Instant::now ();
+            subject.send (PlainData::new (b"first"), false, None);
+            subject.send (PlainData::new (b"last"), true, None);
+            subject.tick (start + Duration::from_millis (1500)).unwrap ();
+            let finished_before_ack = subject.is_finished ();
+            subject.arrived (&request (0, 2), &test_utils::make_meaningless_route ());
+            subject.tick (start + Duration::from_millis (10000)).unwrap ();
+            let finished_before_last_request = subject.is_finished ();
+            subject.last_request_arrived ();
+            (finished_before_ack, finished_before_last_request, subject.is_finished ())
+        });
+
+        assert_eq! (finished, (false, false, true));
+        assert_eq! (packages, vec! (
+            response (stream_key, 0, 0, false, b"first"),
+            response (stream_key, 1, 0, true, b"last"),
+            response (stream_key, 0, 0, false, b"first")
+        ));
+    }
+
+    #[test]
+    fn the_stream_is_given_up_when_a_response_goes_unacknowledged_too_often () {
+        let stream_key = StreamKey::new ();
+
+        let (result, packages) = with_subject (stream_key, MAX_TRANSMISSIONS as usize, |subject| {
+            let mut now = Instant::now ();
+            subject.send (PlainData::new (b"first"), false, None);
+            for _ in 1..MAX_TRANSMISSIONS {
+                now += Duration::from_secs (60);
+                subject.tick (now).unwrap ();
+            }
+            subject.tick (now + Duration::from_secs (60))
+        });
+
+        assert_eq! (result, Err (format! ("Response 0 went unacknowledged after {} tries", MAX_TRANSMISSIONS)));
+        assert_eq! (packages.len (), MAX_TRANSMISSIONS as usize);
+    }
+
+    #[test]
+    fn the_stream_reader_is_held_back_while_too_many_responses_wait_for_the_window () {
+        let stream_key = StreamKey::new ();
+
+        let (results, _) = with_subject (stream_key, 4, |subject| {
+            let mut poll_flush = || future::lazy (|| Ok::<_, ()> (subject.poll_flush ())).wait ().unwrap ();
+            for _ in 0..(4 + MAX_WAITING - 1) {subject.send (PlainData::new (b"data"), false, None);}
+            let before_full = poll_flush ();
+            subject.send (PlainData::new (b"data"), false, None);
+            let when_full = poll_flush ();
+            let reader_waiting = subject.inner.lock ().unwrap ().reader.is_some ();
+            subject.arrived (&request (0, 1), &test_utils::make_meaningless_route ());
+            let reader_woken = subject.inner.lock ().unwrap ().reader.is_none ();
+            (before_full, when_full, reader_waiting, reader_woken, poll_flush ())
+        });
+
+        assert_eq! (results, (Async::Ready (()), Async::NotReady, true, true, Async::Ready (())));
+    }
+
+    #[test]
+    fn nothing_more_is_sent_after_the_last_response () {
+        let stream_key = StreamKey::new ();
+
+        let (finished, packages) = with_subject (stream_key, 1, |subject| {
+            subject.server_stopped_reading ();
+            subject.send (PlainData::new (b""), false, None);
+            subject.send (PlainData::new (b""), true, Some (StreamFailure::DnsFailure));
+            subject.send (PlainData::new (b"late"), false, None);
+            subject.arrived (&request (0, 1), &test_utils::make_meaningless_route ());
+            subject.is_finished ()
+        });
+
+        assert_eq! (finished, true);
+        assert_eq! (packages, vec! (IncipientCoresPackage::new (test_utils::make_meaningless_route (), ClientResponsePayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            last_response: true,
+            stopped_reading: true,
+            data: PlainData::new (&[]),
+            failure: Some (StreamFailure::DnsFailure)
+        }, &Key::new (b"originator"))));
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use sub_lib::multipath::DEFAULT_MAX_PATH_LAG;
+use sub_lib::multipath::PathMonitor;
+use sub_lib::proxy_server::ClientRequestPayload;
+use sub_lib::route::Route;
+
+/// The routes one stream's responses can take back to the originating Node, one for each path its
+/// requests have come over. The StreamHandlerPool learns them as requests arrive; the stream's
+/// ResponseSender spreads its responses across them.
+#[derive (Clone)]
+pub struct ReturnPaths {
+    inner: Arc<Mutex<ReturnPathsInner>>
+}
+
+struct ReturnPathsInner {
+    routes: BTreeMap<u8, Route>,
+    // Paths are never reopened once they've been closed
+    closed: Vec<u8>,
+    monitor: PathMonitor,
+    // The newest request whose list of open paths has been heeded; older lists are out of date
+    newest_open_paths: Option<u64>,
+    turn: usize
+}
+
+impl ReturnPaths {
+    pub fn new (path_id: u8, route: Route) -> ReturnPaths {
+        let mut routes = BTreeMap::new ();
+        routes.insert (path_id, route);
+        let mut monitor = PathMonitor::new (DEFAULT_MAX_PATH_LAG);
+        monitor.add_path (path_id);
+        ReturnPaths {
+            inner: Arc::new (Mutex::new (ReturnPathsInner {
+                routes,
+                closed: vec! (),
+                monitor,
+                newest_open_paths: None,
+                turn: 0
+            }))
+        }
+    }
+
+    /// Learns the way back along the path a request came over, and stops using the paths the
+    /// originating Node has closed or that have fallen behind the others. Returns the paths that
+    /// were closed. The last open path is never closed.
+    pub fn arrived (&self, payload: &ClientRequestPayload, route: &Route) -> Vec<u8> {
+        let mut inner = self.inner.lock ().expect ("ReturnPaths poisoned");
+        if !inner.closed.contains (&payload.path_id) && !inner.routes.contains_key (&payload.path_id) {
+            inner.routes.insert (payload.path_id, route.clone ());
+            inner.monitor.add_path (payload.path_id);
+        }
+        inner.monitor.arrived (payload.path_id, payload.sequence_number);
+        let mut to_close = inner.monitor.degraded_paths ();
+        // An empty request carries the number of the next one, which may share its list of open paths
+        if !payload.open_paths.is_empty () && (inner.newest_open_paths.map (|n| payload.sequence_number >= n).unwrap_or (true)) {
+            inner.newest_open_paths = Some (payload.sequence_number);
+            to_close.extend (inner.routes.keys ().filter (|path_id| !payload.open_paths.contains (path_id)));
+        }
+        let mut closed = vec! ();
+        for path_id in to_close {
+            if (inner.routes.len () > 1) && inner.routes.remove (&path_id).is_some () {
+                inner.monitor.remove_path (path_id);
+                inner.closed.push (path_id);
+                closed.push (path_id);
+            }
+        }
+        closed
+    }
+
+    /// The path for the next response, taking the open paths in turn
+    pub fn next (&self) -> (u8, Route) {
+        let mut inner = self.inner.lock ().expect ("ReturnPaths poisoned");
+        let index = inner.turn % inner.routes.len ();
+        inner.turn = index + 1;
+        let (path_id, route) = inner.routes.iter ().nth (index).expect ("Stream has no return paths");
+        (*path_id, route.clone ())
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use sub_lib::cryptde_null::CryptDENull;
+    use sub_lib::cryptde::Key;
+    use sub_lib::cryptde::PlainData;
+    use sub_lib::cryptde::StreamKey;
+    use sub_lib::dispatcher::Component;
+    use sub_lib::proxy_server::ProxyProtocol;
+    use sub_lib::route::RouteSegment;
+
+    fn route (name: &str) -> Route {
+        Route::new (vec! (RouteSegment::new (vec! (&Key::new (name.as_bytes ()), &Key::new (b"originator")), Component::ProxyServer)),
+            &CryptDENull::new ()).unwrap ()
+    }
+
+    fn request (sequence_number: u64, path_id: u8, open_paths: Vec<u8>) -> ClientRequestPayload {
+        ClientRequestPayload {
+            stream_key: StreamKey::new (),
+            sequence_number,
+            ack: 0,
+            path_id,
+            open_paths,
+            last_data: false,
+            stopped_reading: false,
+            data: PlainData::new (&[]),
+            target_hostname: None,
+            target_port: 0,
+            protocol: ProxyProtocol::Tunnel,
+            originator_public_key: Key::new (b"originator")
+        }
+    }
+
+    #[test]
+    fn responses_take_each_path_requests_have_come_over_in_turn () {
+        let subject = ReturnPaths::new (0, route ("zero"));
+        subject.arrived (&request (1, 1, vec! (0, 1)), &route ("one"));
+
+        let paths: Vec<(u8, Route)> = (0..3).map (|_| subject.next ()).collect ();
+
+        assert_eq! (paths, vec! ((0, route ("zero")), (1, route ("one")), (0, route ("zero"))));
+    }
+
+    #[test]
+    fn paths_the_originator_closes_are_not_used_or_relearned () {
+        let subject = ReturnPaths::new (0, route ("zero"));
+        subject.arrived (&request (1, 1, vec! (0, 1)), &route ("one"));
+
+        let closed = subject.arrived (&request (2, 0, vec! (0)), &route ("zero"));
+        subject.arrived (&request (3, 1, vec! ()), &route ("one"));
+
+        assert_eq! (closed, vec! (1));
+        assert_eq! (subject.next (), (0, route ("zero")));
+        assert_eq! (subject.next (), (0, route ("zero")));
+    }
+
+    #[test]
+    fn out_of_date_lists_of_open_paths_are_ignored () {
+        let subject = ReturnPaths::new (0, route ("zero"));
+        subject.arrived (&request (2, 1, vec! (0, 1)), &route ("one"));
+
+        let closed = subject.arrived (&request (1, 0, vec! (0)), &route ("zero"));
+
+        assert_eq! (closed, Vec::<u8>::new ());
+        assert_eq! (subject.next (), (0, route ("zero")));
+        assert_eq! (subject.next (), (1, route ("one")));
+    }
+
+    #[test]
+    fn paths_that_fall_behind_are_closed_but_never_the_last_one () {
+        let subject = ReturnPaths::new (0, route ("zero"));
+        subject.arrived (&request (1, 1, vec! ()), &route ("one"));
+
+        let closed: Vec<u8> = (2..(DEFAULT_MAX_PATH_LAG + 3))
+            .flat_map (|sequence_number| subject.arrived (&request (sequence_number, 0, vec! ()), &route ("zero")))
+            .collect ();
+        let still_open = subject.arrived (&request (DEFAULT_MAX_PATH_LAG + 3, 1, vec! (1)), &route ("one"));
+
+        assert_eq! (closed, vec! (1));
+        assert_eq! (still_open, Vec::<u8>::new ());
+        assert_eq! (subject.next (), (0, route ("zero")));
+    }
+}
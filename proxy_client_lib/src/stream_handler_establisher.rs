@@ -1,21 +1,30 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 use std::io;
 use std::io::Error;
+use std::io::ErrorKind;
 use std::net::IpAddr;
 use std::sync::mpsc::Sender;
 use std::thread;
+use actix::Arbiter;
 use actix::Recipient;
 use actix::Syn;
+use futures::future;
+use futures::sync::oneshot;
+use futures::Future;
 use trust_dns_resolver::error::ResolveError;
 use trust_dns_resolver::lookup_ip::LookupIp;
+use tokio_core::reactor::Handle;
+use response_sender::ResponseSender;
 use stream_handler_pool::StreamHandlerPoolReal;
 use stream_reader::StreamReader;
 use stream_writer::StreamWriter;
 use sub_lib::cryptde::StreamKey;
-use sub_lib::hopper::ExpiredCoresPackage;
 use sub_lib::hopper::IncipientCoresPackage;
 use sub_lib::logger::Logger;
+use sub_lib::proxy_client::StreamFailure;
 use sub_lib::proxy_server::ClientRequestPayload;
+use sub_lib::stream_timeouts::StreamClock;
+use sub_lib::stream_timeouts::StreamTimeouts;
 use sub_lib::tcp_wrappers::TcpStreamWrapper;
 use sub_lib::tcp_wrappers::TcpStreamWrapperFactory;
 
@@ -24,6 +33,7 @@ pub struct StreamHandlerEstablisher {
     pub hopper_sub: Recipient<Syn, IncipientCoresPackage>,
     pub stream_adder_tx: Sender<(StreamKey, StreamWriter)>,
     pub stream_killer_tx: Sender<StreamKey>,
+    pub stream_timeouts: StreamTimeouts,
     pub logger: Logger
 }
 
@@ -34,68 +44,94 @@ impl StreamHandlerEstablisher {
             hopper_sub: pool.hopper_sub.clone (),
             stream_adder_tx: pool.stream_adder_tx.clone (),
             stream_killer_tx: pool.stream_killer_tx.clone (),
+            stream_timeouts: pool.stream_timeouts,
             logger: Logger::new ("Proxy Client")
         }
     }
 
-    pub fn after_resolution (&mut self, payload: &ClientRequestPayload, package: &ExpiredCoresPackage, lookup_result: Result<LookupIp, ResolveError>) -> io::Result<StreamWriter> {
+    // Connecting takes as long as the server's side cares to make it, and the reactor has every
+    // other stream to look after meanwhile, so the connection is made on a thread of its own
+    pub fn connect (&mut self, payload: &ClientRequestPayload, lookup_result: Result<LookupIp, ResolveError>) -> Box<Future<Item=Box<TcpStreamWrapper>, Error=StreamFailure>> {
         let target_hostname = payload.target_hostname.clone ().expect ("Internal error: DNS resolution succeeded on missing hostname");
         let ip_addrs: Vec<IpAddr> = match lookup_result {
             Err (e) => {
                 self.logger.error (format! ("Could not find IP address for host {}: {}", target_hostname, e));
-                return Err (Error::from (e))
+                return Box::new (future::err (StreamFailure::DnsFailure))
             },
             Ok (lookup_ip) => lookup_ip.iter ().map (|x| x).collect ()
         };
         self.logger.debug (format! ("Found IP addresses for {}: {:?}", target_hostname, &ip_addrs));
-        let mut stored_write_stream = self.tcp_stream_wrapper_factory.make ();
-        match StreamHandlerPoolReal::connect_stream (&mut stored_write_stream, ip_addrs, &target_hostname, payload.target_port, &self.logger) {
-            Err (e) => return Err (e),
-            Ok (()) => ()
-        }
-        match stored_write_stream.set_read_timeout (None) {
+        let mut stream = self.tcp_stream_wrapper_factory.make ();
+        let target_port = payload.target_port;
+        let (tx, rx) = oneshot::channel ();
+        thread::spawn (move || {
+            let result = StreamHandlerPoolReal::connect_stream (&mut stream, ip_addrs, &target_hostname, target_port, &Logger::new ("Proxy Client"));
+            tx.send ((stream, result)).is_ok ();
+        });
+        Box::new (rx.then (|received| match received {
+            Ok ((stream, Ok (()))) => Ok (stream),
+            Ok ((_, Err (e))) => Err (StreamHandlerEstablisher::failure_from_connect_error (&e)),
+            Err (_) => Err (StreamFailure::ConnectionFailed)
+        }))
+    }
+
+    pub fn after_connection (&mut self, payload: &ClientRequestPayload, response_sender: &ResponseSender, mut stored_write_stream: Box<TcpStreamWrapper>) -> Result<StreamWriter, StreamFailure> {
+        let target_hostname = payload.target_hostname.clone ().expect ("Internal error: connected to missing hostname");
+        let handle = Arbiter::handle ();
+        match stored_write_stream.register (&handle) {
             Err (e) => {
                 let target = match stored_write_stream.peer_addr () {
                     Ok (s) => format! ("{}", s),
                     Err (_) => target_hostname.clone ()
                 };
-                self.logger.error (format! ("Could not set the read timeout for connection to {}", target));
-                return Err (e)
+                self.logger.error (format! ("Could not register connection to {} with the reactor: {}", target, e));
+                return Err (StreamFailure::ConnectionFailed)
             },
             Ok (()) => ()
         }
-        self.logger.debug (format! ("New stream set to block for reads"));
-        match self.spawn_stream_reader (package, payload, &stored_write_stream) {
-            Err (e) => return Err (e),
+        // Traffic either way keeps the stream alive, so the reader and the writer share a clock
+        let clock = StreamClock::new (self.stream_timeouts);
+        match self.spawn_stream_reader (response_sender, payload, &stored_write_stream, clock.clone (), &handle) {
+            Err (_) => return Err (StreamFailure::ConnectionFailed),
             Ok (_) => ()
         }
-        let stream_writer = StreamWriter::new (stored_write_stream);
+        let stream_writer = StreamWriter::new (stored_write_stream, handle.remote ().clone (), clock);
         let returned_write_stream = stream_writer.clone ();
         self.stream_adder_tx.send ((payload.stream_key, stream_writer)).expect("StreamHandlerPool died");
         Ok (returned_write_stream)
     }
 
-    fn spawn_stream_reader (&self, package: &ExpiredCoresPackage, payload: &ClientRequestPayload, write_stream: &Box<TcpStreamWrapper>) -> io::Result<()> {
-        let read_stream = match write_stream.try_clone () {
+    fn failure_from_connect_error (error: &Error) -> StreamFailure {
+        match error.kind () {
+            ErrorKind::ConnectionRefused => StreamFailure::ConnectionRefused,
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => StreamFailure::Timeout,
+            _ => StreamFailure::ConnectionFailed
+        }
+    }
+
+    fn spawn_stream_reader (&self, response_sender: &ResponseSender, payload: &ClientRequestPayload, write_stream: &Box<TcpStreamWrapper>,
+                            clock: StreamClock, handle: &Handle) -> io::Result<()> {
+        let mut read_stream = match write_stream.try_clone () {
             Err (e) => {self.logger.error (format! ("Could not clone stream: {}", e)); return Err (e)},
             Ok (s) => s
         };
-        let framer = StreamHandlerPoolReal::framer_from_protocol (payload.protocol);
         let peer_addr = match (&read_stream).peer_addr () {Ok (a) => format! ("{}", a), Err (_) => format! ("<unknown>")};
-        let mut stream_reader = StreamReader::new (
+        if let Err (e) = read_stream.register (handle) {
+            self.logger.error (format! ("Could not register stream from {} with the reactor: {}", peer_addr, e));
+            return Err (e)
+        }
+        let framer = StreamHandlerPoolReal::framer_from_protocol (payload.protocol);
+        let stream_reader = StreamReader::new (
             payload.stream_key,
-            self.hopper_sub.clone (),
             read_stream,
             self.stream_killer_tx.clone (),
             peer_addr.clone (),
-            package.remaining_route.clone (),
+            response_sender.clone (),
             framer,
-            payload.originator_public_key.clone (),
+            clock,
         );
-        self.logger.debug (format! ("Spawning StreamReader for {}", peer_addr));
-        thread::spawn(move || {
-            stream_reader.run();
-        });
+        self.logger.debug (format! ("Starting StreamReader for {}", peer_addr));
+        stream_reader.start (handle);
         Ok (())
     }
 }
@@ -104,7 +140,7 @@ impl StreamHandlerEstablisher {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::ErrorKind;
+    use sub_lib::cryptde::StreamKey;
     use std::net::SocketAddr;
     use std::str::FromStr;
     use std::sync::mpsc;
@@ -119,10 +155,12 @@ mod tests {
     use test_utils::test_utils::cryptde;
     use local_test_utils::TcpStreamWrapperMock;
     use local_test_utils::ResolverWrapperMock;
+    use return_paths::ReturnPaths;
 
 
     #[test]
     fn spawn_stream_reader_handles_http () {
+        let stream_key = StreamKey::new ();
         let hopper = Recorder::new ();
         let awaiter = hopper.get_awaiter ();
         let hopper_recording_arc = hopper.get_recording ();
@@ -132,29 +170,38 @@ mod tests {
             let hopper_sub = test_utils::make_peer_actors_from (None, None, Some (hopper), None, None).hopper.from_hopper_client;
             let read_stream = Box::new (TcpStreamWrapperMock::new ()
                 .peer_addr_result (Ok (SocketAddr::from_str ("1.2.3.4:5678").unwrap ()))
+                .register_result (Ok (()))
                 .read_buffer (vec! (0x16, 0x03, 0x03, 0x00, 0x00))
                 .read_result (Ok (5))
                 .read_buffer (b"HTTP/1.1 200 OK\r\n\r\n".to_vec ())
                 .read_result (Ok (19))
-                .read_result (Err (Error::from (ErrorKind::BrokenPipe))));
+                .read_result (Err (Error::from (ErrorKind::BrokenPipe)))
+                .shutdown_result (Ok (())));
             let stored_write_stream: Box<TcpStreamWrapper> = Box::new(TcpStreamWrapperMock::new ()
                 .try_clone_result (Ok (read_stream)));
             let pool = StreamHandlerPoolReal::new(Box::new(ResolverWrapperMock::new()),
-                                                  cryptde(), hopper_sub);
+                                                  cryptde(), hopper_sub, StreamTimeouts::none ());
             let subject = StreamHandlerEstablisher::new(&pool);
 
             let result = subject.spawn_stream_reader(
-                &ExpiredCoresPackage::new(test_utils::make_meaningless_route(), PlainData::new(&[])),
+                &ResponseSender::new (stream_key, ReturnPaths::new (0, test_utils::make_meaningless_route ()), pool.hopper_sub.clone (), Key::new (&[])),
                 &ClientRequestPayload {
-                    stream_key: SocketAddr::from_str("255.255.255.255:65535").unwrap(),
+                    stream_key,
+                    sequence_number: 0,
+                    ack: 0,
+                    path_id: 0,
+                    open_paths: vec! (),
                     last_data: false,
+                    stopped_reading: false,
                     data: PlainData::new(&[]),
                     target_hostname: Some("blah".to_string()),
                     target_port: 0,
                     protocol: ProxyProtocol::HTTP,
                     originator_public_key: Key::new(&[]),
                 },
-                &stored_write_stream
+                &stored_write_stream,
+                StreamClock::new (StreamTimeouts::none ()),
+                &Arbiter::handle ()
             );
             tx.send (result).is_ok ();
             system.run ();
@@ -170,6 +217,7 @@ mod tests {
 
     #[test]
     fn spawn_stream_reader_handles_tls () {
+        let stream_key = StreamKey::new ();
         let hopper = Recorder::new ();
         let awaiter = hopper.get_awaiter ();
         let hopper_recording_arc = hopper.get_recording ();
@@ -179,29 +227,38 @@ mod tests {
             let hopper_sub = test_utils::make_peer_actors_from (None, None, Some (hopper), None, None).hopper.from_hopper_client;
             let read_stream = Box::new (TcpStreamWrapperMock::new ()
                 .peer_addr_result (Ok (SocketAddr::from_str ("1.2.3.4:5678").unwrap ()))
+                .register_result (Ok (()))
                 .read_buffer (b"HTTP/1.1 200 OK\r\n\r\n".to_vec ())
                 .read_result (Ok (19))
                 .read_buffer (vec! (0x16, 0x03, 0x03, 0x00, 0x00))
                 .read_result (Ok (5))
-                .read_result (Err (Error::from (ErrorKind::BrokenPipe))));
+                .read_result (Err (Error::from (ErrorKind::BrokenPipe)))
+                .shutdown_result (Ok (())));
             let stored_write_stream: Box<TcpStreamWrapper> = Box::new(TcpStreamWrapperMock::new ()
                 .try_clone_result (Ok (read_stream)));
             let pool = StreamHandlerPoolReal::new(Box::new(ResolverWrapperMock::new()),
-                                                  cryptde(), hopper_sub);
+                                                  cryptde(), hopper_sub, StreamTimeouts::none ());
             let subject = StreamHandlerEstablisher::new(&pool);
 
             let result = subject.spawn_stream_reader(
-                &ExpiredCoresPackage::new(test_utils::make_meaningless_route(), PlainData::new(&[])),
+                &ResponseSender::new (stream_key, ReturnPaths::new (0, test_utils::make_meaningless_route ()), pool.hopper_sub.clone (), Key::new (&[])),
                 &ClientRequestPayload {
-                    stream_key: SocketAddr::from_str("255.255.255.255:65535").unwrap(),
+                    stream_key,
+                    sequence_number: 0,
+                    ack: 0,
+                    path_id: 0,
+                    open_paths: vec! (),
                     last_data: false,
+                    stopped_reading: false,
                     data: PlainData::new(&[]),
                     target_hostname: None,
                     target_port: 0,
                     protocol: ProxyProtocol::TLS,
                     originator_public_key: Key::new(&[]),
                 },
-                &stored_write_stream
+                &stored_write_stream,
+                StreamClock::new (StreamTimeouts::none ()),
+                &Arbiter::handle ()
             );
             tx.send (result).is_ok ();
             system.run ();
@@ -214,4 +271,69 @@ mod tests {
         assert_eq! (response.last_response, false);
         assert_eq! (response.data.data, vec! (0x16, 0x03, 0x03, 0x00, 0x00));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn spawn_stream_reader_handles_tunnel () {
+        let stream_key = StreamKey::new ();
+        let hopper = Recorder::new ();
+        let awaiter = hopper.get_awaiter ();
+        let hopper_recording_arc = hopper.get_recording ();
+        let (tx, rx) = mpsc::channel::<io::Result<()>> ();
+        thread::spawn(move || {
+            let system = System::new ("test");
+            let hopper_sub = test_utils::make_peer_actors_from (None, None, Some (hopper), None, None).hopper.from_hopper_client;
+            let read_stream = Box::new (TcpStreamWrapperMock::new ()
+                .peer_addr_result (Ok (SocketAddr::from_str ("1.2.3.4:5678").unwrap ()))
+                .register_result (Ok (()))
+                .read_buffer (b"SSH-2.0-OpenSSH_7.6\r\n".to_vec ())
+                .read_result (Ok (21))
+                .read_result (Err (Error::from (ErrorKind::BrokenPipe)))
+                .shutdown_result (Ok (())));
+            let stored_write_stream: Box<TcpStreamWrapper> = Box::new(TcpStreamWrapperMock::new ()
+                .try_clone_result (Ok (read_stream)));
+            let pool = StreamHandlerPoolReal::new(Box::new(ResolverWrapperMock::new()),
+                                                  cryptde(), hopper_sub, StreamTimeouts::none ());
+            let subject = StreamHandlerEstablisher::new(&pool);
+
+            let result = subject.spawn_stream_reader(
+                &ResponseSender::new (stream_key, ReturnPaths::new (0, test_utils::make_meaningless_route ()), pool.hopper_sub.clone (), Key::new (&[])),
+                &ClientRequestPayload {
+                    stream_key,
+                    sequence_number: 0,
+                    ack: 0,
+                    path_id: 0,
+                    open_paths: vec! (),
+                    last_data: false,
+                    stopped_reading: false,
+                    data: PlainData::new(&[]),
+                    target_hostname: None,
+                    target_port: 0,
+                    protocol: ProxyProtocol::Tunnel,
+                    originator_public_key: Key::new(&[]),
+                },
+                &stored_write_stream,
+                StreamClock::new (StreamTimeouts::none ()),
+                &Arbiter::handle ()
+            );
+            tx.send (result).is_ok ();
+            system.run ();
+        });
+        rx.recv ().unwrap ().expect ("spawn_stream_reader () failed");
+        awaiter.await_message_count (1);
+        let hopper_recording = hopper_recording_arc.lock ().unwrap ();
+        let record = hopper_recording.get_record::<IncipientCoresPackage> (0);
+        let response = serde_cbor::de::from_slice::<ClientResponsePayload> (&record.payload.data[..]).unwrap ();
+        assert_eq! (response.last_response, false);
+        assert_eq! (response.data.data, b"SSH-2.0-OpenSSH_7.6\r\n".to_vec ());
+    }
+
+    #[test]
+    fn connection_errors_are_classified_for_the_originating_node () {
+        let classify = |kind| StreamHandlerEstablisher::failure_from_connect_error (&Error::from (kind));
+
+        assert_eq! (classify (ErrorKind::ConnectionRefused), StreamFailure::ConnectionRefused);
+        assert_eq! (classify (ErrorKind::TimedOut), StreamFailure::Timeout);
+        assert_eq! (classify (ErrorKind::WouldBlock), StreamFailure::Timeout);
+        assert_eq! (classify (ErrorKind::AddrNotAvailable), StreamFailure::ConnectionFailed);
+    }
+}
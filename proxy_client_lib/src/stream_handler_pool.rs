@@ -10,6 +10,8 @@ use std::net::SocketAddr;
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
 use std::sync::mpsc::Receiver;
+use std::time::Duration;
+use std::time::Instant;
 use actix::Arbiter;
 use futures::future::Executor;
 use futures::future::Future;
@@ -22,15 +24,21 @@ use sub_lib::hopper::IncipientCoresPackage;
 use sub_lib::http_packet_framer::HttpPacketFramer;
 use sub_lib::http_response_start_finder::HttpResponseStartFinder;
 use sub_lib::logger::Logger;
-use sub_lib::proxy_client::ClientResponsePayload;
+use sub_lib::proxy_client::StreamFailure;
 use sub_lib::proxy_server::ClientRequestPayload;
 use sub_lib::proxy_server::ProxyProtocol;
-use sub_lib::route::Route;
+use sub_lib::reorder_buffer::DEFAULT_MAX_REORDER_BYTES;
+use sub_lib::reorder_buffer::default_reorder_gap_timeout;
+use sub_lib::reorder_buffer::ReorderBuffer;
 use sub_lib::tcp_wrappers::TcpStreamWrapper;
 use sub_lib::tcp_wrappers::TcpStreamWrapperFactory;
 use sub_lib::tcp_wrappers::TcpStreamWrapperFactoryReal;
+use sub_lib::passthrough_framer::PassthroughFramer;
 use sub_lib::tls_framer::TlsFramer;
+use sub_lib::stream_timeouts::StreamTimeouts;
 use resolver_wrapper::ResolverWrapper;
+use response_sender::ResponseSender;
+use return_paths::ReturnPaths;
 use stream_writer::StreamWriter;
 use stream_handler_establisher::StreamHandlerEstablisher;
 use std::net::Shutdown;
@@ -39,16 +47,27 @@ use actix::Syn;
 
 pub trait StreamHandlerPool {
     fn process_package (&mut self, package: ExpiredCoresPackage);
+    // Called every RELIABILITY_TICK_MS to resend overdue responses, send owed acknowledgements,
+    // and give up on requests that have been missing too long
+    fn tick (&mut self);
 }
 
 pub struct StreamHandlerPoolReal {
     pub hopper_sub: Recipient<Syn, IncipientCoresPackage>,
     pub stream_writers: HashMap<StreamKey, StreamWriter>,
+    reorder_buffers: HashMap<StreamKey, ReorderBuffer<ClientRequestPayload>>,
+    reorder_gap_timeout: Duration,
+    response_senders: HashMap<StreamKey, ResponseSender>,
+    held_acks: HashMap<StreamKey, u64>,
+    // Streams whose servers are still being looked up and connected to, with the payloads that
+    // arrived for them meanwhile
+    establishing: HashMap<StreamKey, Vec<ClientRequestPayload>>,
     pub stream_adder_tx: Sender<(StreamKey, StreamWriter)>,
     pub stream_adder_rx: Receiver<(StreamKey, StreamWriter)>,
     pub stream_killer_tx: Sender<StreamKey>,
     pub stream_killer_rx: Receiver<StreamKey>,
     pub tcp_stream_wrapper_factory: Box<TcpStreamWrapperFactory>,
+    pub stream_timeouts: StreamTimeouts,
     resolver: Box<ResolverWrapper>,
     _cryptde: &'static CryptDE, // This is not used now, but a version of it may be used in the future when ser/de and en/decrypt are combined.
     logger: Logger,
@@ -66,46 +85,160 @@ impl StreamHandlerPool for StreamHandlerPoolReal {
                 return
             }
         };
-        let hopper_sub = self.hopper_sub.clone ();
+        let stream_key = payload.stream_key;
+        let response_sender = self.note_arrival (&payload, &package);
+        // Empty requests that don't end the stream aren't numbered; they only carry acknowledgements and news of paths
+        if payload.data.data.is_empty () && !payload.last_data && !payload.stopped_reading {return}
+        let ready = {
+            let size = payload.data.data.len ();
+            let reorder_gap_timeout = self.reorder_gap_timeout;
+            let reorder_buffer = self.reorder_buffers.entry (stream_key).or_insert_with (|| {
+                ReorderBuffer::new (DEFAULT_MAX_REORDER_BYTES, reorder_gap_timeout)
+            });
+            reorder_buffer.add (payload.sequence_number, payload, size, Instant::now ())
+        };
+        match ready {
+            Ok (ready) => {
+                for payload in ready {
+                    self.process_payload (payload)
+                }
+                // Requests seen before are acknowledged again, in case the last acknowledgement went missing
+                let next_sequence_number = self.reorder_buffers.get (&stream_key).map (|b| b.next_sequence_number ()).unwrap_or (0);
+                self.acknowledge (stream_key, &response_sender, next_sequence_number);
+            },
+            Err (msg) => {
+                self.logger.error (format! ("Abandoning stream {}: {}", stream_key, msg));
+                self.abandon_stream (stream_key)
+            }
+        }
+    }
+
+    // A stream is forgotten once its last response has been acknowledged and its last request has
+    // arrived, unless the server stopped reading, in which case there's no waiting for requests
+    fn tick (&mut self) {
+        self.do_housekeeping ();
+        let now = Instant::now ();
+        let mut finished = vec! ();
+        let mut abandoned = vec! ();
+        self.release_held_acks ();
+        for (stream_key, response_sender) in self.response_senders.iter () {
+            match response_sender.tick (now) {
+                Err (msg) => abandoned.push ((*stream_key, msg)),
+                Ok (()) => if response_sender.is_finished () {finished.push (*stream_key)}
+            }
+        }
+        for (stream_key, reorder_buffer) in self.reorder_buffers.iter () {
+            if let Err (msg) = reorder_buffer.expire (now) {abandoned.push ((*stream_key, msg))}
+        }
+        for stream_key in finished {
+            self.reorder_buffers.remove (&stream_key);
+            self.response_senders.remove (&stream_key);
+            self.held_acks.remove (&stream_key);
+            self.establishing.remove (&stream_key);
+            self.stream_writers.remove (&stream_key);
+        }
+        for (stream_key, msg) in abandoned {
+            self.logger.error (format! ("Abandoning stream {}: {}", stream_key, msg));
+            self.abandon_stream (stream_key)
+        }
+    }
+}
+
+impl StreamHandlerPoolReal {
+    pub fn new (resolver: Box<ResolverWrapper>, cryptde: &'static CryptDE, hopper_sub: Recipient<Syn, IncipientCoresPackage>,
+                stream_timeouts: StreamTimeouts) -> StreamHandlerPoolReal {
+        let (stream_killer_tx, stream_killer_rx) = mpsc::channel ();
+        let (stream_adder_tx, stream_adder_rx) = mpsc::channel ();
+        StreamHandlerPoolReal {
+            hopper_sub,
+            stream_writers: HashMap::new (),
+            reorder_buffers: HashMap::new (),
+            reorder_gap_timeout: default_reorder_gap_timeout (),
+            response_senders: HashMap::new (),
+            held_acks: HashMap::new (),
+            establishing: HashMap::new (),
+            stream_adder_tx,
+            stream_adder_rx,
+            stream_killer_tx,
+            stream_killer_rx,
+            tcp_stream_wrapper_factory: Box::new (TcpStreamWrapperFactoryReal {}),
+            stream_timeouts,
+            resolver,
+            _cryptde: cryptde,
+            logger: Logger::new ("Proxy Client")
+        }
+    }
+
+    // Requests may come over any of the stream's paths, and each one shows the way back along its path.
+    // The StreamReader shares the stream's ResponseSender, so a path learned after the stream opens is
+    // used for its responses too, and the responses a request acknowledges are no longer kept for it.
+    fn note_arrival (&mut self, payload: &ClientRequestPayload, package: &ExpiredCoresPackage) -> ResponseSender {
+        let hopper_sub = &self.hopper_sub;
+        let response_sender = self.response_senders.entry (payload.stream_key).or_insert_with (|| {
+            ResponseSender::new (payload.stream_key, ReturnPaths::new (payload.path_id, package.remaining_route.clone ()),
+                hopper_sub.clone (), payload.originator_public_key.clone ())
+        }).clone ();
+        for path_id in response_sender.arrived (payload, &package.remaining_route) {
+            self.logger.info (format! ("No longer sending responses for stream {} over path {}", payload.stream_key, path_id));
+        }
+        response_sender
+    }
+
+    fn process_payload (&mut self, payload: ClientRequestPayload) {
+        let response_sender = self.response_senders.get (&payload.stream_key).cloned ().expect ("Stream has no ResponseSender");
+        if payload.last_data {response_sender.last_request_arrived ()}
         let mut establisher = StreamHandlerEstablisher::new (self);
-        let mut stream_writer_ref_opt = self.stream_writers.get_mut (&payload.stream_key);
-        match stream_writer_ref_opt {
-            Some (ref mut writer_ref) => {
+        let write_result = match self.stream_writers.get_mut (&payload.stream_key) {
+            Some (writer_ref) => {
                 self.logger.debug (format! ("Writing {} bytes to {} over existing stream", payload.data.data.len (), writer_ref.peer_addr ()));
-                match StreamHandlerPoolReal::perform_write (&payload, writer_ref) {
-                    Ok (_) => (),
-                    Err (_) => {
-                        StreamHandlerPoolReal::send_terminating_package(package.remaining_route, &payload, &hopper_sub)
-                    }
-                }
+                Some (StreamHandlerPoolReal::perform_write (&payload, writer_ref))
+            },
+            None => None
+        };
+        match write_result {
+            Some (Ok (_)) => (),
+            // The server may already have started answering, so it's the stream's StreamReader that
+            // ends it; that way the last response is numbered after all the others
+            Some (Err (_)) => {
+                response_sender.server_stopped_reading ();
+                self.close_stream (payload.stream_key)
+            },
+            // Later payloads wait for the first one to open the stream, rather than opening more
+            None if self.establishing.contains_key (&payload.stream_key) => {
+                self.logger.debug (format! ("Stream {} is still being opened; holding {}-byte payload", payload.stream_key, payload.data.data.len ()));
+                self.establishing.get_mut (&payload.stream_key).expect ("Stream disappeared").push (payload)
             },
             None => {
-                // TODO: Figure out what to do if a flurry of requests for a particular stream key
-                // come flooding in so densely that several of them arrive in the time it takes to
-                // resolve the first one and add it to the stream_writers map.
                 self.logger.debug (format! ("No stream to {:?} exists; resolving host", &payload.target_hostname));
                 let mut fqdn = match &payload.target_hostname {
                     &None => {
                         self.logger.error (format! ("Cannot open new stream with key {}: no hostname supplied", payload.stream_key));
-                        StreamHandlerPoolReal::send_terminating_package(package.remaining_route, &payload, &hopper_sub);
+                        StreamHandlerPoolReal::send_terminating_package (&response_sender, StreamFailure::MissingHostname);
                         return
                     },
                     &Some (ref s) => s.clone ()
                 };
                 fqdn.push('.');
+                self.establishing.insert (payload.stream_key, vec! ());
                 let future = self.resolver.lookup_ip(&fqdn[..]).then(move |lookup_result| {
                     establisher.logger.debug (format! ("Resolution closure beginning"));
-                    let write_result = establisher.after_resolution (&payload, &package, lookup_result).and_then (|mut stream_writer| {
-                        StreamHandlerPoolReal::perform_write (&payload, &mut stream_writer)
-                    });
-                    match write_result {
-                        Ok (_) => (),
-                        Err (_) => {
-                            StreamHandlerPoolReal::send_terminating_package(package.remaining_route, &payload, &establisher.hopper_sub)
+                    establisher.connect (&payload, lookup_result).then (move |connect_result| {
+                        let write_result = connect_result.and_then (|stream| {
+                            establisher.after_connection (&payload, &response_sender, stream)
+                        }).and_then (|mut stream_writer| {
+                            StreamHandlerPoolReal::perform_write (&payload, &mut stream_writer).map_err (|_| StreamFailure::ConnectionFailed)
+                        });
+                        match write_result {
+                            Ok (_) => (),
+                            Err (failure) => {
+                                StreamHandlerPoolReal::send_terminating_package (&response_sender, failure);
+                                // The payloads waiting for the stream have nowhere to go
+                                establisher.stream_killer_tx.send (payload.stream_key).is_ok ();
+                            }
                         }
-                    }
-                    let result: Result<(), ()> = Ok (());
-                    result
+                        let result: Result<(), ()> = Ok (());
+                        result
+                    })
                 });
                 self.logger.debug (format! ("Host resolution scheduled"));
                 Arbiter::handle ().execute (future).expect ("Actix executor failed for TRustDNSResolver");
@@ -113,29 +246,59 @@ impl StreamHandlerPool for StreamHandlerPoolReal {
             }
         }
     }
-}
 
-impl StreamHandlerPoolReal {
-    pub fn new (resolver: Box<ResolverWrapper>, cryptde: &'static CryptDE, hopper_sub: Recipient<Syn, IncipientCoresPackage>) -> StreamHandlerPoolReal {
-        let (stream_killer_tx, stream_killer_rx) = mpsc::channel ();
-        let (stream_adder_tx, stream_adder_rx) = mpsc::channel ();
-        StreamHandlerPoolReal {
-            hopper_sub,
-            stream_writers: HashMap::new (),
-            stream_adder_tx,
-            stream_adder_rx,
-            stream_killer_tx,
-            stream_killer_rx,
-            tcp_stream_wrapper_factory: Box::new (TcpStreamWrapperFactoryReal {}),
-            resolver,
-            _cryptde: cryptde,
-            logger: Logger::new ("Proxy Client")
+    // Requests that have arrived are acknowledged only once they've been written to the server and
+    // the server is keeping up with them. Until then the originating Node's send window fills, and
+    // once it's full its client isn't read.
+    fn acknowledge (&mut self, stream_key: StreamKey, response_sender: &ResponseSender, next_sequence_number: u64) {
+        if self.holds_acks (&stream_key) {
+            self.held_acks.insert (stream_key, next_sequence_number);
+        }
+        else {
+            self.held_acks.remove (&stream_key);
+            response_sender.received (next_sequence_number);
+        }
+    }
+
+    fn release_held_acks (&mut self) {
+        let released: Vec<(StreamKey, u64)> = self.held_acks.iter ()
+            .filter (|&(stream_key, _)| !self.holds_acks (stream_key))
+            .map (|(stream_key, next_sequence_number)| (*stream_key, *next_sequence_number))
+            .collect ();
+        for (stream_key, next_sequence_number) in released {
+            self.held_acks.remove (&stream_key);
+            if let Some (response_sender) = self.response_senders.get (&stream_key) {
+                response_sender.received (next_sequence_number)
+            }
+        }
+    }
+
+    // The payload that opens a stream is written as soon as the stream is open, but those waiting
+    // behind it aren't written until the next housekeeping
+    fn holds_acks (&self, stream_key: &StreamKey) -> bool {
+        self.establishing.get (stream_key).map (|waiting| !waiting.is_empty ()).unwrap_or (false) ||
+            self.stream_writers.get (stream_key).map (|w| w.is_backed_up ()).unwrap_or (false)
+    }
+
+    fn abandon_stream (&mut self, stream_key: StreamKey) {
+        self.reorder_buffers.remove (&stream_key);
+        self.response_senders.remove (&stream_key);
+        self.held_acks.remove (&stream_key);
+        self.establishing.remove (&stream_key);
+        self.close_stream (stream_key)
+    }
+
+    // Shutting the stream down wakes its StreamReader, which sends the stream's last response
+    fn close_stream (&mut self, stream_key: StreamKey) {
+        if let Some (mut writer) = self.stream_writers.remove (&stream_key) {
+            writer.shutdown (Shutdown::Both).is_ok ();
         }
     }
 
+    // A stream that dies right after it's opened is added before it's cleaned up
     fn do_housekeeping (&mut self) {
-        self.clean_up_dead_streams ();
         self.add_new_streams ();
+        self.clean_up_dead_streams ();
     }
 
     fn clean_up_dead_streams (&mut self) {
@@ -143,6 +306,7 @@ impl StreamHandlerPoolReal {
             match self.stream_killer_rx.try_recv () {
                 Err (_) => break,
                 Ok (stream_key) => {
+                    self.establishing.remove (&stream_key);
                     match self.stream_writers.remove (&stream_key) {
                         Some (writer_ref) => self.logger.debug (format! ("Killed StreamWriter for stream to {} under key {}", writer_ref.peer_addr (), stream_key)),
                         None => self.logger.debug (format! ("Tried to kill StreamWriter for key {}, but it was not found", stream_key))
@@ -158,7 +322,11 @@ impl StreamHandlerPoolReal {
                 Err (_) => break,
                 Ok ((stream_key, stream_writer)) => {
                     self.logger.debug (format! ("Persisting StreamWriter to {} under key {}", stream_writer.peer_addr (), stream_key));
-                    self.stream_writers.insert (stream_key, stream_writer)
+                    self.stream_writers.insert (stream_key, stream_writer);
+                    // Whatever arrived while the stream was being opened follows the first payload, in order
+                    for payload in self.establishing.remove (&stream_key).unwrap_or (vec! ()) {
+                        self.process_payload (payload)
+                    }
                 }
             };
         }
@@ -167,7 +335,7 @@ impl StreamHandlerPoolReal {
     fn extract_payload (&self, package: &ExpiredCoresPackage) -> io::Result<ClientRequestPayload> {
         match package.payload::<ClientRequestPayload> () {
             Err(e) => {
-                self.logger.error(format!("Error ('{}') interpreting payload for transmission: {:?}", e, package.payload.data));
+                self.logger.error(format!("Error ('{}') interpreting payload for transmission: {:?}", e, &package.payload.data[..]));
                 Err (Error::from (ErrorKind::Other))
             },
             Ok(payload) => Ok (payload)
@@ -186,11 +354,12 @@ impl StreamHandlerPoolReal {
                 Ok (())
             }
         }.and_then (|_count| {
-            if payload_ref.last_data {
-                writer_ref.shutdown (Shutdown::Both)
-            }
-            else {
-                Ok (())
+            // Shutting down the reading side wakes the stream's StreamReader, which sends the last response
+            match (payload_ref.last_data, payload_ref.stopped_reading) {
+                (true, true) => writer_ref.shutdown (Shutdown::Both),
+                (true, false) => writer_ref.shutdown (Shutdown::Write),
+                (false, true) => writer_ref.shutdown (Shutdown::Read),
+                (false, false) => Ok (())
             }
         })
     }
@@ -219,19 +388,16 @@ impl StreamHandlerPoolReal {
     pub fn framer_from_protocol (protocol: ProxyProtocol) -> Box<Framer> {
         match protocol {
             ProxyProtocol::HTTP => Box::new (HttpPacketFramer::new (Box::new (HttpResponseStartFinder{}))),
-            ProxyProtocol::TLS => Box::new (TlsFramer::new ())
+            ProxyProtocol::TLS => Box::new (TlsFramer::new ()),
+            ProxyProtocol::Tunnel => Box::new (PassthroughFramer::new ())
         }
     }
 
-    fn send_terminating_package(route: Route, request: &ClientRequestPayload, hopper_sub: &Recipient<Syn, IncipientCoresPackage>) {
-        let response = ClientResponsePayload {
-            stream_key: request.stream_key,
-            last_response: true,
-            data: PlainData::new (&[]),
-        };
-        let package = IncipientCoresPackage::new (route, response,
-            &request.originator_public_key);
-        hopper_sub.try_send (package).expect("Hopper died");
+    // The originating Node explains the failure to the client, if its protocol has a way to. Only
+    // streams that never got as far as a StreamReader end this way, so this is their first response.
+    fn send_terminating_package (response_sender: &ResponseSender, failure: StreamFailure) {
+        response_sender.server_stopped_reading ();
+        response_sender.send (PlainData::new (&[]), true, Some (failure));
     }
 }
 
@@ -239,26 +405,29 @@ impl StreamHandlerPoolReal {
 
 pub trait StreamHandlerPoolFactory {
     fn make (&self, resolver: Box<ResolverWrapper>, cryptde: &'static CryptDE,
-        hopper_sub: Recipient<Syn, IncipientCoresPackage>) -> Box<StreamHandlerPool>;
+        hopper_sub: Recipient<Syn, IncipientCoresPackage>, stream_timeouts: StreamTimeouts) -> Box<StreamHandlerPool>;
 }
 
 pub struct StreamHandlerPoolFactoryReal {}
 
 impl StreamHandlerPoolFactory for StreamHandlerPoolFactoryReal {
     fn make(&self, resolver: Box<ResolverWrapper>, cryptde: &'static CryptDE,
-            hopper_sub: Recipient<Syn, IncipientCoresPackage>) -> Box<StreamHandlerPool> {
-        Box::new(StreamHandlerPoolReal::new (resolver, cryptde, hopper_sub))
+            hopper_sub: Recipient<Syn, IncipientCoresPackage>, stream_timeouts: StreamTimeouts) -> Box<StreamHandlerPool> {
+        Box::new(StreamHandlerPoolReal::new (resolver, cryptde, hopper_sub, stream_timeouts))
     }
 }
 
 #[cfg (test)]
 mod tests {
     use super::*;
+    use sub_lib::cryptde::StreamKey;
+    use sub_lib::stream_timeouts::StreamClock;
     use std::net::IpAddr;
     use std::ops::Deref;
     use std::str::FromStr;
     use std::sync::Arc;
     use std::sync::Mutex;
+    use std::sync::mpsc;
     use std::thread;
     use actix::System;
     use serde_cbor;
@@ -266,7 +435,9 @@ mod tests {
     use trust_dns_resolver::error::ResolveErrorKind;
     use sub_lib::cryptde::Key;
     use sub_lib::hopper::ExpiredCoresPackage;
+    use sub_lib::proxy_client::ClientResponsePayload;
     use sub_lib::proxy_server::ProxyProtocol;
+    use sub_lib::route::Route;
     use test_utils::test_utils;
     use test_utils::test_utils::init_test_logging;
     use test_utils::test_utils::Recorder;
@@ -275,7 +446,13 @@ mod tests {
     use local_test_utils::ResolverWrapperMock;
     use local_test_utils::TcpStreamWrapperFactoryMock;
     use local_test_utils::TcpStreamWrapperMock;
+    use local_test_utils::make_remote;
     use std::net::Shutdown;
+    use tokio_core::reactor::Core;
+    use futures::future;
+    use futures::task;
+    use futures::Async;
+    use sub_lib::write_queue::MAX_PENDING_BYTES;
 
     #[test]
     fn invalid_package_is_logged_and_discarded () {
@@ -289,7 +466,7 @@ mod tests {
             let package = ExpiredCoresPackage::new (test_utils::make_meaningless_route (),
                 PlainData::new (&b"invalid"[..]));
             let mut subject = StreamHandlerPoolReal::new (Box::new (ResolverWrapperMock::new ()),
-                                                         cryptde(), hopper_sub);
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
 
             subject.process_package(package);
 
@@ -302,9 +479,15 @@ mod tests {
 
     #[test]
     fn non_terminal_payload_can_be_sent_over_existing_connection () {
+        let stream_key = StreamKey::new ();
         let client_request_payload = ClientRequestPayload {
-            stream_key: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
             last_data: false,
+            stopped_reading: false,
             data: PlainData::new (&b"These are the times"[..]),
             target_hostname: None,
             target_port: 80,
@@ -325,9 +508,9 @@ mod tests {
             .write_result (Ok (123))
             .shutdown_parameters (&mut shutdown_parameters);
         let mut subject = StreamHandlerPoolReal::new (Box::new (ResolverWrapperMock::new ()),
-                                                      cryptde(), hopper_sub);
+                                                      cryptde(), hopper_sub, StreamTimeouts::none ());
         subject.stream_writers.insert (client_request_payload.stream_key,
-                                       StreamWriter::new (Box::new (write_stream)));
+                                       StreamWriter::new (Box::new (write_stream), make_remote (), StreamClock::new (StreamTimeouts::none ())));
 
         subject.process_package(package);
 
@@ -335,11 +518,213 @@ mod tests {
         assert_eq! (shutdown_parameters.lock ().unwrap ().len (), 0);
     }
 
+    fn package_for (stream_key: StreamKey, sequence_number: u64, data: &[u8]) -> ExpiredCoresPackage {
+        package_on_path (stream_key, sequence_number, 0, test_utils::make_meaningless_route (), data)
+    }
+
+    fn package_on_path (stream_key: StreamKey, sequence_number: u64, path_id: u8, remaining_route: Route, data: &[u8]) -> ExpiredCoresPackage {
+        let client_request_payload = ClientRequestPayload {
+            stream_key,
+            sequence_number,
+            ack: 0,
+            path_id,
+            open_paths: vec! (),
+            last_data: false,
+            stopped_reading: false,
+            data: PlainData::new (data),
+            target_hostname: None,
+            target_port: 80,
+            protocol: ProxyProtocol::HTTP,
+            originator_public_key: Key::new (&b"men's souls"[..])
+        };
+        ExpiredCoresPackage::new (remaining_route,
+                                  PlainData::new (&(serde_cbor::ser::to_vec (&client_request_payload).unwrap ())[..]))
+    }
+
+    fn bare_ack (stream_key: StreamKey, path_id: u8, route: Route, ack: u64) -> IncipientCoresPackage {
+        IncipientCoresPackage::new (route, ClientResponsePayload {
+            stream_key,
+            sequence_number: 0,
+            ack,
+            path_id,
+            last_response: false,
+            stopped_reading: false,
+            data: PlainData::new (&[]),
+            failure: None
+        }, &Key::new (&b"men's souls"[..]))
+    }
+
+    #[test]
+    fn empty_payloads_that_do_not_end_the_stream_are_not_written_but_open_their_paths () {
+        let stream_key = StreamKey::new ();
+        let hopper = Recorder::new ();
+        let awaiter = hopper.get_awaiter ();
+        let recording = hopper.get_recording ();
+        let mut write_parameters = Arc::new (Mutex::new (vec! ()));
+        let write_stream = TcpStreamWrapperMock::new ()
+            .peer_addr_result (Err (Error::from (ErrorKind::AddrInUse)))
+            .write_parameters (&mut write_parameters)
+            .write_result (Ok (4))
+            .write_result (Ok (3));
+        let other_route = test_utils::route_to_proxy_server (&Key::new (&b"men's souls"[..]), cryptde ());
+        let other_route_inner = other_route.clone ();
+        thread::spawn (move || {
+            let system = System::new ("test");
+            let hopper_sub =
+                test_utils::make_peer_actors_from(None, None, Some (hopper), None, None).hopper.from_hopper_client;
+            let mut subject = StreamHandlerPoolReal::new (Box::new (ResolverWrapperMock::new ()),
+                                                          cryptde(), hopper_sub, StreamTimeouts::none ());
+            subject.stream_writers.insert (stream_key, StreamWriter::new (Box::new (write_stream), make_remote (), StreamClock::new (StreamTimeouts::none ())));
+
+            subject.process_package (package_for (stream_key, 0, b"zero"));
+            // Empty payloads carry the number of the next one
+            subject.process_package (package_on_path (stream_key, 1, 1, other_route_inner, b""));
+            subject.process_package (package_for (stream_key, 1, b"one"));
+            subject.tick ();
+            // A payload seen before is acknowledged again
+            subject.process_package (package_for (stream_key, 1, b"one"));
+            subject.tick ();
+
+            system.run ();
+        });
+
+        awaiter.await_message_count (2);
+        assert_eq! (*write_parameters.lock ().unwrap (), vec! (b"zero".to_vec (), b"one".to_vec ()));
+        let recording = recording.lock ().unwrap ();
+        assert_eq! (recording.get_record::<IncipientCoresPackage> (0), &bare_ack (stream_key, 0, test_utils::make_meaningless_route (), 2));
+        assert_eq! (recording.get_record::<IncipientCoresPackage> (1), &bare_ack (stream_key, 1, other_route, 2));
+    }
+
+    #[test]
+    fn requests_are_not_acknowledged_while_the_server_is_behind_on_them () {
+        let stream_key = StreamKey::new ();
+        let hopper = Recorder::new ();
+        let awaiter = hopper.get_awaiter ();
+        let recording = hopper.get_recording ();
+        let mut write_parameters = Arc::new (Mutex::new (vec! ()));
+        let write_stream = TcpStreamWrapperMock::new ()
+            .peer_addr_result (Err (Error::from (ErrorKind::AddrInUse)))
+            .write_parameters (&mut write_parameters)
+            .write_result (Err (Error::from (ErrorKind::WouldBlock)))
+            .write_result (Ok (MAX_PENDING_BYTES))
+            .write_result (Ok (3));
+        thread::spawn (move || {
+            let system = System::new ("test");
+            let mut core = Core::new ().unwrap ();
+            let hopper_sub =
+                test_utils::make_peer_actors_from(None, None, Some (hopper), None, None).hopper.from_hopper_client;
+            let mut subject = StreamHandlerPoolReal::new (Box::new (ResolverWrapperMock::new ()),
+                                                          cryptde(), hopper_sub, StreamTimeouts::none ());
+            subject.stream_writers.insert (stream_key, StreamWriter::new (Box::new (write_stream), core.remote (), StreamClock::new (StreamTimeouts::none ())));
+
+            subject.process_package (package_for (stream_key, 0, &vec! (b'x'; MAX_PENDING_BYTES)[..]));
+            subject.tick ();
+            subject.process_package (package_for (stream_key, 1, b"one"));
+            while subject.stream_writers.get (&stream_key).unwrap ().is_backed_up () {
+                core.turn (Some (Duration::from_millis (10)));
+            }
+            subject.tick ();
+
+            system.run ();
+        });
+
+        awaiter.await_message_count (1);
+        assert_eq! (write_parameters.lock ().unwrap ()[2], b"one".to_vec ());
+        let recording = recording.lock ().unwrap ();
+        assert_eq! (recording.get_record::<IncipientCoresPackage> (0), &bare_ack (stream_key, 0, test_utils::make_meaningless_route (), 2));
+    }
+
+    #[test]
+    fn payloads_that_arrive_out_of_order_are_written_in_order () {
+        let stream_key = StreamKey::new ();
+        let _system = System::new("test");
+        let hopper_sub =
+            test_utils::make_peer_actors_from(None, None, Some (Recorder::new ()), None, None).hopper.from_hopper_client;
+        let mut write_parameters = Arc::new (Mutex::new (vec! ()));
+        let write_stream = TcpStreamWrapperMock::new ()
+            .peer_addr_result (Err (Error::from (ErrorKind::AddrInUse)))
+            .write_parameters (&mut write_parameters)
+            .write_result (Ok (4))
+            .write_result (Ok (3))
+            .write_result (Ok (3));
+        let mut subject = StreamHandlerPoolReal::new (Box::new (ResolverWrapperMock::new ()),
+                                                      cryptde(), hopper_sub, StreamTimeouts::none ());
+        subject.stream_writers.insert (stream_key, StreamWriter::new (Box::new (write_stream), make_remote (), StreamClock::new (StreamTimeouts::none ())));
+
+        subject.process_package (package_for (stream_key, 2, b"two"));
+        subject.process_package (package_for (stream_key, 1, b"one"));
+        subject.process_package (package_for (stream_key, 1, b"one again"));
+        assert_eq! (write_parameters.lock ().unwrap ().len (), 0);
+        subject.process_package (package_for (stream_key, 0, b"zero"));
+
+        assert_eq! (*write_parameters.lock ().unwrap (), vec! (b"zero".to_vec (), b"one".to_vec (), b"two".to_vec ()));
+    }
+
+    #[test]
+    fn stream_that_holds_too_much_behind_a_missing_payload_is_abandoned () {
+        init_test_logging();
+        let stream_key = StreamKey::new ();
+        let _system = System::new("test");
+        let hopper_sub =
+            test_utils::make_peer_actors_from(None, None, Some (Recorder::new ()), None, None).hopper.from_hopper_client;
+        let mut write_parameters = Arc::new (Mutex::new (vec! ()));
+        let mut shutdown_parameters = Arc::new (Mutex::new (vec! ()));
+        let write_stream = TcpStreamWrapperMock::new ()
+            .peer_addr_result (Err (Error::from (ErrorKind::AddrInUse)))
+            .write_parameters (&mut write_parameters)
+            .shutdown_parameters (&mut shutdown_parameters)
+            .shutdown_result (Ok (()));
+        let mut subject = StreamHandlerPoolReal::new (Box::new (ResolverWrapperMock::new ()),
+                                                      cryptde(), hopper_sub, StreamTimeouts::none ());
+        subject.stream_writers.insert (stream_key, StreamWriter::new (Box::new (write_stream), make_remote (), StreamClock::new (StreamTimeouts::none ())));
+
+        subject.process_package (package_for (stream_key, 1, &vec! (0u8; DEFAULT_MAX_REORDER_BYTES)[..]));
+        subject.process_package (package_for (stream_key, 2, b"too much"));
+
+        assert_eq! (write_parameters.lock ().unwrap ().len (), 0);
+        assert_eq! (shutdown_parameters.lock ().unwrap ().remove (0), Shutdown::Both);
+        assert_eq! (subject.stream_writers.contains_key (&stream_key), false);
+        TestLogHandler::new ().exists_log_containing (&format! ("ERROR: Proxy Client: Abandoning stream {}: Payload 0 is still missing", stream_key));
+    }
+
+    #[test]
+    fn stream_whose_missing_payload_never_arrives_is_abandoned_on_a_tick () {
+        init_test_logging();
+        let stream_key = StreamKey::new ();
+        let _system = System::new("test");
+        let hopper_sub =
+            test_utils::make_peer_actors_from(None, None, Some (Recorder::new ()), None, None).hopper.from_hopper_client;
+        let mut shutdown_parameters = Arc::new (Mutex::new (vec! ()));
+        let write_stream = TcpStreamWrapperMock::new ()
+            .peer_addr_result (Err (Error::from (ErrorKind::AddrInUse)))
+            .shutdown_parameters (&mut shutdown_parameters)
+            .shutdown_result (Ok (()));
+        let mut subject = StreamHandlerPoolReal::new (Box::new (ResolverWrapperMock::new ()),
+                                                      cryptde(), hopper_sub, StreamTimeouts::none ());
+        subject.reorder_gap_timeout = Duration::from_millis (0);
+        subject.stream_writers.insert (stream_key, StreamWriter::new (Box::new (write_stream), make_remote (), StreamClock::new (StreamTimeouts::none ())));
+        subject.process_package (package_for (stream_key, 1, b"one"));
+        assert_eq! (subject.stream_writers.contains_key (&stream_key), true);
+
+        subject.tick ();
+
+        assert_eq! (shutdown_parameters.lock ().unwrap ().remove (0), Shutdown::Both);
+        assert_eq! (subject.stream_writers.contains_key (&stream_key), false);
+        assert_eq! (subject.reorder_buffers.contains_key (&stream_key), false);
+        TestLogHandler::new ().exists_log_containing (&format! ("ERROR: Proxy Client: Abandoning stream {}: Payload 0 is still missing after", stream_key));
+    }
+
     #[test]
-    fn terminal_payload_will_close_existing_connection () {
+    fn terminal_payload_will_shut_down_existing_connection_for_writing () {
+        let stream_key = StreamKey::new ();
         let client_request_payload = ClientRequestPayload {
-            stream_key: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
             last_data: true,
+            stopped_reading: false,
             data: PlainData::new (&b"These are the times"[..]),
             target_hostname: None,
             target_port: 80,
@@ -361,9 +746,51 @@ mod tests {
             .shutdown_parameters (&mut shutdown_parameters)
             .shutdown_result (Ok (()));
         let mut subject = StreamHandlerPoolReal::new (Box::new (ResolverWrapperMock::new ()),
-                                                      cryptde(), hopper_sub);
+                                                      cryptde(), hopper_sub, StreamTimeouts::none ());
         subject.stream_writers.insert (client_request_payload.stream_key,
-           StreamWriter::new (Box::new (write_stream)));
+           StreamWriter::new (Box::new (write_stream), make_remote (), StreamClock::new (StreamTimeouts::none ())));
+
+        subject.process_package(package);
+
+        assert_eq! (write_parameters.lock ().unwrap ().remove (0), client_request_payload.data.data);
+        assert_eq! (shutdown_parameters.lock ().unwrap ().remove (0), Shutdown::Write);
+    }
+
+    #[test]
+    fn terminal_payload_from_a_client_that_stopped_reading_shuts_down_existing_connection_entirely () {
+        let stream_key = StreamKey::new ();
+        let client_request_payload = ClientRequestPayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
+            last_data: true,
+            stopped_reading: true,
+            data: PlainData::new (&b"These are the times"[..]),
+            target_hostname: None,
+            target_port: 80,
+            protocol: ProxyProtocol::HTTP,
+            originator_public_key: Key::new (&b"men's souls"[..])
+        };
+        let package = ExpiredCoresPackage::new (test_utils::make_meaningless_route (),
+           PlainData::new (&(serde_cbor::ser::to_vec (&client_request_payload).unwrap ())[..]));
+        let _system = System::new("test");
+        let hopper = Recorder::new ();
+        let hopper_sub =
+            test_utils::make_peer_actors_from(None, None, Some (hopper), None, None).hopper.from_hopper_client;
+        let mut write_parameters = Arc::new (Mutex::new (vec! ()));
+        let mut shutdown_parameters = Arc::new (Mutex::new (vec! ()));
+        let write_stream = TcpStreamWrapperMock::new ()
+            .peer_addr_result (Err (Error::from (ErrorKind::AddrInUse)))
+            .write_parameters (&mut write_parameters)
+            .write_result (Ok (123))
+            .shutdown_parameters (&mut shutdown_parameters)
+            .shutdown_result (Ok (()));
+        let mut subject = StreamHandlerPoolReal::new (Box::new (ResolverWrapperMock::new ()),
+                                                      cryptde(), hopper_sub, StreamTimeouts::none ());
+        subject.stream_writers.insert (client_request_payload.stream_key,
+           StreamWriter::new (Box::new (write_stream), make_remote (), StreamClock::new (StreamTimeouts::none ())));
 
         subject.process_package(package);
 
@@ -372,15 +799,27 @@ mod tests {
     }
 
     #[test]
-    fn write_failure_for_existing_stream_generates_log_and_termination_message () {
+    fn write_failure_for_existing_stream_generates_log_and_closes_stream () {
+        let stream_key = StreamKey::new ();
         init_test_logging();
         let hopper = Recorder::new();
-        let hopper_awaiter = hopper.get_awaiter ();
         let hopper_recording_arc = hopper.get_recording ();
+        let mut shutdown_parameters = Arc::new (Mutex::new (vec! ()));
+        let stream = TcpStreamWrapperMock::new()
+            .peer_addr_result(Ok(SocketAddr::from_str("2.3.4.5:80").unwrap()))
+            .write_result(Err(Error::from(ErrorKind::BrokenPipe)))
+            .shutdown_parameters(&mut shutdown_parameters)
+            .shutdown_result(Ok(()));
+        let (tx, rx) = mpsc::channel ();
         thread::spawn (move || {
             let client_request_payload = ClientRequestPayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                open_paths: vec! (),
                 last_data: false,
+                stopped_reading: false,
                 data: PlainData::new(&b"These are the times"[..]),
                 target_hostname: Some(String::from("that.try")),
                 target_port: 80,
@@ -392,36 +831,39 @@ mod tests {
             let system = System::new("test");
             let hopper_sub =
                 test_utils::make_peer_actors_from(None, None, Some(hopper), None, None).hopper.from_hopper_client;
-            let stream = TcpStreamWrapperMock::new()
-                .peer_addr_result(Ok(SocketAddr::from_str("2.3.4.5:80").unwrap()))
-                .write_result(Err(Error::from(ErrorKind::BrokenPipe)));
             let mut subject = StreamHandlerPoolReal::new(Box::new(ResolverWrapperMock::new()),
-                                                         cryptde(), hopper_sub);
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
             subject.stream_writers.insert(client_request_payload.stream_key,
-                                          StreamWriter::new(Box::new(stream)));
+                                          StreamWriter::new (Box::new(stream), make_remote (), StreamClock::new (StreamTimeouts::none ())));
 
             subject.process_package(package);
 
+            tx.send (subject.stream_writers.contains_key (&stream_key)).unwrap ();
             system.run();
         });
-        hopper_awaiter.await_message_count(1);
-        let hopper_recording = hopper_recording_arc.lock ().unwrap ();
-        let package = hopper_recording.get_record::<IncipientCoresPackage> (0);
-        let payload = serde_cbor::de::from_slice::<ClientResponsePayload> (&package.payload.data[..]).unwrap ();
-        assert_eq! (payload.last_response, true);
+        assert_eq! (rx.recv ().unwrap (), false);
         TestLogHandler::new ().await_log_containing("ERROR: Proxy Client: Error writing 19 bytes to 2.3.4.5:80: broken pipe", 1000);
+        assert_eq! (shutdown_parameters.lock ().unwrap ().remove (0), Shutdown::Both);
+        // The stream's StreamReader sends the last response when it sees the stream close
+        assert_eq! (hopper_recording_arc.lock ().unwrap ().len (), 0);
     }
 
     #[test]
     fn write_failure_for_nonexistent_stream_generates_log_and_termination_message () {
+        let stream_key = StreamKey::new ();
         init_test_logging();
         let hopper = Recorder::new();
         let hopper_awaiter = hopper.get_awaiter ();
         let hopper_recording_arc = hopper.get_recording ();
         thread::spawn (move || {
             let client_request_payload = ClientRequestPayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                open_paths: vec! (),
                 last_data: false,
+                stopped_reading: false,
                 data: PlainData::new(&b"These are the times"[..]),
                 target_hostname: Some(String::from("that.try")),
                 target_port: 80,
@@ -438,19 +880,19 @@ mod tests {
                 .lookup_ip_success(vec!(IpAddr::from_str("2.3.4.5").unwrap()));
             let read_stream =  TcpStreamWrapperMock::new()
                 .peer_addr_result(Ok(SocketAddr::from_str("3.4.5.6:80").unwrap()))
-                .read_delay (0xFFFFFFFF);
-            let second_write_stream = TcpStreamWrapperMock::new ()
-                .write_result(Err (Error::from (ErrorKind::AlreadyExists)));
+                .register_result(Ok(()))
+                .read_blocks ();
             let write_stream = TcpStreamWrapperMock::new ()
                 .peer_addr_result(Ok(SocketAddr::from_str("3.4.5.6:80").unwrap()))
                 .connect_result(Ok(()))
-                .set_read_timeout_result(Ok(()))
+                .register_result(Ok(()))
                 .try_clone_result (Ok (Box::new (read_stream)))
-                .try_clone_result (Ok (Box::new (second_write_stream)));
+                .write_result(Err (Error::from (ErrorKind::AlreadyExists)))
+                .shutdown_result(Ok(()));
             let stream_factory = TcpStreamWrapperFactoryMock::new()
                 .tcp_stream_wrapper(write_stream);
             let mut subject = StreamHandlerPoolReal::new(Box::new(resolver),
-                                                         cryptde(), hopper_sub);
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
             subject.tcp_stream_wrapper_factory = Box::new(stream_factory);
 
             subject.process_package(package);
@@ -462,19 +904,26 @@ mod tests {
         let package = hopper_recording.get_record::<IncipientCoresPackage> (0);
         let payload = serde_cbor::de::from_slice::<ClientResponsePayload> (&package.payload.data[..]).unwrap ();
         assert_eq! (payload.last_response, true);
+        assert_eq! (payload.failure, Some (StreamFailure::ConnectionFailed));
         TestLogHandler::new ().await_log_containing("ERROR: Proxy Client: Error writing 19 bytes to 3.4.5.6:80: entity already exists", 1000);
     }
 
     #[test]
     fn missing_hostname_for_nonexistent_stream_generates_log_and_termination_message () {
+        let stream_key = StreamKey::new ();
         init_test_logging();
         let hopper = Recorder::new();
         let hopper_awaiter = hopper.get_awaiter ();
         let hopper_recording_arc = hopper.get_recording ();
         thread::spawn (move || {
             let client_request_payload = ClientRequestPayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                open_paths: vec! (),
                 last_data: false,
+                stopped_reading: false,
                 data: PlainData::new(&b"These are the times"[..]),
                 target_hostname: None,
                 target_port: 80,
@@ -494,7 +943,7 @@ mod tests {
             let stream_factory = TcpStreamWrapperFactoryMock::new()
                 .tcp_stream_wrapper(stream);
             let mut subject = StreamHandlerPoolReal::new(Box::new(resolver),
-                                                         cryptde(), hopper_sub);
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
             subject.tcp_stream_wrapper_factory = Box::new(stream_factory);
 
             subject.process_package(package);
@@ -506,17 +955,19 @@ mod tests {
         let package = hopper_recording.get_record::<IncipientCoresPackage> (0);
         let payload = serde_cbor::de::from_slice::<ClientResponsePayload> (&package.payload.data[..]).unwrap ();
         assert_eq! (payload.last_response, true);
-        TestLogHandler::new ().exists_log_containing("ERROR: Proxy Client: Cannot open new stream with key 1.2.3.4:5678: no hostname supplied");
+        assert_eq! (payload.failure, Some (StreamFailure::MissingHostname));
+        TestLogHandler::new ().exists_log_containing(&format! ("ERROR: Proxy Client: Cannot open new stream with key {}: no hostname supplied", stream_key));
     }
 
     #[test]
     fn nonexistent_connection_springs_into_being_and_is_persisted_to_handle_transaction () {
+        let stream_key = StreamKey::new ();
         let lookup_ip_parameters = Arc::new(Mutex::new(vec!()));
         let lookup_ip_parameters_a = lookup_ip_parameters.clone ();
         let connect_parameters = Arc::new(Mutex::new(vec!()));
         let connect_parameters_a = connect_parameters.clone ();
-        let set_read_timeout_parameters = Arc::new(Mutex::new(vec!()));
-        let set_read_timeout_parameters_a = set_read_timeout_parameters.clone ();
+        let register_parameters = Arc::new(Mutex::new(vec!()));
+        let register_parameters_a = register_parameters.clone ();
         let write_parameters = Arc::new(Mutex::new(vec!()));
         let write_parameters_a = write_parameters.clone ();
         let hopper = Recorder::new();
@@ -524,8 +975,13 @@ mod tests {
         let hopper_awaiter = hopper.get_awaiter();
         thread::spawn (move || {
             let client_request_payload = ClientRequestPayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                open_paths: vec! (),
                 last_data: false,
+                stopped_reading: false,
                 data: PlainData::new(&b"These are the times"[..]),
                 target_hostname: Some(String::from("that.try")),
                 target_port: 80,
@@ -547,19 +1003,22 @@ mod tests {
                 .read_buffer(b"HTTP/1.1 200 OK\r\n\r\n".to_vec())
                 .read_result(Ok(19))
                 .read_result(Err(Error::from(ErrorKind::ConnectionAborted)))
+                .shutdown_result(Ok(()))
                 // preparations for writing
                 .connect_parameters(&connect_parameters)
                 .connect_result(Err(Error::from(ErrorKind::InvalidInput)))
                 .connect_result(Ok(()))
-                .set_read_timeout_parameters(&set_read_timeout_parameters)
-                .set_read_timeout_result(Ok(()))
+                // one registration for the writer, one for the reader
+                .register_parameters(&register_parameters)
+                .register_result(Ok(()))
+                .register_result(Ok(()))
                 .write_parameters(&write_parameters)
-                .write_result(Ok(123))
+                .write_result(Ok(19))
                 .mocked_try_clone(false);
             let stream_factory = TcpStreamWrapperFactoryMock::new()
                 .tcp_stream_wrapper(stream);
             let mut subject = StreamHandlerPoolReal::new(Box::new(resolver),
-                                                         cryptde(), hopper_sub);
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
             subject.tcp_stream_wrapper_factory = Box::new(stream_factory);
 
             subject.process_package(package);
@@ -570,31 +1029,180 @@ mod tests {
         assert_eq! (lookup_ip_parameters_a.lock ().unwrap ().deref (), &vec! (String::from ("that.try.")));
         assert_eq! (connect_parameters_a.lock ().unwrap ().remove (0), SocketAddr::from_str ("2.3.4.5:80").unwrap ());
         assert_eq! (connect_parameters_a.lock ().unwrap ().remove (0), SocketAddr::from_str ("3.4.5.6:80").unwrap ());
-        assert_eq! (set_read_timeout_parameters_a.lock ().unwrap ().remove (0), None);
+        assert_eq! (register_parameters_a.lock ().unwrap ().len (), 2);
         assert_eq! (write_parameters_a.lock ().unwrap ().remove (0), b"These are the times".to_vec ());
         let hopper_recording = hopper_recording_arc.lock ().unwrap ();
         let record = hopper_recording.get_record::<IncipientCoresPackage> (0);
         assert_eq! (*record, IncipientCoresPackage::new (
             test_utils::make_meaningless_route(),
             ClientResponsePayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 1,
+                path_id: 0,
                 last_response: false,
+                stopped_reading: false,
                 data: PlainData::new (&b"HTTP/1.1 200 OK\r\n\r\n"[..]),
+                failure: None,
             },
             &Key::new(&b"men's souls"[..])
         ));
     }
 
+    #[test]
+    fn payloads_that_arrive_while_the_stream_is_being_opened_follow_the_first_one_over_the_same_connection () {
+        let stream_key = StreamKey::new ();
+        let lookup_ip_parameters = Arc::new(Mutex::new(vec!()));
+        let lookup_ip_parameters_a = lookup_ip_parameters.clone ();
+        let connect_parameters = Arc::new(Mutex::new(vec!()));
+        let connect_parameters_a = connect_parameters.clone ();
+        let write_parameters = Arc::new(Mutex::new(vec!()));
+        let write_parameters_a = write_parameters.clone ();
+        let hopper = Recorder::new();
+        let hopper_recording_arc = hopper.get_recording();
+        let hopper_awaiter = hopper.get_awaiter();
+        thread::spawn (move || {
+            let system = System::new("test");
+            let hopper_sub =
+                test_utils::make_peer_actors_from(None, None, Some(hopper), None, None)
+                    .hopper.from_hopper_client;
+            let resolver = ResolverWrapperMock::new()
+                .lookup_ip_parameters(&lookup_ip_parameters)
+                .lookup_ip_success(vec!(IpAddr::from_str("2.3.4.5").unwrap()));
+            let stream = TcpStreamWrapperMock::new()
+                .peer_addr_result(Ok(SocketAddr::from_str("2.3.4.5:80").unwrap()))
+                .read_blocks()
+                .connect_parameters(&connect_parameters)
+                .connect_result(Ok(()))
+                .register_result(Ok(()))
+                .register_result(Ok(()))
+                .write_parameters(&write_parameters)
+                .write_result(Ok(4))
+                .write_result(Ok(3))
+                .mocked_try_clone(false);
+            let stream_factory = TcpStreamWrapperFactoryMock::new()
+                .tcp_stream_wrapper(stream);
+            let mut subject = StreamHandlerPoolReal::new(Box::new(resolver),
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
+            subject.tcp_stream_wrapper_factory = Box::new(stream_factory);
+
+            subject.process_package(package_to_host (stream_key, 1, b"one"));
+            subject.process_package(package_to_host (stream_key, 0, b"zero"));
+            let mut ticks = 0;
+            Arbiter::handle ().spawn (future::poll_fn (move || {
+                subject.tick ();
+                ticks += 1;
+                // Acknowledgements go out on the first tick after both payloads have been written
+                if ticks > 1 && subject.held_acks.is_empty () {return Ok (Async::Ready (()))}
+                task::current ().notify ();
+                Ok (Async::NotReady)
+            }));
+
+            system.run();
+        });
+        hopper_awaiter.await_message_count (1);
+        assert_eq! (lookup_ip_parameters_a.lock ().unwrap ().len (), 1);
+        assert_eq! (connect_parameters_a.lock ().unwrap ().len (), 1);
+        assert_eq! (*write_parameters_a.lock ().unwrap (), vec! (b"zero".to_vec (), b"one".to_vec ()));
+        let hopper_recording = hopper_recording_arc.lock ().unwrap ();
+        assert_eq! (hopper_recording.get_record::<IncipientCoresPackage> (0), &bare_ack (stream_key, 0, test_utils::make_meaningless_route (), 2));
+    }
+
+    #[test]
+    fn other_streams_carry_on_while_a_server_is_slow_to_accept_a_connection () {
+        let slow_stream_key = StreamKey::new ();
+        let fast_stream_key = StreamKey::new ();
+        let slow_write_parameters = Arc::new(Mutex::new(vec!()));
+        let slow_write_parameters_a = slow_write_parameters.clone ();
+        let fast_write_parameters = Arc::new(Mutex::new(vec!()));
+        let fast_write_parameters_a = fast_write_parameters.clone ();
+        let (gate_tx, gate_rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("test");
+            let hopper_sub =
+                test_utils::make_peer_actors_from(None, None, Some(Recorder::new ()), None, None)
+                    .hopper.from_hopper_client;
+            let resolver = ResolverWrapperMock::new()
+                .lookup_ip_success(vec!(IpAddr::from_str("2.3.4.5").unwrap()))
+                .lookup_ip_success(vec!(IpAddr::from_str("3.4.5.6").unwrap()));
+            let slow_stream = TcpStreamWrapperMock::new()
+                .peer_addr_result(Ok(SocketAddr::from_str("2.3.4.5:80").unwrap()))
+                .read_blocks()
+                .connect_gate(gate_rx)
+                .connect_result(Ok(()))
+                .register_result(Ok(()))
+                .register_result(Ok(()))
+                .write_parameters(&slow_write_parameters)
+                .write_result(Ok(4))
+                .mocked_try_clone(false);
+            let fast_stream = TcpStreamWrapperMock::new()
+                .peer_addr_result(Ok(SocketAddr::from_str("3.4.5.6:80").unwrap()))
+                .read_blocks()
+                .connect_result(Ok(()))
+                .register_result(Ok(()))
+                .register_result(Ok(()))
+                .write_parameters(&fast_write_parameters)
+                .write_result(Ok(4))
+                .mocked_try_clone(false);
+            let stream_factory = TcpStreamWrapperFactoryMock::new()
+                .tcp_stream_wrapper(slow_stream)
+                .tcp_stream_wrapper(fast_stream);
+            let mut subject = StreamHandlerPoolReal::new(Box::new(resolver),
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
+            subject.tcp_stream_wrapper_factory = Box::new(stream_factory);
+
+            subject.process_package(package_to_host (slow_stream_key, 0, b"slow"));
+            subject.process_package(package_to_host (fast_stream_key, 0, b"fast"));
+
+            system.run();
+        });
+        let await_write = |parameters: &Arc<Mutex<Vec<Vec<u8>>>>| {
+            let deadline = Instant::now () + Duration::from_secs (1);
+            while parameters.lock ().unwrap ().is_empty () && Instant::now () < deadline {
+                thread::sleep (Duration::from_millis (10))
+            }
+            parameters.lock ().unwrap ().clone ()
+        };
+        assert_eq! (await_write (&fast_write_parameters_a), vec! (b"fast".to_vec ()));
+        gate_tx.send (()).unwrap ();
+        assert_eq! (await_write (&slow_write_parameters_a), vec! (b"slow".to_vec ()));
+    }
+
+    fn package_to_host (stream_key: StreamKey, sequence_number: u64, data: &[u8]) -> ExpiredCoresPackage {
+        let client_request_payload = ClientRequestPayload {
+            stream_key,
+            sequence_number,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
+            last_data: false,
+            stopped_reading: false,
+            data: PlainData::new (data),
+            target_hostname: Some (String::from ("that.try")),
+            target_port: 80,
+            protocol: ProxyProtocol::HTTP,
+            originator_public_key: Key::new (&b"men's souls"[..])
+        };
+        ExpiredCoresPackage::new (test_utils::make_meaningless_route (),
+                                  PlainData::new (&(serde_cbor::ser::to_vec (&client_request_payload).unwrap ())[..]))
+    }
+
     #[test]
     fn if_none_of_the_resolved_ips_work_we_get_a_log_and_an_error_result () {
+        let stream_key = StreamKey::new ();
         init_test_logging();
         let hopper = Recorder::new();
         let hopper_awaiter = hopper.get_awaiter ();
         let hopper_recording_arc = hopper.get_recording ();
         thread::spawn (move || {
             let client_request_payload = ClientRequestPayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                open_paths: vec! (),
                 last_data: false,
+                stopped_reading: false,
                 data: PlainData::new(&b"These are the times"[..]),
                 target_hostname: Some(String::from("that.try")),
                 target_port: 80,
@@ -615,7 +1223,7 @@ mod tests {
             let stream_factory = TcpStreamWrapperFactoryMock::new()
                 .tcp_stream_wrapper(write_stream);
             let mut subject = StreamHandlerPoolReal::new(Box::new(resolver),
-                                                         cryptde(), hopper_sub);
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
             subject.tcp_stream_wrapper_factory = Box::new(stream_factory);
 
             subject.process_package(package);
@@ -627,19 +1235,76 @@ mod tests {
         let record = hopper_recording.get_record::<IncipientCoresPackage> (0);
         let client_response_payload = serde_cbor::de::from_slice::<ClientResponsePayload> (&record.payload.data[..]).unwrap ();
         assert_eq! (client_response_payload.last_response, true);
+        assert_eq! (client_response_payload.failure, Some (StreamFailure::ConnectionFailed));
         TestLogHandler::new ().await_log_containing ("ERROR: Proxy Client: Could not connect to any of the IP addresses supplied for that.try: [\"2.3.4.5:80\", \"3.4.5.6:80\"]", 1000);
     }
 
     #[test]
-    fn if_setting_read_timeout_fails_we_get_a_log_and_an_error_result () {
+    fn refused_connection_is_reported_as_such () {
+        let stream_key = StreamKey::new ();
+        let hopper = Recorder::new();
+        let hopper_awaiter = hopper.get_awaiter ();
+        let hopper_recording_arc = hopper.get_recording ();
+        thread::spawn (move || {
+            let client_request_payload = ClientRequestPayload {
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                open_paths: vec! (),
+                last_data: false,
+                stopped_reading: false,
+                data: PlainData::new(&b"These are the times"[..]),
+                target_hostname: Some(String::from("that.try")),
+                target_port: 80,
+                protocol: ProxyProtocol::HTTP,
+                originator_public_key: Key::new(&b"men's souls"[..])
+            };
+            let package = ExpiredCoresPackage::new(test_utils::make_meaningless_route(),
+                                                   PlainData::new(&(serde_cbor::ser::to_vec(&client_request_payload).unwrap())[..]));
+            let system = System::new("test");
+            let hopper_sub =
+                test_utils::make_peer_actors_from(None, None, Some(hopper), None, None)
+                    .hopper.from_hopper_client;
+            let resolver = ResolverWrapperMock::new()
+                .lookup_ip_success(vec!(IpAddr::from_str("2.3.4.5").unwrap(), IpAddr::from_str("3.4.5.6").unwrap()));
+            let write_stream = TcpStreamWrapperMock::new()
+                .connect_result(Err(Error::from(ErrorKind::TimedOut)))
+                .connect_result(Err(Error::from(ErrorKind::ConnectionRefused)));
+            let stream_factory = TcpStreamWrapperFactoryMock::new()
+                .tcp_stream_wrapper(write_stream);
+            let mut subject = StreamHandlerPoolReal::new(Box::new(resolver),
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
+            subject.tcp_stream_wrapper_factory = Box::new(stream_factory);
+
+            subject.process_package(package);
+
+            system.run();
+        });
+        hopper_awaiter.await_message_count (1);
+        let hopper_recording = hopper_recording_arc.lock ().unwrap ();
+        let record = hopper_recording.get_record::<IncipientCoresPackage> (0);
+        let client_response_payload = serde_cbor::de::from_slice::<ClientResponsePayload> (&record.payload.data[..]).unwrap ();
+        assert_eq! (client_response_payload.last_response, true);
+        assert_eq! (client_response_payload.failure, Some (StreamFailure::ConnectionRefused));
+    }
+
+    #[test]
+    fn if_registering_with_the_reactor_fails_we_get_a_log_and_an_error_result () {
+        let stream_key = StreamKey::new ();
         init_test_logging();
         let hopper = Recorder::new();
         let hopper_awaiter = hopper.get_awaiter ();
         let hopper_recording_arc = hopper.get_recording ();
         thread::spawn (move || {
             let client_request_payload = ClientRequestPayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                open_paths: vec! (),
                 last_data: true,
+                stopped_reading: false,
                 data: PlainData::new(&b"These are the times"[..]),
                 target_hostname: Some(String::from("that.try")),
                 target_port: 80,
@@ -657,11 +1322,11 @@ mod tests {
             let write_stream = TcpStreamWrapperMock::new()
                 .peer_addr_result(Ok(SocketAddr::from_str("1.2.3.4:5678").unwrap()))
                 .connect_result(Ok(()))
-                .set_read_timeout_result(Err(Error::from(ErrorKind::AddrNotAvailable)));
+                .register_result(Err(Error::from(ErrorKind::AddrNotAvailable)));
             let stream_factory = TcpStreamWrapperFactoryMock::new()
                 .tcp_stream_wrapper(write_stream);
             let mut subject = StreamHandlerPoolReal::new(Box::new(resolver),
-                                                         cryptde(), hopper_sub);
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
             subject.tcp_stream_wrapper_factory = Box::new(stream_factory);
 
             subject.process_package(package);
@@ -673,19 +1338,26 @@ mod tests {
         let record = hopper_recording.get_record::<IncipientCoresPackage> (0);
         let client_response_payload = serde_cbor::de::from_slice::<ClientResponsePayload> (&record.payload.data[..]).unwrap ();
         assert_eq! (client_response_payload.last_response, true);
-        TestLogHandler::new ().await_log_containing ("ERROR: Proxy Client: Could not set the read timeout for connection to 1.2.3.4:5678", 1000);
+        assert_eq! (client_response_payload.failure, Some (StreamFailure::ConnectionFailed));
+        TestLogHandler::new ().await_log_containing ("ERROR: Proxy Client: Could not register connection to 1.2.3.4:5678 with the reactor", 1000);
     }
 
     #[test]
-    fn if_setting_read_timeout_fails_and_peer_addr_fails_we_get_a_log_and_an_error_result () {
+    fn if_registering_with_the_reactor_fails_and_peer_addr_fails_we_get_a_log_and_an_error_result () {
+        let stream_key = StreamKey::new ();
         init_test_logging();
         let hopper = Recorder::new();
         let hopper_awaiter = hopper.get_awaiter ();
         let hopper_recording_arc = hopper.get_recording ();
         thread::spawn (move || {
             let client_request_payload = ClientRequestPayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                open_paths: vec! (),
                 last_data: false,
+                stopped_reading: false,
                 data: PlainData::new(&b"These are the times"[..]),
                 target_hostname: Some(String::from("that.try")),
                 target_port: 80,
@@ -703,11 +1375,11 @@ mod tests {
             let write_stream = TcpStreamWrapperMock::new()
                 .peer_addr_result(Err(Error::from(ErrorKind::AddrNotAvailable)))
                 .connect_result(Ok(()))
-                .set_read_timeout_result(Err(Error::from(ErrorKind::AddrNotAvailable)));
+                .register_result(Err(Error::from(ErrorKind::AddrNotAvailable)));
             let stream_factory = TcpStreamWrapperFactoryMock::new()
                 .tcp_stream_wrapper(write_stream);
             let mut subject = StreamHandlerPoolReal::new(Box::new(resolver),
-                                                         cryptde(), hopper_sub);
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
             subject.tcp_stream_wrapper_factory = Box::new(stream_factory);
 
             subject.process_package(package);
@@ -719,20 +1391,26 @@ mod tests {
         let record = hopper_recording.get_record::<IncipientCoresPackage> (0);
         let client_response_payload = serde_cbor::de::from_slice::<ClientResponsePayload> (&record.payload.data[..]).unwrap ();
         assert_eq! (client_response_payload.last_response, true);
-        TestLogHandler::new ().await_log_containing ("ERROR: Proxy Client: Could not set the read timeout for connection to that.try", 1000);
+        assert_eq! (client_response_payload.failure, Some (StreamFailure::ConnectionFailed));
+        TestLogHandler::new ().await_log_containing ("ERROR: Proxy Client: Could not register connection to that.try with the reactor", 1000);
     }
 
     #[test]
     fn bad_dns_lookup_produces_log_and_sends_error_response () {
         init_test_logging();
-        let stream_key = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
         let hopper = Recorder::new();
         let hopper_awaiter = hopper.get_awaiter();
         let recording_arc = hopper.get_recording ();
         thread::spawn (move || {
             let client_request_payload = ClientRequestPayload {
-                stream_key: stream_key,
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                open_paths: vec! (),
                 last_data: true,
+                stopped_reading: false,
                 data: PlainData::new(&b"These are the times"[..]),
                 target_hostname: Some(String::from("that.try")),
                 target_port: 80,
@@ -750,7 +1428,7 @@ mod tests {
                 .lookup_ip_parameters(&mut lookup_ip_parameters)
                 .lookup_ip_failure(ResolveError::from(ResolveErrorKind::Io));
             let mut subject = StreamHandlerPoolReal::new(Box::new(resolver),
-                                                         cryptde(), hopper_sub);
+                                                         cryptde(), hopper_sub, StreamTimeouts::none ());
 
             subject.process_package(package);
 
@@ -763,13 +1441,19 @@ mod tests {
         let client_response_payload = serde_cbor::de::from_slice::<ClientResponsePayload> (&record.payload.data[..]).unwrap ();
         assert_eq! (client_response_payload, ClientResponsePayload {
             stream_key,
+            sequence_number: 0,
+            ack: 1, // the request that failed has arrived
+            path_id: 0,
             last_response: true,
+            stopped_reading: true,
             data: PlainData::new (&[]),
+            failure: Some (StreamFailure::DnsFailure),
         });
     }
 
     #[test]
     fn try_clone_error_is_logged_and_returned () {
+        let stream_key = StreamKey::new ();
         init_test_logging();
         let cryptde = cryptde();
         let hopper = Recorder::new();
@@ -777,8 +1461,13 @@ mod tests {
         let hopper_recording_arc = hopper.get_recording ();
         thread::spawn (move || {
             let client_request_payload = ClientRequestPayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:5678").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                open_paths: vec! (),
                 last_data: false,
+                stopped_reading: false,
                 data: PlainData::new(&b"These are the times"[..]),
                 target_hostname: Some(String::from("that.try")),
                 target_port: 80,
@@ -796,13 +1485,12 @@ mod tests {
             let write_stream = TcpStreamWrapperMock::new()
                 .peer_addr_result(Ok(SocketAddr::from_str("2.3.4.5:80").unwrap()))
                 .connect_result(Ok(()))
-                .set_read_timeout_result(Ok(()))
-                .try_clone_result(Err(Error::from (ErrorKind::ConnectionReset)))
-                .write_result(Ok(123));
+                .register_result(Ok(()))
+                .try_clone_result(Err(Error::from (ErrorKind::ConnectionReset)));
             let stream_factory = TcpStreamWrapperFactoryMock::new()
                 .tcp_stream_wrapper(write_stream);
             let mut subject = StreamHandlerPoolReal::new(Box::new(resolver),
-                                                         cryptde, hopper_sub);
+                                                         cryptde, hopper_sub, StreamTimeouts::none ());
             subject.tcp_stream_wrapper_factory = Box::new(stream_factory);
 
             subject.process_package(package);
@@ -814,6 +1502,7 @@ mod tests {
         let record = hopper_recording.get_record::<IncipientCoresPackage> (0);
         let client_response_payload = serde_cbor::de::from_slice::<ClientResponsePayload> (&record.payload.data[..]).unwrap ();
         assert_eq! (client_response_payload.last_response, true);
+        assert_eq! (client_response_payload.failure, Some (StreamFailure::ConnectionFailed));
         TestLogHandler::new ().await_log_containing ("Could not clone stream: connection reset", 1000);
     }
 }
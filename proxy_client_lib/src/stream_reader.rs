@@ -1,86 +1,149 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::cell::RefCell;
 use std::net::Shutdown;
 use std::sync::mpsc::Sender;
-use actix::Recipient;
-use actix::Syn;
-use sub_lib::cryptde::Key;
+use std::time::Instant;
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use futures::task;
+use tokio_core::reactor::Handle;
 use sub_lib::cryptde::PlainData;
 use sub_lib::cryptde::StreamKey;
 use sub_lib::framer::Framer;
-use sub_lib::hopper::IncipientCoresPackage;
 use sub_lib::logger::Logger;
-use sub_lib::proxy_client::ClientResponsePayload;
-use sub_lib::route::Route;
+use sub_lib::stream_timeouts::ExpiryTimer;
+use sub_lib::stream_timeouts::StreamClock;
 use sub_lib::tcp_wrappers::TcpStreamWrapper;
 use sub_lib::utils::indicates_dead_stream;
 use sub_lib::utils::to_string;
+use sub_lib::utils::MAX_READ_ERRORS_IN_A_ROW;
+use sub_lib::utils::MAX_READS_PER_POLL;
+use response_sender::ResponseSender;
+
+thread_local! {
+    // Every StreamReader on a thread reads into the same buffer, one at a time
+    static READ_BUFFER: RefCell<Vec<u8>> = RefCell::new (vec! [0; 16384]);
+}
 
 pub struct StreamReader {
     stream_key: StreamKey,
-    hopper_sub: Recipient<Syn, IncipientCoresPackage>,
     stream: Box<TcpStreamWrapper>,
     stream_killer: Sender<StreamKey>,
     peer_addr: String,
-    remaining_route: Route,
+    response_sender: ResponseSender,
     framer: Box<Framer>,
-    originator_public_key: Key,
+    clock: StreamClock,
+    expiry_timer: ExpiryTimer,
+    read_errors: usize,
     logger: Logger,
 }
 
+impl Future for StreamReader {
+    type Item = ();
+    type Error = ();
+
+    fn poll (&mut self) -> Poll<(), ()> {
+        if let Some (expiry) = self.expiry_timer.poll (&self.clock) {
+            self.logger.info (format! ("Closing stream from {}: {}", self.peer_addr, expiry));
+            self.shutdown ();
+            return Ok (Async::Ready (()))
+        }
+        // Until the Hopper has room, the server's data waits in the stream, and then the server waits
+        if let Async::NotReady = self.response_sender.poll_flush () {return Ok (Async::NotReady)}
+        for _ in 0..MAX_READS_PER_POLL {
+            match self.read_buffer () {
+                Async::NotReady => return Ok (Async::NotReady),
+                Async::Ready (false) => return Ok (Async::Ready (())),
+                Async::Ready (true) => if !self.write_loop () {return Ok (Async::Ready (()))}
+            }
+            if let Async::NotReady = self.response_sender.poll_flush () {
+                self.logger.debug (format! ("Hopper is full; pausing stream from {}", self.peer_addr));
+                return Ok (Async::NotReady)
+            }
+        }
+        // There may be more to read, but the other streams on this thread get their turns first
+        task::current ().notify ();
+        Ok (Async::NotReady)
+    }
+}
+
 impl StreamReader {
 
-    pub fn new (stream_key: StreamKey, hopper_sub: Recipient<Syn, IncipientCoresPackage>,
-        stream: Box<TcpStreamWrapper>, stream_killer: Sender<StreamKey>, peer_addr: String,
-        remaining_route: Route, framer: Box<Framer>, originator_public_key: Key) -> StreamReader {
+    pub fn new (stream_key: StreamKey, stream: Box<TcpStreamWrapper>, stream_killer: Sender<StreamKey>, peer_addr: String,
+        response_sender: ResponseSender, framer: Box<Framer>, clock: StreamClock) -> StreamReader {
         StreamReader {
             stream_key,
-            hopper_sub,
             stream,
             stream_killer,
             peer_addr,
-            remaining_route,
+            response_sender,
             framer,
-            originator_public_key,
+            clock,
+            expiry_timer: ExpiryTimer::none (),
+            read_errors: 0,
             logger: Logger::new ("Proxy Client"),
         }
     }
 
-    pub fn run(&mut self) {
-        let mut buf: [u8; 16384] = [0; 16384];
-        while self.read_buffer (&mut buf) && self.write_loop() {
-            // keep calling read and write until one of them returns false
-        }
+    // The stream must already be registered with the reactor behind the Handle
+    pub fn start (mut self, handle: &Handle) {
+        self.expiry_timer = match ExpiryTimer::new (self.clock.timeouts (), handle) {
+            Ok (expiry_timer) => expiry_timer,
+            Err (e) => {
+                self.logger.warning (format! ("Stream from {} won't expire: could not start timer: {}", self.peer_addr, e));
+                ExpiryTimer::none ()
+            }
+        };
+        handle.spawn (self);
     }
 
-    fn read_buffer (&mut self, buf: &mut [u8]) -> bool {
-        match self.stream.read (buf) {
-            Ok (0) => { // see RETURN VALUE section of recv man page (Unix)
-                self.logger.debug (format! ("Stream from {} was closed: (0-byte read)", self.peer_addr));
-                self.shutdown();
-                false
-            },
-            Ok (len) => {
-                self.logger.debug (format! ("Read {}-byte chunk from {}: {}", len, self.peer_addr,
-                                            to_string (&Vec::from (&buf[0..len]))));
-                self.framer.add_data (&buf[0..len]);
-                true
-            },
-            Err (e) => {
-                if indicates_dead_stream(e.kind ()) {
-                    self.logger.debug (format! ("Stream from {} was closed: {}", self.peer_addr, e));
-                    self.shutdown();
-                    false
-                }
-                else {
-                    self.logger.warning(format! ("Continuing after read error on stream from {}: {}", self.peer_addr, e));
-                    true
+    // Ready (false) means the stream is finished; NotReady, that the StreamReader should give up its turn
+    fn read_buffer (&mut self) -> Async<bool> {
+        READ_BUFFER.with (|buf| {
+            let mut buf = buf.borrow_mut ();
+            match self.stream.poll_read (&mut buf[..]) {
+                Ok (Async::NotReady) => Async::NotReady,
+                Ok (Async::Ready (0)) => { // see RETURN VALUE section of recv man page (Unix)
+                    // The server may still be reading, so the stream stays open for writing
+                    self.logger.debug (format! ("Stream from {} was closed: (0-byte read)", self.peer_addr));
+                    self.response_sender.send (PlainData::new (&[]), true, None);
+                    Async::Ready (false)
+                },
+                Ok (Async::Ready (len)) => {
+                    self.logger.debug (format! ("Read {}-byte chunk from {}: {}", len, self.peer_addr,
+                                                to_string (&Vec::from (&buf[0..len]))));
+                    self.clock.traffic (Instant::now ());
+                    self.read_errors = 0;
+                    self.framer.add_data (&buf[0..len]);
+                    Async::Ready (true)
+                },
+                Err (e) => {
+                    if indicates_dead_stream(e.kind ()) {
+                        self.logger.debug (format! ("Stream from {} was closed: {}", self.peer_addr, e));
+                        self.shutdown();
+                        Async::Ready (false)
+                    }
+                    else {
+                        self.read_errors += 1;
+                        if self.read_errors >= MAX_READ_ERRORS_IN_A_ROW {
+                            self.logger.warning (format! ("Closing stream from {} after {} read errors in a row: {}", self.peer_addr, self.read_errors, e));
+                            self.shutdown ();
+                            return Async::Ready (false)
+                        }
+                        self.logger.warning(format! ("Continuing after read error on stream from {}: {}", self.peer_addr, e));
+                        // The error may not have cleared by the next read, so the other streams on this thread go first
+                        task::current ().notify ();
+                        Async::NotReady
+                    }
                 }
             }
-        }
+        })
     }
 
-    fn shutdown(&self) {
-        self.send_cores_response (self.stream_key, PlainData::new (&[]), true);
+    fn shutdown(&mut self) {
+        self.response_sender.server_stopped_reading ();
+        self.response_sender.send (PlainData::new (&[]), true, None);
         self.stream.shutdown (Shutdown::Both).is_ok ();
         self.stream_killer.send (self.stream_key).is_ok ();
     }
@@ -92,12 +155,9 @@ impl StreamReader {
                     self.logger.debug (format! ("Framed {}-byte {} response chunk, '{}'", response_chunk.chunk.len (),
                                                 if response_chunk.last_chunk {"final"} else {"non-final"},
                                                 to_string (&response_chunk.chunk)));
-                    self.send_cores_response(
-                        self.stream_key,
-                        PlainData::new (&response_chunk.chunk[..]),
-                        response_chunk.last_chunk
-                    );
-                    if response_chunk.last_chunk {
+                    let last_chunk = response_chunk.last_chunk;
+                    self.response_sender.send (PlainData::from (response_chunk.chunk), last_chunk, None);
+                    if last_chunk {
                         self.stream.shutdown (Shutdown::Both).is_ok ();
                         self.stream_killer.send (self.stream_key).is_ok ();
                         return false;
@@ -109,23 +169,16 @@ impl StreamReader {
             }
         }
     }
-
-    fn send_cores_response(&self, stream_key: StreamKey, response_data: PlainData, last_response: bool) {
-        let response_payload = ClientResponsePayload {
-            stream_key,
-            last_response,
-            data: response_data
-        };
-        let incipient_cores_package =
-            IncipientCoresPackage::new (self.remaining_route.clone (),
-                                        response_payload, &self.originator_public_key);
-        self.hopper_sub.try_send(incipient_cores_package).expect ("Hopper is dead");
-    }
 }
 
 #[cfg (test)]
 mod tests {
     use super::*;
+    use sub_lib::cryptde::Key;
+    use sub_lib::hopper::IncipientCoresPackage;
+    use sub_lib::proxy_client::ClientResponsePayload;
+    use return_paths::ReturnPaths;
+    use sub_lib::cryptde::StreamKey;
     use std::io::Error;
     use std::net::SocketAddr;
     use std::str::FromStr;
@@ -133,6 +186,7 @@ mod tests {
     use std::sync::Mutex;
     use std::sync::mpsc;
     use std::thread;
+    use actix::Arbiter;
     use actix::System;
     use serde_cbor;
     use sub_lib::framer::FramedChunk;
@@ -144,6 +198,8 @@ mod tests {
     use test_utils::test_utils::TestLogHandler;
     use local_test_utils::TcpStreamWrapperMock;
     use std::io::ErrorKind;
+    use std::time::Duration;
+    use sub_lib::stream_timeouts::StreamTimeouts;
 
     struct StreamEndingFramer {}
 
@@ -156,7 +212,7 @@ mod tests {
 
     #[test]
     fn when_framer_identifies_last_chunk_stream_reader_takes_down_connection_properly() {
-        let stream_key = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
         let hopper = Recorder::new();
         let recording = hopper.get_recording();
         let awaiter = hopper.get_awaiter();
@@ -167,7 +223,7 @@ mod tests {
             .shutdown_parameters(&mut shutdown_parameters)
             .shutdown_result(Ok(())));
         let (stream_killer, rx) = mpsc::channel();
-        let remaining_route = test_utils::make_meaningless_route();
+        let return_paths = ReturnPaths::new(0, test_utils::make_meaningless_route());
         let framer = Box::new(StreamEndingFramer {});
         let originator_public_key = Key::new(&b"men's souls"[..]);
         let logger = Logger::new("test");
@@ -175,19 +231,20 @@ mod tests {
         thread::spawn(move || {
             let system = System::new("test");
             let hopper_sub = test_utils::make_peer_actors_from(None, None, Some(hopper), None, None).hopper.from_hopper_client;
-            let mut subject = StreamReader {
+            let subject = StreamReader {
                 stream_key,
-                hopper_sub,
                 stream,
                 stream_killer,
                 peer_addr: String::new(),
-                remaining_route,
+                response_sender: ResponseSender::new(stream_key, return_paths, hopper_sub, originator_public_key),
                 framer,
-                originator_public_key,
+                clock: StreamClock::new (StreamTimeouts::none ()),
+                expiry_timer: ExpiryTimer::none (),
+                read_errors: 0,
                 logger
             };
 
-            subject.run();
+            subject.start(&Arbiter::handle());
 
             system.run();
         });
@@ -203,8 +260,13 @@ mod tests {
             route: test_utils::make_meaningless_route(),
             payload: PlainData::new(&serde_cbor::ser::to_vec(&ClientResponsePayload {
                 stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
                 last_response: true,
+                stopped_reading: false,
                 data: PlainData::new(&[]),
+                failure: None,
             }).unwrap()[..]),
             payload_destination_key: Key::new(&b"men's souls"[..]),
         });
@@ -212,6 +274,7 @@ mod tests {
 
     #[test]
     fn stream_reader_can_handle_multiple_packets_followed_by_dropped_stream() {
+        let stream_key = StreamKey::new ();
         let hopper = Recorder::new();
         let awaiter = hopper.get_awaiter();
         let hopper_recording_arc = hopper.get_recording();
@@ -233,19 +296,20 @@ mod tests {
                 test_utils::make_peer_actors_from(None, None, Some(hopper), None, None)
                     .hopper.from_hopper_client;
             let (stream_killer, _) = mpsc::channel::<StreamKey>();
-            let mut subject = StreamReader {
-                stream_key: SocketAddr::from_str("1.2.3.4:80").unwrap(),
-                hopper_sub,
+            let subject = StreamReader {
+                stream_key,
                 stream: Box::new(stream),
                 stream_killer,
                 peer_addr: String::from("Peer Address"),
-                remaining_route: test_utils::make_meaningless_route(),
+                response_sender: ResponseSender::new(stream_key, ReturnPaths::new(0, test_utils::make_meaningless_route()), hopper_sub, Key::new(&b"abcd"[..])),
                 framer: Box::new(HttpPacketFramer::new(Box::new(HttpResponseStartFinder {}))),
-                originator_public_key: Key::new(&b"abcd"[..]),
+                clock: StreamClock::new (StreamTimeouts::none ()),
+                expiry_timer: ExpiryTimer::none (),
+                read_errors: 0,
                 logger: Logger::new("test"),
             };
 
-            subject.run();
+            subject.start(&Arbiter::handle());
 
             system.run();
         });
@@ -255,36 +319,56 @@ mod tests {
         assert_eq!(hopper_recording.get_record::<IncipientCoresPackage>(0), &IncipientCoresPackage::new(
             test_utils::make_meaningless_route(),
             ClientResponsePayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:80").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
                 last_response: false,
+                stopped_reading: false,
                 data: PlainData::new(&b"HTTP/1.1 200 OK\r\n\r\n"[..]),
+                failure: None,
             },
             &Key::new(&b"abcd"[..])
         ));
         assert_eq!(hopper_recording.get_record::<IncipientCoresPackage>(1), &IncipientCoresPackage::new(
             test_utils::make_meaningless_route(),
             ClientResponsePayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:80").unwrap(),
+                stream_key,
+                sequence_number: 1,
+                ack: 0,
+                path_id: 0,
                 last_response: false,
+                stopped_reading: false,
                 data: PlainData::new(&b"HTTP/1.1 404 File not found\r\n\r\n"[..]),
+                failure: None,
             },
             &Key::new(&b"abcd"[..])
         ));
         assert_eq!(hopper_recording.get_record::<IncipientCoresPackage>(2), &IncipientCoresPackage::new(
             test_utils::make_meaningless_route(),
             ClientResponsePayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:80").unwrap(),
+                stream_key,
+                sequence_number: 2,
+                ack: 0,
+                path_id: 0,
                 last_response: false,
+                stopped_reading: false,
                 data: PlainData::new(&b"HTTP/1.1 503 Server error\r\n\r\n"[..]),
+                failure: None,
             },
             &Key::new(&b"abcd"[..])
         ));
         assert_eq!(hopper_recording.get_record::<IncipientCoresPackage>(3), &IncipientCoresPackage::new(
             test_utils::make_meaningless_route(),
             ClientResponsePayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:80").unwrap(),
+                stream_key,
+                sequence_number: 3,
+                ack: 0,
+                path_id: 0,
                 last_response: true,
+                stopped_reading: true,
                 data: PlainData::new(&b""[..]),
+                failure: None,
             },
             &Key::new(&b"abcd"[..])
         ));
@@ -293,12 +377,12 @@ mod tests {
     }
 
     #[test]
-    fn receiving_0_bytes_sends_empty_cores_response_and_kills_stream() {
+    fn receiving_0_bytes_sends_empty_cores_response_but_leaves_stream_open_for_writing() {
         init_test_logging();
         let hopper = Recorder::new();
         let awaiter = hopper.get_awaiter();
         let hopper_recording_arc = hopper.get_recording();
-        let stream_key = SocketAddr::from_str("1.2.3.4:80").unwrap();
+        let stream_key = StreamKey::new ();
         let mut shutdown_parameters = Arc::new(Mutex::new(vec!()));
         let (stream_killer, rx) = mpsc::channel::<StreamKey>();
             let stream = TcpStreamWrapperMock::new()
@@ -309,52 +393,164 @@ mod tests {
                 .shutdown_result(Ok(()));
 
         thread::spawn(move || {
-            let system = System::new("receiving_0_bytes_sends_empty_cores_response_and_kills_stream");
+            let system = System::new("receiving_0_bytes_sends_empty_cores_response_but_leaves_stream_open_for_writing");
             let hopper_sub =
                 test_utils::make_peer_actors_from(None, None, Some(hopper), None, None)
                     .hopper.from_hopper_client;
-            let mut subject = StreamReader {
+            let subject = StreamReader {
                 stream_key,
-                hopper_sub,
                 stream: Box::new(stream),
                 stream_killer,
                 peer_addr: String::from("Peer Address"),
-                remaining_route: test_utils::make_meaningless_route(),
+                response_sender: ResponseSender::new(stream_key, ReturnPaths::new(0, test_utils::make_meaningless_route()), hopper_sub, Key::new(&b"abcd"[..])),
                 framer: Box::new(HttpPacketFramer::new(Box::new(HttpResponseStartFinder {}))),
-                originator_public_key: Key::new(&b"abcd"[..]),
+                clock: StreamClock::new (StreamTimeouts::none ()),
+                expiry_timer: ExpiryTimer::none (),
+                read_errors: 0,
                 logger: Logger::new("test"),
             };
 
-            subject.run();
+            subject.start(&Arbiter::handle());
 
             system.run();
         });
 
         awaiter.await_message_count(1);
-        let kill_stream_key = rx.recv().unwrap();
-        assert_eq!(kill_stream_key, stream_key);
-        let shutdown_parameter = shutdown_parameters.lock().unwrap()[0];
-        assert_eq!(shutdown_parameter, Shutdown::Both);
+        assert_eq!(rx.try_recv().is_err(), true);
+        assert_eq!(shutdown_parameters.lock().unwrap().len(), 0);
         let hopper_recording = hopper_recording_arc.lock().unwrap();
         assert_eq!(hopper_recording.get_record::<IncipientCoresPackage>(0), &IncipientCoresPackage::new(
             test_utils::make_meaningless_route(),
             ClientResponsePayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:80").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
                 last_response: true,
+                stopped_reading: false,
                 data: PlainData::new(&[]),
+                failure: None,
             },
             &Key::new(&b"abcd"[..])
         ));
         TestLogHandler::new().exists_log_containing("Stream from Peer Address was closed: (0-byte read)");
     }
 
+    #[test]
+    fn expired_stream_is_shut_down_and_originator_is_told_the_server_is_gone() {
+        init_test_logging();
+        let hopper = Recorder::new();
+        let awaiter = hopper.get_awaiter();
+        let hopper_recording_arc = hopper.get_recording();
+        let stream_key = StreamKey::new ();
+        let mut shutdown_parameters = Arc::new(Mutex::new(vec!()));
+        let (stream_killer, rx) = mpsc::channel::<StreamKey>();
+        let stream = TcpStreamWrapperMock::new()
+            .read_result(Err(Error::from(ErrorKind::WouldBlock)))
+            .shutdown_parameters(&mut shutdown_parameters)
+            .shutdown_result(Ok(()));
+        let timeouts = StreamTimeouts {idle: Some (Duration::from_millis (1000)), lifetime: None};
+        let clock = StreamClock::starting_at (timeouts, Instant::now () - Duration::from_millis (1500));
+
+        thread::spawn(move || {
+            let system = System::new("expired_stream_is_shut_down_and_originator_is_told_the_server_is_gone");
+            let hopper_sub =
+                test_utils::make_peer_actors_from(None, None, Some(hopper), None, None)
+                    .hopper.from_hopper_client;
+            let subject = StreamReader {
+                stream_key,
+                stream: Box::new(stream),
+                stream_killer,
+                peer_addr: String::from("Peer Address"),
+                response_sender: ResponseSender::new(stream_key, ReturnPaths::new(0, test_utils::make_meaningless_route()), hopper_sub, Key::new(&b"abcd"[..])),
+                framer: Box::new(HttpPacketFramer::new(Box::new(HttpResponseStartFinder {}))),
+                clock,
+                expiry_timer: ExpiryTimer::none (),
+                read_errors: 0,
+                logger: Logger::new("test"),
+            };
+
+            subject.start(&Arbiter::handle());
+
+            system.run();
+        });
+
+        awaiter.await_message_count(1);
+        assert_eq!(rx.recv().unwrap(), stream_key);
+        assert_eq!(*shutdown_parameters.lock().unwrap(), vec!(Shutdown::Both));
+        let hopper_recording = hopper_recording_arc.lock().unwrap();
+        assert_eq!(hopper_recording.get_record::<IncipientCoresPackage>(0), &IncipientCoresPackage::new(
+            test_utils::make_meaningless_route(),
+            ClientResponsePayload {
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                last_response: true,
+                stopped_reading: true,
+                data: PlainData::new(&[]),
+                failure: None,
+            },
+            &Key::new(&b"abcd"[..])
+        ));
+        TestLogHandler::new().exists_log_containing("INFO: test: Closing stream from Peer Address: idle for more than 1000ms");
+    }
+
+    #[test]
+    fn stream_is_shut_down_when_read_errors_keep_coming() {
+        init_test_logging();
+        let hopper = Recorder::new();
+        let awaiter = hopper.get_awaiter();
+        let hopper_recording_arc = hopper.get_recording();
+        let stream_key = StreamKey::new ();
+        let (stream_killer, stream_killer_params) = mpsc::channel::<StreamKey>();
+        let mut shutdown_parameters = Arc::new(Mutex::new(vec!()));
+        let stream = (0..MAX_READ_ERRORS_IN_A_ROW).fold (TcpStreamWrapperMock::new(), |stream, _| stream.read_result(Err(Error::from(ErrorKind::Other))))
+            .shutdown_parameters(&mut shutdown_parameters)
+            .shutdown_result(Ok(()));
+
+        thread::spawn(move || {
+            let system = System::new("stream_is_shut_down_when_read_errors_keep_coming");
+            let hopper_sub =
+                test_utils::make_peer_actors_from(None, None, Some(hopper), None, None)
+                    .hopper.from_hopper_client;
+            let subject = StreamReader {
+                stream_key,
+                stream: Box::new(stream),
+                stream_killer,
+                peer_addr: String::from("Peer Address"),
+                response_sender: ResponseSender::new(stream_key, ReturnPaths::new(0, test_utils::make_meaningless_route()), hopper_sub, Key::new(&b"abcd"[..])),
+                framer: Box::new(HttpPacketFramer::new(Box::new(HttpResponseStartFinder {}))),
+                clock: StreamClock::new (StreamTimeouts::none ()),
+                expiry_timer: ExpiryTimer::none (),
+                read_errors: 0,
+                logger: Logger::new("test"),
+            };
+
+            subject.start(&Arbiter::handle());
+
+            system.run();
+        });
+
+        awaiter.await_message_count(1);
+        assert_eq!(stream_killer_params.recv_timeout(Duration::from_secs(1)).unwrap(), stream_key);
+        TestLogHandler::new().exists_log_containing(&format!("WARN: test: Closing stream from Peer Address after {} read errors in a row",
+            MAX_READ_ERRORS_IN_A_ROW));
+        let hopper_recording = hopper_recording_arc.lock().unwrap();
+        let record = hopper_recording.get_record::<IncipientCoresPackage>(0);
+        let payload: ClientResponsePayload = serde_cbor::de::from_slice(&record.payload.data[..]).unwrap();
+        assert_eq!(payload.last_response, true);
+        assert_eq!(payload.stopped_reading, true);
+        assert_eq!(shutdown_parameters.lock().unwrap()[0], Shutdown::Both);
+    }
+
     #[test]
     fn non_dead_stream_read_errors_log_but_do_not_shut_down() {
         init_test_logging();
         let hopper = Recorder::new();
         let awaiter = hopper.get_awaiter();
         let hopper_recording_arc = hopper.get_recording();
-        let stream_key = SocketAddr::from_str("1.2.3.4:80").unwrap();
+        let stream_key = StreamKey::new ();
         let (stream_killer, _) = mpsc::channel::<StreamKey>();
         let mut shutdown_parameters = Arc::new(Mutex::new(vec!()));
         let stream = TcpStreamWrapperMock::new()
@@ -370,19 +566,20 @@ mod tests {
             let hopper_sub =
                 test_utils::make_peer_actors_from(None, None, Some(hopper), None, None)
                     .hopper.from_hopper_client;
-            let mut subject = StreamReader {
+            let subject = StreamReader {
                 stream_key,
-                hopper_sub,
                 stream: Box::new(stream),
                 stream_killer,
                 peer_addr: String::from("Peer Address"),
-                remaining_route: test_utils::make_meaningless_route(),
+                response_sender: ResponseSender::new(stream_key, ReturnPaths::new(0, test_utils::make_meaningless_route()), hopper_sub, Key::new(&b"abcd"[..])),
                 framer: Box::new(HttpPacketFramer::new(Box::new(HttpResponseStartFinder {}))),
-                originator_public_key: Key::new(&b"abcd"[..]),
+                clock: StreamClock::new (StreamTimeouts::none ()),
+                expiry_timer: ExpiryTimer::none (),
+                read_errors: 0,
                 logger: Logger::new("test"),
             };
 
-            subject.run();
+            subject.start(&Arbiter::handle());
 
             system.run();
         });
@@ -393,9 +590,14 @@ mod tests {
         assert_eq!(hopper_recording.get_record::<IncipientCoresPackage>(0), &IncipientCoresPackage::new(
             test_utils::make_meaningless_route(),
             ClientResponsePayload {
-                stream_key: SocketAddr::from_str("1.2.3.4:80").unwrap(),
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
                 last_response: false,
+                stopped_reading: false,
                 data: PlainData::new(&b"HTTP/1.1 200 OK\r\n\r\n"[..]),
+                failure: None,
             },
             &Key::new(&b"abcd"[..])
         ));
@@ -6,21 +6,30 @@ use std::fmt;
 use std::io::Write;
 use std::io;
 use std::net::Shutdown;
+use std::time::Instant;
+use tokio_core::reactor::Remote;
+use sub_lib::stream_timeouts::StreamClock;
+use sub_lib::write_queue::WriteQueue;
 
+/// Clones write to the same stream, through the same WriteQueue
+#[derive (Clone)]
 pub struct StreamWriter {
-    stream: Box<TcpStreamWrapper>,
+    write_queue: WriteQueue,
     peer_addr: String,
+    clock: StreamClock
 }
 
 impl StreamWriter {
-    pub fn new (stream: Box<TcpStreamWrapper>) -> StreamWriter {
+    // The stream must already be registered with the reactor behind the Remote
+    pub fn new (stream: Box<TcpStreamWrapper>, remote: Remote, clock: StreamClock) -> StreamWriter {
         let peer_addr = match stream.peer_addr () {
             Ok (a) => format! ("{}", a),
             Err (_) => String::from ("<unknown>")
         };
         StreamWriter {
             peer_addr,
-            stream,
+            write_queue: WriteQueue::new (stream, remote),
+            clock
         }
     }
 }
@@ -31,21 +40,16 @@ impl Debug for StreamWriter {
     }
 }
 
-impl Clone for StreamWriter {
-    fn clone(&self) -> Self {
-        StreamWriter {
-            stream: self.stream.try_clone ().expect ("Error cloning stream"),
-            peer_addr: self.peer_addr.clone (),
-        }
-    }
-}
-
 impl Write for StreamWriter {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.stream.write (buf)
+        let result = self.write_queue.write (buf);
+        if result.is_ok () {self.clock.traffic (Instant::now ())}
+        result
     }
+
+    // Whatever the stream hasn't taken yet, the WriteQueue writes on its own
     fn flush(&mut self) -> io::Result<()> {
-        self.stream.flush ()
+        Ok (())
     }
 }
 
@@ -54,8 +58,13 @@ impl StreamWriter {
         self.peer_addr.clone ()
     }
 
+    /// The server has fallen too far behind what's been written to it
+    pub fn is_backed_up (&self) -> bool {
+        self.write_queue.is_backed_up ()
+    }
+
     pub fn shutdown (&mut self, shutdown: Shutdown) -> io::Result<()> {
-        self.stream.shutdown (shutdown)
+        self.write_queue.shutdown (shutdown)
     }
 }
 
@@ -63,17 +72,19 @@ impl StreamWriter {
 mod tests {
     use super::*;
     use local_test_utils::TcpStreamWrapperMock;
+    use local_test_utils::make_remote;
     use std::io::Error;
     use std::io::ErrorKind;
     use std::net::SocketAddr;
     use std::str::FromStr;
+    use sub_lib::stream_timeouts::StreamTimeouts;
 
     #[test]
     fn stream_writer_saves_peer_addr_when_available() {
         let write_stream = TcpStreamWrapperMock::new()
             .peer_addr_result(Ok(SocketAddr::from_str("1.2.3.4:5678").unwrap()));
 
-        let subject = StreamWriter::new(Box::new(write_stream));
+        let subject = StreamWriter::new(Box::new(write_stream), make_remote (), StreamClock::new (StreamTimeouts::none ()));
 
         assert_eq!(subject.peer_addr, String::from("1.2.3.4:5678"));
     }
@@ -83,7 +94,7 @@ mod tests {
         let write_stream = TcpStreamWrapperMock::new()
             .peer_addr_result(Err(Error::from(ErrorKind::AddrInUse)));
 
-        let subject = StreamWriter::new(Box::new(write_stream));
+        let subject = StreamWriter::new(Box::new(write_stream), make_remote (), StreamClock::new (StreamTimeouts::none ()));
 
         assert_eq!(subject.peer_addr, String::from("<unknown>"));
     }
@@ -0,0 +1,118 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+
+// Measures what it costs, in time and memory, to carry client data from the ProxyServer to the exit
+// Node's ProxyClient. Each 16KB chunk is made into a ClientRequestPayload, kept the way the send
+// window keeps it until it's acknowledged, wrapped and sent by the outbound Hopper, taken back off
+// the wire by the inbound Hopper, and decoded again. A counting allocator tallies every allocation
+// along the way. Run it with `cargo bench -p proxy_server_lib --bench request_path`.
+extern crate hopper_lib;
+extern crate proxy_server_lib;
+extern crate sub_lib;
+extern crate test_utils;
+
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::alloc::System;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use hopper_lib::package_processor::HopperOutcome;
+use hopper_lib::package_processor::HopperWork;
+use hopper_lib::package_processor::PackageProcessor;
+use proxy_server_lib::client_request_payload_factory::ClientRequestPayloadFactory;
+use sub_lib::cryptde::CryptDE;
+use sub_lib::cryptde::StreamKey;
+use sub_lib::dispatcher::InboundClientData;
+use sub_lib::hopper::IncipientCoresPackage;
+use sub_lib::logger::Logger;
+use sub_lib::proxy_server::ClientRequestPayload;
+use sub_lib::proxy_server::default_port_protocols;
+use test_utils::test_utils::cryptde;
+use test_utils::test_utils::route_from_proxy_server;
+
+const CHUNK_COUNT: usize = 20000;
+const CHUNK_SIZE: usize = 16384;
+// How many payloads are kept at once, as if they were waiting to be acknowledged
+const PAYLOADS_KEPT: usize = 64;
+
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new (0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new (0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new (0);
+static PEAK_LIVE_BYTES: AtomicUsize = AtomicUsize::new (0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc (&self, layout: Layout) -> *mut u8 {
+        ALLOCATED_BYTES.fetch_add (layout.size (), Ordering::Relaxed);
+        ALLOCATIONS.fetch_add (1, Ordering::Relaxed);
+        let live_bytes = LIVE_BYTES.fetch_add (layout.size (), Ordering::Relaxed) + layout.size ();
+        if live_bytes > PEAK_LIVE_BYTES.load (Ordering::Relaxed) {PEAK_LIVE_BYTES.store (live_bytes, Ordering::Relaxed)}
+        System.alloc (layout)
+    }
+
+    unsafe fn dealloc (&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub (layout.size (), Ordering::Relaxed);
+        System.dealloc (ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main () {
+    let cryptde = cryptde ();
+    let logger = Logger::new ("request_path");
+    let factory = ClientRequestPayloadFactory::new (&default_port_protocols ());
+    let processor = PackageProcessor::new (cryptde, None);
+    let route = route_from_proxy_server (&cryptde.public_key (), cryptde);
+    let socket_addr = SocketAddr::from_str ("1.2.3.4:5678").unwrap ();
+    let stream_key = StreamKey::new ();
+    let mut chunk = b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n".to_vec ();
+    chunk.resize (CHUNK_SIZE, b'x');
+    let mut kept: VecDeque<ClientRequestPayload> = VecDeque::new ();
+    let mut delivered_bytes = 0;
+
+    let live_bytes_before = LIVE_BYTES.load (Ordering::Relaxed);
+    PEAK_LIVE_BYTES.store (live_bytes_before, Ordering::Relaxed);
+    let allocated_bytes_before = ALLOCATED_BYTES.load (Ordering::Relaxed);
+    let allocations_before = ALLOCATIONS.load (Ordering::Relaxed);
+    let start = Instant::now ();
+    for _ in 0..CHUNK_COUNT {
+        // The StreamReader copies each chunk off the client's socket
+        let ibcd = InboundClientData {socket_addr, origin_port: Some (80), target_hostname: None, proxy_protocol: None,
+            last_data: false, stopped_reading: false, data: chunk.clone ().into ()};
+        let payload = factory.make (&ibcd, stream_key, cryptde, &logger).expect ("No payload");
+        kept.push_back (payload.clone ());
+        if kept.len () > PAYLOADS_KEPT {kept.pop_front ();}
+        let package = IncipientCoresPackage::new (route.clone (), payload, &cryptde.public_key ());
+        let transmissions = match processor.process (HopperWork::Outbound (package)) {
+            HopperOutcome::Transmit (transmissions) => transmissions,
+            _ => panic! ("Outbound Hopper transmitted nothing")
+        };
+        for transmission in transmissions {
+            // The exit Node's StreamReader copies it off its own socket
+            let inbound = InboundClientData {socket_addr, origin_port: None, target_hostname: None, proxy_protocol: None,
+                last_data: false, stopped_reading: false, data: transmission.data.to_vec ().into ()};
+            match processor.process (HopperWork::Inbound (inbound)) {
+                HopperOutcome::Deliver {package, ..} => {
+                    let request: ClientRequestPayload = package.payload ().expect ("Undecodable payload");
+                    delivered_bytes += request.data.data.len ();
+                },
+                _ => panic! ("Inbound Hopper delivered nothing")
+            }
+        }
+    }
+    let elapsed = start.elapsed ();
+    assert_eq! (delivered_bytes, CHUNK_COUNT * CHUNK_SIZE);
+
+    let elapsed_us = elapsed.as_secs () * 1000000 + u64::from (elapsed.subsec_nanos () / 1000);
+    println! ("{} {}-byte chunks:", CHUNK_COUNT, CHUNK_SIZE);
+    println! ("  bytes allocated   {:.0} MB", (ALLOCATED_BYTES.load (Ordering::Relaxed) - allocated_bytes_before) as f64 / 1048576.0);
+    println! ("  allocations       {}", ALLOCATIONS.load (Ordering::Relaxed) - allocations_before);
+    println! ("  time per chunk    {:.1}us", elapsed_us as f64 / CHUNK_COUNT as f64);
+    println! ("  peak live memory  {:.2} MB", (PEAK_LIVE_BYTES.load (Ordering::Relaxed) - live_bytes_before) as f64 / 1048576.0);
+}
@@ -2,41 +2,50 @@
 use std::collections::HashMap;
 use sub_lib::cryptde::CryptDE;
 use sub_lib::cryptde::PlainData;
+use sub_lib::cryptde::StreamKey;
 use sub_lib::dispatcher::InboundClientData;
 use sub_lib::logger::Logger;
 use sub_lib::proxy_server::ClientRequestPayload;
-use http_protocol_pack::HttpProtocolPack;
+use sub_lib::proxy_server::ProxyProtocol;
 use protocol_pack::ProtocolPack;
-use tls_protocol_pack::TlsProtocolPack;
+use protocol_pack::protocol_pack_for;
 
 pub struct ClientRequestPayloadFactory {
     protocol_packs: HashMap<u16, Box<ProtocolPack>>
 }
 
 impl ClientRequestPayloadFactory {
-    pub fn new () -> ClientRequestPayloadFactory {
-        let mut protocol_packs: HashMap<u16, Box<ProtocolPack>> = HashMap::new ();
-        protocol_packs.insert (80, Box::new (HttpProtocolPack{}));
-        protocol_packs.insert (443, Box::new (TlsProtocolPack{}));
+    pub fn new (port_protocols: &HashMap<u16, ProxyProtocol>) -> ClientRequestPayloadFactory {
+        let protocol_packs: HashMap<u16, Box<ProtocolPack>> = port_protocols.iter ()
+            .flat_map (|(port, protocol)| protocol_pack_for (*protocol).map (|protocol_pack| (*port, protocol_pack)))
+            .collect ();
         ClientRequestPayloadFactory {
             protocol_packs
         }
     }
 
-    pub fn make (&self, ibcd: &InboundClientData, cryptde: &CryptDE, logger: &Logger) -> Option<ClientRequestPayload> {
-        let plain_data = PlainData::new (&ibcd.data);
+    pub fn make (&self, ibcd: &InboundClientData, stream_key: StreamKey, cryptde: &CryptDE, logger: &Logger) -> Option<ClientRequestPayload> {
+        let plain_data = PlainData::from (ibcd.data.clone ());
         let origin_port = match ibcd.origin_port {
-            None => {logger.error (format! ("No origin port specified with {}-byte packet: {:?}", plain_data.data.len (), &plain_data.data)); return None},
+            None => {logger.error (format! ("No origin port specified with {}-byte packet: {:?}", plain_data.data.len (), &plain_data.data[..])); return None},
             Some (origin_port) => origin_port
         };
-        let protocol_pack = match self.protocol_packs.get (&origin_port) {
-            None => {logger.error (format! ("No protocol associated with origin port {} for {}-byte packet: {:?}", origin_port, plain_data.data.len (), &plain_data.data)); return None},
+        // What the Dispatcher saw on the stream trumps what the port is supposed to carry
+        let sniffed_protocol_pack = ibcd.proxy_protocol.and_then (protocol_pack_for);
+        let protocol_pack = match sniffed_protocol_pack.as_ref ().or_else (|| self.protocol_packs.get (&origin_port)) {
+            None => {logger.error (format! ("No protocol associated with origin port {} for {}-byte packet: {:?}", origin_port, plain_data.data.len (), &plain_data.data[..])); return None},
             Some (protocol_pack) => protocol_pack
         };
-        let host_name = protocol_pack.find_host_name (&plain_data);
+        // Old clients leave the hostname out; the address they connected to may still tell us
+        let host_name = protocol_pack.find_host_name (&plain_data).or_else (|| ibcd.target_hostname.clone ());
         Some (ClientRequestPayload {
-            stream_key: ibcd.socket_addr,
+            stream_key,
+            sequence_number: 0, // the ProxyServer numbers payloads and picks their paths as it sends them
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
             last_data: ibcd.last_data,
+            stopped_reading: false,
             data: plain_data,
             target_hostname: host_name,
             target_port: origin_port,
@@ -49,10 +58,11 @@ impl ClientRequestPayloadFactory {
 #[cfg (test)]
 mod tests {
     use super::*;
+    use bytes::Bytes;
     use std::net::SocketAddr;
     use std::str::FromStr;
     use sub_lib::cryptde_null::CryptDENull;
-    use sub_lib::proxy_server::ProxyProtocol;
+    use sub_lib::proxy_server::default_port_protocols;
     use test_utils::test_utils::init_test_logging;
     use test_utils::test_utils::TestLogHandler;
 
@@ -62,18 +72,27 @@ mod tests {
         let ibcd = InboundClientData {
             socket_addr: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
             origin_port: Some (80),
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
+            stopped_reading: false,
             data: data.data.clone (),
         };
+        let stream_key = StreamKey::new ();
         let cryptde = CryptDENull::new ();
         let logger = Logger::new ("test");
-        let subject = ClientRequestPayloadFactory::new ();
+        let subject = ClientRequestPayloadFactory::new (&default_port_protocols ());
 
-        let result = subject.make (&ibcd, &cryptde, &logger);
+        let result = subject.make (&ibcd, stream_key, &cryptde, &logger);
 
         assert_eq! (result, Some (ClientRequestPayload {
-            stream_key: SocketAddr::from_str ("1.2.3.4:5678").unwrap(),
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
             last_data: false,
+            stopped_reading: false,
             data,
             target_hostname: Some (String::from ("borkoed.com")),
             target_port: 80,
@@ -82,6 +101,43 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn handles_http_on_configured_port () {
+        let data = PlainData::new (&b"GET http://borkoed.com/fleebs.html HTTP/1.1\r\n\r\n"[..]);
+        let ibcd = InboundClientData {
+            socket_addr: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
+            origin_port: Some (8080),
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: data.data.clone (),
+        };
+        let stream_key = StreamKey::new ();
+        let cryptde = CryptDENull::new ();
+        let logger = Logger::new ("test");
+        let mut port_protocols = HashMap::new ();
+        port_protocols.insert (8080, ProxyProtocol::HTTP);
+        let subject = ClientRequestPayloadFactory::new (&port_protocols);
+
+        let result = subject.make (&ibcd, stream_key, &cryptde, &logger);
+
+        assert_eq! (result, Some (ClientRequestPayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
+            last_data: false,
+            stopped_reading: false,
+            data,
+            target_hostname: Some (String::from ("borkoed.com")),
+            target_port: 8080,
+            protocol: ProxyProtocol::HTTP,
+            originator_public_key: cryptde.public_key (),
+        }));
+    }
+
     #[test]
     fn handles_tls_with_hostname () {
         let data = PlainData::new (&[
@@ -105,18 +161,27 @@ mod tests {
         let ibcd = InboundClientData {
             socket_addr: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
             origin_port: Some (443),
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
+            stopped_reading: false,
             data: data.data.clone (),
         };
+        let stream_key = StreamKey::new ();
         let cryptde = CryptDENull::new ();
         let logger = Logger::new ("test");
-        let subject = ClientRequestPayloadFactory::new ();
+        let subject = ClientRequestPayloadFactory::new (&default_port_protocols ());
 
-        let result = subject.make (&ibcd, &cryptde, &logger);
+        let result = subject.make (&ibcd, stream_key, &cryptde, &logger);
 
         assert_eq! (result, Some (ClientRequestPayload {
-            stream_key: SocketAddr::from_str ("1.2.3.4:5678").unwrap(),
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
             last_data: false,
+            stopped_reading: false,
             data,
             target_hostname: Some (String::from ("server.com")),
             target_port: 443,
@@ -142,18 +207,27 @@ mod tests {
         let ibcd = InboundClientData {
             socket_addr: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
             origin_port: Some (443),
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: true,
+            stopped_reading: false,
             data: data.data.clone (),
         };
+        let stream_key = StreamKey::new ();
         let cryptde = CryptDENull::new ();
         let logger = Logger::new ("test");
-        let subject = ClientRequestPayloadFactory::new ();
+        let subject = ClientRequestPayloadFactory::new (&default_port_protocols ());
 
-        let result = subject.make (&ibcd, &cryptde, &logger);
+        let result = subject.make (&ibcd, stream_key, &cryptde, &logger);
 
         assert_eq! (result, Some (ClientRequestPayload {
-            stream_key: SocketAddr::from_str ("1.2.3.4:5678").unwrap(),
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
             last_data: true,
+            stopped_reading: false,
             data,
             target_hostname: None,
             target_port: 443,
@@ -162,20 +236,81 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn falls_back_to_mapped_hostname_when_data_has_none () {
+        let data = PlainData::new (&b"GET /fleebs.html HTTP/1.0\r\n\r\n"[..]);
+        let ibcd = InboundClientData {
+            socket_addr: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
+            origin_port: Some (80),
+            target_hostname: Some (String::from ("borkoed.com")),
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: data.data.clone (),
+        };
+        let stream_key = StreamKey::new ();
+        let cryptde = CryptDENull::new ();
+        let logger = Logger::new ("test");
+        let subject = ClientRequestPayloadFactory::new (&default_port_protocols ());
+
+        let result = subject.make (&ibcd, stream_key, &cryptde, &logger);
+
+        assert_eq! (result.unwrap ().target_hostname, Some (String::from ("borkoed.com")));
+    }
+
+    #[test]
+    fn sniffed_protocol_overrides_port_protocol () {
+        let data = PlainData::new (&b"GET http://borkoed.com/fleebs.html HTTP/1.1\r\n\r\n"[..]);
+        let ibcd = InboundClientData {
+            socket_addr: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
+            origin_port: Some (443),
+            target_hostname: None,
+            proxy_protocol: Some (ProxyProtocol::HTTP),
+            last_data: false,
+            stopped_reading: false,
+            data: data.data.clone (),
+        };
+        let stream_key = StreamKey::new ();
+        let cryptde = CryptDENull::new ();
+        let logger = Logger::new ("test");
+        let subject = ClientRequestPayloadFactory::new (&default_port_protocols ());
+
+        let result = subject.make (&ibcd, stream_key, &cryptde, &logger);
+
+        assert_eq! (result, Some (ClientRequestPayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (),
+            last_data: false,
+            stopped_reading: false,
+            data,
+            target_hostname: Some (String::from ("borkoed.com")),
+            target_port: 443,
+            protocol: ProxyProtocol::HTTP,
+            originator_public_key: cryptde.public_key (),
+        }));
+    }
+
     #[test]
     fn makes_no_payload_if_origin_port_is_not_specified () {
         init_test_logging();
         let ibcd = InboundClientData {
             socket_addr: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
             origin_port: None,
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
-            data: vec!(0x10, 0x11, 0x12),
+            stopped_reading: false,
+            data: Bytes::from (vec!(0x10, 0x11, 0x12)),
         };
+        let stream_key = StreamKey::new ();
         let cryptde = CryptDENull::new ();
         let logger = Logger::new ("test");
-        let subject = ClientRequestPayloadFactory::new ();
+        let subject = ClientRequestPayloadFactory::new (&default_port_protocols ());
 
-        let result = subject.make (&ibcd, &cryptde, &logger);
+        let result = subject.make (&ibcd, stream_key, &cryptde, &logger);
 
         assert_eq! (result, None);
         TestLogHandler::new ().exists_log_containing ("ERROR: test: No origin port specified with 3-byte packet: [16, 17, 18]");
@@ -187,14 +322,18 @@ mod tests {
         let ibcd = InboundClientData {
             socket_addr: SocketAddr::from_str ("1.2.3.4:5678").unwrap (),
             origin_port: Some (1234),
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
-            data: vec!(0x10, 0x11, 0x12),
+            stopped_reading: false,
+            data: Bytes::from (vec!(0x10, 0x11, 0x12)),
         };
+        let stream_key = StreamKey::new ();
         let cryptde = CryptDENull::new ();
         let logger = Logger::new ("test");
-        let subject = ClientRequestPayloadFactory::new ();
+        let subject = ClientRequestPayloadFactory::new (&default_port_protocols ());
 
-        let result = subject.make (&ibcd, &cryptde, &logger);
+        let result = subject.make (&ibcd, stream_key, &cryptde, &logger);
 
         assert_eq! (result, None);
         TestLogHandler::new ().exists_log_containing ("ERROR: test: No protocol associated with origin port 1234 for 3-byte packet: [16, 17, 18]");
@@ -0,0 +1,208 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use sub_lib::proxy_server::HeaderRule;
+use sub_lib::utils::index_of;
+
+const CRLF: &[u8] = b"\r\n";
+const DOUBLE_CRLF: &[u8] = b"\r\n\r\n";
+
+enum HeaderFate {
+    Keep,
+    Drop,
+    Replace (String)
+}
+
+/// Rewrites the header section of plain HTTP requests on their way out of the Node so that they say
+/// less about whoever sent them. The request line and the body go through exactly as they came, and
+/// the configuration refuses rules for the headers that frame the request, so Content-Length stays right.
+pub struct HeaderScrubber {
+    rules: Vec<HeaderRule>
+}
+
+impl HeaderScrubber {
+    pub fn new (rules: Vec<HeaderRule>) -> HeaderScrubber {
+        HeaderScrubber {rules}
+    }
+
+    /// Anything that doesn't start with a complete request head (the rest of a long chunked body,
+    /// for instance) comes back unchanged.
+    pub fn scrub (&self, data: &[u8], target_hostname: Option<&str>) -> Vec<u8> {
+        if self.rules.is_empty () {return data.to_vec ()}
+        let (first_line_end, head_end) = match HeaderScrubber::find_request_head (data) {
+            Some (offsets) => offsets,
+            None => return data.to_vec ()
+        };
+        let mut result = data[..first_line_end + CRLF.len ()].to_vec ();
+        // Folded continuation lines follow the fate of the header they continue
+        let mut keeping = true;
+        let mut remaining = &data[first_line_end + CRLF.len ()..head_end + CRLF.len ()];
+        while let Some (line_end) = index_of (remaining, CRLF) {
+            let line = &remaining[..line_end];
+            remaining = &remaining[line_end + CRLF.len ()..];
+            if line.starts_with (b" ") || line.starts_with (b"\t") {
+                if keeping {result.extend (line); result.extend (CRLF);}
+                continue
+            }
+            keeping = false;
+            match self.fate_of (line, target_hostname) {
+                HeaderFate::Keep => {keeping = true; result.extend (line); result.extend (CRLF)},
+                HeaderFate::Drop => (),
+                HeaderFate::Replace (header) => {result.extend (header.as_bytes ()); result.extend (CRLF)}
+            }
+        }
+        result.extend (CRLF);
+        result.extend (&data[head_end + DOUBLE_CRLF.len ()..]);
+        result
+    }
+
+    // Returns the offsets of the end of the request line and of the end of the last header line
+    fn find_request_head (data: &[u8]) -> Option<(usize, usize)> {
+        let first_line_end = index_of (data, CRLF)?;
+        let first_line = String::from_utf8_lossy (&data[..first_line_end]);
+        let pieces: Vec<&str> = first_line.split (' ').collect ();
+        if (pieces.len () != 3) || !pieces[2].starts_with ("HTTP/") {return None}
+        if data[first_line_end..].starts_with (DOUBLE_CRLF) {return Some ((first_line_end, first_line_end))}
+        let head_end = index_of (&data[first_line_end..], DOUBLE_CRLF)? + first_line_end;
+        Some ((first_line_end, head_end))
+    }
+
+    fn fate_of (&self, line: &[u8], target_hostname: Option<&str>) -> HeaderFate {
+        let line = String::from_utf8_lossy (line);
+        let (name, value) = match line.find (':') {
+            Some (index) => (line[..index].trim (), line[index + 1..].trim ()),
+            None => return HeaderFate::Keep
+        };
+        for rule in &self.rules {
+            match rule {
+                &HeaderRule::Remove {name: ref rule_name} if rule_name.eq_ignore_ascii_case (name) =>
+                    return HeaderFate::Drop,
+                &HeaderRule::Rewrite {name: ref rule_name, ref value} if rule_name.eq_ignore_ascii_case (name) =>
+                    return HeaderFate::Replace (format! ("{}: {}", name, value)),
+                &HeaderRule::StripCrossOriginReferer if name.eq_ignore_ascii_case ("Referer")
+                    && !HeaderScrubber::is_same_host (value, target_hostname) => return HeaderFate::Drop,
+                _ => ()
+            }
+        }
+        HeaderFate::Keep
+    }
+
+    // Compares hostnames only: a page on http://server.com may refer to https://server.com:8443.
+    // If we don't know where the request is going, we can't vouch for the Referer.
+    fn is_same_host (referer: &str, target_hostname: Option<&str>) -> bool {
+        let target_hostname = match target_hostname {
+            Some (hostname) => hostname,
+            None => return false
+        };
+        let after_scheme = match referer.find ("://") {
+            Some (index) => &referer[index + 3..],
+            None => return false
+        };
+        let authority = after_scheme.split (|c| (c == '/') || (c == '?') || (c == '#')).next ().unwrap_or ("");
+        let host_and_port = authority.rsplit ('@').next ().unwrap_or ("");
+        let host = match host_and_port.rfind (':') {
+            Some (index) if !host_and_port.ends_with (']') => &host_and_port[..index],
+            _ => host_and_port
+        };
+        host.eq_ignore_ascii_case (target_hostname)
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+
+    fn scrub (rules: Vec<HeaderRule>, data: &[u8], target_hostname: Option<&str>) -> String {
+        String::from_utf8 (HeaderScrubber::new (rules).scrub (data, target_hostname)).unwrap ()
+    }
+
+    fn remove (name: &str) -> HeaderRule {
+        HeaderRule::Remove {name: String::from (name)}
+    }
+
+    #[test]
+    fn without_rules_requests_pass_verbatim () {
+        let request = b"GET / HTTP/1.1\r\nHost: server.com\r\nVia: 1.1 snoop\r\n\r\n";
+
+        let result = scrub (vec! (), request, Some ("server.com"));
+
+        assert_eq! (result.as_bytes (), &request[..]);
+    }
+
+    #[test]
+    fn removes_named_headers_regardless_of_case_and_leaves_the_body_alone () {
+        let request = b"POST /form HTTP/1.1\r\nHost: server.com\r\nx-forwarded-for: 10.0.0.1\r\nContent-Length: 22\r\nVIA: 1.1 snoop\r\nVia: 1.0 other\r\n\r\nVia: 1.1 body\r\n\r\nstuff";
+
+        let result = scrub (vec! (remove ("X-Forwarded-For"), remove ("Via")), request, Some ("server.com"));
+
+        assert_eq! (result, "POST /form HTTP/1.1\r\nHost: server.com\r\nContent-Length: 22\r\n\r\nVia: 1.1 body\r\n\r\nstuff");
+    }
+
+    #[test]
+    fn rewrites_headers_that_are_present_without_adding_missing_ones () {
+        let request = b"GET / HTTP/1.1\r\nHost: server.com\r\nuser-agent: Booga/1.0 (Wooga 7; x86)\r\n\r\n";
+        let rules = vec! (
+            HeaderRule::Rewrite {name: String::from ("User-Agent"), value: String::from ("Mozilla/5.0")},
+            HeaderRule::Rewrite {name: String::from ("Accept-Language"), value: String::from ("en")}
+        );
+
+        let result = scrub (rules, request, Some ("server.com"));
+
+        assert_eq! (result, "GET / HTTP/1.1\r\nHost: server.com\r\nuser-agent: Mozilla/5.0\r\n\r\n");
+    }
+
+    #[test]
+    fn first_matching_rule_wins () {
+        let request = b"GET / HTTP/1.1\r\nCookie: id=12345\r\n\r\n";
+        let rules = vec! (
+            HeaderRule::Rewrite {name: String::from ("Cookie"), value: String::from ("id=0")},
+            remove ("Cookie")
+        );
+
+        let result = scrub (rules, request, None);
+
+        assert_eq! (result, "GET / HTTP/1.1\r\nCookie: id=0\r\n\r\n");
+    }
+
+    #[test]
+    fn folded_continuation_lines_go_with_their_header () {
+        let request = b"GET / HTTP/1.1\r\nX-Tracking: abc\r\n def\r\nAccept: text/html,\r\n\ttext/plain\r\nX-Rewritten: abc\r\n def\r\n\r\n";
+        let rules = vec! (
+            remove ("X-Tracking"),
+            HeaderRule::Rewrite {name: String::from ("X-Rewritten"), value: String::from ("xyz")}
+        );
+
+        let result = scrub (rules, request, None);
+
+        assert_eq! (result, "GET / HTTP/1.1\r\nAccept: text/html,\r\n\ttext/plain\r\nX-Rewritten: xyz\r\n\r\n");
+    }
+
+    #[test]
+    fn strips_cross_origin_referer_but_keeps_same_host_referer () {
+        let rules = vec! (HeaderRule::StripCrossOriginReferer);
+
+        let cross = scrub (rules.clone (), b"GET / HTTP/1.1\r\nHost: server.com\r\nReferer: https://tracker.com/page?server.com\r\n\r\n", Some ("server.com"));
+        let same = scrub (rules.clone (), b"GET / HTTP/1.1\r\nHost: server.com\r\nReferer: https://user@SERVER.com:8443/page\r\n\r\n", Some ("server.com"));
+        let unknown = scrub (rules.clone (), b"GET / HTTP/1.1\r\nReferer: http://server.com/\r\n\r\n", None);
+
+        assert_eq! (cross, "GET / HTTP/1.1\r\nHost: server.com\r\n\r\n");
+        assert_eq! (same, "GET / HTTP/1.1\r\nHost: server.com\r\nReferer: https://user@SERVER.com:8443/page\r\n\r\n");
+        assert_eq! (unknown, "GET / HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn request_without_headers_survives () {
+        let result = scrub (vec! (remove ("Via")), b"GET / HTTP/1.0\r\n\r\n", None);
+
+        assert_eq! (result, "GET / HTTP/1.0\r\n\r\n");
+    }
+
+    #[test]
+    fn data_that_isnt_a_complete_request_head_passes_verbatim () {
+        let rules = vec! (remove ("Via"));
+
+        let body_chunk = scrub (rules.clone (), b"5\r\nVia: x\r\n\r\n", None);
+        let partial_head = scrub (rules.clone (), b"GET / HTTP/1.1\r\nVia: x\r\n", None);
+
+        assert_eq! (body_chunk, "5\r\nVia: x\r\n\r\n");
+        assert_eq! (partial_head, "GET / HTTP/1.1\r\nVia: x\r\n");
+    }
+}
@@ -1,5 +1,6 @@
 use sub_lib::proxy_server::ProxyProtocol;
 use sub_lib::cryptde::PlainData;
+use sub_lib::proxy_client::StreamFailure;
 use sub_lib::utils::index_of;
 use protocol_pack::ProtocolPack;
 
@@ -15,10 +16,42 @@ impl ProtocolPack for HttpProtocolPack {
         }
         HttpProtocolPack::find_url_host_name (&data.data[..])
     }
+
+    fn failure_response (&self, failure: StreamFailure, hostname: Option<&str>) -> Vec<u8> {
+        let site = HttpProtocolPack::escape_html (hostname.unwrap_or ("the site"));
+        let (status, explanation) = match failure {
+            StreamFailure::MissingHostname => ("400 Bad Request", String::from ("The request didn't say which site it was for.")),
+            StreamFailure::DnsFailure => ("502 Bad Gateway", format! ("The exit Node couldn't find an address for {}.", site)),
+            StreamFailure::ConnectionRefused => ("502 Bad Gateway", format! ("{} refused the exit Node's connection.", site)),
+            StreamFailure::Timeout => ("504 Gateway Timeout", format! ("{} took too long to answer the exit Node.", site)),
+            StreamFailure::ConnectionFailed => ("502 Bad Gateway", format! ("The exit Node couldn't talk to {}.", site)),
+            StreamFailure::PolicyBlock => ("403 Forbidden", format! ("{} is blocked.", site)),
+        };
+        let body = format! ("<html><head><title>{}</title></head><body><h1>{}</h1><p>{}</p><p>SubstratumNode</p></body></html>\n",
+            status, status, explanation);
+        let head = format! ("HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status, body.len ());
+        (head + &body).into_bytes ()
+    }
 }
 
 impl HttpProtocolPack {
 
+    // Hostnames come from whatever the client sent us, so they don't go into a page unescaped
+    fn escape_html (text: &str) -> String {
+        text.chars ().fold (String::new (), |mut escaped, c| {
+            match c {
+                '<' => escaped.push_str ("&lt;"),
+                '>' => escaped.push_str ("&gt;"),
+                '&' => escaped.push_str ("&amp;"),
+                '"' => escaped.push_str ("&quot;"),
+                '\'' => escaped.push_str ("&#39;"),
+                c => escaped.push (c)
+            }
+            escaped
+        })
+    }
+
     fn find_header_host_name (data: &[u8]) -> Option<String> {
         let idx = index_of (data, &b"\r\n\r\n"[..])?;
         let headers = &data[0..idx + 2];
@@ -72,6 +105,29 @@ mod tests {
         assert_eq! (result, ProxyProtocol::HTTP);
     }
 
+    #[test]
+    fn failure_response_is_a_complete_error_page_with_an_escaped_hostname () {
+        let result = String::from_utf8 (HttpProtocolPack{}.failure_response (StreamFailure::DnsFailure, Some ("<b>bad</b>.com"))).unwrap ();
+
+        let body = "<html><head><title>502 Bad Gateway</title></head><body><h1>502 Bad Gateway</h1><p>The exit Node couldn't find an address for &lt;b&gt;bad&lt;/b&gt;.com.</p><p>SubstratumNode</p></body></html>\n";
+        assert_eq! (result, format! ("HTTP/1.1 502 Bad Gateway\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len (), body));
+    }
+
+    #[test]
+    fn failure_response_status_depends_on_the_failure () {
+        let status_of = |failure, hostname| {
+            let response = HttpProtocolPack{}.failure_response (failure, hostname);
+            String::from_utf8 (response[..index_of (&response[..], b"\r\n").unwrap ()].to_vec ()).unwrap ()
+        };
+
+        assert_eq! (status_of (StreamFailure::MissingHostname, None), "HTTP/1.1 400 Bad Request");
+        assert_eq! (status_of (StreamFailure::ConnectionRefused, Some ("server.com")), "HTTP/1.1 502 Bad Gateway");
+        assert_eq! (status_of (StreamFailure::Timeout, Some ("server.com")), "HTTP/1.1 504 Gateway Timeout");
+        assert_eq! (status_of (StreamFailure::ConnectionFailed, Some ("server.com")), "HTTP/1.1 502 Bad Gateway");
+        assert_eq! (status_of (StreamFailure::PolicyBlock, Some ("server.com")), "HTTP/1.1 403 Forbidden");
+    }
+
     #[test]
     fn returns_none_if_no_double_crlf () {
         let data = PlainData::new (b"no\r\ndouble\r\ncrlf\r\n");
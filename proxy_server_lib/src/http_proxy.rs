@@ -0,0 +1,105 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use sub_lib::utils::index_of;
+
+pub const CONNECT_RESPONSE: &[u8] = b"HTTP/1.1 200 Connection established\r\n\r\n";
+
+// A stream goes to one server, so a client that wants another one has to open another connection
+pub const NEW_TARGET_RESPONSE: &[u8] = b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+
+const DEFAULT_HTTP_PORT: u16 = 80;
+
+/// What a client pointed at the forward-proxy port has asked for
+#[derive (Debug, PartialEq)]
+pub enum HttpProxyRequest {
+    // CONNECT host:port: open a tunnel and pass everything after this through untouched
+    Connect {hostname: String, port: u16},
+    // GET http://host:port/path: send the request, rewritten to origin form, to host:port
+    Forward {hostname: String, port: u16, data: Vec<u8>}
+}
+
+pub fn parse_http_proxy_request (data: &[u8]) -> Option<HttpProxyRequest> {
+    let line_end = index_of (data, &b"\r\n"[..])?;
+    let first_line = String::from_utf8 (data[0..line_end].to_vec ()).ok ()?;
+    let pieces: Vec<&str> = first_line.split (' ').collect ();
+    if pieces.len () != 3 {return None}
+    let (method, target, version) = (pieces[0], pieces[1], pieces[2]);
+    if method == "CONNECT" {
+        let (hostname, port) = split_authority (target, None)?;
+        return Some (HttpProxyRequest::Connect {hostname, port})
+    }
+    let scheme = "http://";
+    if !target.to_lowercase ().starts_with (scheme) {return None}
+    let remainder = &target[scheme.len ()..];
+    let (authority, path) = match remainder.find ('/') {
+        Some (index) => (&remainder[..index], &remainder[index..]),
+        None => (remainder, "/")
+    };
+    let (hostname, port) = split_authority (authority, Some (DEFAULT_HTTP_PORT))?;
+    let mut rewritten = format! ("{} {} {}", method, path, version).into_bytes ();
+    rewritten.extend (&data[line_end..]);
+    Some (HttpProxyRequest::Forward {hostname, port, data: rewritten})
+}
+
+fn split_authority (authority: &str, default_port: Option<u16>) -> Option<(String, u16)> {
+    let (hostname, port) = match authority.rfind (':') {
+        Some (index) => (&authority[..index], authority[index + 1..].parse::<u16> ().ok ()?),
+        None => (authority, default_port?)
+    };
+    if hostname.is_empty () {return None}
+    Some ((String::from (hostname), port))
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_request_becomes_tunnel_to_host_and_port () {
+        let result = parse_http_proxy_request (b"CONNECT secure.com:8443 HTTP/1.1\r\nHost: secure.com:8443\r\n\r\n");
+
+        assert_eq! (result, Some (HttpProxyRequest::Connect {hostname: String::from ("secure.com"), port: 8443}));
+    }
+
+    #[test]
+    fn connect_request_without_port_is_rejected () {
+        let result = parse_http_proxy_request (b"CONNECT secure.com HTTP/1.1\r\n\r\n");
+
+        assert_eq! (result, None);
+    }
+
+    #[test]
+    fn absolute_form_request_is_rewritten_to_origin_form () {
+        let result = parse_http_proxy_request (b"GET http://borkoed.com:8080/fleebs.html?x=y HTTP/1.1\r\nHost: borkoed.com:8080\r\n\r\n");
+
+        assert_eq! (result, Some (HttpProxyRequest::Forward {
+            hostname: String::from ("borkoed.com"),
+            port: 8080,
+            data: b"GET /fleebs.html?x=y HTTP/1.1\r\nHost: borkoed.com:8080\r\n\r\n".to_vec ()
+        }));
+    }
+
+    #[test]
+    fn absolute_form_request_without_port_or_path_defaults_both () {
+        let result = parse_http_proxy_request (b"HEAD http://borkoed.com HTTP/1.1\r\n\r\n");
+
+        assert_eq! (result, Some (HttpProxyRequest::Forward {
+            hostname: String::from ("borkoed.com"),
+            port: 80,
+            data: b"HEAD / HTTP/1.1\r\n\r\n".to_vec ()
+        }));
+    }
+
+    #[test]
+    fn origin_form_request_is_not_a_proxy_request () {
+        let result = parse_http_proxy_request (b"GET /fleebs.html HTTP/1.1\r\nHost: borkoed.com\r\n\r\n");
+
+        assert_eq! (result, None);
+    }
+
+    #[test]
+    fn request_without_complete_first_line_is_not_a_proxy_request () {
+        let result = parse_http_proxy_request (b"GET http://borkoed.com/ HTTP/1.1");
+
+        assert_eq! (result, None);
+    }
+}
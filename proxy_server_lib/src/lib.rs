@@ -2,12 +2,16 @@
 extern crate sub_lib;
 extern crate serde_cbor;
 extern crate actix;
+extern crate bytes;
 
 #[cfg (test)]
 extern crate test_utils;
 
 pub mod client_request_payload_factory;
 pub mod proxy_server;
+pub mod header_scrubber;
 pub mod http_protocol_pack;
+pub mod http_proxy;
 pub mod protocol_pack;
+pub mod socks;
 pub mod tls_protocol_pack;
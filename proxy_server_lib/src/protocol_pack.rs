@@ -1,7 +1,21 @@
 use sub_lib::proxy_server::ProxyProtocol;
+use sub_lib::proxy_client::StreamFailure;
 use sub_lib::cryptde::PlainData;
+use http_protocol_pack::HttpProtocolPack;
+use tls_protocol_pack::TlsProtocolPack;
 
 pub trait ProtocolPack {
     fn proxy_protocol (&self) -> ProxyProtocol;
     fn find_host_name (&self, data: &PlainData) -> Option<String>;
+    // What to send the client, just before closing its stream, to tell it why it's being closed
+    fn failure_response (&self, failure: StreamFailure, hostname: Option<&str>) -> Vec<u8>;
+}
+
+// Tunneled data is opaque, so there's nothing for a ProtocolPack to look at
+pub fn protocol_pack_for (protocol: ProxyProtocol) -> Option<Box<ProtocolPack>> {
+    match protocol {
+        ProxyProtocol::HTTP => Some (Box::new (HttpProtocolPack {})),
+        ProxyProtocol::TLS => Some (Box::new (TlsProtocolPack {})),
+        ProxyProtocol::Tunnel => None
+    }
 }
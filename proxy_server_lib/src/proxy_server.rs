@@ -1,30 +1,183 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::HashMap;
+use std::cmp::min;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::time::Instant;
+use actix;
 use actix::Actor;
 use actix::Addr;
+use actix::AsyncContext;
 use actix::Context;
+use actix::fut::ActorFuture;
+use actix::fut::wrap_future;
 use actix::Handler;
 use actix::Recipient;
 use actix::Syn;
+use bytes::Bytes;
+use sub_lib::blocklist::BlockSource;
+use sub_lib::blocklist::Blocklist;
 use sub_lib::cryptde::CryptDE;
+use sub_lib::cryptde::Key;
+use sub_lib::cryptde::PlainData;
+use sub_lib::cryptde::StreamKey;
 use sub_lib::dispatcher::Component;
 use sub_lib::dispatcher::Endpoint;
 use sub_lib::dispatcher::InboundClientData;
 use sub_lib::hopper::IncipientCoresPackage;
 use sub_lib::hopper::ExpiredCoresPackage;
 use sub_lib::logger::Logger;
+use sub_lib::outbox::Outbox;
+use sub_lib::multipath::DEFAULT_MAX_PATH_LAG;
+use sub_lib::multipath::PathMonitor;
+use sub_lib::neighborhood::RouteQueryMessage;
+use sub_lib::neighborhood::RouteQueryResponse;
 use sub_lib::peer_actors::BindMessage;
 use sub_lib::proxy_client::ClientResponsePayload;
+use sub_lib::proxy_client::StreamFailure;
+use sub_lib::proxy_server::ClientRequestPayload;
+use sub_lib::proxy_server::ProxyProtocol;
+use sub_lib::proxy_server::ProxyServerConfig;
 use sub_lib::proxy_server::ProxyServerSubs;
+use sub_lib::reorder_buffer::DEFAULT_MAX_REORDER_BYTES;
+use sub_lib::reorder_buffer::default_reorder_gap_timeout;
+use sub_lib::reorder_buffer::ReorderBuffer;
 use sub_lib::route::Route;
 use sub_lib::route::RouteSegment;
+use sub_lib::send_window::MAX_TRANSMISSIONS;
+use sub_lib::send_window::RELIABILITY_TICK_MS;
+use sub_lib::send_window::SendWindow;
+use sub_lib::stream_handler_pool::PauseReadingMsg;
 use sub_lib::stream_handler_pool::TransmitDataMsg;
+use sub_lib::stream_handler_pool::WriteBacklogMsg;
+use sub_lib::stream_timeouts::StreamClock;
+use sub_lib::stream_timeouts::StreamTimeouts;
 use sub_lib::utils::NODE_MAILBOX_CAPACITY;
+use sub_lib::utils::to_string;
 use client_request_payload_factory::ClientRequestPayloadFactory;
+use header_scrubber::HeaderScrubber;
+use http_proxy::CONNECT_RESPONSE;
+use http_proxy::NEW_TARGET_RESPONSE;
+use http_proxy::HttpProxyRequest;
+use http_proxy::parse_http_proxy_request;
+use protocol_pack::protocol_pack_for;
+use socks::negotiate;
+use socks::SocksState;
+use socks::SocksStep;
+
+// A stream that hasn't heard from its exit yet can still move to another one, as long as it
+// hasn't sent more than this
+const MAX_RETRY_BUFFER_BYTES: usize = 65536;
+
+// A request that has to be sent this many times suggests the stream's routes have broken somewhere
+// along the way, so fresh ones to the same exit are sought
+const REROUTE_AFTER_TRANSMISSIONS: u32 = 3;
+
+// Everything a new stream has sent so far, in case its exit can't reach the server
+struct StreamSetup {
+    payloads: Vec<ClientRequestPayload>,
+    buffered_bytes: usize,
+    exit_key: Key,
+    failed_exits: Vec<Key>,
+    awaiting_route: bool
+}
+
+// How a stream reaches its exit. Every chunk of a stream goes to the same exit, taking the circuit's
+// disjoint routes to it in turn; responses come back along those routes' return segments.
+struct Circuit {
+    // Each route is known to both ends by the path ID the payloads that take it carry
+    paths: Vec<(u8, Route)>,
+    next_path_id: u8,
+    turn: usize,
+    exit_key: Key,
+    // An empty payload for the stream, for opening and closing paths when the client has nothing to send
+    template: ClientRequestPayload,
+    monitor: PathMonitor,
+    widened: bool,
+    // Requests and responses both keep the circuit from going idle
+    clock: StreamClock
+}
+
+impl Circuit {
+    fn new (route: Route, exit_key: Key, payload: &ClientRequestPayload, timeouts: StreamTimeouts) -> Circuit {
+        let mut template = payload.clone ();
+        template.last_data = false;
+        template.stopped_reading = false;
+        template.data = PlainData::new (&[]);
+        Circuit {
+            paths: vec! ((0, route)),
+            next_path_id: 1,
+            turn: 0,
+            exit_key,
+            template,
+            monitor: PathMonitor::new (DEFAULT_MAX_PATH_LAG),
+            widened: false,
+            clock: StreamClock::new (timeouts)
+        }
+    }
+
+    fn add_path (&mut self, route: Route) -> usize {
+        self.paths.push ((self.next_path_id, route));
+        self.next_path_id += 1;
+        self.paths.len () - 1
+    }
+
+    // The last path is never removed
+    fn remove_path (&mut self, path_id: u8) -> bool {
+        if self.paths.len () < 2 {return false}
+        let before = self.paths.len ();
+        self.paths.retain (|&(id, _)| id != path_id);
+        self.monitor.remove_path (path_id);
+        self.paths.len () < before
+    }
+
+    fn next_path (&mut self) -> usize {
+        let index = self.turn % self.paths.len ();
+        self.turn = index + 1;
+        index
+    }
+
+    fn package (&self, mut payload: ClientRequestPayload, index: usize) -> IncipientCoresPackage {
+        let (path_id, ref route) = self.paths[index];
+        payload.path_id = path_id;
+        payload.open_paths = self.paths.iter ().map (|&(id, _)| id).collect ();
+        IncipientCoresPackage::new (route.clone (), payload, &self.exit_key)
+    }
+}
 
 pub struct ProxyServer {
     dispatcher: Option<Recipient<Syn, TransmitDataMsg>>,
-    hopper: Option<Recipient<Syn, IncipientCoresPackage>>,
+    pause_reading: Option<Recipient<Syn, PauseReadingMsg>>,
+    hopper: Option<Outbox<IncipientCoresPackage>>,
+    route_query: Option<Recipient<Syn, RouteQueryMessage>>,
     client_request_payload_factory: ClientRequestPayloadFactory,
+    header_scrubber: HeaderScrubber,
+    blocklist: Blocklist,
+    config: ProxyServerConfig,
+    tunnels: HashMap<SocketAddr, (String, u16)>,
+    // Where each HTTP proxy client's stream goes; the exit can't move a stream to another server
+    http_proxy_targets: HashMap<SocketAddr, (String, u16)>,
+    socks_negotiations: HashMap<SocketAddr, SocksState>,
+    // Clients' connections are known by their addresses here, but by their StreamKeys on the network
+    stream_keys: HashMap<SocketAddr, StreamKey>,
+    stream_sockets: HashMap<StreamKey, SocketAddr>,
+    stream_protocols: HashMap<StreamKey, (ProxyProtocol, Option<String>)>,
+    stream_setups: HashMap<StreamKey, StreamSetup>,
+    circuits: HashMap<StreamKey, Circuit>,
+    request_sequence_numbers: HashMap<StreamKey, u64>,
+    // Requests are kept until the exit acknowledges them
+    request_windows: HashMap<StreamKey, SendWindow<ClientRequestPayload>>,
+    // Streams whose clients aren't read from until the exit acknowledges more of their requests
+    paused_clients: HashSet<StreamKey>,
+    response_buffers: HashMap<StreamKey, ReorderBuffer<ClientResponsePayload>>,
+    reorder_gap_timeout: Duration,
+    // Streams whose exits haven't yet heard about the latest responses to arrive
+    acks_owed: HashSet<StreamKey>,
+    // Streams whose clients are behind on their responses, and how far the exits have heard they got
+    ack_limits: HashMap<StreamKey, u64>,
+    // Streams whose exits have finished responding, which are forgotten once their clients have finished too
+    responses_finished: HashSet<StreamKey>,
     cryptde: &'static CryptDE,
     logger: Logger
 }
@@ -39,7 +192,10 @@ impl Handler<BindMessage> for ProxyServer {
     fn handle(&mut self, msg: BindMessage, ctx: &mut Self::Context) -> Self::Result {
         ctx.set_mailbox_capacity(NODE_MAILBOX_CAPACITY);
         self.dispatcher = Some(msg.peer_actors.dispatcher.from_proxy_server);
-        self.hopper = Some(msg.peer_actors.hopper.from_hopper_client);
+        self.pause_reading = Some(msg.peer_actors.dispatcher.pause_reading);
+        self.hopper = Some(Outbox::new (msg.peer_actors.hopper.from_hopper_client, "Hopper"));
+        self.route_query = Some(msg.peer_actors.neighborhood.route_query);
+        self.schedule_tick (ctx);
         ()
     }
 }
@@ -47,36 +203,79 @@ impl Handler<BindMessage> for ProxyServer {
 impl Handler<InboundClientData> for ProxyServer {
     type Result = ();
 
-    fn handle(&mut self, msg: InboundClientData, _ctx: &mut Self::Context) -> Self::Result {
-        let hopper = self.hopper.as_ref ().expect ("Hopper unbound in ProxyServer");
-        let payload = match self.client_request_payload_factory.make (&msg, self.cryptde, &self.logger) {
-            None => { self.logger.error(format! ("Couldn't create ClientRequestPayload")); return (); },
-            Some (payload) => payload
+    fn handle(&mut self, msg: InboundClientData, ctx: &mut Self::Context) -> Self::Result {
+        self.hopper.as_ref ().expect ("Hopper unbound in ProxyServer");
+        let socket_addr = msg.socket_addr;
+        let payload = match self.tunnels.get (&msg.socket_addr).cloned () {
+            Some ((hostname, port)) => {
+                if msg.last_data {self.tunnels.remove (&msg.socket_addr);}
+                self.make_payload (&msg, PlainData::from (msg.data.clone ()), Some (hostname), port, ProxyProtocol::Tunnel)
+            },
+            None if ProxyServer::is_from_port (&msg, self.config.http_proxy_port) => {
+                match self.make_http_proxy_payload (msg) {
+                    None => return (),
+                    Some (payload) => payload
+                }
+            },
+            None if ProxyServer::is_from_port (&msg, self.config.socks_port) => {
+                return self.negotiate_socks (msg)
+            },
+            None if ProxyServer::is_from_tcp_forward_port (&msg, &self.config.tcp_forward_ports) => {
+                match self.make_tcp_forward_payload (&msg) {
+                    None => return (),
+                    Some (payload) => payload
+                }
+            },
+            None => match self.client_request_payload_factory.make (&msg, self.stream_key_for (&msg.socket_addr), self.cryptde, &self.logger) {
+                None => { self.logger.error(format! ("Couldn't create ClientRequestPayload")); return (); },
+                Some (payload) => payload
+            }
         };
-        // TODO this should come from the Neighborhood
-        let route = Route::new(vec! (
-                RouteSegment::new(vec! (&self.cryptde.public_key(), &self.cryptde.public_key ()), Component::ProxyClient),
-                RouteSegment::new(vec! (&self.cryptde.public_key(), &self.cryptde.public_key()), Component::ProxyServer)
-            ), self.cryptde).expect("Couldn't create route");
-        let pkg = IncipientCoresPackage::new(route, payload, &self.cryptde.public_key());
-        hopper.try_send(pkg ).expect ("Hopper is dead")
+        if self.is_blocked (&payload) {
+            self.logger.warning (format! ("Closing stream from {}: {} is blocked", socket_addr,
+                payload.target_hostname.as_ref ().expect ("Blocked payload has no hostname")));
+            self.tunnels.remove (&socket_addr);
+            let response = ProxyServer::failure_response (payload.protocol, StreamFailure::PolicyBlock,
+                payload.target_hostname.as_ref ().map (|s| s.as_str ()));
+            return self.reply_to_client (socket_addr, response, true)
+        }
+        // An empty chunk that doesn't end the stream would look like a bare acknowledgement, and the
+        // end of a stream the network never heard of, or has already forgotten, needn't go anywhere
+        if payload.data.data.is_empty () && (!payload.last_data || !self.stream_keys.contains_key (&socket_addr)) {return ()}
+        self.stream_sockets.insert (payload.stream_key, socket_addr);
+        // Once the client has finished, a new connection from the same address is a new stream
+        if payload.last_data {self.stream_keys.remove (&socket_addr);}
+        else {self.stream_keys.insert (socket_addr, payload.stream_key);}
+        let mut payload = self.scrub_headers (payload);
+        payload.sequence_number = self.next_sequence_number (payload.stream_key);
+        let new_stream = self.stream_protocols.insert (payload.stream_key, (payload.protocol, payload.target_hostname.clone ())).is_none ();
+        if self.keep_for_retry (&payload, new_stream) {
+            self.send_to_hopper (payload, ctx)
+        }
     }
 }
 
 impl Handler<ExpiredCoresPackage> for ProxyServer {
     type Result = ();
 
-    fn handle(&mut self, msg: ExpiredCoresPackage, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: ExpiredCoresPackage, ctx: &mut Self::Context) -> Self::Result {
         match msg.payload::<ClientResponsePayload>() {
             Ok(payload) => {
-                self.logger.debug (format! ("Relaying {}-byte ExpiredCoresPackage payload from Hopper to Dispatcher", payload.data.data.len ()));
-                self.dispatcher.as_ref().expect("Dispatcher unbound in ProxyServer")
-                    .try_send(TransmitDataMsg {
-                        endpoint: Endpoint::Socket(payload.stream_key),
-                        last_data: payload.last_response,
-                        data: payload.data.data.clone()
-                    }).expect ("Dispatcher is dead");
-                ()
+                if let Some (failure) = payload.failure {
+                    if self.retry_stream_setup (payload.stream_key, failure, ctx) {return ()}
+                }
+                self.stream_setups.remove (&payload.stream_key);
+                self.acknowledged (&payload, ctx);
+                self.watch_paths (&payload, ctx);
+                // Empty responses that don't end the stream aren't numbered; they only carry acknowledgements
+                if payload.data.data.is_empty () && !payload.last_response {return ()}
+                let stream_key = payload.stream_key;
+                if self.stream_sockets.contains_key (&stream_key) {self.acks_owed.insert (stream_key);}
+                for payload in self.put_in_order (payload) {
+                    // The exit keeps its last response until it hears it's arrived
+                    if payload.last_response {self.send_control_payload (stream_key, None, ctx)}
+                    self.relay_response (payload)
+                }
             },
             Err(_) => { self.logger.error(format! ("ClientResponsePayload is not OK")); return (); },
         }
@@ -84,12 +283,59 @@ impl Handler<ExpiredCoresPackage> for ProxyServer {
     }
 }
 
+// Responses a client hasn't taken yet aren't acknowledged, so the exit stops reading from the server
+// once its send window fills, rather than piling the responses up in the client's WriteQueue
+impl Handler<WriteBacklogMsg> for ProxyServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: WriteBacklogMsg, _ctx: &mut Self::Context) -> Self::Result {
+        let stream_keys: Vec<StreamKey> = self.stream_sockets.iter ()
+            .filter (|&(_, socket_addr)| *socket_addr == msg.socket_addr)
+            .map (|(stream_key, _)| *stream_key)
+            .collect ();
+        for stream_key in stream_keys {
+            if msg.backed_up {
+                let ack = self.response_ack (stream_key);
+                self.logger.debug (format! ("Client {} is behind; acknowledging no more than {} responses on stream {}", msg.socket_addr, ack, stream_key));
+                self.ack_limits.insert (stream_key, ack);
+            }
+            else if self.ack_limits.remove (&stream_key).is_some () {
+                self.logger.debug (format! ("Client {} has caught up; acknowledging responses on stream {} again", msg.socket_addr, stream_key));
+                self.acks_owed.insert (stream_key);
+            }
+        }
+        ()
+    }
+}
+
 impl ProxyServer {
-    pub fn new(cryptde: &'static CryptDE) -> ProxyServer {
+    // The Blocklist is shared with the Entry DNS server, which may have turned the hostname away already
+    pub fn new(cryptde: &'static CryptDE, config: ProxyServerConfig, blocklist: Blocklist) -> ProxyServer {
         ProxyServer {
             dispatcher: None,
+            pause_reading: None,
             hopper: None,
-            client_request_payload_factory: ClientRequestPayloadFactory::new (),
+            route_query: None,
+            client_request_payload_factory: ClientRequestPayloadFactory::new (&config.port_protocols),
+            header_scrubber: HeaderScrubber::new (config.header_rules.clone ()),
+            blocklist,
+            config,
+            tunnels: HashMap::new (),
+            http_proxy_targets: HashMap::new (),
+            socks_negotiations: HashMap::new (),
+            stream_keys: HashMap::new (),
+            stream_sockets: HashMap::new (),
+            stream_protocols: HashMap::new (),
+            stream_setups: HashMap::new (),
+            circuits: HashMap::new (),
+            request_sequence_numbers: HashMap::new (),
+            request_windows: HashMap::new (),
+            paused_clients: HashSet::new (),
+            response_buffers: HashMap::new (),
+            reorder_gap_timeout: default_reorder_gap_timeout (),
+            acks_owed: HashSet::new (),
+            ack_limits: HashMap::new (),
+            responses_finished: HashSet::new (),
             cryptde,
             logger: Logger::new ("Proxy Server"),
         }
@@ -100,6 +346,672 @@ impl ProxyServer {
             bind: addr.clone ().recipient::<BindMessage>(),
             from_dispatcher: addr.clone ().recipient::<InboundClientData>(),
             from_hopper: addr.clone ().recipient::<ExpiredCoresPackage>(),
+            write_backlog: addr.clone ().recipient::<WriteBacklogMsg>(),
+        }
+    }
+
+    fn is_from_port (msg: &InboundClientData, port: Option<u16>) -> bool {
+        msg.origin_port.is_some () && (msg.origin_port == port)
+    }
+
+    fn is_from_tcp_forward_port (msg: &InboundClientData, ports: &Vec<u16>) -> bool {
+        match msg.origin_port {
+            Some (port) => ports.contains (&port),
+            None => false
+        }
+    }
+
+    // Forwarded protocols don't say where they're going, so we rely on the hostname the Dispatcher
+    // worked out from the address the client connected to. The client connected to the port it
+    // wanted on the far end, so that's the listener's port.
+    fn make_tcp_forward_payload (&self, msg: &InboundClientData) -> Option<ClientRequestPayload> {
+        match (msg.target_hostname.clone (), msg.origin_port) {
+            (Some (hostname), Some (port)) => Some (self.make_payload (msg, PlainData::from (msg.data.clone ()), Some (hostname), port, ProxyProtocol::Tunnel)),
+            _ => {
+                self.logger.error (format! ("Can't forward {}-byte chunk from {}: it wasn't sent to an address the Entry DNS server handed out",
+                    msg.data.len (), msg.socket_addr));
+                None
+            }
+        }
+    }
+
+    // Traffic from the forward-proxy port names its target in the request line rather than in the
+    // Host header or SNI, and after a CONNECT the rest of the stream is an opaque tunnel.
+    fn make_http_proxy_payload (&mut self, msg: InboundClientData) -> Option<ClientRequestPayload> {
+        match parse_http_proxy_request (&msg.data[..]) {
+            Some (HttpProxyRequest::Connect {hostname, port}) => {
+                if self.refuse_new_target (&msg, &hostname, port) {return None}
+                self.logger.debug (format! ("Opening tunnel from {} to {}:{}", msg.socket_addr, hostname, port));
+                self.tunnels.insert (msg.socket_addr, (hostname, port));
+                self.reply_to_client (msg.socket_addr, CONNECT_RESPONSE.to_vec (), false);
+                None
+            },
+            Some (HttpProxyRequest::Forward {hostname, port, data}) => {
+                if self.refuse_new_target (&msg, &hostname, port) {return None}
+                if msg.last_data {self.http_proxy_targets.remove (&msg.socket_addr);}
+                else {self.http_proxy_targets.insert (msg.socket_addr, (hostname.clone (), port));}
+                Some (self.make_payload (&msg, PlainData::from (data), Some (hostname), port, ProxyProtocol::HTTP))
+            },
+            None if msg.last_data && msg.data.is_empty () => {
+                self.http_proxy_targets.remove (&msg.socket_addr);
+                Some (self.make_payload (&msg, PlainData::new (&[]), None, 80, ProxyProtocol::HTTP))
+            },
+            None => {
+                self.logger.error (format! ("Couldn't find proxy target in {}-byte request from {}", msg.data.len (), msg.socket_addr));
+                None
+            }
+        }
+    }
+
+    // A keep-alive client may send its next request to a different server. The exit would write it
+    // to the server the stream is already connected to, so the client is told to reconnect instead.
+    fn refuse_new_target (&mut self, msg: &InboundClientData, hostname: &String, port: u16) -> bool {
+        if !self.stream_keys.contains_key (&msg.socket_addr) {return false}
+        match self.http_proxy_targets.get (&msg.socket_addr) {
+            Some (&(ref target_hostname, target_port)) if (target_hostname, target_port) != (hostname, port) => {
+                self.logger.warning (format! ("Closing stream from {}: it moved from {}:{} to {}:{}", msg.socket_addr,
+                    target_hostname, target_port, hostname, port));
+            },
+            _ => return false
+        }
+        self.http_proxy_targets.remove (&msg.socket_addr);
+        self.reply_to_client (msg.socket_addr, NEW_TARGET_RESPONSE.to_vec (), true);
+        true
+    }
+
+    // Like the 200 we send for HTTP CONNECT, the SOCKS success reply goes out before the far end has
+    // been reached; if that connection fails, the client will see its stream closed.
+    fn negotiate_socks (&mut self, msg: InboundClientData) {
+        let state = self.socks_negotiations.remove (&msg.socket_addr).unwrap_or (SocksState::AwaitingGreeting);
+        if msg.data.is_empty () {
+            if !msg.last_data {self.socks_negotiations.insert (msg.socket_addr, state);}
+            return
+        }
+        match negotiate (&state, &msg.data[..], &self.config.socks_credentials) {
+            SocksStep::Continue {reply, next_state: SocksState::Tunneling {hostname, port}} => {
+                self.logger.debug (format! ("Opening SOCKS tunnel from {} to {}:{}", msg.socket_addr, hostname, port));
+                self.tunnels.insert (msg.socket_addr, (hostname, port));
+                self.reply_to_client (msg.socket_addr, reply, false)
+            },
+            SocksStep::Continue {reply, next_state} => {
+                if !msg.last_data {self.socks_negotiations.insert (msg.socket_addr, next_state);}
+                self.reply_to_client (msg.socket_addr, reply, false)
+            },
+            SocksStep::Refuse {reply} => {
+                self.logger.warning (format! ("Refusing SOCKS client {} during {:?}", msg.socket_addr, state));
+                self.reply_to_client (msg.socket_addr, reply, true)
+            }
+        }
+    }
+
+    fn is_blocked (&self, payload: &ClientRequestPayload) -> bool {
+        match payload.target_hostname {
+            Some (ref hostname) => self.blocklist.blocks (hostname, BlockSource::ProxyServer),
+            None => false
+        }
+    }
+
+    // Only plain HTTP can be read on its way through; everything else is encrypted or opaque
+    fn scrub_headers (&self, mut payload: ClientRequestPayload) -> ClientRequestPayload {
+        if payload.protocol == ProxyProtocol::HTTP {
+            let scrubbed = self.header_scrubber.scrub (&payload.data.data[..], payload.target_hostname.as_ref ().map (|s| s.as_str ()));
+            payload.data = PlainData::new (&scrubbed[..]);
+        }
+        payload
+    }
+
+    fn relay_response (&mut self, payload: ClientResponsePayload) {
+        let socket_addr = match self.stream_sockets.get (&payload.stream_key).cloned () {
+            Some (socket_addr) => socket_addr,
+            None => {
+                self.logger.error (format! ("Discarding {}-byte response for unknown stream {}", payload.data.data.len (), payload.stream_key));
+                return
+            }
+        };
+        if let Some (circuit) = self.circuits.get (&payload.stream_key) {circuit.clock.traffic (Instant::now ());}
+        let stream = self.stream_protocols.get (&payload.stream_key).cloned ();
+        let data = match (payload.failure, stream) {
+            (None, _) => payload.data.data,
+            (Some (failure), Some ((protocol, hostname))) => {
+                self.logger.warning (format! ("Exit Node couldn't serve stream {}: {:?}", payload.stream_key, failure));
+                Bytes::from (ProxyServer::failure_response (protocol, failure, hostname.as_ref ().map (|s| s.as_str ())))
+            },
+            (Some (failure), None) => {
+                self.logger.warning (format! ("Exit Node couldn't serve stream {} of unknown protocol: {:?}", payload.stream_key, failure));
+                Bytes::new ()
+            }
+        };
+        self.logger.debug (format! ("Relaying {}-byte ExpiredCoresPackage payload from Hopper to Dispatcher", data.len ()));
+        // The Dispatcher waits on the ProxyServer when the ProxyServer is full, so the ProxyServer can't wait on it
+        self.dispatcher.as_ref().expect("Dispatcher unbound in ProxyServer")
+            .do_send(TransmitDataMsg {
+                endpoint: Endpoint::Socket(socket_addr),
+                last_data: payload.last_response,
+                stopped_reading: payload.stopped_reading,
+                data
+            }).expect ("Dispatcher is dead");
+        // Nothing the client sends from here on would be read
+        if payload.stopped_reading {return self.forget_stream (payload.stream_key)}
+        if payload.last_response {
+            self.responses_finished.insert (payload.stream_key);
+            self.forget_if_finished (payload.stream_key)
+        }
+    }
+
+    fn next_sequence_number (&mut self, stream_key: StreamKey) -> u64 {
+        let sequence_number = self.request_sequence_numbers.get (&stream_key).cloned ().unwrap_or (0);
+        self.request_sequence_numbers.insert (stream_key, sequence_number + 1);
+        sequence_number
+    }
+
+    // How many of the stream's responses have arrived in order
+    fn response_ack (&self, stream_key: StreamKey) -> u64 {
+        self.response_buffers.get (&stream_key).map (|b| b.next_sequence_number ()).unwrap_or (0)
+    }
+
+    // How many of the stream's responses the exit hears have arrived
+    fn ack_to_send (&self, stream_key: StreamKey) -> u64 {
+        let ack = self.response_ack (stream_key);
+        self.ack_limits.get (&stream_key).map (|limit| min (*limit, ack)).unwrap_or (ack)
+    }
+
+    // Responses may overtake one another on the way back from the exit. If they can't be put back
+    // in order, the client's stream is closed rather than handed a corrupted byte stream.
+    fn put_in_order (&mut self, payload: ClientResponsePayload) -> Vec<ClientResponsePayload> {
+        let stream_key = payload.stream_key;
+        if !self.stream_sockets.contains_key (&stream_key) {return vec! (payload)}
+        let sequence_number = payload.sequence_number;
+        let size = payload.data.data.len ();
+        let reorder_gap_timeout = self.reorder_gap_timeout;
+        let result = self.response_buffers.entry (stream_key).or_insert_with (|| {
+            ReorderBuffer::new (DEFAULT_MAX_REORDER_BYTES, reorder_gap_timeout)
+        }).add (sequence_number, payload, size, Instant::now ());
+        match result {
+            Ok (ready) => ready,
+            Err (msg) => {
+                self.logger.error (format! ("Abandoning stream {}: {}", stream_key, msg));
+                vec! (ProxyServer::abandonment (stream_key, sequence_number))
+            }
+        }
+    }
+
+    // Closing the client's connection is the only honest thing left to do with a stream that can't be put back together
+    fn abandonment (stream_key: StreamKey, sequence_number: u64) -> ClientResponsePayload {
+        ClientResponsePayload {
+            stream_key,
+            sequence_number,
+            ack: 0,
+            path_id: 0,
+            last_response: true,
+            stopped_reading: true,
+            data: PlainData::new (&[]),
+            failure: None
+        }
+    }
+
+    // Only new streams are kept, and only until they hear from their exit or send too much to keep.
+    // Returns false if the stream is waiting for a new route, which the payload will go out with.
+    fn keep_for_retry (&mut self, payload: &ClientRequestPayload, new_stream: bool) -> bool {
+        if new_stream && (self.config.route_retries > 0) {
+            self.stream_setups.insert (payload.stream_key, StreamSetup {
+                payloads: vec! (),
+                buffered_bytes: 0,
+                exit_key: self.cryptde.public_key (),
+                failed_exits: vec! (),
+                awaiting_route: false
+            });
+        }
+        let (too_big, send_now) = match self.stream_setups.get_mut (&payload.stream_key) {
+            None => return true,
+            Some (setup) => {
+                setup.buffered_bytes += payload.data.data.len ();
+                setup.payloads.push (payload.clone ());
+                (setup.buffered_bytes > MAX_RETRY_BUFFER_BYTES, !setup.awaiting_route)
+            }
+        };
+        if too_big && send_now {
+            self.logger.debug (format! ("Stream {} has sent too much to move to another exit", payload.stream_key));
+            self.stream_setups.remove (&payload.stream_key);
+        }
+        send_now
+    }
+
+    // Returns true if the failure won't reach the client because another exit is going to be tried
+    fn retry_stream_setup (&mut self, stream_key: StreamKey, failure: StreamFailure, ctx: &mut Context<ProxyServer>) -> bool {
+        if !ProxyServer::is_retryable (failure) {return false}
+        let route_retries = self.config.route_retries;
+        let query = match self.stream_setups.get_mut (&stream_key) {
+            Some (ref mut setup) if !setup.awaiting_route => {
+                self.logger.warning (format! ("Exit Node {} couldn't set up stream {}: {:?}",
+                    to_string (&setup.exit_key.data), stream_key, failure));
+                setup.failed_exits.push (setup.exit_key.clone ());
+                if setup.failed_exits.len () > route_retries {return false}
+                self.logger.info (format! ("Asking the Neighborhood for another route for stream {} (retry {} of {})",
+                    stream_key, setup.failed_exits.len (), route_retries));
+                setup.awaiting_route = true;
+                RouteQueryMessage {excluded_exits: setup.failed_exits.clone (), exit_key: None, route_count: 1}
+            },
+            _ => return false
+        };
+        let future = self.route_query.as_ref ().expect ("Neighborhood unbound in ProxyServer").send (query);
+        ctx.spawn (wrap_future::<_, ProxyServer> (future).then (move |result, proxy_server, ctx| {
+            proxy_server.resume_stream_setup (stream_key, failure, result.unwrap_or (None), ctx);
+            actix::fut::ok (())
+        }));
+        true
+    }
+
+    fn resume_stream_setup (&mut self, stream_key: StreamKey, failure: StreamFailure, response: Option<RouteQueryResponse>,
+                            ctx: &mut Context<ProxyServer>) {
+        let mut setup = match self.stream_setups.remove (&stream_key) {
+            Some (setup) => setup,
+            None => return
+        };
+        let response = match response {
+            Some (response) => response,
+            None => {
+                self.logger.error (format! ("No other route for stream {}; giving up", stream_key));
+                return self.relay_response (ClientResponsePayload {
+                    stream_key,
+                    sequence_number: 0,
+                    ack: 0,
+                    path_id: 0,
+                    last_response: true,
+                    stopped_reading: true,
+                    data: PlainData::new (&[]),
+                    failure: Some (failure)
+                })
+            }
+        };
+        self.logger.info (format! ("Resending {} bytes of stream {} through exit Node {}", setup.buffered_bytes, stream_key,
+            to_string (&response.exit_key.data)));
+        setup.exit_key = response.exit_key.clone ();
+        setup.awaiting_route = false;
+        // The new exit has acknowledged nothing
+        self.request_windows.remove (&stream_key);
+        let route = response.routes.into_iter ().next ().expect ("Neighborhood offered no routes");
+        let new_circuit = Circuit::new (route, response.exit_key, &setup.payloads[0], self.circuit_timeouts ());
+        if self.circuits.insert (stream_key, new_circuit).is_none () {
+            self.schedule_idle_check (stream_key, ctx);
+        }
+        for payload in setup.payloads.iter () {
+            self.send_to_hopper (payload.clone (), ctx)
+        }
+        if setup.buffered_bytes <= MAX_RETRY_BUFFER_BYTES {
+            self.stream_setups.insert (stream_key, setup);
+        }
+    }
+
+    // The exit didn't manage to reach the server; a different exit might. Anything else would fail again.
+    fn is_retryable (failure: StreamFailure) -> bool {
+        match failure {
+            StreamFailure::DnsFailure | StreamFailure::ConnectionRefused | StreamFailure::Timeout
+                | StreamFailure::ConnectionFailed => true,
+            StreamFailure::MissingHostname | StreamFailure::PolicyBlock => false
+        }
+    }
+
+    // Only a window's worth of a stream's requests is in flight at once; the rest wait for acknowledgements
+    fn send_to_hopper (&mut self, payload: ClientRequestPayload, ctx: &mut Context<ProxyServer>) {
+        let stream_key = payload.stream_key;
+        let sequence_number = payload.sequence_number;
+        let ready = self.request_windows.entry (stream_key).or_insert_with (SendWindow::new)
+            .send (sequence_number, payload, Instant::now ());
+        for payload in ready {
+            self.transmit (payload, ctx)
+        }
+        self.pace_client (stream_key)
+    }
+
+    // A client is read from only while its stream's requests have room to wait for the exit
+    fn pace_client (&mut self, stream_key: StreamKey) {
+        let full = self.request_windows.get (&stream_key).map (|window| window.is_full ()).unwrap_or (false);
+        if full == self.paused_clients.contains (&stream_key) {return}
+        let socket_addr = match self.stream_sockets.get (&stream_key) {
+            Some (socket_addr) => *socket_addr,
+            None => {self.paused_clients.remove (&stream_key); return}
+        };
+        if full {
+            self.logger.debug (format! ("Exit is behind on stream {}; pausing client {}", stream_key, socket_addr));
+            self.paused_clients.insert (stream_key);
+        }
+        else {
+            self.logger.debug (format! ("Exit has caught up on stream {}; resuming client {}", stream_key, socket_addr));
+            self.paused_clients.remove (&stream_key);
+        }
+        self.pause_reading.as_ref ().expect ("Dispatcher unbound in ProxyServer")
+            .do_send (PauseReadingMsg {socket_addr, paused: full}).expect ("Dispatcher is dead");
+    }
+
+    // A stream's circuit lasts until the exit finishes responding or the stream goes quiet for too
+    // long; the client's last request may still have to be sent again after the client has finished
+    fn transmit (&mut self, mut payload: ClientRequestPayload, ctx: &mut Context<ProxyServer>) {
+        let stream_key = payload.stream_key;
+        payload.ack = self.ack_to_send (stream_key);
+        self.acks_owed.remove (&stream_key);
+        let pkg = {
+            let circuit = self.circuit_for (&payload, ctx);
+            circuit.clock.traffic (Instant::now ());
+            let index = circuit.next_path ();
+            circuit.package (payload, index)
+        };
+        self.send_package (pkg, ctx)
+    }
+
+    // While the Hopper is full, the ProxyServer takes nothing more from the Dispatcher
+    fn send_package (&mut self, pkg: IncipientCoresPackage, ctx: &mut Context<ProxyServer>) {
+        self.hopper.as_mut ().expect ("Hopper unbound in ProxyServer")
+            .send_from (pkg, ctx, |proxy_server: &mut ProxyServer| proxy_server.hopper.as_mut ().expect ("Hopper unbound in ProxyServer"))
+    }
+
+    fn acknowledged (&mut self, payload: &ClientResponsePayload, ctx: &mut Context<ProxyServer>) {
+        let ready = match self.request_windows.get_mut (&payload.stream_key) {
+            Some (window) => window.acknowledge (payload.ack, Instant::now ()),
+            None => return
+        };
+        for request in ready {
+            self.transmit (request, ctx)
+        }
+        self.pace_client (payload.stream_key);
+        self.forget_if_finished (payload.stream_key)
+    }
+
+    // New streams go through this Node as their own exit until the Neighborhood finds them another
+    fn circuit_for (&mut self, payload: &ClientRequestPayload, ctx: &mut Context<ProxyServer>) -> &mut Circuit {
+        let stream_key = payload.stream_key;
+        if !self.circuits.contains_key (&stream_key) {
+            // TODO this should come from the Neighborhood
+            let route = Route::new(vec! (
+                    RouteSegment::new(vec! (&self.cryptde.public_key(), &self.cryptde.public_key ()), Component::ProxyClient),
+                    RouteSegment::new(vec! (&self.cryptde.public_key(), &self.cryptde.public_key()), Component::ProxyServer)
+                ), self.cryptde).expect("Couldn't create route");
+            let circuit = Circuit::new (route, self.cryptde.public_key (), payload, self.circuit_timeouts ());
+            self.circuits.insert (stream_key, circuit);
+            self.schedule_idle_check (stream_key, ctx);
+        }
+        self.circuits.get_mut (&stream_key).expect ("Circuit disappeared")
+    }
+
+    // Once a stream has heard from its exit, it can be spread across more routes to that exit. A path
+    // is judged by the responses that come back over it, and dropped once it falls behind the others.
+    fn watch_paths (&mut self, payload: &ClientResponsePayload, ctx: &mut Context<ProxyServer>) {
+        let stream_key = payload.stream_key;
+        let stream_paths = self.config.stream_paths;
+        let (widen_to, degraded) = match self.circuits.get_mut (&stream_key) {
+            None => return,
+            Some (circuit) => {
+                if circuit.paths.iter ().any (|&(id, _)| id == payload.path_id) {circuit.monitor.add_path (payload.path_id)}
+                circuit.monitor.arrived (payload.path_id, payload.sequence_number);
+                let widen_to = if (stream_paths > 1) && !circuit.widened && !payload.last_response {
+                    circuit.widened = true;
+                    Some (circuit.exit_key.clone ())
+                } else {None};
+                (widen_to, circuit.monitor.degraded_paths ())
+            }
+        };
+        if !degraded.is_empty () {self.close_paths (stream_key, degraded, ctx)}
+        if let Some (exit_key) = widen_to {
+            let query = RouteQueryMessage {excluded_exits: vec! (), exit_key: Some (exit_key), route_count: stream_paths};
+            let future = self.route_query.as_ref ().expect ("Neighborhood unbound in ProxyServer").send (query);
+            ctx.spawn (wrap_future::<_, ProxyServer> (future).then (move |result, proxy_server, ctx| {
+                if let Some (response) = result.unwrap_or (None) {proxy_server.open_paths (stream_key, response, ctx)}
+                actix::fut::ok (())
+            }));
+        }
+    }
+
+    // The exit only learns the way back along a path from a payload that came over it, so each new
+    // path is opened with an empty one
+    fn open_paths (&mut self, stream_key: StreamKey, response: RouteQueryResponse, ctx: &mut Context<ProxyServer>) {
+        let stream_paths = self.config.stream_paths;
+        let new_paths = match self.circuits.get_mut (&stream_key) {
+            Some (ref mut circuit) if circuit.exit_key == response.exit_key => {
+                let room = stream_paths.saturating_sub (circuit.paths.len ());
+                // The first route goes straight to the exit, like the one the circuit already has
+                let new_paths: Vec<usize> = response.routes.into_iter ().skip (1).take (room)
+                    .map (|route| circuit.add_path (route)).collect ();
+                new_paths
+            },
+            _ => return
+        };
+        self.logger.info (format! ("Spreading stream {} across {} more routes", stream_key, new_paths.len ()));
+        for index in new_paths {
+            self.send_control_payload (stream_key, Some (index), ctx)
+        }
+    }
+
+    fn close_paths (&mut self, stream_key: StreamKey, path_ids: Vec<u8>, ctx: &mut Context<ProxyServer>) {
+        let mut closed_any = false;
+        if let Some (circuit) = self.circuits.get_mut (&stream_key) {
+            for path_id in path_ids {
+                if circuit.remove_path (path_id) {
+                    self.logger.warning (format! ("Path {} of stream {} has fallen behind; sending over the other {} only",
+                        path_id, stream_key, circuit.paths.len ()));
+                    closed_any = true;
+                }
+            }
+        }
+        // The exit has to hear that the paths are closed even if the client has nothing more to send
+        if closed_any {self.send_control_payload (stream_key, None, ctx)}
+    }
+
+    // Sends an empty payload that tells the exit which paths are open and how many responses have
+    // arrived, over the given path or the next one. It isn't numbered, since nothing waits for it
+    // or resends it; it carries the number of the next request instead.
+    fn send_control_payload (&mut self, stream_key: StreamKey, index: Option<usize>, ctx: &mut Context<ProxyServer>) {
+        let mut payload = match self.circuits.get (&stream_key) {
+            Some (circuit) => circuit.template.clone (),
+            None => return
+        };
+        payload.sequence_number = self.request_sequence_numbers.get (&stream_key).cloned ().unwrap_or (0);
+        payload.ack = self.ack_to_send (stream_key);
+        self.acks_owed.remove (&stream_key);
+        let pkg = {
+            let circuit = self.circuit_for (&payload, ctx);
+            let index = index.unwrap_or_else (|| circuit.next_path ());
+            circuit.package (payload, index)
+        };
+        self.send_package (pkg, ctx)
+    }
+
+    // A request that keeps going unacknowledged suggests the stream's routes have broken somewhere
+    // along the way, so the stream moves to fresh ones to the same exit
+    fn reroute (&mut self, stream_key: StreamKey, ctx: &mut Context<ProxyServer>) {
+        let query = match self.circuits.get (&stream_key) {
+            Some (circuit) => RouteQueryMessage {excluded_exits: vec! (), exit_key: Some (circuit.exit_key.clone ()), route_count: circuit.paths.len ()},
+            None => return
+        };
+        self.logger.warning (format! ("Stream {} isn't getting through; asking the Neighborhood for fresh routes", stream_key));
+        let future = self.route_query.as_ref ().expect ("Neighborhood unbound in ProxyServer").send (query);
+        ctx.spawn (wrap_future::<_, ProxyServer> (future).then (move |result, proxy_server, ctx| {
+            match result.unwrap_or (None) {
+                Some (response) => proxy_server.replace_paths (stream_key, response, ctx),
+                None => proxy_server.logger.warning (format! ("No fresh routes for stream {}", stream_key))
+            }
+            actix::fut::ok (())
+        }));
+    }
+
+    // The new paths get new IDs, so the exit stops using the old ones as soon as it hears about them
+    fn replace_paths (&mut self, stream_key: StreamKey, response: RouteQueryResponse, ctx: &mut Context<ProxyServer>) {
+        let path_count = match self.circuits.get_mut (&stream_key) {
+            Some (ref mut circuit) if circuit.exit_key == response.exit_key => {
+                let old_path_ids: Vec<u8> = circuit.paths.iter ().map (|&(id, _)| id).collect ();
+                for route in response.routes.into_iter ().take (old_path_ids.len ()) {
+                    circuit.add_path (route);
+                }
+                for path_id in old_path_ids {
+                    circuit.remove_path (path_id);
+                }
+                circuit.paths.len ()
+            },
+            _ => return
+        };
+        self.logger.info (format! ("Moving stream {} to {} fresh routes", stream_key, path_count));
+        for index in 0..path_count {
+            self.send_control_payload (stream_key, Some (index), ctx)
+        }
+    }
+
+    fn schedule_tick (&self, ctx: &mut Context<ProxyServer>) {
+        ctx.run_later (Duration::from_millis (RELIABILITY_TICK_MS), |proxy_server, ctx| {
+            proxy_server.tick (ctx);
+            proxy_server.schedule_tick (ctx)
+        });
+    }
+
+    // Resends each stream's oldest unacknowledged request if it's overdue, sends a bare
+    // acknowledgement to each exit that's owed one, and gives up on responses missing too long
+    fn tick (&mut self, ctx: &mut Context<ProxyServer>) {
+        let now = Instant::now ();
+        let stream_keys: Vec<StreamKey> = self.request_windows.keys ().cloned ().collect ();
+        for stream_key in stream_keys {
+            self.resend_overdue (stream_key, now, ctx)
+        }
+        let expired: Vec<(StreamKey, String)> = self.response_buffers.iter ()
+            .filter_map (|(stream_key, buffer)| buffer.expire (now).err ().map (|msg| (*stream_key, msg)))
+            .collect ();
+        for (stream_key, msg) in expired {
+            self.logger.error (format! ("Abandoning stream {}: {}", stream_key, msg));
+            let sequence_number = self.response_ack (stream_key);
+            self.relay_response (ProxyServer::abandonment (stream_key, sequence_number))
+        }
+        let acks_owed: Vec<StreamKey> = self.acks_owed.drain ().collect ();
+        for stream_key in acks_owed {
+            self.send_control_payload (stream_key, None, ctx)
+        }
+    }
+
+    fn resend_overdue (&mut self, stream_key: StreamKey, now: Instant, ctx: &mut Context<ProxyServer>) {
+        let (payload, transmissions) = match self.request_windows.get_mut (&stream_key).and_then (|window| window.due (now)) {
+            Some (due) => due,
+            None => return
+        };
+        if transmissions > MAX_TRANSMISSIONS {
+            self.logger.error (format! ("Abandoning stream {}: request {} went unacknowledged after {} tries", stream_key,
+                payload.sequence_number, MAX_TRANSMISSIONS));
+            self.request_windows.remove (&stream_key);
+            self.pace_client (stream_key);
+            self.stream_setups.remove (&stream_key);
+            let sequence_number = self.response_ack (stream_key);
+            return self.relay_response (ProxyServer::abandonment (stream_key, sequence_number))
+        }
+        if transmissions == REROUTE_AFTER_TRANSMISSIONS {self.reroute (stream_key, ctx)}
+        self.logger.debug (format! ("Resending request {} of stream {} (try {})", payload.sequence_number, stream_key, transmissions));
+        self.transmit (payload, ctx)
+    }
+
+    fn circuit_timeouts (&self) -> StreamTimeouts {
+        StreamTimeouts {idle: Some (self.config.circuit_idle_timeout), lifetime: None}
+    }
+
+    fn schedule_idle_check (&self, stream_key: StreamKey, ctx: &mut Context<ProxyServer>) {
+        let delay = self.circuit_timeouts ().check_interval ().expect ("Circuits have no idle timeout");
+        ctx.run_later (delay, move |proxy_server, ctx| proxy_server.check_idle (stream_key, ctx));
+    }
+
+    // Both ends of an idle stream are closed: the exit hears that the client has finished and won't
+    // read any more, and the client sees its connection close. Anything the client sends after that
+    // starts a new stream, with a new circuit, rather than wandering onto a different route under
+    // the old StreamKey.
+    fn check_idle (&mut self, stream_key: StreamKey, ctx: &mut Context<ProxyServer>) {
+        let (expiry, mut payload) = match self.circuits.get (&stream_key) {
+            Some (circuit) => (circuit.clock.expiry (Instant::now ()), circuit.template.clone ()),
+            None => return
+        };
+        let expiry = match expiry {
+            Some (expiry) => expiry,
+            None => return self.schedule_idle_check (stream_key, ctx)
+        };
+        self.logger.info (format! ("Tearing down circuit for stream {}: {}", stream_key, expiry));
+        payload.sequence_number = self.next_sequence_number (stream_key);
+        payload.ack = self.ack_to_send (stream_key);
+        payload.last_data = true;
+        payload.stopped_reading = true;
+        let pkg = {
+            let circuit = self.circuit_for (&payload, ctx);
+            let index = circuit.next_path ();
+            circuit.package (payload, index)
+        };
+        self.send_package (pkg, ctx);
+        if let Some (socket_addr) = self.stream_sockets.get (&stream_key).cloned () {
+            if self.stream_keys.get (&socket_addr).map (|k| *k == stream_key).unwrap_or (true) {
+                self.reply_to_client (socket_addr, vec! (), true)
+            }
+        }
+        self.forget_stream (stream_key)
+    }
+
+    // The stream is over once the exit has finished responding and the client has finished sending,
+    // and the exit has everything the client sent
+    fn forget_if_finished (&mut self, stream_key: StreamKey) {
+        if !self.responses_finished.contains (&stream_key) {return}
+        let client_sending = match self.stream_sockets.get (&stream_key) {
+            Some (socket_addr) => self.stream_keys.get (socket_addr) == Some (&stream_key),
+            None => false
+        };
+        let requests_unacknowledged = self.request_windows.get (&stream_key).map (|w| !w.is_empty ()).unwrap_or (false);
+        if !client_sending && !requests_unacknowledged {self.forget_stream (stream_key)}
+    }
+
+    fn forget_stream (&mut self, stream_key: StreamKey) {
+        self.circuits.remove (&stream_key);
+        self.stream_setups.remove (&stream_key);
+        self.stream_protocols.remove (&stream_key);
+        self.request_sequence_numbers.remove (&stream_key);
+        self.request_windows.remove (&stream_key);
+        self.pace_client (stream_key);
+        self.response_buffers.remove (&stream_key);
+        self.acks_owed.remove (&stream_key);
+        self.ack_limits.remove (&stream_key);
+        self.responses_finished.remove (&stream_key);
+        if let Some (socket_addr) = self.stream_sockets.remove (&stream_key) {
+            if self.stream_keys.get (&socket_addr) == Some (&stream_key) {
+                self.stream_keys.remove (&socket_addr);
+                self.http_proxy_targets.remove (&socket_addr);
+            }
+        }
+    }
+
+    // Tunneled protocols have no way to explain themselves, so their clients just see the stream close
+    fn failure_response (protocol: ProxyProtocol, failure: StreamFailure, hostname: Option<&str>) -> Vec<u8> {
+        match protocol_pack_for (protocol) {
+            Some (pack) => pack.failure_response (failure, hostname),
+            None => vec! ()
+        }
+    }
+
+    // The ProxyServer only ever ends these streams outright
+    fn reply_to_client (&self, socket_addr: SocketAddr, data: Vec<u8>, last_data: bool) {
+        self.dispatcher.as_ref ().expect ("Dispatcher unbound in ProxyServer")
+            .do_send (TransmitDataMsg {
+                endpoint: Endpoint::Socket (socket_addr),
+                last_data,
+                stopped_reading: last_data,
+                data: Bytes::from (data)
+            }).expect ("Dispatcher is dead");
+    }
+
+    // A client connection gets a new StreamKey when it first sends something to the network
+    fn stream_key_for (&self, socket_addr: &SocketAddr) -> StreamKey {
+        self.stream_keys.get (socket_addr).cloned ().unwrap_or_else (StreamKey::new)
+    }
+
+    fn make_payload (&self, msg: &InboundClientData, data: PlainData, target_hostname: Option<String>, target_port: u16,
+                     protocol: ProxyProtocol) -> ClientRequestPayload {
+        ClientRequestPayload {
+            stream_key: self.stream_key_for (&msg.socket_addr),
+            sequence_number: 0, // assigned in the handler once the payload is sure to go out
+            ack: 0,
+            path_id: 0, // these two are filled in by the stream's circuit
+            open_paths: vec! (),
+            last_data: msg.last_data,
+            stopped_reading: false,
+            data,
+            target_hostname,
+            target_port,
+            protocol,
+            originator_public_key: self.cryptde.public_key ().clone ()
         }
     }
 }
@@ -107,8 +1019,16 @@ impl ProxyServer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::net::SocketAddr;
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
     use std::str::FromStr;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::thread;
     use actix::msgs;
     use actix::Arbiter;
     use actix::System;
@@ -116,10 +1036,20 @@ mod tests {
     use sub_lib::hopper::ExpiredCoresPackage;
     use sub_lib::proxy_client::ClientResponsePayload;
     use sub_lib::proxy_server::ClientRequestPayload;
+    use http_protocol_pack::HttpProtocolPack;
+    use protocol_pack::ProtocolPack;
+    use sub_lib::proxy_server::HeaderRule;
+    use sub_lib::neighborhood::RouteQueryMessage;
+    use sub_lib::neighborhood::RouteQueryResponse;
     use sub_lib::proxy_server::ProxyProtocol;
+    use sub_lib::proxy_server::ProxyServerConfig;
+    use sub_lib::send_window::MAX_WAITING;
     use test_utils::test_utils::make_peer_actors_from;
     use test_utils::test_utils::Recorder;
+    use test_utils::test_utils::Recording;
     use test_utils::test_utils::cryptde;
+    use test_utils::test_utils::init_test_logging;
+    use test_utils::test_utils::TestLogHandler;
     use test_utils::test_utils::route_from_proxy_server;
     use test_utils::test_utils::route_to_proxy_server;
 
@@ -131,21 +1061,31 @@ mod tests {
         let hopper_log_arc = hopper_mock.get_recording();
         let hopper_awaiter = hopper_mock.get_awaiter();
         let cryptde = cryptde();
-        let subject = ProxyServer::new(cryptde);
+        let mut subject = ProxyServer::new(cryptde, ProxyServerConfig::new (), Blocklist::new ());
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
         let expected_data = http_request.to_vec();
         let msg_from_dispatcher = InboundClientData {
             socket_addr: socket_addr.clone(),
             origin_port: Some (80),
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: true,
-            data: expected_data.clone()
+            stopped_reading: false,
+            data: Bytes::from (expected_data.clone())
         };
         let expected_http_request = PlainData::new(http_request);
         let key = cryptde.public_key();
         let route = route_from_proxy_server(&key, cryptde);
         let expected_payload = ClientRequestPayload {
-            stream_key: socket_addr.clone(),
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (0),
             last_data: true,
+            stopped_reading: false,
             data: expected_http_request.clone(),
             target_hostname: Some (String::from("nowhere.com")),
             target_port: 80,
@@ -170,52 +1110,43 @@ mod tests {
     }
 
     #[test]
-    fn proxy_server_receives_tls_client_hello_from_dispatcher_then_sends_cores_package_to_hopper() {
-        let system = System::new("proxy_server_receives_tls_client_hello_from_dispatcher_then_sends_cores_package_to_hopper");
-        let tls_request = &[
-            0x16, // content_type: Handshake
-            0x00, 0x00, 0x00, 0x00, // version, length: don't care
-            0x01, // handshake_type: ClientHello
-            0x00, 0x00, 0x00, 0x00, 0x00, // length, version: don't care
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // random: don't care
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // random: don't care
-            0x00, // session_id_length
-            0x00, 0x00, // cipher_suites_length
-            0x00, // compression_methods_length
-            0x00, 0x13, // extensions_length
-            0x00, 0x00, // extension_type: server_name
-            0x00, 0x0F, // extension_length
-            0x00, 0x0D, // server_name_list_length
-            0x00, // server_name_type
-            0x00, 0x0A, // server_name_length
-            's' as u8, 'e' as u8, 'r' as u8, 'v' as u8, 'e' as u8, 'r' as u8, '.' as u8, 'c' as u8, 'o' as u8, 'm' as u8, // server_name
-        ];
+    fn proxy_server_scrubs_headers_of_http_request_before_sending_it_to_hopper() {
+        let system = System::new("proxy_server_scrubs_headers_of_http_request_before_sending_it_to_hopper");
         let hopper_mock = Recorder::new();
         let hopper_log_arc = hopper_mock.get_recording();
         let hopper_awaiter = hopper_mock.get_awaiter();
         let cryptde = cryptde();
-        let subject = ProxyServer::new(cryptde);
+        let mut config = ProxyServerConfig::new ();
+        config.header_rules = vec! (HeaderRule::Remove {name: String::from ("Via")}, HeaderRule::StripCrossOriginReferer);
+        let mut subject = ProxyServer::new(cryptde, config, Blocklist::new ());
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
-        let expected_data = tls_request.to_vec();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
         let msg_from_dispatcher = InboundClientData {
             socket_addr: socket_addr.clone(),
-            origin_port: Some (443),
+            origin_port: Some (80),
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
-            data: expected_data.clone()
+            stopped_reading: false,
+            data: Bytes::from (b"POST /form HTTP/1.1\r\nHost: nowhere.com\r\nVia: 1.1 snoop\r\nReferer: http://tracker.com/\r\nContent-Length: 4\r\n\r\nbody".to_vec ())
         };
-        let expected_tls_request = PlainData::new(tls_request);
         let key = cryptde.public_key();
-        let route = route_from_proxy_server(&key, cryptde);
         let expected_payload = ClientRequestPayload {
-            stream_key: socket_addr.clone(),
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (0),
             last_data: false,
-            data: expected_tls_request.clone(),
-            target_hostname: Some (String::from("server.com")),
-            target_port: 443,
-            protocol: ProxyProtocol::TLS,
+            stopped_reading: false,
+            data: PlainData::new(b"POST /form HTTP/1.1\r\nHost: nowhere.com\r\nContent-Length: 4\r\n\r\nbody"),
+            target_hostname: Some (String::from("nowhere.com")),
+            target_port: 80,
+            protocol: ProxyProtocol::HTTP,
             originator_public_key: key.clone()
         };
-        let expected_pkg = IncipientCoresPackage::new(route.clone(), expected_payload, &key);
+        let expected_pkg = IncipientCoresPackage::new(route_from_proxy_server(&key, cryptde), expected_payload, &key);
         let subject_addr: Addr<Syn, ProxyServer> = subject.start();
         let mut peer_actors = make_peer_actors_from(None, None, Some(hopper_mock), None, None);
         peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
@@ -233,140 +1164,1668 @@ mod tests {
     }
 
     #[test]
-    fn proxy_server_receives_tls_handshake_packet_other_than_client_hello_from_dispatcher_then_sends_cores_package_to_hopper() {
-        let system = System::new("proxy_server_receives_tls_client_hello_from_dispatcher_then_sends_cores_package_to_hopper");
-        let tls_request = &[
-            0x16, // content_type: Handshake
-            0x00, 0x00, 0x00, 0x00, // version, length: don't care
-            0x10, // handshake_type: ClientKeyExchange (not important--just not ClientHello)
-            0x00, 0x00, 0x00, // length: 0
-        ];
+    fn proxy_server_closes_streams_to_blocked_hosts_instead_of_sending_them_to_hopper() {
+        let system = System::new("proxy_server_closes_streams_to_blocked_hosts_instead_of_sending_them_to_hopper");
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
         let hopper_mock = Recorder::new();
         let hopper_log_arc = hopper_mock.get_recording();
         let hopper_awaiter = hopper_mock.get_awaiter();
         let cryptde = cryptde();
-        let subject = ProxyServer::new(cryptde);
-        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
-        let expected_data = tls_request.to_vec();
-        let msg_from_dispatcher = InboundClientData {
-            socket_addr: socket_addr.clone(),
-            origin_port: Some (443),
-            last_data: false,
-            data: expected_data.clone()
-        };
-        let expected_tls_request = PlainData::new(tls_request);
-        let key = cryptde.public_key();
-        let route = route_from_proxy_server(&key, cryptde);
-        let expected_payload = ClientRequestPayload {
-            stream_key: socket_addr.clone(),
-            last_data: false,
-            data: expected_tls_request.clone(),
-            target_hostname: None,
-            target_port: 443,
-            protocol: ProxyProtocol::TLS,
-            originator_public_key: key.clone()
-        };
-        let expected_pkg = IncipientCoresPackage::new(route.clone(), expected_payload, &key);
+        let dir = env::current_dir ().unwrap ().join (Path::new ("generated")).join (Path::new ("ProxyServer"));
+        fs::create_dir_all (dir.clone ()).unwrap ();
+        let path = dir.join (Path::new ("blocklist"));
+        write! (File::create (path.clone ()).unwrap (), "tracker.com\n").unwrap ();
+        let blocklist = Blocklist::new ();
+        blocklist.load (vec! (path), None).unwrap ();
+        let mut subject = ProxyServer::new(cryptde, ProxyServerConfig::new (), blocklist.clone ());
+        let blocked_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let allowed_addr = SocketAddr::from_str("1.2.3.4:5679").unwrap();
+        let allowed_key = StreamKey::new ();
+        open_stream (&mut subject, allowed_addr, allowed_key);
         let subject_addr: Addr<Syn, ProxyServer> = subject.start();
-        let mut peer_actors = make_peer_actors_from(None, None, Some(hopper_mock), None, None);
+        let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
         peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
         subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
 
-        subject_addr.try_send(msg_from_dispatcher).unwrap ();
+        subject_addr.try_send(from_client (blocked_addr, 80, b"GET /pixel.gif HTTP/1.1\r\nHost: ads.tracker.com\r\n\r\n")).unwrap ();
+        subject_addr.try_send(from_client (allowed_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
 
         Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
         system.run();
 
+        dispatcher_awaiter.await_message_count(1);
         hopper_awaiter.await_message_count(1);
-        let recording = hopper_log_arc.lock().unwrap();
-        let record = recording.get_record::<IncipientCoresPackage>(0);
-        assert_eq!(record, &expected_pkg);
+        let dispatcher_recording = dispatcher_log_arc.lock().unwrap();
+        let record = dispatcher_recording.get_record::<TransmitDataMsg>(0);
+        assert_eq!(record.endpoint, Endpoint::Socket(blocked_addr));
+        assert_eq!(record.data, HttpProtocolPack {}.failure_response (StreamFailure::PolicyBlock, Some ("ads.tracker.com")));
+        assert_eq!(record.last_data, true);
+        assert_eq!(record.stopped_reading, true);
+        let hopper_recording = hopper_log_arc.lock().unwrap();
+        let record = hopper_recording.get_record::<IncipientCoresPackage>(0);
+        let payload: ClientRequestPayload = serde_cbor::de::from_slice (&record.payload.data[..]).unwrap ();
+        assert_eq!(payload.stream_key, allowed_key);
+        assert_eq!(blocklist.stats ().proxy_server_blocks, 1);
     }
 
-    #[test]
-    fn proxy_server_receives_tls_packet_other_than_handshake_from_dispatcher_then_sends_cores_package_to_hopper() {
-        let system = System::new("proxy_server_receives_tls_client_hello_from_dispatcher_then_sends_cores_package_to_hopper");
-        let tls_request = &[
+    fn http_proxy_config (port: u16) -> ProxyServerConfig {
+        let mut config = ProxyServerConfig::new ();
+        config.http_proxy_port = Some (port);
+        config
+    }
+
+    fn socks_config (port: u16, credentials: Option<(String, String)>) -> ProxyServerConfig {
+        let mut config = ProxyServerConfig::new ();
+        config.socks_port = Some (port);
+        config.socks_credentials = credentials;
+        config
+    }
+
+    fn open_stream (subject: &mut ProxyServer, socket_addr: SocketAddr, stream_key: StreamKey) {
+        subject.stream_keys.insert (socket_addr, stream_key);
+        subject.stream_sockets.insert (stream_key, socket_addr);
+    }
+
+    fn from_client (socket_addr: SocketAddr, origin_port: u16, data: &[u8]) -> InboundClientData {
+        InboundClientData {
+            socket_addr,
+            origin_port: Some (origin_port),
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: Bytes::from (data.to_vec ())
+        }
+    }
+
+    #[test]
+    fn proxy_server_receives_absolute_form_request_on_http_proxy_port_then_sends_rewritten_request_to_hopper() {
+        let system = System::new("proxy_server_receives_absolute_form_request_on_http_proxy_port_then_sends_rewritten_request_to_hopper");
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let cryptde = cryptde();
+        let mut subject = ProxyServer::new(cryptde, http_proxy_config (8080), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
+        let msg_from_dispatcher = InboundClientData {
+            socket_addr: socket_addr.clone(),
+            origin_port: Some (8080),
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: Bytes::from (b"GET http://nowhere.com:8888/index.html HTTP/1.1\r\nHost: nowhere.com:8888\r\n\r\n".to_vec ())
+        };
+        let key = cryptde.public_key();
+        let expected_payload = ClientRequestPayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (0),
+            last_data: false,
+            stopped_reading: false,
+            data: PlainData::new(b"GET /index.html HTTP/1.1\r\nHost: nowhere.com:8888\r\n\r\n"),
+            target_hostname: Some (String::from("nowhere.com")),
+            target_port: 8888,
+            protocol: ProxyProtocol::HTTP,
+            originator_public_key: key.clone()
+        };
+        let expected_pkg = IncipientCoresPackage::new(route_from_proxy_server(&key, cryptde), expected_payload, &key);
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, None, Some(hopper_mock), None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(msg_from_dispatcher).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        hopper_awaiter.await_message_count(1);
+        let recording = hopper_log_arc.lock().unwrap();
+        let record = recording.get_record::<IncipientCoresPackage>(0);
+        assert_eq!(record, &expected_pkg);
+    }
+
+    #[test]
+    fn proxy_server_refuses_a_request_to_a_new_target_on_an_http_proxy_clients_open_stream() {
+        let system = System::new("proxy_server_refuses_a_request_to_a_new_target_on_an_http_proxy_clients_open_stream");
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let subject = ProxyServer::new(cryptde (), http_proxy_config (8080), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(from_client (socket_addr, 8080, b"GET http://first.com/ HTTP/1.1\r\nHost: first.com\r\n\r\n")).unwrap ();
+        subject_addr.try_send(from_client (socket_addr, 8080, b"GET http://first.com/again HTTP/1.1\r\nHost: first.com\r\n\r\n")).unwrap ();
+        subject_addr.try_send(from_client (socket_addr, 8080, b"GET http://second.com/ HTTP/1.1\r\nHost: second.com\r\n\r\n")).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        hopper_awaiter.await_message_count(2);
+        dispatcher_awaiter.await_message_count(1);
+        let sent = sent_requests (&hopper_log_arc);
+        assert_eq!(sent.iter ().map (|&(_, _, ref data)| data.clone ()).collect::<Vec<_>> (), vec! (
+            b"GET / HTTP/1.1\r\nHost: first.com\r\n\r\n".to_vec (),
+            b"GET /again HTTP/1.1\r\nHost: first.com\r\n\r\n".to_vec ()
+        ));
+        let dispatcher_recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(dispatcher_recording.get_record::<TransmitDataMsg>(0), &TransmitDataMsg {
+            endpoint: Endpoint::Socket (socket_addr),
+            last_data: true,
+            stopped_reading: true,
+            data: Bytes::from (NEW_TARGET_RESPONSE.to_vec ())
+        });
+        assert_eq!(dispatcher_recording.len (), 1);
+    }
+
+    #[test]
+    fn proxy_server_answers_connect_on_http_proxy_port_then_tunnels_subsequent_data_to_hopper() {
+        let system = System::new("proxy_server_answers_connect_on_http_proxy_port_then_tunnels_subsequent_data_to_hopper");
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let cryptde = cryptde();
+        let mut subject = ProxyServer::new(cryptde, http_proxy_config (8080), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
+        let connect_msg = InboundClientData {
+            socket_addr: socket_addr.clone(),
+            origin_port: Some (8080),
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: Bytes::from (b"CONNECT secure.com:443 HTTP/1.1\r\nHost: secure.com:443\r\n\r\n".to_vec ())
+        };
+        let tunneled_msg = InboundClientData {
+            socket_addr: socket_addr.clone(),
+            origin_port: Some (8080),
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: Bytes::from (vec! (0x16, 0x03, 0x01, 0x00, 0x01, 0x01))
+        };
+        let key = cryptde.public_key();
+        let expected_payload = ClientRequestPayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (0),
+            last_data: false,
+            stopped_reading: false,
+            data: PlainData::new(&[0x16, 0x03, 0x01, 0x00, 0x01, 0x01]),
+            target_hostname: Some (String::from("secure.com")),
+            target_port: 443,
+            protocol: ProxyProtocol::Tunnel,
+            originator_public_key: key.clone()
+        };
+        let expected_pkg = IncipientCoresPackage::new(route_from_proxy_server(&key, cryptde), expected_payload, &key);
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(connect_msg).unwrap ();
+        subject_addr.try_send(tunneled_msg).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        dispatcher_awaiter.await_message_count(1);
+        let dispatcher_recording = dispatcher_log_arc.lock().unwrap();
+        let record = dispatcher_recording.get_record::<TransmitDataMsg>(0);
+        assert_eq!(record.endpoint, Endpoint::Socket(socket_addr));
+        assert_eq!(record.last_data, false);
+        assert_eq!(record.data, b"HTTP/1.1 200 Connection established\r\n\r\n".to_vec());
+        hopper_awaiter.await_message_count(1);
+        let hopper_recording = hopper_log_arc.lock().unwrap();
+        assert_eq!(hopper_recording.len(), 1);
+        let record = hopper_recording.get_record::<IncipientCoresPackage>(0);
+        assert_eq!(record, &expected_pkg);
+    }
+
+    #[test]
+    fn proxy_server_tunnels_data_on_tcp_forward_port_to_hostname_from_dispatcher() {
+        let system = System::new("proxy_server_tunnels_data_on_tcp_forward_port_to_hostname_from_dispatcher");
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let cryptde = cryptde();
+        let mut config = ProxyServerConfig::new ();
+        config.tcp_forward_ports = vec! (22, 993);
+        let mut subject = ProxyServer::new(cryptde, config, Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
+        let unmapped_msg = from_client (socket_addr, 22, b"SSH-2.0-OpenSSH_7.6\r\n");
+        let mut mapped_msg = from_client (socket_addr, 22, b"SSH-2.0-OpenSSH_7.6\r\n");
+        mapped_msg.target_hostname = Some (String::from ("server.com"));
+        let key = cryptde.public_key();
+        let expected_payload = ClientRequestPayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (0),
+            last_data: false,
+            stopped_reading: false,
+            data: PlainData::new(b"SSH-2.0-OpenSSH_7.6\r\n"),
+            target_hostname: Some (String::from("server.com")),
+            target_port: 22,
+            protocol: ProxyProtocol::Tunnel,
+            originator_public_key: key.clone()
+        };
+        let expected_pkg = IncipientCoresPackage::new(route_from_proxy_server(&key, cryptde), expected_payload, &key);
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, None, Some(hopper_mock), None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(unmapped_msg).unwrap ();
+        subject_addr.try_send(mapped_msg).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        hopper_awaiter.await_message_count(1);
+        let recording = hopper_log_arc.lock().unwrap();
+        assert_eq!(recording.len(), 1);
+        let record = recording.get_record::<IncipientCoresPackage>(0);
+        assert_eq!(record, &expected_pkg);
+    }
+
+    #[test]
+    fn proxy_server_negotiates_socks_connect_then_tunnels_subsequent_data_to_hopper() {
+        let system = System::new("proxy_server_negotiates_socks_connect_then_tunnels_subsequent_data_to_hopper");
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let cryptde = cryptde();
+        let credentials = Some ((String::from ("booga"), String::from ("wooga")));
+        let mut subject = ProxyServer::new(cryptde, socks_config (1080, credentials), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
+        let key = cryptde.public_key();
+        let expected_payload = ClientRequestPayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (0),
+            last_data: false,
+            stopped_reading: false,
+            data: PlainData::new(b"SSH-2.0-OpenSSH_7.6\r\n"),
+            target_hostname: Some (String::from("server.com")),
+            target_port: 22,
+            protocol: ProxyProtocol::Tunnel,
+            originator_public_key: key.clone()
+        };
+        let expected_pkg = IncipientCoresPackage::new(route_from_proxy_server(&key, cryptde), expected_payload, &key);
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(from_client (socket_addr, 1080, &[0x05, 0x01, 0x02])).unwrap ();
+        subject_addr.try_send(from_client (socket_addr, 1080, b"\x01\x05booga\x05wooga")).unwrap ();
+        subject_addr.try_send(from_client (socket_addr, 1080, b"\x05\x01\x00\x03\x0Aserver.com\x00\x16")).unwrap ();
+        subject_addr.try_send(from_client (socket_addr, 1080, b"SSH-2.0-OpenSSH_7.6\r\n")).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        dispatcher_awaiter.await_message_count(3);
+        let dispatcher_recording = dispatcher_log_arc.lock().unwrap();
+        let replies: Vec<(Vec<u8>, bool)> = (0..3).map (|index| {
+            let record = dispatcher_recording.get_record::<TransmitDataMsg>(index);
+            assert_eq!(record.endpoint, Endpoint::Socket(socket_addr));
+            (record.data.to_vec (), record.last_data)
+        }).collect ();
+        assert_eq!(replies, vec! (
+            (vec! (0x05, 0x02), false),
+            (vec! (0x01, 0x00), false),
+            (vec! (0x05, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00), false),
+        ));
+        hopper_awaiter.await_message_count(1);
+        let hopper_recording = hopper_log_arc.lock().unwrap();
+        assert_eq!(hopper_recording.len(), 1);
+        let record = hopper_recording.get_record::<IncipientCoresPackage>(0);
+        assert_eq!(record, &expected_pkg);
+    }
+
+    #[test]
+    fn proxy_server_refuses_socks_client_with_wrong_credentials_and_closes_stream() {
+        let system = System::new("proxy_server_refuses_socks_client_with_wrong_credentials_and_closes_stream");
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let cryptde = cryptde();
+        let credentials = Some ((String::from ("booga"), String::from ("wooga")));
+        let subject = ProxyServer::new(cryptde, socks_config (1080, credentials), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), None, None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(from_client (socket_addr, 1080, &[0x05, 0x01, 0x02])).unwrap ();
+        subject_addr.try_send(from_client (socket_addr, 1080, b"\x01\x05booga\x05gooba")).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        dispatcher_awaiter.await_message_count(2);
+        let dispatcher_recording = dispatcher_log_arc.lock().unwrap();
+        let record = dispatcher_recording.get_record::<TransmitDataMsg>(1);
+        assert_eq!(record.endpoint, Endpoint::Socket(socket_addr));
+        assert_eq!(record.data, vec! (0x01, 0x01));
+        assert_eq!(record.last_data, true);
+    }
+
+    #[test]
+    fn proxy_server_receives_tls_client_hello_from_dispatcher_then_sends_cores_package_to_hopper() {
+        let system = System::new("proxy_server_receives_tls_client_hello_from_dispatcher_then_sends_cores_package_to_hopper");
+        let tls_request = &[
+            0x16, // content_type: Handshake
+            0x00, 0x00, 0x00, 0x00, // version, length: don't care
+            0x01, // handshake_type: ClientHello
+            0x00, 0x00, 0x00, 0x00, 0x00, // length, version: don't care
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // random: don't care
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // random: don't care
+            0x00, // session_id_length
+            0x00, 0x00, // cipher_suites_length
+            0x00, // compression_methods_length
+            0x00, 0x13, // extensions_length
+            0x00, 0x00, // extension_type: server_name
+            0x00, 0x0F, // extension_length
+            0x00, 0x0D, // server_name_list_length
+            0x00, // server_name_type
+            0x00, 0x0A, // server_name_length
+            's' as u8, 'e' as u8, 'r' as u8, 'v' as u8, 'e' as u8, 'r' as u8, '.' as u8, 'c' as u8, 'o' as u8, 'm' as u8, // server_name
+        ];
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let cryptde = cryptde();
+        let mut subject = ProxyServer::new(cryptde, ProxyServerConfig::new (), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
+        let expected_data = tls_request.to_vec();
+        let msg_from_dispatcher = InboundClientData {
+            socket_addr: socket_addr.clone(),
+            origin_port: Some (443),
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: Bytes::from (expected_data.clone())
+        };
+        let expected_tls_request = PlainData::new(tls_request);
+        let key = cryptde.public_key();
+        let route = route_from_proxy_server(&key, cryptde);
+        let expected_payload = ClientRequestPayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (0),
+            last_data: false,
+            stopped_reading: false,
+            data: expected_tls_request.clone(),
+            target_hostname: Some (String::from("server.com")),
+            target_port: 443,
+            protocol: ProxyProtocol::TLS,
+            originator_public_key: key.clone()
+        };
+        let expected_pkg = IncipientCoresPackage::new(route.clone(), expected_payload, &key);
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, None, Some(hopper_mock), None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(msg_from_dispatcher).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        hopper_awaiter.await_message_count(1);
+        let recording = hopper_log_arc.lock().unwrap();
+        let record = recording.get_record::<IncipientCoresPackage>(0);
+        assert_eq!(record, &expected_pkg);
+    }
+
+    #[test]
+    fn proxy_server_receives_tls_handshake_packet_other_than_client_hello_from_dispatcher_then_sends_cores_package_to_hopper() {
+        let system = System::new("proxy_server_receives_tls_client_hello_from_dispatcher_then_sends_cores_package_to_hopper");
+        let tls_request = &[
+            0x16, // content_type: Handshake
+            0x00, 0x00, 0x00, 0x00, // version, length: don't care
+            0x10, // handshake_type: ClientKeyExchange (not important--just not ClientHello)
+            0x00, 0x00, 0x00, // length: 0
+        ];
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let cryptde = cryptde();
+        let mut subject = ProxyServer::new(cryptde, ProxyServerConfig::new (), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
+        let expected_data = tls_request.to_vec();
+        let msg_from_dispatcher = InboundClientData {
+            socket_addr: socket_addr.clone(),
+            origin_port: Some (443),
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: false,
+            stopped_reading: false,
+            data: Bytes::from (expected_data.clone())
+        };
+        let expected_tls_request = PlainData::new(tls_request);
+        let key = cryptde.public_key();
+        let route = route_from_proxy_server(&key, cryptde);
+        let expected_payload = ClientRequestPayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (0),
+            last_data: false,
+            stopped_reading: false,
+            data: expected_tls_request.clone(),
+            target_hostname: None,
+            target_port: 443,
+            protocol: ProxyProtocol::TLS,
+            originator_public_key: key.clone()
+        };
+        let expected_pkg = IncipientCoresPackage::new(route.clone(), expected_payload, &key);
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, None, Some(hopper_mock), None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(msg_from_dispatcher).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        hopper_awaiter.await_message_count(1);
+        let recording = hopper_log_arc.lock().unwrap();
+        let record = recording.get_record::<IncipientCoresPackage>(0);
+        assert_eq!(record, &expected_pkg);
+    }
+
+    #[test]
+    fn proxy_server_receives_tls_packet_other_than_handshake_from_dispatcher_then_sends_cores_package_to_hopper() {
+        let system = System::new("proxy_server_receives_tls_client_hello_from_dispatcher_then_sends_cores_package_to_hopper");
+        let tls_request = &[
             0xFF, // content_type: don't care, just not Handshake
             0x00, 0x00, 0x00, 0x00, // version, length: don't care
         ];
         let hopper_mock = Recorder::new();
         let hopper_log_arc = hopper_mock.get_recording();
         let hopper_awaiter = hopper_mock.get_awaiter();
-        let cryptde = cryptde();
-        let subject = ProxyServer::new(cryptde);
+        let cryptde = cryptde();
+        let mut subject = ProxyServer::new(cryptde, ProxyServerConfig::new (), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
+        let expected_data = tls_request.to_vec();
+        let msg_from_dispatcher = InboundClientData {
+            socket_addr: socket_addr.clone(),
+            origin_port: Some (443),
+            target_hostname: None,
+            proxy_protocol: None,
+            last_data: true,
+            stopped_reading: false,
+            data: Bytes::from (expected_data.clone())
+        };
+        let expected_tls_request = PlainData::new(tls_request);
+        let key = cryptde.public_key();
+        let route = route_from_proxy_server(&key, cryptde);
+        let expected_payload = ClientRequestPayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            open_paths: vec! (0),
+            last_data: true,
+            stopped_reading: false,
+            data: expected_tls_request.clone(),
+            target_hostname: None,
+            target_port: 443,
+            protocol: ProxyProtocol::TLS,
+            originator_public_key: key.clone()
+        };
+        let expected_pkg = IncipientCoresPackage::new(route.clone(), expected_payload, &key);
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, None, Some(hopper_mock), None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(msg_from_dispatcher).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        hopper_awaiter.await_message_count(1);
+        let recording = hopper_log_arc.lock().unwrap();
+        let record = recording.get_record::<IncipientCoresPackage>(0);
+        assert_eq!(record, &expected_pkg);
+    }
+
+    #[test]
+    fn proxy_server_receives_terminal_response_from_hopper() {
+        let system = System::new("proxy_server_receives_response_from_hopper");
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let cryptde = cryptde();
+        let mut subject = ProxyServer::new(cryptde, ProxyServerConfig::new (), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
+        let key = cryptde.public_key();
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let remaining_route = route_to_proxy_server(&key, cryptde);
+        let client_response_payload = ClientResponsePayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            last_response: true,
+            stopped_reading: false,
+            data: PlainData::new(b"data"),
+            failure: None
+        };
+        let incipient_cores_package = IncipientCoresPackage::new(remaining_route.clone(), client_response_payload, &key);
+        let expired_cores_package = ExpiredCoresPackage::new(remaining_route, incipient_cores_package.payload);
+        let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), None, None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(expired_cores_package).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        dispatcher_awaiter.await_message_count(1);
+
+        let recording = dispatcher_log_arc.lock().unwrap();
+        let record = recording.get_record::<TransmitDataMsg>(0);
+        assert_eq!(record.endpoint, Endpoint::Socket(socket_addr));
+        assert_eq!(record.last_data, true);
+        assert_eq!(record.data, b"data".to_vec());
+    }
+
+    #[test]
+    fn proxy_server_explains_exit_failures_to_clients_it_knows_the_protocol_of() {
+        let system = System::new("proxy_server_explains_exit_failures_to_clients_it_knows_the_protocol_of");
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let hopper_mock = Recorder::new();
+        let cryptde = cryptde();
+        let mut config = ProxyServerConfig::new ();
+        config.route_retries = 0;
+        let mut subject = ProxyServer::new(cryptde, config, Blocklist::new ());
+        let http_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let http_key = StreamKey::new ();
+        open_stream (&mut subject, http_addr, http_key);
+        let unprotocoled_addr = SocketAddr::from_str("1.2.3.4:5679").unwrap();
+        let unprotocoled_key = StreamKey::new ();
+        open_stream (&mut subject, unprotocoled_addr, unprotocoled_key);
+        let key = cryptde.public_key();
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let remaining_route = route_to_proxy_server(&key, cryptde);
+        let failure_package = |stream_key| {
+            let client_response_payload = ClientResponsePayload {
+                stream_key,
+                sequence_number: 0,
+                ack: 0,
+                path_id: 0,
+                last_response: true,
+                stopped_reading: true,
+                data: PlainData::new(&[]),
+                failure: Some (StreamFailure::DnsFailure)
+            };
+            let incipient_cores_package = IncipientCoresPackage::new(remaining_route.clone(), client_response_payload, &key);
+            ExpiredCoresPackage::new(remaining_route.clone(), incipient_cores_package.payload)
+        };
+        let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(from_client (http_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+        subject_addr.try_send(failure_package (http_key)).unwrap ();
+        subject_addr.try_send(failure_package (unprotocoled_key)).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        dispatcher_awaiter.await_message_count(2);
+        let recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(recording.get_record::<TransmitDataMsg>(0), &TransmitDataMsg {
+            endpoint: Endpoint::Socket(http_addr),
+            last_data: true,
+            stopped_reading: true,
+            data: Bytes::from (HttpProtocolPack {}.failure_response (StreamFailure::DnsFailure, Some ("nowhere.com")))
+        });
+        assert_eq!(recording.get_record::<TransmitDataMsg>(1), &TransmitDataMsg {
+            endpoint: Endpoint::Socket(unprotocoled_addr),
+            last_data: true,
+            stopped_reading: true,
+            data: Bytes::new ()
+        });
+    }
+
+    fn exit_failure (stream_key: StreamKey, failure: StreamFailure) -> ExpiredCoresPackage {
+        let cryptde = cryptde ();
+        let remaining_route = route_to_proxy_server(&cryptde.public_key(), cryptde);
+        let client_response_payload = ClientResponsePayload {
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            last_response: true,
+            stopped_reading: true,
+            data: PlainData::new(&[]),
+            failure: Some (failure)
+        };
+        let incipient_cores_package = IncipientCoresPackage::new(remaining_route.clone(), client_response_payload, &cryptde.public_key());
+        ExpiredCoresPackage::new(remaining_route, incipient_cores_package.payload)
+    }
+
+    fn alternate_route (exit_key: &Key) -> RouteQueryResponse {
+        let cryptde = cryptde ();
+        let own_key = cryptde.public_key ();
+        RouteQueryResponse {
+            routes: vec! (Route::new (vec! (
+                RouteSegment::new (vec! (&own_key, exit_key), Component::ProxyClient),
+                RouteSegment::new (vec! (exit_key, &own_key), Component::ProxyServer)
+            ), cryptde).unwrap ()),
+            exit_key: exit_key.clone ()
+        }
+    }
+
+    #[test]
+    fn proxy_server_resends_new_stream_through_another_exit_when_the_first_cannot_set_it_up() {
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let exit_key = Key::new (b"other exit");
+        let neighborhood_mock = Recorder::new().route_query_response (Some (alternate_route (&exit_key)));
+        let neighborhood_log_arc = neighborhood_mock.get_recording();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_resends_new_stream_through_another_exit_when_the_first_cannot_set_it_up");
+            let mut subject = ProxyServer::new(cryptde (), ProxyServerConfig::new (), Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, Some(neighborhood_mock));
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+            subject_addr.try_send(from_client (socket_addr, 80, b"POST / HTTP/1.1\r\nHost: nowhere.com\r\nContent-Length: 4\r\n\r\n")).unwrap ();
+            subject_addr.try_send(from_client (socket_addr, 80, b"body")).unwrap ();
+            subject_addr.try_send(exit_failure (stream_key, StreamFailure::Timeout)).unwrap ();
+
+            system.run();
+        });
+
+        hopper_awaiter.await_message_count(4);
+        let hopper_recording = hopper_log_arc.lock().unwrap();
+        let payload_of = |index| {
+            let record = hopper_recording.get_record::<IncipientCoresPackage>(index);
+            serde_cbor::de::from_slice::<ClientRequestPayload> (&record.payload.data[..]).unwrap ()
+        };
+        let resent_request = hopper_recording.get_record::<IncipientCoresPackage>(2);
+        let resent_body = hopper_recording.get_record::<IncipientCoresPackage>(3);
+        assert_eq!(resent_request.route, alternate_route (&exit_key).routes[0]);
+        assert_eq!(resent_request.payload_destination_key, exit_key);
+        assert_eq!(resent_body.route, alternate_route (&exit_key).routes[0]);
+        assert_eq!(payload_of (2), payload_of (0));
+        assert_eq!(payload_of (3), payload_of (1));
+        let neighborhood_recording = neighborhood_log_arc.lock().unwrap();
+        assert_eq!(neighborhood_recording.get_record::<RouteQueryMessage>(0), &RouteQueryMessage {
+            excluded_exits: vec! (cryptde ().public_key ()),
+            exit_key: None,
+            route_count: 1
+        });
+        assert_eq!(dispatcher_log_arc.lock().unwrap().len (), 0);
+    }
+
+    #[test]
+    fn proxy_server_explains_failure_when_retries_run_out() {
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let hopper_mock = Recorder::new();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let exit_key = Key::new (b"other exit");
+        let neighborhood_mock = Recorder::new().route_query_response (Some (alternate_route (&exit_key)));
+        let neighborhood_log_arc = neighborhood_mock.get_recording();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_explains_failure_when_retries_run_out");
+            let mut config = ProxyServerConfig::new ();
+            config.route_retries = 1;
+            let mut subject = ProxyServer::new(cryptde (), config, Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, Some(neighborhood_mock));
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+            subject_addr.try_send(exit_failure (stream_key, StreamFailure::ConnectionRefused)).unwrap ();
+            tx.send (subject_addr).unwrap ();
+
+            system.run();
+        });
+        let subject_addr = rx.recv ().unwrap ();
+        hopper_awaiter.await_message_count(2);
+
+        subject_addr.try_send(exit_failure (stream_key, StreamFailure::DnsFailure)).unwrap ();
+
+        dispatcher_awaiter.await_message_count(1);
+        let dispatcher_recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(dispatcher_recording.get_record::<TransmitDataMsg>(0), &TransmitDataMsg {
+            endpoint: Endpoint::Socket(socket_addr),
+            last_data: true,
+            stopped_reading: true,
+            data: Bytes::from (HttpProtocolPack {}.failure_response (StreamFailure::DnsFailure, Some ("nowhere.com")))
+        });
+        assert_eq!(neighborhood_log_arc.lock().unwrap().len (), 1);
+    }
+
+    #[test]
+    fn proxy_server_explains_failure_when_the_neighborhood_has_no_other_route() {
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let neighborhood_mock = Recorder::new();
+        let neighborhood_log_arc = neighborhood_mock.get_recording();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_explains_failure_when_the_neighborhood_has_no_other_route");
+            let mut subject = ProxyServer::new(cryptde (), ProxyServerConfig::new (), Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, Some(neighborhood_mock));
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+            subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+            subject_addr.try_send(exit_failure (stream_key, StreamFailure::ConnectionFailed)).unwrap ();
+
+            system.run();
+        });
+
+        dispatcher_awaiter.await_message_count(1);
+        let dispatcher_recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(dispatcher_recording.get_record::<TransmitDataMsg>(0), &TransmitDataMsg {
+            endpoint: Endpoint::Socket(socket_addr),
+            last_data: true,
+            stopped_reading: true,
+            data: Bytes::from (HttpProtocolPack {}.failure_response (StreamFailure::ConnectionFailed, Some ("nowhere.com")))
+        });
+        assert_eq!(neighborhood_log_arc.lock().unwrap().len (), 1);
+        assert_eq!(hopper_log_arc.lock().unwrap().len (), 1);
+    }
+
+    #[test]
+    fn proxy_server_does_not_retry_failures_another_exit_would_repeat() {
+        let system = System::new("proxy_server_does_not_retry_failures_another_exit_would_repeat");
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let neighborhood_mock = Recorder::new();
+        let neighborhood_log_arc = neighborhood_mock.get_recording();
+        let mut subject = ProxyServer::new(cryptde (), ProxyServerConfig::new (), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), None, None, Some(neighborhood_mock));
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+        subject_addr.try_send(exit_failure (stream_key, StreamFailure::PolicyBlock)).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        dispatcher_awaiter.await_message_count(1);
+        let dispatcher_recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(dispatcher_recording.get_record::<TransmitDataMsg>(0).data,
+            HttpProtocolPack {}.failure_response (StreamFailure::PolicyBlock, Some ("nowhere.com")));
+        assert_eq!(neighborhood_log_arc.lock().unwrap().len (), 0);
+    }
+
+    #[test]
+    fn proxy_server_keeps_a_rerouted_stream_on_its_new_circuit_until_the_client_finishes() {
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let exit_key = Key::new (b"other exit");
+        let neighborhood_mock = Recorder::new().route_query_response (Some (alternate_route (&exit_key)));
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_keeps_a_rerouted_stream_on_its_new_circuit_until_the_client_finishes");
+            let mut subject = ProxyServer::new(cryptde (), ProxyServerConfig::new (), Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, None, Some(hopper_mock), None, Some(neighborhood_mock));
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+            subject_addr.try_send(exit_failure (stream_key, StreamFailure::Timeout)).unwrap ();
+            tx.send (subject_addr).unwrap ();
+
+            system.run();
+        });
+        let subject_addr = rx.recv ().unwrap ();
+        hopper_awaiter.await_message_count(2);
+        let mut last_msg = from_client (socket_addr, 80, b"GET /again HTTP/1.1\r\nHost: nowhere.com\r\n\r\n");
+        last_msg.last_data = true;
+
+        subject_addr.try_send(last_msg).unwrap ();
+        subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+
+        hopper_awaiter.await_message_count(4);
+        let hopper_recording = hopper_log_arc.lock().unwrap();
+        let later_chunk = hopper_recording.get_record::<IncipientCoresPackage>(2);
+        assert_eq!(later_chunk.route, alternate_route (&exit_key).routes[0]);
+        assert_eq!(later_chunk.payload_destination_key, exit_key);
+        let new_stream = hopper_recording.get_record::<IncipientCoresPackage>(3);
+        let own_key = cryptde ().public_key ();
+        assert_eq!(new_stream.route, route_from_proxy_server (&own_key, cryptde ()));
+        assert_eq!(new_stream.payload_destination_key, own_key);
+        let new_payload: ClientRequestPayload = serde_cbor::de::from_slice (&new_stream.payload.data[..]).unwrap ();
+        assert_ne!(new_payload.stream_key, stream_key);
+    }
+
+    #[test]
+    fn proxy_server_closes_both_ends_of_an_idle_stream_and_starts_a_new_stream_afterward() {
+        init_test_logging();
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
-        let expected_data = tls_request.to_vec();
-        let msg_from_dispatcher = InboundClientData {
-            socket_addr: socket_addr.clone(),
-            origin_port: Some (443),
-            last_data: true,
-            data: expected_data.clone()
-        };
-        let expected_tls_request = PlainData::new(tls_request);
-        let key = cryptde.public_key();
-        let route = route_from_proxy_server(&key, cryptde);
-        let expected_payload = ClientRequestPayload {
-            stream_key: socket_addr.clone(),
+        let stream_key = StreamKey::new ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_closes_both_ends_of_an_idle_stream_and_starts_a_new_stream_afterward");
+            let mut config = ProxyServerConfig::new ();
+            config.circuit_idle_timeout = Duration::from_millis (50);
+            let mut subject = ProxyServer::new(cryptde (), config, Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+            tx.send (subject_addr).unwrap ();
+
+            system.run();
+        });
+        let subject_addr = rx.recv ().unwrap ();
+        hopper_awaiter.await_message_count(1);
+        TestLogHandler::new ().await_log_containing (&format! ("Tearing down circuit for stream {}", stream_key), 5000);
+
+        subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+        subject_addr.try_send(exit_failure (stream_key, StreamFailure::ConnectionFailed)).unwrap ();
+        // The ProxyServer takes its messages in order, so this one's arrival at the Hopper means the failure has been handled
+        subject_addr.try_send(from_client (socket_addr, 80, b"GET /again HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+
+        hopper_awaiter.await_message_count(4);
+        let hopper_recording = hopper_log_arc.lock().unwrap();
+        let payload_of = |index| serde_cbor::de::from_slice::<ClientRequestPayload> (&hopper_recording.get_record::<IncipientCoresPackage>(index).payload.data[..]).unwrap ();
+        let last_payload = payload_of (1);
+        assert_eq!(last_payload.stream_key, stream_key);
+        assert_eq!(last_payload.sequence_number, 1);
+        assert_eq!(last_payload.last_data, true);
+        assert_eq!(last_payload.stopped_reading, true);
+        assert_eq!(last_payload.data, PlainData::new (&[]));
+        assert_ne!(payload_of (2).stream_key, stream_key);
+        let dispatcher_recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(dispatcher_recording.get_record::<TransmitDataMsg>(0), &TransmitDataMsg {
+            endpoint: Endpoint::Socket (socket_addr),
             last_data: true,
-            data: expected_tls_request.clone(),
-            target_hostname: None,
-            target_port: 443,
-            protocol: ProxyProtocol::TLS,
-            originator_public_key: key.clone()
-        };
-        let expected_pkg = IncipientCoresPackage::new(route.clone(), expected_payload, &key);
+            stopped_reading: true,
+            data: Bytes::new ()
+        });
+        // A failure the exit reports on the old stream after it's been torn down goes nowhere
+        assert_eq!(dispatcher_recording.len (), 1);
+    }
+
+    #[test]
+    fn proxy_server_gives_each_client_connection_its_own_random_stream_key() {
+        let system = System::new("proxy_server_gives_each_client_connection_its_own_random_stream_key");
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let subject = ProxyServer::new(cryptde(), ProxyServerConfig::new (), Blocklist::new ());
+        let first_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let second_addr = SocketAddr::from_str("1.2.3.4:5679").unwrap();
+        let request = b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n";
+        let mut closing_msg = from_client (first_addr, 80, request);
+        closing_msg.last_data = true;
         let subject_addr: Addr<Syn, ProxyServer> = subject.start();
         let mut peer_actors = make_peer_actors_from(None, None, Some(hopper_mock), None, None);
         peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
         subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
 
-        subject_addr.try_send(msg_from_dispatcher).unwrap ();
+        subject_addr.try_send(from_client (first_addr, 80, request)).unwrap ();
+        subject_addr.try_send(from_client (second_addr, 80, request)).unwrap ();
+        subject_addr.try_send(closing_msg).unwrap ();
+        subject_addr.try_send(from_client (first_addr, 80, request)).unwrap ();
 
         Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
         system.run();
 
-        hopper_awaiter.await_message_count(1);
+        hopper_awaiter.await_message_count(4);
         let recording = hopper_log_arc.lock().unwrap();
-        let record = recording.get_record::<IncipientCoresPackage>(0);
-        assert_eq!(record, &expected_pkg);
+        let stream_key_of = |index| {
+            let record = recording.get_record::<IncipientCoresPackage>(index);
+            serde_cbor::de::from_slice::<ClientRequestPayload> (&record.payload.data[..]).unwrap ().stream_key
+        };
+        assert_ne!(stream_key_of (0), stream_key_of (1));
+        assert_eq!(stream_key_of (2), stream_key_of (0));
+        assert_ne!(stream_key_of (3), stream_key_of (0));
+        assert_ne!(stream_key_of (3), stream_key_of (1));
     }
 
     #[test]
-    fn proxy_server_receives_terminal_response_from_hopper() {
-        let system = System::new("proxy_server_receives_response_from_hopper");
+    fn proxy_server_discards_responses_for_streams_it_does_not_know() {
+        let system = System::new("proxy_server_discards_responses_for_streams_it_does_not_know");
         let dispatcher_mock = Recorder::new();
         let dispatcher_log_arc = dispatcher_mock.get_recording();
-        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
         let cryptde = cryptde();
-        let subject = ProxyServer::new(cryptde);
-        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
         let key = cryptde.public_key();
+        let subject = ProxyServer::new(cryptde, ProxyServerConfig::new (), Blocklist::new ());
         let subject_addr: Addr<Syn, ProxyServer> = subject.start();
         let remaining_route = route_to_proxy_server(&key, cryptde);
         let client_response_payload = ClientResponsePayload {
-            stream_key: socket_addr.clone(),
-            last_response: true,
-            data: PlainData::new(b"data")
+            stream_key: StreamKey::new (),
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
+            last_response: false,
+            stopped_reading: false,
+            data: PlainData::new(b"data"),
+            failure: None
         };
         let incipient_cores_package = IncipientCoresPackage::new(remaining_route.clone(), client_response_payload, &key);
-        let expired_cores_package = ExpiredCoresPackage::new(remaining_route, incipient_cores_package.payload);
         let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), None, None, None);
         peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
         subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
 
-        subject_addr.try_send(expired_cores_package).unwrap ();
+        subject_addr.try_send(ExpiredCoresPackage::new(remaining_route, incipient_cores_package.payload)).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        assert_eq!(dispatcher_log_arc.lock().unwrap().len (), 0);
+    }
+
+    #[test]
+    fn proxy_server_numbers_each_streams_requests_from_zero() {
+        let system = System::new("proxy_server_numbers_each_streams_requests_from_zero");
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let subject = ProxyServer::new(cryptde(), ProxyServerConfig::new (), Blocklist::new ());
+        let first_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let second_addr = SocketAddr::from_str("1.2.3.4:5679").unwrap();
+        let request = b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n";
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, None, Some(hopper_mock), None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(from_client (first_addr, 80, request)).unwrap ();
+        subject_addr.try_send(from_client (first_addr, 80, request)).unwrap ();
+        subject_addr.try_send(from_client (second_addr, 80, request)).unwrap ();
+        subject_addr.try_send(from_client (first_addr, 80, request)).unwrap ();
 
         Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
         system.run();
 
+        hopper_awaiter.await_message_count(4);
+        let recording = hopper_log_arc.lock().unwrap();
+        let sequence_number_of = |index| {
+            let record = recording.get_record::<IncipientCoresPackage>(index);
+            serde_cbor::de::from_slice::<ClientRequestPayload> (&record.payload.data[..]).unwrap ().sequence_number
+        };
+        assert_eq!(sequence_number_of (0), 0);
+        assert_eq!(sequence_number_of (1), 1);
+        assert_eq!(sequence_number_of (2), 0);
+        assert_eq!(sequence_number_of (3), 2);
+    }
+
+    fn response_package (stream_key: StreamKey, sequence_number: u64, last_response: bool, data: &[u8]) -> ExpiredCoresPackage {
+        response_on_path (stream_key, sequence_number, 0, last_response, data)
+    }
+
+    fn response_on_path (stream_key: StreamKey, sequence_number: u64, path_id: u8, last_response: bool, data: &[u8]) -> ExpiredCoresPackage {
+        let cryptde = cryptde();
+        let key = cryptde.public_key();
+        let remaining_route = route_to_proxy_server(&key, cryptde);
+        let client_response_payload = ClientResponsePayload {
+            stream_key,
+            sequence_number,
+            ack: 0,
+            path_id,
+            last_response,
+            stopped_reading: false,
+            data: PlainData::new(data),
+            failure: None
+        };
+        let incipient_cores_package = IncipientCoresPackage::new(remaining_route.clone(), client_response_payload, &key);
+        ExpiredCoresPackage::new(remaining_route, incipient_cores_package.payload)
+    }
+
+    // The empty response that ends a stream from the server's side
+    fn final_response (stream_key: StreamKey, sequence_number: u64, stopped_reading: bool) -> ExpiredCoresPackage {
+        let cryptde = cryptde();
+        let key = cryptde.public_key();
+        let remaining_route = route_to_proxy_server(&key, cryptde);
+        let client_response_payload = ClientResponsePayload {
+            stream_key,
+            sequence_number,
+            ack: 0,
+            path_id: 0,
+            last_response: true,
+            stopped_reading,
+            data: PlainData::new(&[]),
+            failure: None
+        };
+        let incipient_cores_package = IncipientCoresPackage::new(remaining_route.clone(), client_response_payload, &key);
+        ExpiredCoresPackage::new(remaining_route, incipient_cores_package.payload)
+    }
+
+    // An empty response, which only acknowledges requests
+    fn acknowledgement (stream_key: StreamKey, next_sequence_number: u64, ack: u64) -> ExpiredCoresPackage {
+        let cryptde = cryptde();
+        let key = cryptde.public_key();
+        let remaining_route = route_to_proxy_server(&key, cryptde);
+        let client_response_payload = ClientResponsePayload {
+            stream_key,
+            sequence_number: next_sequence_number,
+            ack,
+            path_id: 0,
+            last_response: false,
+            stopped_reading: false,
+            data: PlainData::new(&[]),
+            failure: None
+        };
+        let incipient_cores_package = IncipientCoresPackage::new(remaining_route.clone(), client_response_payload, &key);
+        ExpiredCoresPackage::new(remaining_route, incipient_cores_package.payload)
+    }
+
+    // A route to this Node as exit by way of a neighbor
+    fn relayed_route (relay_key: &Key) -> Route {
+        let cryptde = cryptde ();
+        let own_key = cryptde.public_key ();
+        Route::new (vec! (
+            RouteSegment::new (vec! (&own_key, relay_key, &own_key), Component::ProxyClient),
+            RouteSegment::new (vec! (&own_key, relay_key, &own_key), Component::ProxyServer)
+        ), cryptde).unwrap ()
+    }
+
+    fn multipath_routes (relay_keys: &[&Key]) -> RouteQueryResponse {
+        let own_key = cryptde ().public_key ();
+        let mut routes = vec! (route_from_proxy_server (&own_key, cryptde ()));
+        routes.extend (relay_keys.iter ().map (|relay_key| relayed_route (relay_key)));
+        RouteQueryResponse {routes, exit_key: own_key}
+    }
+
+    #[test]
+    fn proxy_server_spreads_a_stream_across_more_routes_once_its_exit_answers() {
+        let dispatcher_mock = Recorder::new();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let first_relay = Key::new (b"first relay");
+        let second_relay = Key::new (b"second relay");
+        let neighborhood_mock = Recorder::new().route_query_response (Some (multipath_routes (&[&first_relay, &second_relay])));
+        let neighborhood_log_arc = neighborhood_mock.get_recording();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_spreads_a_stream_across_more_routes_once_its_exit_answers");
+            let mut config = ProxyServerConfig::new ();
+            config.stream_paths = 3;
+            let mut subject = ProxyServer::new(cryptde (), config, Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, Some(neighborhood_mock));
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+            subject_addr.try_send(response_package (stream_key, 0, false, b"HTTP/1.1 200 OK\r\n\r\n")).unwrap ();
+            tx.send (subject_addr).unwrap ();
+
+            system.run();
+        });
+        let subject_addr = rx.recv ().unwrap ();
+        hopper_awaiter.await_message_count(3);
+
+        subject_addr.try_send(from_client (socket_addr, 80, b"GET /a HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+        subject_addr.try_send(from_client (socket_addr, 80, b"GET /b HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+
+        hopper_awaiter.await_message_count(5);
+        let own_key = cryptde ().public_key ();
+        let neighborhood_recording = neighborhood_log_arc.lock().unwrap();
+        assert_eq!(neighborhood_recording.get_record::<RouteQueryMessage>(0), &RouteQueryMessage {
+            excluded_exits: vec! (),
+            exit_key: Some (own_key.clone ()),
+            route_count: 3
+        });
+        let hopper_recording = hopper_log_arc.lock().unwrap();
+        let sent = |index| {
+            let record = hopper_recording.get_record::<IncipientCoresPackage>(index);
+            let payload: ClientRequestPayload = serde_cbor::de::from_slice (&record.payload.data[..]).unwrap ();
+            (record.route.clone (), payload.sequence_number, payload.path_id, payload.open_paths, payload.data.data.to_vec ())
+        };
+        let direct = route_from_proxy_server (&own_key, cryptde ());
+        assert_eq!(sent (0), (direct.clone (), 0, 0, vec! (0), b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n".to_vec ()));
+        assert_eq!(sent (1), (relayed_route (&first_relay), 1, 1, vec! (0, 1, 2), vec! ()));
+        assert_eq!(sent (2), (relayed_route (&second_relay), 1, 2, vec! (0, 1, 2), vec! ()));
+        assert_eq!(sent (3), (relayed_route (&first_relay), 1, 1, vec! (0, 1, 2), b"GET /a HTTP/1.1\r\nHost: nowhere.com\r\n\r\n".to_vec ()));
+        assert_eq!(sent (4), (relayed_route (&second_relay), 2, 2, vec! (0, 1, 2), b"GET /b HTTP/1.1\r\nHost: nowhere.com\r\n\r\n".to_vec ()));
+    }
+
+    #[test]
+    fn proxy_server_stops_using_a_path_whose_responses_fall_behind() {
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let relay = Key::new (b"slow relay");
+        let neighborhood_mock = Recorder::new().route_query_response (Some (multipath_routes (&[&relay])));
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_stops_using_a_path_whose_responses_fall_behind");
+            let mut config = ProxyServerConfig::new ();
+            config.stream_paths = 2;
+            let mut subject = ProxyServer::new(cryptde (), config, Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, Some(neighborhood_mock));
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+            subject_addr.try_send(response_package (stream_key, 0, false, b"first")).unwrap ();
+            tx.send (subject_addr).unwrap ();
+
+            system.run();
+        });
+        let subject_addr = rx.recv ().unwrap ();
+        hopper_awaiter.await_message_count(2);
+
+        subject_addr.try_send(response_on_path (stream_key, 1, 1, false, b"slow")).unwrap ();
+        for sequence_number in 2..(DEFAULT_MAX_PATH_LAG + 3) {
+            subject_addr.try_send(response_on_path (stream_key, sequence_number, 0, false, b"fast")).unwrap ();
+            // Give the Dispatcher time to keep up
+            if sequence_number % 8 == 0 {dispatcher_awaiter.await_message_count(sequence_number as usize + 1)}
+        }
+        subject_addr.try_send(from_client (socket_addr, 80, b"GET /a HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+
+        let own_key = cryptde ().public_key ();
+        let direct = route_from_proxy_server (&own_key, cryptde ());
+        let sent = || {
+            let hopper_recording = hopper_log_arc.lock().unwrap();
+            (0..hopper_recording.len ()).map (|index| {
+                let record = hopper_recording.get_record::<IncipientCoresPackage>(index);
+                let payload: ClientRequestPayload = serde_cbor::de::from_slice (&record.payload.data[..]).unwrap ();
+                (record.route.clone (), payload.sequence_number, payload.path_id, payload.open_paths, payload.data.data.to_vec ())
+            }).collect::<Vec<_>> ()
+        };
+        // Bare acknowledgements go out on every tick, so wait for the request itself
+        let begin = Instant::now ();
+        while sent ().iter ().filter (|record| !record.4.is_empty ()).count () < 2 {
+            assert! (begin.elapsed () < Duration::from_secs (1), "Request never sent");
+            thread::sleep (Duration::from_millis (10));
+        }
+        let sent = sent ();
+        assert_eq!(sent[1], (relayed_route (&relay), 1, 1, vec! (0, 1), vec! ()));
+        let closed_at = 2 + sent[2..].iter ().position (|record| record.3 == vec! (0)).unwrap ();
+        assert_eq!(sent[closed_at], (direct.clone (), 1, 0, vec! (0), vec! ()));
+        assert! (sent[closed_at..].iter ().all (|record| record.0 == direct && record.2 == 0), "{:?}", sent);
+        assert_eq!(sent.last ().unwrap (), &(direct, 1, 0, vec! (0), b"GET /a HTTP/1.1\r\nHost: nowhere.com\r\n\r\n".to_vec ()));
+    }
+
+    fn sent_requests (hopper_log_arc: &Arc<Mutex<Recording>>) -> Vec<(u64, u64, Vec<u8>)> {
+        let hopper_recording = hopper_log_arc.lock().unwrap();
+        (0..hopper_recording.len ()).map (|index| {
+            let record = hopper_recording.get_record::<IncipientCoresPackage>(index);
+            let payload: ClientRequestPayload = serde_cbor::de::from_slice (&record.payload.data[..]).unwrap ();
+            (payload.sequence_number, payload.ack, payload.data.data.to_vec ())
+        }).collect ()
+    }
+
+    #[test]
+    fn proxy_server_holds_requests_beyond_its_window_until_the_exit_acknowledges_some() {
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        let request = |n: u8| format! ("GET /{} HTTP/1.1\r\nHost: nowhere.com\r\n\r\n", n).into_bytes ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_holds_requests_beyond_its_window_until_the_exit_acknowledges_some");
+            let mut subject = ProxyServer::new(cryptde (), ProxyServerConfig::new (), Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            for n in 0..6 {
+                subject_addr.try_send(from_client (socket_addr, 80, &request (n))).unwrap ();
+            }
+            tx.send (subject_addr).unwrap ();
+
+            system.run();
+        });
+        let subject_addr = rx.recv ().unwrap ();
+        hopper_awaiter.await_message_count(4);
+        thread::sleep (Duration::from_millis (50));
+        assert_eq!(hopper_log_arc.lock().unwrap().len (), 4);
+
+        subject_addr.try_send(acknowledgement (stream_key, 0, 2)).unwrap ();
+
+        hopper_awaiter.await_message_count(6);
+        let sent = sent_requests (&hopper_log_arc);
+        assert_eq!(sent, (0..6).map (|n| (n as u64, 0, request (n))).collect::<Vec<_>> ());
+        assert_eq!(dispatcher_log_arc.lock().unwrap().len (), 0);
+    }
+
+    #[test]
+    fn proxy_server_pauses_a_client_whose_requests_pile_up_until_the_exit_acknowledges_some() {
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let hopper_mock = Recorder::new();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_pauses_a_client_whose_requests_pile_up_until_the_exit_acknowledges_some");
+            let mut subject = ProxyServer::new(cryptde (), ProxyServerConfig::new (), Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            // Four requests go out; the rest wait for the exit
+            for n in 0..(4 + MAX_WAITING) {
+                subject_addr.do_send(from_client (socket_addr, 80, format! ("GET /{} HTTP/1.1\r\nHost: nowhere.com\r\n\r\n", n).as_bytes ()));
+            }
+            tx.send (subject_addr).unwrap ();
+
+            system.run();
+        });
+        let subject_addr = rx.recv ().unwrap ();
+        dispatcher_awaiter.await_message_count(1);
+
+        subject_addr.try_send(acknowledgement (stream_key, 0, 1)).unwrap ();
+
+        dispatcher_awaiter.await_message_count(2);
+        let dispatcher_recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(dispatcher_recording.get_record::<PauseReadingMsg>(0), &PauseReadingMsg {socket_addr, paused: true});
+        assert_eq!(dispatcher_recording.get_record::<PauseReadingMsg>(1), &PauseReadingMsg {socket_addr, paused: false});
+        assert_eq!(dispatcher_recording.len (), 2);
+    }
+
+    #[test]
+    fn proxy_server_acknowledges_responses_with_its_next_request_or_on_its_own() {
+        let dispatcher_mock = Recorder::new();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_acknowledges_responses_with_its_next_request_or_on_its_own");
+            let mut subject = ProxyServer::new(cryptde (), ProxyServerConfig::new (), Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+            subject_addr.try_send(response_package (stream_key, 0, false, b"zero")).unwrap ();
+            tx.send (subject_addr).unwrap ();
+
+            system.run();
+        });
+        let subject_addr = rx.recv ().unwrap ();
+        hopper_awaiter.await_message_count(2);
+        subject_addr.try_send(response_package (stream_key, 1, false, b"one")).unwrap ();
+        subject_addr.try_send(from_client (socket_addr, 80, b"GET /a HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+
+        hopper_awaiter.await_message_count(3);
+        let sent = sent_requests (&hopper_log_arc);
+        assert_eq!(sent[0], (0, 0, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n".to_vec ()));
+        assert_eq!(sent[1], (1, 1, vec! ()));
+        assert_eq!(sent[2], (1, 2, b"GET /a HTTP/1.1\r\nHost: nowhere.com\r\n\r\n".to_vec ()));
+    }
+
+    #[test]
+    fn proxy_server_stops_acknowledging_responses_while_the_client_is_behind_on_them() {
+        let dispatcher_mock = Recorder::new();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_stops_acknowledging_responses_while_the_client_is_behind_on_them");
+            let mut subject = ProxyServer::new(cryptde (), ProxyServerConfig::new (), Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+            subject_addr.try_send(response_package (stream_key, 0, false, b"zero")).unwrap ();
+            tx.send (subject_addr).unwrap ();
+
+            system.run();
+        });
+        let subject_addr = rx.recv ().unwrap ();
+        hopper_awaiter.await_message_count(2);
+        subject_addr.try_send(WriteBacklogMsg {socket_addr, backed_up: true}).unwrap ();
+        subject_addr.try_send(response_package (stream_key, 1, false, b"one")).unwrap ();
+        subject_addr.try_send(from_client (socket_addr, 80, b"GET /a HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+        // A tick may come between the response and the request, with a bare acknowledgement
+        hopper_awaiter.await_message_count(3);
+        let held = if sent_requests (&hopper_log_arc)[2].2.is_empty () {4} else {3};
+        hopper_awaiter.await_message_count(held);
+
+        subject_addr.try_send(WriteBacklogMsg {socket_addr, backed_up: false}).unwrap ();
+
+        hopper_awaiter.await_message_count(held + 1);
+        let sent = sent_requests (&hopper_log_arc);
+        assert_eq!(sent[0], (0, 0, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n".to_vec ()));
+        assert_eq!(sent[1], (1, 1, vec! ()));
+        assert!(sent[2..held].iter ().all (|&(_, ack, _)| ack == 1));
+        assert_eq!(sent[held - 1], (1, 1, b"GET /a HTTP/1.1\r\nHost: nowhere.com\r\n\r\n".to_vec ()));
+        assert_eq!(sent[held], (2, 2, vec! ()));
+    }
+
+    #[test]
+    fn proxy_server_resends_a_request_the_exit_does_not_acknowledge_in_time() {
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_resends_a_request_the_exit_does_not_acknowledge_in_time");
+            let mut subject = ProxyServer::new(cryptde (), ProxyServerConfig::new (), Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, None, Some(hopper_mock), None, None);
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+
+            system.run();
+        });
+        hopper_awaiter.await_message_count(1);
+        thread::sleep (Duration::from_millis (900));
+        assert_eq!(hopper_log_arc.lock().unwrap().len (), 1);
+
+        hopper_awaiter.await_message_count(2);
+        let sent = sent_requests (&hopper_log_arc);
+        assert_eq!(sent[1], sent[0]);
+    }
+
+    #[test]
+    fn proxy_server_keeps_sending_for_a_client_after_the_server_finishes_sending() {
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_keeps_sending_for_a_client_after_the_server_finishes_sending");
+            let mut subject = ProxyServer::new(cryptde (), ProxyServerConfig::new (), Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+            subject_addr.try_send(final_response (stream_key, 0, false)).unwrap ();
+            tx.send (subject_addr).unwrap ();
+
+            system.run();
+        });
+        let subject_addr = rx.recv ().unwrap ();
+        dispatcher_awaiter.await_message_count(1);
+
+        subject_addr.try_send(from_client (socket_addr, 80, b"GET /more HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+
+        hopper_awaiter.await_message_count(3);
+        assert_eq!(dispatcher_log_arc.lock().unwrap().get_record::<TransmitDataMsg>(0), &TransmitDataMsg {
+            endpoint: Endpoint::Socket(socket_addr),
+            last_data: true,
+            stopped_reading: false,
+            data: Bytes::new ()
+        });
+        let hopper_recording = hopper_log_arc.lock().unwrap();
+        let record = hopper_recording.get_record::<IncipientCoresPackage>(2);
+        let payload: ClientRequestPayload = serde_cbor::de::from_slice (&record.payload.data[..]).unwrap ();
+        assert_eq!(payload.stream_key, stream_key);
+        assert_eq!(payload.sequence_number, 1);
+        assert_eq!(payload.data.data, b"GET /more HTTP/1.1\r\nHost: nowhere.com\r\n\r\n".to_vec ());
+    }
+
+    #[test]
+    fn proxy_server_forgets_a_stream_whose_server_stopped_reading() {
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let hopper_mock = Recorder::new();
+        let hopper_log_arc = hopper_mock.get_recording();
+        let hopper_awaiter = hopper_mock.get_awaiter();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        let (tx, rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_forgets_a_stream_whose_server_stopped_reading");
+            let mut subject = ProxyServer::new(cryptde (), ProxyServerConfig::new (), Blocklist::new ());
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), Some(hopper_mock), None, None);
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+            subject_addr.try_send(from_client (socket_addr, 80, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+            subject_addr.try_send(final_response (stream_key, 0, true)).unwrap ();
+            tx.send (subject_addr).unwrap ();
+
+            system.run();
+        });
+        let subject_addr = rx.recv ().unwrap ();
+        dispatcher_awaiter.await_message_count(1);
+
+        let mut client_finished = from_client (socket_addr, 80, b"");
+        client_finished.last_data = true;
+        subject_addr.try_send(client_finished).unwrap ();
+        // A new connection from the same address can only start a new stream once the old one is forgotten
+        subject_addr.try_send(from_client (socket_addr, 80, b"GET /next HTTP/1.1\r\nHost: nowhere.com\r\n\r\n")).unwrap ();
+
+        hopper_awaiter.await_message_count(3);
+        assert_eq!(dispatcher_log_arc.lock().unwrap().get_record::<TransmitDataMsg>(0), &TransmitDataMsg {
+            endpoint: Endpoint::Socket(socket_addr),
+            last_data: true,
+            stopped_reading: true,
+            data: Bytes::new ()
+        });
+        let hopper_recording = hopper_log_arc.lock().unwrap();
+        let sent: Vec<(StreamKey, u64, Vec<u8>, bool)> = (0..hopper_recording.len ()).map (|index| {
+            let record = hopper_recording.get_record::<IncipientCoresPackage>(index);
+            let payload: ClientRequestPayload = serde_cbor::de::from_slice (&record.payload.data[..]).unwrap ();
+            (payload.stream_key, payload.sequence_number, payload.data.data.to_vec (), payload.last_data)
+        }).collect ();
+        assert_eq!(sent[..2].to_vec (), vec! (
+            (stream_key, 0, b"GET / HTTP/1.1\r\nHost: nowhere.com\r\n\r\n".to_vec (), false),
+            (stream_key, 1, vec! (), false)
+        ));
+        assert_ne!(sent[2].0, stream_key);
+        assert_eq!(sent.len (), 3);
+    }
+
+    #[test]
+    fn proxy_server_abandons_a_stream_whose_missing_response_never_arrives() {
+        init_test_logging();
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        thread::spawn (move || {
+            let system = System::new("proxy_server_abandons_a_stream_whose_missing_response_never_arrives");
+            let mut subject = ProxyServer::new(cryptde(), ProxyServerConfig::new (), Blocklist::new ());
+            subject.reorder_gap_timeout = Duration::from_millis (0);
+            open_stream (&mut subject, socket_addr, stream_key);
+            let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+            let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), None, None, None);
+            peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+            subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+            subject_addr.try_send(response_package (stream_key, 1, false, b"one")).unwrap ();
+
+            system.run();
+        });
+
         dispatcher_awaiter.await_message_count(1);
+        assert_eq!(dispatcher_log_arc.lock().unwrap().get_record::<TransmitDataMsg>(0), &TransmitDataMsg {
+            endpoint: Endpoint::Socket(socket_addr),
+            last_data: true,
+            stopped_reading: true,
+            data: Bytes::new ()
+        });
+        TestLogHandler::new ().exists_log_containing (&format! ("ERROR: Proxy Server: Abandoning stream {}: Payload 0 is still missing after", stream_key));
+    }
+
+    #[test]
+    fn proxy_server_relays_responses_in_the_order_the_exit_sent_them() {
+        let system = System::new("proxy_server_relays_responses_in_the_order_the_exit_sent_them");
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let mut subject = ProxyServer::new(cryptde(), ProxyServerConfig::new (), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), None, None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+
+        subject_addr.try_send(response_package (stream_key, 2, true, b"two")).unwrap ();
+        subject_addr.try_send(response_package (stream_key, 1, false, b"one")).unwrap ();
+        subject_addr.try_send(response_package (stream_key, 0, false, b"zero")).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
+
+        dispatcher_awaiter.await_message_count(3);
+        let recording = dispatcher_log_arc.lock().unwrap();
+        let data_of = |index| recording.get_record::<TransmitDataMsg>(index).data.clone ();
+        assert_eq!(data_of (0), b"zero".to_vec ());
+        assert_eq!(data_of (1), b"one".to_vec ());
+        assert_eq!(data_of (2), b"two".to_vec ());
+        assert_eq!(recording.get_record::<TransmitDataMsg>(2).last_data, true);
+    }
+
+    #[test]
+    fn proxy_server_closes_client_stream_that_holds_too_much_behind_a_missing_response() {
+        let system = System::new("proxy_server_closes_client_stream_that_holds_too_much_behind_a_missing_response");
+        let dispatcher_mock = Recorder::new();
+        let dispatcher_log_arc = dispatcher_mock.get_recording();
+        let dispatcher_awaiter = dispatcher_mock.get_awaiter();
+        let mut subject = ProxyServer::new(cryptde(), ProxyServerConfig::new (), Blocklist::new ());
+        let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
+        let subject_addr: Addr<Syn, ProxyServer> = subject.start();
+        let mut peer_actors = make_peer_actors_from(None, Some(dispatcher_mock), None, None, None);
+        peer_actors.proxy_server = ProxyServer::make_subs_from(&subject_addr);
+        subject_addr.try_send(BindMessage { peer_actors }).unwrap ();
+        let big_data = vec! (0u8; DEFAULT_MAX_REORDER_BYTES);
+
+        subject_addr.try_send(response_package (stream_key, 1, false, &big_data)).unwrap ();
+        subject_addr.try_send(response_package (stream_key, 2, false, b"one too many")).unwrap ();
+        subject_addr.try_send(response_package (stream_key, 0, false, b"too late")).unwrap ();
+
+        Arbiter::system().try_send(msgs::SystemExit(0)).unwrap ();
+        system.run();
 
+        dispatcher_awaiter.await_message_count(1);
+        thread::sleep (Duration::from_millis (100));
         let recording = dispatcher_log_arc.lock().unwrap();
+        assert_eq!(recording.len (), 1);
         let record = recording.get_record::<TransmitDataMsg>(0);
         assert_eq!(record.endpoint, Endpoint::Socket(socket_addr));
         assert_eq!(record.last_data, true);
-        assert_eq!(record.data, b"data".to_vec());
+        assert_eq!(record.data, Vec::<u8>::new ());
     }
 
     #[test]
@@ -376,15 +2835,22 @@ mod tests {
         let dispatcher_log_arc = dispatcher_mock.get_recording();
         let dispatcher_awaiter = dispatcher_mock.get_awaiter();
         let cryptde = cryptde();
-        let subject = ProxyServer::new(cryptde);
+        let mut subject = ProxyServer::new(cryptde, ProxyServerConfig::new (), Blocklist::new ());
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
         let key = cryptde.public_key();
         let subject_addr: Addr<Syn, ProxyServer> = subject.start();
         let remaining_route = route_to_proxy_server(&key, cryptde);
         let client_response_payload = ClientResponsePayload {
-            stream_key: socket_addr.clone(),
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
             last_response: false,
-            data: PlainData::new(b"data")
+            stopped_reading: false,
+            data: PlainData::new(b"data"),
+            failure: None
         };
         let incipient_cores_package = IncipientCoresPackage::new(remaining_route.clone(), client_response_payload, &key);
         let expired_cores_package = ExpiredCoresPackage::new(remaining_route, incipient_cores_package.payload);
@@ -411,15 +2877,22 @@ mod tests {
     fn panics_if_dispatcher_is_unbound() {
         let system = System::new("panics_if_dispatcher_is_unbound");
         let cryptde = cryptde();
-        let subject = ProxyServer::new(cryptde);
+        let mut subject = ProxyServer::new(cryptde, ProxyServerConfig::new (), Blocklist::new ());
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
+        let stream_key = StreamKey::new ();
+        open_stream (&mut subject, socket_addr, stream_key);
         let key = cryptde.public_key();
         let subject_addr: Addr<Syn, ProxyServer> = subject.start();
         let remaining_route = route_to_proxy_server(&key, cryptde);
         let client_response_payload = ClientResponsePayload {
-            stream_key: socket_addr,
+            stream_key,
+            sequence_number: 0,
+            ack: 0,
+            path_id: 0,
             last_response: true,
-            data: PlainData::new(b"data")
+            stopped_reading: false,
+            data: PlainData::new(b"data"),
+            failure: None
         };
         let incipient_cores_package = IncipientCoresPackage::new(remaining_route.clone(), client_response_payload, &key);
         let expired_cores_package = ExpiredCoresPackage::new(remaining_route, incipient_cores_package.payload);
@@ -435,14 +2908,17 @@ mod tests {
     fn panics_if_hopper_is_unbound() {
         let system = System::new("panics_if_hopper_is_unbound");
         let http_request = b"GET /index.html HTTP/1.1\r\nHost: nowhere.com\r\n\r\n";
-        let subject = ProxyServer::new(cryptde());
+        let subject = ProxyServer::new(cryptde(), ProxyServerConfig::new (), Blocklist::new ());
         let socket_addr = SocketAddr::from_str("1.2.3.4:5678").unwrap();
         let expected_data = http_request.to_vec();
         let msg_from_dispatcher = InboundClientData {
             socket_addr: socket_addr.clone(),
             origin_port: Some (53),
+            target_hostname: None,
+            proxy_protocol: None,
             last_data: false,
-            data: expected_data.clone()
+            stopped_reading: false,
+            data: Bytes::from (expected_data.clone())
         };
         let subject_addr: Addr<Syn, ProxyServer> = subject.start();
 
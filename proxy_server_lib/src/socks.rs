@@ -0,0 +1,239 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+
+// RFC 1928 (SOCKS5) and RFC 1929 (username/password authentication)
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_VERSION: u8 = 0x01;
+const NO_AUTHENTICATION: u8 = 0x00;
+const USERNAME_PASSWORD: u8 = 0x02;
+const NO_ACCEPTABLE_METHODS: u8 = 0xFF;
+const AUTH_SUCCEEDED: u8 = 0x00;
+const AUTH_FAILED: u8 = 0x01;
+const CONNECT: u8 = 0x01;
+const ADDRESS_IPV4: u8 = 0x01;
+const ADDRESS_DOMAIN_NAME: u8 = 0x03;
+const ADDRESS_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+/// Where a SOCKS client is in its conversation with us
+#[derive (Clone, Debug, PartialEq)]
+pub enum SocksState {
+    AwaitingGreeting,
+    AwaitingCredentials,
+    AwaitingRequest,
+    Tunneling {hostname: String, port: u16}
+}
+
+#[derive (Debug, PartialEq)]
+pub enum SocksStep {
+    // Send the reply and keep talking
+    Continue {reply: Vec<u8>, next_state: SocksState},
+    // Send the reply and hang up
+    Refuse {reply: Vec<u8>}
+}
+
+/// Handles one framed message from a SOCKS client that hasn't finished negotiating. If credentials
+/// are given, clients must use username/password authentication; otherwise they must use none.
+pub fn negotiate (state: &SocksState, data: &[u8], credentials: &Option<(String, String)>) -> SocksStep {
+    match state {
+        &SocksState::AwaitingGreeting => handle_greeting (data, credentials),
+        &SocksState::AwaitingCredentials => handle_credentials (data, credentials),
+        &SocksState::AwaitingRequest => handle_request (data),
+        &SocksState::Tunneling {..} => panic! ("Tunneled data is not part of SOCKS negotiation")
+    }
+}
+
+fn handle_greeting (data: &[u8], credentials: &Option<(String, String)>) -> SocksStep {
+    let (method, next_state) = match credentials {
+        &Some (_) => (USERNAME_PASSWORD, SocksState::AwaitingCredentials),
+        &None => (NO_AUTHENTICATION, SocksState::AwaitingRequest)
+    };
+    let offered = (data.len () >= 2) && (data[0] == SOCKS_VERSION) && (data.len () == 2 + data[1] as usize)
+        && data[2..].contains (&method);
+    if offered {
+        SocksStep::Continue {reply: vec! (SOCKS_VERSION, method), next_state}
+    }
+    else {
+        SocksStep::Refuse {reply: vec! (SOCKS_VERSION, NO_ACCEPTABLE_METHODS)}
+    }
+}
+
+fn handle_credentials (data: &[u8], credentials: &Option<(String, String)>) -> SocksStep {
+    let accepted = match (parse_credentials (data), credentials) {
+        (Some ((ref username, ref password)), &Some ((ref expected_username, ref expected_password))) =>
+            (username == expected_username) && (password == expected_password),
+        _ => false
+    };
+    if accepted {
+        SocksStep::Continue {reply: vec! (AUTH_VERSION, AUTH_SUCCEEDED), next_state: SocksState::AwaitingRequest}
+    }
+    else {
+        SocksStep::Refuse {reply: vec! (AUTH_VERSION, AUTH_FAILED)}
+    }
+}
+
+fn parse_credentials (data: &[u8]) -> Option<(String, String)> {
+    if (data.len () < 2) || (data[0] != AUTH_VERSION) {return None}
+    let username_end = 2 + data[1] as usize;
+    if data.len () <= username_end {return None}
+    let password_end = username_end + 1 + data[username_end] as usize;
+    if data.len () != password_end {return None}
+    let username = String::from_utf8 (data[2..username_end].to_vec ()).ok ()?;
+    let password = String::from_utf8 (data[username_end + 1..password_end].to_vec ()).ok ()?;
+    Some ((username, password))
+}
+
+fn handle_request (data: &[u8]) -> SocksStep {
+    if (data.len () < 4) || (data[0] != SOCKS_VERSION) {
+        return SocksStep::Refuse {reply: make_reply (REPLY_GENERAL_FAILURE)}
+    }
+    if data[1] != CONNECT {
+        return SocksStep::Refuse {reply: make_reply (REPLY_COMMAND_NOT_SUPPORTED)}
+    }
+    let (hostname, port_offset) = match data[3] {
+        ADDRESS_IPV4 if data.len () == 10 => {
+            (Ipv4Addr::new (data[4], data[5], data[6], data[7]).to_string (), 8)
+        },
+        ADDRESS_DOMAIN_NAME if (data.len () > 4) && (data.len () == 7 + data[4] as usize) => {
+            match String::from_utf8 (data[5..data.len () - 2].to_vec ()) {
+                Ok (hostname) => (hostname, data.len () - 2),
+                Err (_) => return SocksStep::Refuse {reply: make_reply (REPLY_GENERAL_FAILURE)}
+            }
+        },
+        ADDRESS_IPV6 if data.len () == 22 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice (&data[4..20]);
+            (Ipv6Addr::from (octets).to_string (), 20)
+        },
+        ADDRESS_IPV4 | ADDRESS_DOMAIN_NAME | ADDRESS_IPV6 => return SocksStep::Refuse {reply: make_reply (REPLY_GENERAL_FAILURE)},
+        _ => return SocksStep::Refuse {reply: make_reply (REPLY_ADDRESS_TYPE_NOT_SUPPORTED)}
+    };
+    let port = ((data[port_offset] as u16) << 8) | (data[port_offset + 1] as u16);
+    SocksStep::Continue {reply: make_reply (REPLY_SUCCEEDED), next_state: SocksState::Tunneling {hostname, port}}
+}
+
+// We don't know the bound address on the far end, so we report 0.0.0.0:0 like many servers do.
+fn make_reply (reply_code: u8) -> Vec<u8> {
+    vec! (SOCKS_VERSION, reply_code, 0x00, ADDRESS_IPV4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00)
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+
+    fn credentials () -> Option<(String, String)> {
+        Some ((String::from ("booga"), String::from ("wooga")))
+    }
+
+    #[test]
+    fn greeting_offering_no_authentication_is_accepted_when_no_credentials_are_configured () {
+        let result = negotiate (&SocksState::AwaitingGreeting, &[0x05, 0x02, 0x02, 0x00], &None);
+
+        assert_eq! (result, SocksStep::Continue {reply: vec! (0x05, 0x00), next_state: SocksState::AwaitingRequest});
+    }
+
+    #[test]
+    fn greeting_offering_only_username_password_is_refused_when_no_credentials_are_configured () {
+        let result = negotiate (&SocksState::AwaitingGreeting, &[0x05, 0x01, 0x02], &None);
+
+        assert_eq! (result, SocksStep::Refuse {reply: vec! (0x05, 0xFF)});
+    }
+
+    #[test]
+    fn greeting_offering_username_password_is_accepted_when_credentials_are_configured () {
+        let result = negotiate (&SocksState::AwaitingGreeting, &[0x05, 0x02, 0x00, 0x02], &credentials ());
+
+        assert_eq! (result, SocksStep::Continue {reply: vec! (0x05, 0x02), next_state: SocksState::AwaitingCredentials});
+    }
+
+    #[test]
+    fn greeting_offering_only_no_authentication_is_refused_when_credentials_are_configured () {
+        let result = negotiate (&SocksState::AwaitingGreeting, &[0x05, 0x01, 0x00], &credentials ());
+
+        assert_eq! (result, SocksStep::Refuse {reply: vec! (0x05, 0xFF)});
+    }
+
+    #[test]
+    fn greeting_from_socks4_client_is_refused () {
+        let result = negotiate (&SocksState::AwaitingGreeting, &[0x04, 0x01, 0x00, 0x50, 0x01, 0x02, 0x03, 0x04, 0x00], &None);
+
+        assert_eq! (result, SocksStep::Refuse {reply: vec! (0x05, 0xFF)});
+    }
+
+    #[test]
+    fn correct_credentials_are_accepted () {
+        let result = negotiate (&SocksState::AwaitingCredentials, b"\x01\x05booga\x05wooga", &credentials ());
+
+        assert_eq! (result, SocksStep::Continue {reply: vec! (0x01, 0x00), next_state: SocksState::AwaitingRequest});
+    }
+
+    #[test]
+    fn incorrect_credentials_are_refused () {
+        let result = negotiate (&SocksState::AwaitingCredentials, b"\x01\x05booga\x05gooba", &credentials ());
+
+        assert_eq! (result, SocksStep::Refuse {reply: vec! (0x01, 0x01)});
+    }
+
+    #[test]
+    fn malformed_credentials_are_refused () {
+        let result = negotiate (&SocksState::AwaitingCredentials, b"\x01\x05booga\x09wooga", &credentials ());
+
+        assert_eq! (result, SocksStep::Refuse {reply: vec! (0x01, 0x01)});
+    }
+
+    #[test]
+    fn connect_request_with_domain_name_opens_tunnel () {
+        let result = negotiate (&SocksState::AwaitingRequest, b"\x05\x01\x00\x03\x0Aserver.com\x00\x16", &None);
+
+        assert_eq! (result, SocksStep::Continue {
+            reply: vec! (0x05, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+            next_state: SocksState::Tunneling {hostname: String::from ("server.com"), port: 22}
+        });
+    }
+
+    #[test]
+    fn connect_request_with_ipv4_address_opens_tunnel () {
+        let result = negotiate (&SocksState::AwaitingRequest, &[0x05, 0x01, 0x00, 0x01, 1, 2, 3, 4, 0x1F, 0x90], &None);
+
+        assert_eq! (result, SocksStep::Continue {
+            reply: vec! (0x05, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+            next_state: SocksState::Tunneling {hostname: String::from ("1.2.3.4"), port: 8080}
+        });
+    }
+
+    #[test]
+    fn connect_request_with_ipv6_address_opens_tunnel () {
+        let result = negotiate (&SocksState::AwaitingRequest, &[0x05, 0x01, 0x00, 0x04,
+            0x20, 0x01, 0x0D, 0xB8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x01, 0xBB], &None);
+
+        assert_eq! (result, SocksStep::Continue {
+            reply: vec! (0x05, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+            next_state: SocksState::Tunneling {hostname: String::from ("2001:db8::1"), port: 443}
+        });
+    }
+
+    #[test]
+    fn bind_request_is_refused () {
+        let result = negotiate (&SocksState::AwaitingRequest, &[0x05, 0x02, 0x00, 0x01, 1, 2, 3, 4, 0x1F, 0x90], &None);
+
+        assert_eq! (result, SocksStep::Refuse {reply: vec! (0x05, 0x07, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00)});
+    }
+
+    #[test]
+    fn request_with_unknown_address_type_is_refused () {
+        let result = negotiate (&SocksState::AwaitingRequest, &[0x05, 0x01, 0x00, 0x02, 1, 2, 3, 4, 0x1F, 0x90], &None);
+
+        assert_eq! (result, SocksStep::Refuse {reply: vec! (0x05, 0x08, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00)});
+    }
+
+    #[test]
+    fn truncated_request_is_refused () {
+        let result = negotiate (&SocksState::AwaitingRequest, &[0x05, 0x01, 0x00, 0x01, 1, 2, 3], &None);
+
+        assert_eq! (result, SocksStep::Refuse {reply: vec! (0x05, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00)});
+    }
+}
@@ -2,6 +2,14 @@
 use protocol_pack::ProtocolPack;
 use sub_lib::proxy_server::ProxyProtocol;
 use sub_lib::cryptde::PlainData;
+use sub_lib::proxy_client::StreamFailure;
+
+// RFC 5246 section 7.2 and RFC 6066 section 3
+const ALERT_CONTENT_TYPE: u8 = 0x15;
+const FATAL: u8 = 0x02;
+const ACCESS_DENIED: u8 = 49;
+const INTERNAL_ERROR: u8 = 80;
+const UNRECOGNIZED_NAME: u8 = 112;
 
 pub struct TlsProtocolPack {}
 
@@ -13,6 +21,17 @@ impl ProtocolPack for TlsProtocolPack {
         if !TlsProtocolPack::is_client_hello (&data) {return None}
         TlsProtocolPack::find_host_name(&data)
     }
+
+    // We can't say much to a client that's expecting a handshake with the server: an unencrypted fatal
+    // alert is all it will accept. The hostname is already in the client's own SNI.
+    fn failure_response (&self, failure: StreamFailure, _hostname: Option<&str>) -> Vec<u8> {
+        let description = match failure {
+            StreamFailure::DnsFailure | StreamFailure::MissingHostname => UNRECOGNIZED_NAME,
+            StreamFailure::PolicyBlock => ACCESS_DENIED,
+            StreamFailure::ConnectionRefused | StreamFailure::Timeout | StreamFailure::ConnectionFailed => INTERNAL_ERROR,
+        };
+        vec! (ALERT_CONTENT_TYPE, 0x03, 0x01, 0x00, 0x02, FATAL, description)
+    }
 }
 
 impl TlsProtocolPack {
@@ -87,6 +106,17 @@ mod tests {
         assert_eq! (result, ProxyProtocol::TLS);
     }
 
+    #[test]
+    fn failure_response_is_a_fatal_alert () {
+        let alert = |failure| TlsProtocolPack{}.failure_response (failure, Some ("server.com"));
+
+        assert_eq! (alert (StreamFailure::DnsFailure), vec! (0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 112));
+        assert_eq! (alert (StreamFailure::MissingHostname), vec! (0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 112));
+        assert_eq! (alert (StreamFailure::PolicyBlock), vec! (0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 49));
+        assert_eq! (alert (StreamFailure::ConnectionRefused), vec! (0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 80));
+        assert_eq! (alert (StreamFailure::Timeout), vec! (0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 80));
+    }
+
     #[test]
     fn rejects_non_empty_packet_that_is_not_handshake () {
         vec! (0x14u8, 0x015u8, 0x17u8).iter ().for_each (|content_type| {
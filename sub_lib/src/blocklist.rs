@@ -0,0 +1,303 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::thread;
+use std::time::Duration;
+use logger::Logger;
+
+// Hosts files name these for the machine's own sake; blocking them would break things
+const HOSTS_FILE_NAMES: &[&str] = &["localhost", "localhost.localdomain", "local", "broadcasthost",
+    "ip6-localhost", "ip6-loopback", "0.0.0.0"];
+
+/// Which part of the Node turned a blocked hostname away
+#[derive (Clone, Copy, Debug, PartialEq)]
+pub enum BlockSource {
+    Dns,
+    ProxyServer
+}
+
+#[derive (Clone, Debug, PartialEq)]
+pub struct BlocklistStats {
+    pub domain_count: usize,
+    pub dns_blocks: u64,
+    pub proxy_server_blocks: u64
+}
+
+struct BlocklistInner {
+    files: Vec<PathBuf>,
+    // Lookups take a reference to the current set and search it without holding the lock
+    domains: Arc<HashSet<String>>,
+    // Bumped by every load, so the reloader thread of an earlier load knows to stop
+    generation: u64,
+    dns_blocks: u64,
+    proxy_server_blocks: u64
+}
+
+/// Hostnames the user never wants to reach, read from hosts-format files ("0.0.0.0 tracker.com")
+/// or plain domain lists ("tracker.com"). Blocking a domain blocks all its subdomains too.
+/// Clones share the same list and counters, so the Entry DNS server and the ProxyServer agree.
+#[derive (Clone)]
+pub struct Blocklist {
+    inner: Arc<Mutex<BlocklistInner>>
+}
+
+impl Blocklist {
+    /// Blocks nothing until files are loaded
+    pub fn new () -> Blocklist {
+        Blocklist {
+            inner: Arc::new (Mutex::new (BlocklistInner {
+                files: vec! (),
+                domains: Arc::new (HashSet::new ()),
+                generation: 0,
+                dns_blocks: 0,
+                proxy_server_blocks: 0
+            }))
+        }
+    }
+
+    /// Reads the files now and, if there's an interval, again on a background thread every time
+    /// that interval passes. A file that can't be read later on leaves the previous list in place.
+    pub fn load (&self, files: Vec<PathBuf>, reload_interval: Option<Duration>) -> Result<(), String> {
+        let domains = Blocklist::read_files (&files)?;
+        let generation = {
+            let mut inner = self.inner.lock ().expect ("Blocklist is poisoned");
+            inner.files = files;
+            inner.domains = Arc::new (domains);
+            inner.generation += 1;
+            inner.generation
+        };
+        if let Some (interval) = reload_interval {
+            let weak_inner = Arc::downgrade (&self.inner);
+            thread::spawn (move || Blocklist::keep_reloading (weak_inner, generation, interval));
+        }
+        Ok (())
+    }
+
+    /// Reads the files again and swaps in what they say, keeping the previous list if any of them
+    /// can't be read. Lookups go on using the previous list while the files are being read.
+    pub fn reload (&self) {
+        let files = self.inner.lock ().expect ("Blocklist is poisoned").files.clone ();
+        let result = Blocklist::read_files (&files);
+        let mut inner = self.inner.lock ().expect ("Blocklist is poisoned");
+        let logger = Logger::new ("Blocklist");
+        match result {
+            Ok (domains) => {
+                inner.domains = Arc::new (domains);
+                logger.info (format! ("Reloaded {} blocked domains; {} DNS queries and {} requests blocked so far",
+                    inner.domains.len (), inner.dns_blocks, inner.proxy_server_blocks));
+            },
+            Err (msg) => logger.error (format! ("Keeping previous {} blocked domains: {}", inner.domains.len (), msg))
+        }
+    }
+
+    /// Counts the block against the source if the hostname or any domain above it is on the list
+    pub fn blocks (&self, hostname: &str, source: BlockSource) -> bool {
+        let domains = self.inner.lock ().expect ("Blocklist is poisoned").domains.clone ();
+        let hostname = Blocklist::normalize (hostname);
+        let mut candidate = &hostname[..];
+        let blocked = loop {
+            if domains.contains (candidate) {break true}
+            match candidate.find ('.') {
+                Some (index) => candidate = &candidate[index + 1..],
+                None => break false
+            }
+        };
+        if blocked {
+            let mut inner = self.inner.lock ().expect ("Blocklist is poisoned");
+            match source {
+                BlockSource::Dns => inner.dns_blocks += 1,
+                BlockSource::ProxyServer => inner.proxy_server_blocks += 1
+            }
+        }
+        blocked
+    }
+
+    pub fn stats (&self) -> BlocklistStats {
+        let inner = self.inner.lock ().expect ("Blocklist is poisoned");
+        BlocklistStats {
+            domain_count: inner.domains.len (),
+            dns_blocks: inner.dns_blocks,
+            proxy_server_blocks: inner.proxy_server_blocks
+        }
+    }
+
+    // Stops once every clone of the Blocklist is gone or the files have been loaded again
+    fn keep_reloading (weak_inner: Weak<Mutex<BlocklistInner>>, generation: u64, interval: Duration) {
+        loop {
+            thread::sleep (interval);
+            let blocklist = match weak_inner.upgrade () {
+                Some (inner) => Blocklist {inner},
+                None => return
+            };
+            if blocklist.inner.lock ().expect ("Blocklist is poisoned").generation != generation {return}
+            blocklist.reload ();
+        }
+    }
+
+    fn read_files (files: &Vec<PathBuf>) -> Result<HashSet<String>, String> {
+        let mut domains = HashSet::new ();
+        for path in files {
+            let mut contents = String::new ();
+            File::open (path).and_then (|mut file| file.read_to_string (&mut contents))
+                .map_err (|e| format! ("Can't read blocklist file {:?}: {}", path, e))?;
+            domains.extend (Blocklist::parse (&contents));
+        }
+        Ok (domains)
+    }
+
+    fn parse (contents: &str) -> Vec<String> {
+        contents.lines ().flat_map (|line| {
+            let line = match line.find ('#') {
+                Some (index) => &line[..index],
+                None => line
+            };
+            let mut words: Vec<&str> = line.split_whitespace ().collect ();
+            if words.first ().map (|word| IpAddr::from_str (word).is_ok ()).unwrap_or (false) {
+                words.remove (0);
+            }
+            words.into_iter ()
+                .map (Blocklist::normalize)
+                .filter (|domain| !domain.is_empty () && !HOSTS_FILE_NAMES.contains (&domain.as_str ()))
+                .collect::<Vec<String>> ()
+        }).collect ()
+    }
+
+    // DNS names are case-insensitive and may or may not carry the root's trailing dot
+    fn normalize (hostname: &str) -> String {
+        hostname.trim_end_matches ('.').to_lowercase ()
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+
+    fn make_file (test_name: &str, file_name: &str, contents: &str) -> PathBuf {
+        let dir = env::current_dir ().unwrap ().join (Path::new ("generated")).join (Path::new ("Blocklist"))
+            .join (Path::new (test_name));
+        fs::create_dir_all (dir.clone ()).unwrap ();
+        let path = dir.join (Path::new (file_name));
+        let mut file = File::create (path.clone ()).unwrap ();
+        write! (file, "{}", contents).unwrap ();
+        path
+    }
+
+    #[test]
+    fn empty_blocklist_blocks_nothing () {
+        let subject = Blocklist::new ();
+
+        assert_eq! (subject.blocks ("tracker.com", BlockSource::Dns), false);
+        assert_eq! (subject.stats (), BlocklistStats {domain_count: 0, dns_blocks: 0, proxy_server_blocks: 0});
+    }
+
+    #[test]
+    fn reads_hosts_files_and_domain_lists () {
+        let hosts = make_file ("reads_hosts_files_and_domain_lists", "hosts",
+            "# ad servers\n127.0.0.1 localhost\n::1 ip6-localhost\n0.0.0.0 ads.example.com Banner.Example.com. # two at once\n\n");
+        let list = make_file ("reads_hosts_files_and_domain_lists", "list", "tracker.com\n  # indented comment\npixel.net\n");
+        let subject = Blocklist::new ();
+
+        subject.load (vec! (hosts, list), None).unwrap ();
+
+        assert_eq! (subject.blocks ("ads.example.com", BlockSource::Dns), true);
+        assert_eq! (subject.blocks ("banner.example.com", BlockSource::Dns), true);
+        assert_eq! (subject.blocks ("tracker.com", BlockSource::Dns), true);
+        assert_eq! (subject.blocks ("pixel.net", BlockSource::Dns), true);
+        assert_eq! (subject.blocks ("example.com", BlockSource::Dns), false);
+        assert_eq! (subject.blocks ("localhost", BlockSource::Dns), false);
+        assert_eq! (subject.stats ().domain_count, 4);
+    }
+
+    #[test]
+    fn blocks_subdomains_but_not_lookalikes () {
+        let list = make_file ("blocks_subdomains_but_not_lookalikes", "list", "tracker.com\n");
+        let subject = Blocklist::new ();
+        subject.load (vec! (list), None).unwrap ();
+
+        assert_eq! (subject.blocks ("cdn.eu.TRACKER.com.", BlockSource::Dns), true);
+        assert_eq! (subject.blocks ("nottracker.com", BlockSource::Dns), false);
+        assert_eq! (subject.blocks ("tracker.com.au", BlockSource::Dns), false);
+    }
+
+    #[test]
+    fn counts_blocks_by_source_through_clones () {
+        let list = make_file ("counts_blocks_by_source_through_clones", "list", "tracker.com\n");
+        let subject = Blocklist::new ();
+        let clone = subject.clone ();
+        subject.load (vec! (list), None).unwrap ();
+
+        clone.blocks ("tracker.com", BlockSource::Dns);
+        clone.blocks ("www.tracker.com", BlockSource::Dns);
+        clone.blocks ("tracker.com", BlockSource::ProxyServer);
+        clone.blocks ("innocent.com", BlockSource::ProxyServer);
+
+        assert_eq! (subject.stats (), BlocklistStats {domain_count: 1, dns_blocks: 2, proxy_server_blocks: 1});
+    }
+
+    #[test]
+    fn unreadable_file_is_reported () {
+        let subject = Blocklist::new ();
+
+        let result = subject.load (vec! (PathBuf::from ("/nonexistent/blocklist")), None);
+
+        assert_eq! (result.is_err (), true);
+        assert_eq! (result.err ().unwrap ().starts_with ("Can't read blocklist file \"/nonexistent/blocklist\": "), true);
+    }
+
+    #[test]
+    fn reloaded_list_replaces_the_old_one_and_survives_a_vanished_file () {
+        let list = make_file ("reloaded_list_replaces_the_old_one_and_survives_a_vanished_file", "list", "first.com\n");
+        let subject = Blocklist::new ();
+        subject.load (vec! (list.clone ()), None).unwrap ();
+        make_file ("reloaded_list_replaces_the_old_one_and_survives_a_vanished_file", "list", "second.com\n");
+
+        subject.reload ();
+        let first_after_reload = subject.blocks ("first.com", BlockSource::Dns);
+        let second_after_reload = subject.blocks ("second.com", BlockSource::Dns);
+        fs::remove_file (list).unwrap ();
+        subject.reload ();
+        let second_after_failed_reload = subject.blocks ("second.com", BlockSource::Dns);
+
+        assert_eq! (first_after_reload, false);
+        assert_eq! (second_after_reload, true);
+        assert_eq! (second_after_failed_reload, true);
+    }
+
+    #[test]
+    fn list_with_reload_interval_is_reloaded_without_any_lookups () {
+        let list = make_file ("list_with_reload_interval_is_reloaded_without_any_lookups", "list", "first.com\n");
+        let subject = Blocklist::new ();
+        subject.load (vec! (list), Some (Duration::from_millis (10))).unwrap ();
+
+        make_file ("list_with_reload_interval_is_reloaded_without_any_lookups", "list", "second.com\nthird.com\n");
+
+        for _ in 0..200 {
+            if subject.stats ().domain_count == 2 {break}
+            thread::sleep (Duration::from_millis (10));
+        }
+        assert_eq! (subject.stats ().domain_count, 2);
+        assert_eq! (subject.blocks ("third.com", BlockSource::Dns), true);
+    }
+
+    #[test]
+    fn list_without_reload_interval_is_never_reloaded () {
+        let list = make_file ("list_without_reload_interval_is_never_reloaded", "list", "first.com\n");
+        let subject = Blocklist::new ();
+        subject.load (vec! (list), None).unwrap ();
+        make_file ("list_without_reload_interval_is_never_reloaded", "list", "second.com\n");
+
+        assert_eq! (subject.blocks ("first.com", BlockSource::Dns), true);
+        assert_eq! (subject.blocks ("second.com", BlockSource::Dns), false);
+    }
+}
@@ -1,6 +1,8 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 use std::fmt;
-use std::net::SocketAddr;
+use bytes::Bytes;
+use rand::thread_rng;
+use rand::Rng;
 use serde;
 use serde::Serialize;
 use serde::Deserialize;
@@ -49,9 +51,10 @@ impl<'a> Visitor<'a> for KeyVisitor {
     }
 }
 
+/// Clones share the same bytes rather than copying them
 #[derive (Clone, Debug, PartialEq)]
 pub struct CryptData {
-    pub data: Vec<u8>
+    pub data: Bytes
 }
 
 impl Serialize for CryptData {
@@ -69,11 +72,23 @@ impl<'de> Deserialize<'de> for CryptData {
 impl CryptData {
     pub fn new (data: &[u8]) -> CryptData {
         CryptData {
-            data: Vec::from (data)
+            data: Bytes::from (data)
         }
     }
 }
 
+impl From<Vec<u8>> for CryptData {
+    fn from (data: Vec<u8>) -> CryptData {
+        CryptData {data: Bytes::from (data)}
+    }
+}
+
+impl From<Bytes> for CryptData {
+    fn from (data: Bytes) -> CryptData {
+        CryptData {data}
+    }
+}
+
 struct CryptDataVisitor;
 
 impl<'a> Visitor<'a> for CryptDataVisitor {
@@ -86,11 +101,16 @@ impl<'a> Visitor<'a> for CryptDataVisitor {
     fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> where E: serde::de::Error {
         Ok (CryptData::new (v))
     }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> where E: serde::de::Error {
+        Ok (CryptData::from (v))
+    }
 }
 
+/// Clones share the same bytes rather than copying them
 #[derive (Clone, Debug, PartialEq)]
 pub struct PlainData {
-    pub data: Vec<u8>
+    pub data: Bytes
 }
 
 impl Serialize for PlainData {
@@ -108,11 +128,23 @@ impl<'de> Deserialize<'de> for PlainData {
 impl PlainData {
     pub fn new (data: &[u8]) -> PlainData {
         PlainData {
-            data: Vec::from (data)
+            data: Bytes::from (data)
         }
     }
 }
 
+impl From<Vec<u8>> for PlainData {
+    fn from (data: Vec<u8>) -> PlainData {
+        PlainData {data: Bytes::from (data)}
+    }
+}
+
+impl From<Bytes> for PlainData {
+    fn from (data: Bytes) -> PlainData {
+        PlainData {data}
+    }
+}
+
 struct PlainDataVisitor;
 
 impl<'a> Visitor<'a> for PlainDataVisitor {
@@ -125,6 +157,10 @@ impl<'a> Visitor<'a> for PlainDataVisitor {
     fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> where E: serde::de::Error {
         Ok (PlainData::new (v))
     }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> where E: serde::de::Error {
+        Ok (PlainData::from (v))
+    }
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -144,13 +180,64 @@ pub trait CryptDE: Send + Sync {
     fn public_key(&self) -> Key;
 }
 
-pub type StreamKey = SocketAddr;
+const STREAM_KEY_LENGTH: usize = 16;
+
+/// Names a stream for its whole trip through the Substratum Network. The originating Node makes it
+/// up at random rather than using its client's address, so that streams from different originators
+/// whose clients happen to use the same address and port can't be confused at an exit they share.
+#[derive (Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StreamKey {
+    id: [u8; STREAM_KEY_LENGTH]
+}
+
+impl StreamKey {
+    pub fn new () -> StreamKey {
+        let mut id = [0u8; STREAM_KEY_LENGTH];
+        thread_rng ().fill (&mut id[..]);
+        StreamKey {id}
+    }
+}
+
+impl fmt::Display for StreamKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.id.iter () {
+            write! (f, "{:02x}", byte)?;
+        }
+        Ok (())
+    }
+}
+
+impl fmt::Debug for StreamKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write! (f, "StreamKey({})", self)
+    }
+}
 
 #[cfg (test)]
 mod tests {
     use super::*;
     use serde_cbor;
 
+    #[test]
+    fn stream_keys_are_random () {
+        let first = StreamKey::new ();
+        let second = StreamKey::new ();
+
+        assert_ne! (first, second);
+    }
+
+    #[test]
+    fn stream_key_displays_as_hex_and_survives_serialization () {
+        let subject = StreamKey {id: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0xFF]};
+
+        let data = serde_cbor::ser::to_vec (&subject).unwrap ();
+        let output = serde_cbor::de::from_slice::<StreamKey> (&data[..]).unwrap ();
+
+        assert_eq! (format! ("{}", subject), "000102030405060708090a0b0c0d0eff");
+        assert_eq! (format! ("{:?}", subject), "StreamKey(000102030405060708090a0b0c0d0eff)");
+        assert_eq! (output, subject);
+    }
+
     #[test]
     fn key_constructor_works_as_expected () {
         let subject = Key::new (&[1, 2, 3, 4]);
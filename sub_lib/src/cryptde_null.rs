@@ -29,7 +29,7 @@ impl CryptDE for CryptDENull {
             Err(CryptdecError::EmptyData)
         } else {
             let other_key = CryptDENull::other_key(key);
-            Ok(CryptData::new (&[&other_key.data[..], &data.data[..]].concat()[..]))
+            Ok(CryptData::from ([&other_key.data[..], &data.data[..]].concat()))
         }
     }
 
@@ -41,11 +41,10 @@ impl CryptDE for CryptDENull {
         } else if key.data.len() > data.data.len() {
             Err(CryptdecError::InvalidKey (CryptDENull::invalid_key_message(key, data)))
         } else {
-            let (k, d) = data.data.split_at(key.data.len());
-            if k != &key.data[..] {
+            if &data.data[..key.data.len()] != &key.data[..] {
                 Err(CryptdecError::InvalidKey (CryptDENull::invalid_key_message(key, data)))
             } else {
-                Ok(PlainData::new (d))
+                Ok(PlainData::from (data.data.slice_from (key.data.len())))
             }
         }
     }
@@ -80,7 +79,7 @@ impl CryptDENull {
     }
 
     fn invalid_key_message (key: &Key, data: &CryptData) -> String {
-        let data_to_print: Vec<u8> = data.clone ().data.into_iter ().take (key.data.len ()).collect ();
+        let data_to_print: Vec<u8> = data.data.iter ().take (key.data.len ()).cloned ().collect ();
         format!("Could not decrypt with {:?} data beginning with {:?}", key.data, data_to_print)
     }
 }
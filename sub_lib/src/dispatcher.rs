@@ -4,8 +4,10 @@ use std::net::SocketAddr;
 use std::fmt;
 use std::fmt::Debug;
 use std::fmt::Formatter;
+use std::str;
 use actix::Recipient;
 use actix::Syn;
+use bytes::Bytes;
 use serde;
 use serde::Serialize;
 use serde::Deserialize;
@@ -15,6 +17,9 @@ use serde::de::Visitor;
 use cryptde::Key;
 use hopper::HopperTemporaryTransmitDataMsg;
 use peer_actors::BindMessage;
+use proxy_server::ProxyProtocol;
+use stream_handler_pool::PauseReadingMsg;
+use stream_handler_pool::WriteBacklogMsg;
 use stream_handler_pool::TransmitDataMsg;
 use utils::to_string;
 
@@ -123,18 +128,25 @@ pub enum DispatcherError {
 pub struct InboundClientData {
     pub socket_addr: SocketAddr,
     pub origin_port: Option<u16>,
+    // The hostname the client meant to reach, if it can be worked out from the address it connected to
+    pub target_hostname: Option<String>,
+    // The protocol the Dispatcher recognized at the start of the stream, if the port allows more than one
+    pub proxy_protocol: Option<ProxyProtocol>,
+    // The client has finished sending, though it may still be reading
     pub last_data: bool,
-    pub data: Vec<u8>
+    // The client has gone altogether
+    pub stopped_reading: bool,
+    pub data: Bytes
 }
 
 impl Debug for InboundClientData {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let data_string = match String::from_utf8 (self.data.clone ()) {
-            Ok (string) => string,
+        let data_string = match str::from_utf8 (&self.data[..]) {
+            Ok (string) => string.to_string (),
             Err (_) => format! ("{:?}", &self.data[..])
         };
-        write! (f, "InboundClientData {{ socket_addr: {:?}, origin_port: {:?}, last_data: {}, data: {} }}",
-                self.socket_addr, self.origin_port, self.last_data, data_string)
+        write! (f, "InboundClientData {{ socket_addr: {:?}, origin_port: {:?}, target_hostname: {:?}, proxy_protocol: {:?}, last_data: {}, stopped_reading: {}, data: {} }}",
+                self.socket_addr, self.origin_port, self.target_hostname, self.proxy_protocol, self.last_data, self.stopped_reading, data_string)
     }
 }
 
@@ -143,6 +155,8 @@ pub struct DispatcherSubs {
     pub bind: Recipient<Syn, BindMessage>,
     // TODO when we are decentralized, rename this to "from_dispatcher_client"
     pub from_proxy_server: Recipient<Syn, TransmitDataMsg>,
+    pub pause_reading: Recipient<Syn, PauseReadingMsg>,
+    pub write_backlog: Recipient<Syn, WriteBacklogMsg>,
     // TODO when we are decentralized, remove this
     pub from_hopper: Recipient<Syn, HopperTemporaryTransmitDataMsg>,
 }
@@ -153,6 +167,8 @@ impl Clone for DispatcherSubs {
             ibcd_sub: self.ibcd_sub.clone (),
             bind: self.bind.clone(),
             from_proxy_server: self.from_proxy_server.clone(),
+            pause_reading: self.pause_reading.clone(),
+            write_backlog: self.write_backlog.clone(),
             from_hopper: self.from_hopper.clone(),
         }
     }
@@ -32,7 +32,7 @@ impl Hop {
 
     pub fn encode (&self, key: &Key, cryptde: &CryptDE) -> Result<CryptData, CryptdecError> {
         let plain_data = match serde_cbor::ser::to_vec (&self) {
-            Ok (data) => PlainData::new (&data[..]),
+            Ok (data) => PlainData::from (data),
             // crashpoint - need to figure out how to return serialize error
             Err (_) => unimplemented!()
         };
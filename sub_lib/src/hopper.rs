@@ -1,6 +1,8 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::time::Duration;
 use actix::Recipient;
 use actix::Syn;
+use bytes::Bytes;
 use serde::de::Deserialize;
 use serde::ser::Serialize;
 use serde_cbor;
@@ -16,7 +18,7 @@ use route::Route;
 pub struct HopperTemporaryTransmitDataMsg {
     pub endpoint: Endpoint,
     pub last_data: bool,
-    pub data: Vec<u8>
+    pub data: Bytes
 }
 
 /// New CORES package about to be sent to the Hopper and thence put on the Substratum Network
@@ -33,7 +35,7 @@ impl IncipientCoresPackage {
         let serialized_payload = serde_cbor::ser::to_vec(&payload).expect ("Serialization failure");
         IncipientCoresPackage {
             route,
-            payload: PlainData::new (&serialized_payload[..]),
+            payload: PlainData::from (serialized_payload),
             payload_destination_key: payload_destination_key.clone ()
         }
     }
@@ -63,6 +65,79 @@ impl ExpiredCoresPackage {
     }
 }
 
+/// Outbound packages whose encrypted size is at least this many bytes are mixed as bulk traffic
+pub const BULK_TRAFFIC_THRESHOLD: usize = 0x1000;
+
+/// How the Hopper holds outbound packages in its mixing pool before releasing them. Each package
+/// waits a random delay between min_delay and max_delay; if batch_size is nonzero, the whole pool
+/// is released in shuffled order as soon as it holds that many packages.
+#[derive (Clone, Copy, Debug, PartialEq)]
+pub struct MixingConfig {
+    pub batch_size: usize,
+    pub min_delay: Duration,
+    pub max_delay: Duration
+}
+
+/// How often, on average, the Hopper emits a dummy CORES package, and how big its payload is
+#[derive (Clone, Copy, Debug, PartialEq)]
+pub struct CoverTrafficConfig {
+    pub interval: Duration,
+    pub payload_size: usize
+}
+
+/// How long the destination Hopper waits for the rest of a fragmented payload before abandoning it
+pub const DEFAULT_REASSEMBLY_TIMEOUT_MS: u64 = 30000;
+
+/// How many bytes of incomplete payloads the destination Hopper buffers before abandoning the oldest
+pub const DEFAULT_MAX_REASSEMBLY_BYTES: usize = 0x1000000;
+
+/// Outbound payloads larger than max_fragment_size bytes are split into numbered fragments. The
+/// reassembly limits apply to fragments arriving from other Nodes whether or not this Node
+/// fragments its own payloads.
+#[derive (Clone, Copy, Debug, PartialEq)]
+pub struct FragmentationConfig {
+    pub max_fragment_size: Option<usize>,
+    pub reassembly_timeout: Duration,
+    pub max_reassembly_bytes: usize
+}
+
+impl FragmentationConfig {
+    pub fn new () -> FragmentationConfig {
+        FragmentationConfig {
+            max_fragment_size: None,
+            reassembly_timeout: Duration::from_millis (DEFAULT_REASSEMBLY_TIMEOUT_MS),
+            max_reassembly_bytes: DEFAULT_MAX_REASSEMBLY_BYTES
+        }
+    }
+}
+
+/// A worker_count of zero keeps all of the Hopper's cryptographic work on the Hopper's own thread.
+/// Cover traffic is routed through the Nodes in cover_relays; with none, it goes to this Node alone.
+#[derive (Clone, Debug, PartialEq)]
+pub struct HopperConfig {
+    pub interactive_mixing: Option<MixingConfig>,
+    pub bulk_mixing: Option<MixingConfig>,
+    pub cover_traffic: Option<CoverTrafficConfig>,
+    pub cover_relays: Vec<Key>,
+    pub bandwidth_limit: Option<usize>,
+    pub fragmentation: FragmentationConfig,
+    pub worker_count: usize
+}
+
+impl HopperConfig {
+    pub fn new () -> HopperConfig {
+        HopperConfig {
+            interactive_mixing: None,
+            bulk_mixing: None,
+            cover_traffic: None,
+            cover_relays: vec! (),
+            bandwidth_limit: None,
+            fragmentation: FragmentationConfig::new (),
+            worker_count: 0
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct HopperSubs {
     pub bind: Recipient<Syn, BindMessage>,
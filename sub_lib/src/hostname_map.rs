@@ -0,0 +1,139 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+// 127.0.x.x is left alone, since other software on the machine is likely to be using it
+const DEFAULT_POOL_START: u32 = 0x7F010001; // 127.1.0.1
+const DEFAULT_POOL_SIZE: u32 = 0x00FEFFFE; // through 127.255.255.254
+
+struct HostnameMapInner {
+    pool_start: u32,
+    pool_size: u32,
+    next_index: u32,
+    addresses: HashMap<String, Ipv4Addr>,
+    hostnames: HashMap<Ipv4Addr, String>
+}
+
+/// Gives each hostname the Entry DNS server is asked about its own loopback address, so that a
+/// stream connected to one of those addresses can be traced back to the hostname it was meant
+/// for even if its protocol never mentions the hostname. Clones share the same mapping.
+/// Only works where the whole loopback block is routed to the loopback interface (Linux does this;
+/// macOS and Windows only answer on 127.0.0.1 unless told otherwise).
+#[derive (Clone)]
+pub struct HostnameMap {
+    inner: Arc<Mutex<HostnameMapInner>>
+}
+
+impl HostnameMap {
+    pub fn new () -> HostnameMap {
+        HostnameMap::with_pool (Ipv4Addr::from (DEFAULT_POOL_START), DEFAULT_POOL_SIZE)
+    }
+
+    /// When every address in the pool has been handed out, the oldest ones are reused.
+    pub fn with_pool (first_address: Ipv4Addr, pool_size: u32) -> HostnameMap {
+        if pool_size == 0 {panic! ("HostnameMap needs at least one address in its pool")}
+        HostnameMap {
+            inner: Arc::new (Mutex::new (HostnameMapInner {
+                pool_start: u32::from (first_address),
+                pool_size,
+                next_index: 0,
+                addresses: HashMap::new (),
+                hostnames: HashMap::new ()
+            }))
+        }
+    }
+
+    pub fn address_for (&self, hostname: &str) -> Ipv4Addr {
+        let hostname = HostnameMap::normalize (hostname);
+        let mut inner = self.inner.lock ().expect ("HostnameMap is poisoned");
+        if let Some (address) = inner.addresses.get (&hostname) {
+            return *address
+        }
+        let address = Ipv4Addr::from (inner.pool_start + inner.next_index);
+        inner.next_index = (inner.next_index + 1) % inner.pool_size;
+        if let Some (previous_hostname) = inner.hostnames.remove (&address) {
+            inner.addresses.remove (&previous_hostname);
+        }
+        inner.addresses.insert (hostname.clone (), address);
+        inner.hostnames.insert (address, hostname);
+        address
+    }
+
+    pub fn hostname_for (&self, address: &IpAddr) -> Option<String> {
+        let address = match address {
+            &IpAddr::V4 (ref address) => address,
+            &IpAddr::V6 (_) => return None
+        };
+        let inner = self.inner.lock ().expect ("HostnameMap is poisoned");
+        inner.hostnames.get (address).cloned ()
+    }
+
+    // DNS names are case-insensitive and may or may not carry the root's trailing dot
+    fn normalize (hostname: &str) -> String {
+        hostname.trim_end_matches ('.').to_lowercase ()
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn default_pool_starts_above_127_0_x_x () {
+        let subject = HostnameMap::new ();
+
+        let result = subject.address_for ("first.com");
+
+        assert_eq! (result, Ipv4Addr::new (127, 1, 0, 1));
+    }
+
+    #[test]
+    fn each_hostname_gets_its_own_stable_address () {
+        let subject = HostnameMap::new ();
+
+        let first = subject.address_for ("first.com");
+        let second = subject.address_for ("second.com");
+        let first_again = subject.address_for ("First.COM.");
+
+        assert_eq! (first, Ipv4Addr::new (127, 1, 0, 1));
+        assert_eq! (second, Ipv4Addr::new (127, 1, 0, 2));
+        assert_eq! (first_again, first);
+    }
+
+    #[test]
+    fn addresses_map_back_to_hostnames_through_clones () {
+        let subject = HostnameMap::new ();
+        let clone = subject.clone ();
+        let address = subject.address_for ("server.com.");
+
+        let result = clone.hostname_for (&IpAddr::V4 (address));
+
+        assert_eq! (result, Some (String::from ("server.com")));
+    }
+
+    #[test]
+    fn unknown_addresses_have_no_hostname () {
+        let subject = HostnameMap::new ();
+
+        assert_eq! (subject.hostname_for (&IpAddr::from_str ("127.0.0.1").unwrap ()), None);
+        assert_eq! (subject.hostname_for (&IpAddr::from_str ("::1").unwrap ()), None);
+    }
+
+    #[test]
+    fn exhausted_pool_reuses_oldest_address () {
+        let subject = HostnameMap::with_pool (Ipv4Addr::new (127, 2, 0, 1), 2);
+        subject.address_for ("first.com");
+        subject.address_for ("second.com");
+
+        let third = subject.address_for ("third.com");
+
+        assert_eq! (third, Ipv4Addr::new (127, 2, 0, 1));
+        assert_eq! (subject.hostname_for (&IpAddr::V4 (third)), Some (String::from ("third.com")));
+        assert_eq! (subject.address_for ("second.com"), Ipv4Addr::new (127, 2, 0, 2));
+        assert_eq! (subject.address_for ("first.com"), Ipv4Addr::new (127, 2, 0, 2));
+    }
+}
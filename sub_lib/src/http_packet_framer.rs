@@ -65,11 +65,14 @@ impl Debug for HttpFramerState {
 
 pub trait HttpPacketStartFinder: Send {
     fn seek_packet_start(&self, framer_state: &mut HttpFramerState) -> bool;
+    // After a packet that switches the connection to another protocol (WebSocket, say), the framer stops framing
+    fn is_upgrade(&self, packet: &[u8]) -> bool;
 }
 
 pub struct HttpPacketFramer {
     framer_state: HttpFramerState,
     start_finder: Box<HttpPacketStartFinder>,
+    upgraded: bool,
     logger: Logger
 }
 
@@ -80,11 +83,19 @@ impl Framer for HttpPacketFramer {
     }
 
     fn take_frame (&mut self) -> Option<FramedChunk> {
-        if self.framer_state.transfer_encoding_chunked == ChunkExistenceState::Chunk {
+        if self.upgraded {
+            self.take_upgraded_frame ()
+        }
+        else if self.framer_state.transfer_encoding_chunked == ChunkExistenceState::Chunk {
             self.take_chunk_frame ()
         }
         else {
-            self.take_packet_frame ()
+            let frame = self.take_packet_frame ()?;
+            if self.start_finder.is_upgrade (&frame.chunk[..]) {
+                self.logger.debug (format! ("Connection upgraded; passing the rest of the stream through unframed"));
+                self.upgraded = true;
+            }
+            Some (frame)
         }
     }
 }
@@ -102,10 +113,22 @@ impl HttpPacketFramer {
                 lines: Vec::new()
             },
             start_finder,
+            upgraded: false,
             logger: Logger::new("HttpRequestFramer")
         }
     }
 
+    /// Whatever has arrived after the last packet taken, for a framer that takes the stream over
+    pub fn take_leftover (&mut self) -> Vec<u8> {
+        self.framer_state.data_so_far.drain (..).collect ()
+    }
+
+    fn take_upgraded_frame (&mut self) -> Option<FramedChunk> {
+        if self.framer_state.data_so_far.is_empty () {return None}
+        let chunk = self.framer_state.data_so_far.drain (..).collect ();
+        Some (FramedChunk {chunk, last_chunk: false})
+    }
+
     fn take_packet_frame (&mut self) -> Option<FramedChunk> {
         if self.framer_state.packet_progress_state == PacketProgressState::SeekingPacketStart {
             if !self.start_finder.seek_packet_start (&mut self.framer_state) {return None}
@@ -302,6 +325,15 @@ const BYTES_TO_PRESERVE: usize = 9;
 const CRLF: &[u8; 2] = b"\r\n";
 const DOUBLE_CRLF: &[u8; 4] = b"\r\n\r\n";
 
+/// Whether the header section of an HTTP packet contains a header with the given (case-insensitive) name
+pub fn has_header (packet: &[u8], name: &str) -> bool {
+    let header_end = index_of (packet, DOUBLE_CRLF).unwrap_or (packet.len ());
+    let prefix = format! ("{}:", name.to_lowercase ());
+    packet[..header_end].split (|byte| *byte == b'\n').skip (1).any (|line| {
+        String::from_utf8_lossy (line).trim_start ().to_lowercase ().starts_with (&prefix[..])
+    })
+}
+
 pub fn summarize_http_packet(request: &Vec<u8>) -> String {
     let first_space_index = match index_of_from (request, &(' ' as u8), 0) {
         None => return String::from("<bad HTTP syntax: no spaces>"),
@@ -349,6 +381,10 @@ mod framer_tests {
                 false
             }
         }
+
+        fn is_upgrade(&self, _packet: &[u8]) -> bool {
+            false
+        }
     }
 
     #[test]
@@ -856,6 +892,37 @@ GOOD_FIRST_LINE\r\nContent-Length: 5\r\n\r\ngooba".as_bytes();
         assert_eq! (subject.framer_state.chunk_size, None);
     }
 
+    #[test]
+    fn switching_protocols_response_turns_framer_into_passthrough () {
+        let handshake = &b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\nSec-WebSocket-Protocol: chat\r\n\r\n"[..];
+        let hello_frame = &[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f][..];
+        let http_lookalike_frame = &b"\x81\x26HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"[..];
+        let mut subject = HttpPacketFramer::new (Box::new (HttpResponseStartFinder {}));
+        let mut data = Vec::from (handshake);
+        data.extend (hello_frame);
+        subject.add_data (&data[..]);
+
+        let handshake_frame = subject.take_frame ();
+        let first_websocket_frame = subject.take_frame ();
+        let nothing = subject.take_frame ();
+        subject.add_data (http_lookalike_frame);
+        let second_websocket_frame = subject.take_frame ();
+
+        assert_eq! (handshake_frame, Some (FramedChunk {chunk: Vec::from (handshake), last_chunk: false}));
+        assert_eq! (first_websocket_frame, Some (FramedChunk {chunk: Vec::from (hello_frame), last_chunk: false}));
+        assert_eq! (nothing, None);
+        assert_eq! (second_websocket_frame, Some (FramedChunk {chunk: Vec::from (http_lookalike_frame), last_chunk: false}));
+    }
+
+    #[test]
+    fn has_header_looks_only_at_header_names_in_the_header_section () {
+        let packet = &b"GET /upgrade: HTTP/1.1\r\nHost: server.com\r\nUPGRADE : no\r\nconnection: upgrade\r\n\r\nUpgrade: body"[..];
+
+        assert_eq! (has_header (packet, "Connection"), true);
+        assert_eq! (has_header (packet, "host"), true);
+        assert_eq! (has_header (packet, "Upgrade"), false);
+    }
+
     #[test]
     fn version_of_troublesome_proxy_client_test () {
         let data = &b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 29\r\n\r\nUser-agent: *\nDisallow: /deny";
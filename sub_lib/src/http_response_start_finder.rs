@@ -39,6 +39,11 @@ impl HttpPacketStartFinder for HttpResponseStartFinder {
             false
         }
     }
+
+    // 101 Switching Protocols: whatever follows belongs to the protocol the client asked for
+    fn is_upgrade(&self, packet: &[u8]) -> bool {
+        (packet.len () >= LONGEST_PREFIX_LEN) && (&packet[8..LONGEST_PREFIX_LEN] == b" 101 ")
+    }
 }
 
 impl HttpResponseStartFinder {
@@ -78,6 +83,15 @@ mod tests {
     use super::*;
     use http_packet_framer::ChunkProgressState;
 
+    #[test]
+    fn only_switching_protocols_responses_are_upgrades () {
+        let subject = HttpResponseStartFinder {};
+
+        assert_eq! (subject.is_upgrade (b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n"), true);
+        assert_eq! (subject.is_upgrade (b"HTTP/1.1 200 OK\r\nUpgrade: h2c\r\nContent-Length: 0\r\n\r\n"), false);
+        assert_eq! (subject.is_upgrade (b"HTTP/1.1 10"), false);
+    }
+
     #[test]
     fn returns_none_if_no_http () {
         let data = b"there is no HTTP followed by a / here";
@@ -1,7 +1,9 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 #[macro_use]
 extern crate actix;
+extern crate bytes;
 extern crate chrono;
+extern crate futures;
 extern crate log;
 extern crate rand;
 extern crate regex;
@@ -9,6 +11,7 @@ extern crate serde;
 extern crate serde_cbor;
 #[macro_use]
 extern crate serde_derive;
+extern crate tokio_core;
 
 #[cfg (test)]
 extern crate test_utils;
@@ -16,6 +19,7 @@ extern crate test_utils;
 #[cfg(unix)]
 extern crate daemonize;
 
+pub mod blocklist;
 pub mod cores_package;
 pub mod cryptde;
 pub mod cryptde_null;
@@ -23,22 +27,30 @@ pub mod dispatcher;
 pub mod framer;
 pub mod framer_utils;
 pub mod hop;
+pub mod hostname_map;
 pub mod hopper;
 pub mod http_packet_framer;
 pub mod http_response_start_finder;
 pub mod limiter;
 pub mod logger;
 pub mod main_tools;
+pub mod multipath;
 pub mod neighborhood;
+pub mod outbox;
 pub mod node_addr;
 pub mod parameter_finder;
+pub mod passthrough_framer;
 pub mod peer_actors;
 pub mod proxy_client;
 pub mod proxy_server;
+pub mod reorder_buffer;
 pub mod route;
+pub mod send_window;
 pub mod socket_server;
 pub mod stream_handler_pool;
+pub mod stream_timeouts;
 pub mod tcp_wrappers;
 pub mod tls_framer;
 pub mod udp_socket_wrapper;
 pub mod utils;
+pub mod write_queue;
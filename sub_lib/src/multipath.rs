@@ -0,0 +1,118 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::BTreeMap;
+
+/// The most routes one stream may be spread across
+pub const MAX_STREAM_PATHS: usize = 16;
+
+/// How many payloads a path may fall behind the rest of its stream before it's given up as degraded
+pub const DEFAULT_MAX_PATH_LAG: u64 = 32;
+
+/// Watches which of a stream's paths its payloads arrive on. The sender takes its paths in turn, so
+/// the newest payload to come over a healthy path is never far behind the newest to come over any
+/// of them; a path that falls more than max_lag payloads behind is holding the whole stream up.
+pub struct PathMonitor {
+    // The newest sequence number seen on each path, or the newest on any path when it was added
+    newest_by_path: BTreeMap<u8, u64>,
+    newest: u64,
+    max_lag: u64
+}
+
+impl PathMonitor {
+    pub fn new (max_lag: u64) -> PathMonitor {
+        PathMonitor {
+            newest_by_path: BTreeMap::new (),
+            newest: 0,
+            max_lag
+        }
+    }
+
+    pub fn add_path (&mut self, path_id: u8) {
+        let newest = self.newest;
+        self.newest_by_path.entry (path_id).or_insert (newest);
+    }
+
+    pub fn remove_path (&mut self, path_id: u8) {
+        self.newest_by_path.remove (&path_id);
+    }
+
+    /// Payloads still on their way over paths that have been removed don't count
+    pub fn arrived (&mut self, path_id: u8, sequence_number: u64) {
+        let newest_on_path = match self.newest_by_path.get_mut (&path_id) {
+            Some (newest_on_path) => newest_on_path,
+            None => return
+        };
+        if sequence_number > *newest_on_path {*newest_on_path = sequence_number}
+        if sequence_number > self.newest {self.newest = sequence_number}
+    }
+
+    pub fn degraded_paths (&self) -> Vec<u8> {
+        self.newest_by_path.iter ()
+            .filter (|&(_, newest_on_path)| self.newest - newest_on_path > self.max_lag)
+            .map (|(path_id, _)| *path_id)
+            .collect ()
+    }
+
+    pub fn path_ids (&self) -> Vec<u8> {
+        self.newest_by_path.keys ().cloned ().collect ()
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_that_keep_up_are_not_degraded () {
+        let mut subject = PathMonitor::new (4);
+        subject.add_path (0);
+        subject.add_path (1);
+
+        for sequence_number in 0..20 {
+            subject.arrived ((sequence_number % 2) as u8, sequence_number);
+        }
+
+        assert_eq! (subject.degraded_paths (), Vec::<u8>::new ());
+    }
+
+    #[test]
+    fn a_path_that_falls_behind_is_degraded () {
+        let mut subject = PathMonitor::new (4);
+        subject.add_path (0);
+        subject.add_path (1);
+        subject.add_path (2);
+        subject.arrived (1, 1);
+
+        for sequence_number in 2..8 {
+            subject.arrived (if sequence_number % 2 == 0 {0} else {2}, sequence_number);
+        }
+
+        assert_eq! (subject.degraded_paths (), vec! (1));
+    }
+
+    #[test]
+    fn a_path_that_never_delivers_is_measured_from_when_it_was_added () {
+        let mut subject = PathMonitor::new (4);
+        subject.add_path (0);
+        subject.arrived (0, 10);
+        subject.add_path (1);
+
+        subject.arrived (0, 14);
+        assert_eq! (subject.degraded_paths (), Vec::<u8>::new ());
+        subject.arrived (0, 15);
+        assert_eq! (subject.degraded_paths (), vec! (1));
+    }
+
+    #[test]
+    fn removed_paths_are_forgotten () {
+        let mut subject = PathMonitor::new (4);
+        subject.add_path (0);
+        subject.add_path (1);
+        subject.arrived (0, 10);
+        subject.remove_path (1);
+
+        subject.arrived (1, 20);
+
+        assert_eq! (subject.path_ids (), vec! (0));
+        assert_eq! (subject.degraded_paths (), Vec::<u8>::new ());
+    }
+}
@@ -5,11 +5,13 @@ use actix::Syn;
 use cryptde::Key;
 use node_addr::NodeAddr;
 use peer_actors::BindMessage;
+use route::Route;
 use std::net::IpAddr;
 
 #[derive(Clone)]
 pub struct NeighborhoodSubs {
     pub bind: Recipient<Syn, BindMessage>,
+    pub route_query: Recipient<Syn, RouteQueryMessage>,
 }
 
 #[derive (Clone, Debug, PartialEq)]
@@ -34,3 +36,23 @@ pub enum NodeQueryMessage {
 impl Message for NodeQueryMessage {
     type Result = Option<NodeDescriptor>;
 }
+
+/// Asks for up to route_count round-trip routes from this Node's ProxyServer to an exit's ProxyClient
+/// and back: through the named exit if there is one, or else through any exit but the ones listed
+#[derive (Clone, Debug, PartialEq)]
+pub struct RouteQueryMessage {
+    pub excluded_exits: Vec<Key>,
+    pub exit_key: Option<Key>,
+    pub route_count: usize,
+}
+
+impl Message for RouteQueryMessage {
+    type Result = Option<RouteQueryResponse>;
+}
+
+#[derive (Clone, Debug, PartialEq)]
+pub struct RouteQueryResponse {
+    // Never empty; no two of these routes share a relay
+    pub routes: Vec<Route>,
+    pub exit_key: Key,
+}
@@ -0,0 +1,243 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use actix::Actor;
+use actix::ActorFuture;
+use actix::AsyncContext;
+use actix::Context;
+use actix::Message;
+use actix::Recipient;
+use actix::Syn;
+use actix::prelude::SendError;
+use futures::Async;
+use futures::Poll;
+
+/// Sends messages to an actor whose mailbox is bounded without ever losing one or making the
+/// sender wait. Whatever the mailbox won't take right away stays here, in order, and whoever is
+/// producing the messages should stop producing more until poll_flush says they've all gone: the
+/// task that last found the mailbox full is woken when it has room again.
+pub struct Outbox<M> where M: Message + Send + 'static, M::Result: Send {
+    recipient: Recipient<Syn, M>,
+    name: &'static str,
+    pending: VecDeque<M>,
+    // A Recipient that's been turned away forgets the task it was told to wake if it's tried again
+    // before the mailbox has room, so the next try goes through a fresh one
+    turned_away: bool,
+    actor_waiting: bool
+}
+
+impl<M> Outbox<M> where M: Message + Send + 'static, M::Result: Send {
+    pub fn new (recipient: Recipient<Syn, M>, name: &'static str) -> Outbox<M> {
+        Outbox {
+            recipient,
+            name,
+            pending: VecDeque::new (),
+            turned_away: false,
+            actor_waiting: false
+        }
+    }
+
+    /// Must be called from inside a task
+    pub fn send (&mut self, msg: M) {
+        self.pending.push_back (msg);
+        self.poll_flush ();
+    }
+
+    /// Sends the message from an actor. If the mailbox is full, the actor takes no more messages of
+    /// its own until this one has gone, so the pressure works its way back to wherever the data is
+    /// coming from. The Outbox is found in the actor again with outbox_of.
+    pub fn send_from<A, F> (&mut self, msg: M, ctx: &mut Context<A>, outbox_of: F)
+            where A: Actor<Context = Context<A>>, F: Fn (&mut A) -> &mut Outbox<M> + 'static {
+        self.send (msg);
+        if !self.pending.is_empty () && !self.actor_waiting {
+            self.actor_waiting = true;
+            ctx.wait (Drain {outbox_of, actor: PhantomData});
+        }
+    }
+
+    /// Ready once everything sent through the Outbox is in the mailbox
+    pub fn poll_flush (&mut self) -> Async<()> {
+        while let Some (msg) = self.pending.pop_front () {
+            if self.turned_away {
+                self.recipient = self.recipient.clone ();
+                self.turned_away = false;
+            }
+            match self.recipient.try_send (msg) {
+                Ok (()) => (),
+                Err (SendError::Full (msg)) => {
+                    self.pending.push_front (msg);
+                    self.turned_away = true;
+                    return Async::NotReady
+                },
+                // crashpoint - actors don't die while the Node is running
+                Err (SendError::Closed (_)) => panic! ("{} is dead", self.name)
+            }
+        }
+        Async::Ready (())
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.pending.is_empty ()
+    }
+}
+
+struct Drain<A, F> {
+    outbox_of: F,
+    actor: PhantomData<A>
+}
+
+impl<A, M, F> ActorFuture for Drain<A, F> where A: Actor<Context = Context<A>>, M: Message + Send + 'static, M::Result: Send,
+        F: Fn (&mut A) -> &mut Outbox<M> {
+    type Item = ();
+    type Error = ();
+    type Actor = A;
+
+    fn poll (&mut self, actor: &mut A, _ctx: &mut Context<A>) -> Poll<(), ()> {
+        let outbox = (self.outbox_of) (actor);
+        match outbox.poll_flush () {
+            Async::NotReady => Ok (Async::NotReady),
+            Async::Ready (()) => {
+                outbox.actor_waiting = false;
+                Ok (Async::Ready (()))
+            }
+        }
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use actix::Addr;
+    use actix::Arbiter;
+    use actix::Handler;
+    use actix::System;
+    use actix::WrapFuture;
+    use futures::Future;
+    use futures::future;
+    use futures::sync::oneshot;
+
+    #[derive (Message)]
+    struct Numbered (u32);
+
+    // Takes no messages until it's released, then just writes down the ones it gets
+    struct Sink {
+        held: Option<oneshot::Receiver<()>>,
+        received: Arc<Mutex<Vec<u32>>>
+    }
+
+    impl Actor for Sink {
+        type Context = Context<Self>;
+
+        fn started (&mut self, ctx: &mut Context<Self>) {
+            let held = self.held.take ().unwrap ();
+            AsyncContext::wait (ctx, held.map_err (|_| ()).into_actor (self));
+        }
+    }
+
+    impl Handler<Numbered> for Sink {
+        type Result = ();
+
+        fn handle (&mut self, msg: Numbered, _ctx: &mut Context<Self>) {
+            self.received.lock ().unwrap ().push (msg.0);
+        }
+    }
+
+    // Passes messages along to the Sink, noting each one as it takes it
+    struct Relay {
+        to_sink: Outbox<Numbered>,
+        taken: Arc<Mutex<Vec<u32>>>
+    }
+
+    impl Actor for Relay {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Numbered> for Relay {
+        type Result = ();
+
+        fn handle (&mut self, msg: Numbered, ctx: &mut Context<Self>) {
+            self.taken.lock ().unwrap ().push (msg.0);
+            self.to_sink.send_from (msg, ctx, |relay: &mut Relay| &mut relay.to_sink);
+        }
+    }
+
+    fn start_sink (held: oneshot::Receiver<()>, received: Arc<Mutex<Vec<u32>>>) -> Recipient<Syn, Numbered> {
+        let addr: Addr<Syn, Sink> = Sink::create (move |ctx| {
+            ctx.set_mailbox_capacity (2);
+            Sink {held: Some (held), received}
+        });
+        addr.recipient::<Numbered> ()
+    }
+
+    fn await_len (list: &Arc<Mutex<Vec<u32>>>, len: usize) {
+        for _ in 0..100 {
+            if list.lock ().unwrap ().len () >= len {return}
+            thread::sleep (Duration::from_millis (10));
+        }
+        panic! ("Expected {} entries, but found {:?}", len, *list.lock ().unwrap ());
+    }
+
+    #[test]
+    fn what_a_full_mailbox_turns_away_waits_and_goes_in_order_once_there_is_room () {
+        let received = Arc::new (Mutex::new (vec! ()));
+        let received_inner = received.clone ();
+        let (release_tx, release_rx) = oneshot::channel ();
+        let (flushed_tx, flushed_rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new ("what_a_full_mailbox_turns_away_waits_and_goes_in_order_once_there_is_room");
+            let mut subject = Outbox::new (start_sink (release_rx, received_inner), "Sink");
+            Arbiter::handle ().spawn (future::lazy (move || {
+                (0..5).for_each (|n| subject.send (Numbered (n)));
+                let mut first_flush = Some (subject.poll_flush ());
+                let mut release_tx = Some (release_tx);
+                future::poll_fn (move || {
+                    if let Some (result) = first_flush.take () {
+                        flushed_tx.send (result).unwrap ();
+                        release_tx.take ().unwrap ().send (()).unwrap ();
+                    }
+                    Ok (subject.poll_flush ())
+                })
+            }));
+            system.run ();
+        });
+
+        assert_eq! (flushed_rx.recv ().unwrap (), Async::NotReady);
+        await_len (&received, 5);
+        assert_eq! (*received.lock ().unwrap (), vec! (0, 1, 2, 3, 4));
+    }
+
+    #[test]
+    fn an_actor_whose_outbox_is_full_takes_no_more_messages_until_it_empties () {
+        let received = Arc::new (Mutex::new (vec! ()));
+        let taken = Arc::new (Mutex::new (vec! ()));
+        let (received_inner, taken_inner) = (received.clone (), taken.clone ());
+        let (release_tx, release_rx) = oneshot::channel ();
+        let (relay_tx, relay_rx) = mpsc::channel ();
+        thread::spawn (move || {
+            let system = System::new ("an_actor_whose_outbox_is_full_takes_no_more_messages_until_it_empties");
+            let to_sink = Outbox::new (start_sink (release_rx, received_inner), "Sink");
+            let relay: Addr<Syn, Relay> = Relay::create (move |ctx| {
+                ctx.set_mailbox_capacity (2);
+                Relay {to_sink, taken: taken_inner}
+            });
+            relay_tx.send (relay.recipient::<Numbered> ()).unwrap ();
+            system.run ();
+        });
+        let relay = relay_rx.recv ().unwrap ();
+
+        // The Sink's mailbox takes two, the Relay holds the third, and its own mailbox takes two more
+        (0..5).for_each (|n| relay.do_send (Numbered (n)).unwrap ());
+        thread::sleep (Duration::from_millis (100));
+        assert_eq! (*taken.lock ().unwrap (), vec! (0, 1, 2));
+        release_tx.send (()).unwrap ();
+
+        await_len (&received, 5);
+        assert_eq! (*taken.lock ().unwrap (), vec! (0, 1, 2, 3, 4));
+        assert_eq! (*received.lock ().unwrap (), vec! (0, 1, 2, 3, 4));
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use framer::FramedChunk;
+use framer::Framer;
+
+/// For tunneled streams, whose contents are nobody's business but the endpoints': every frame is
+/// simply whatever has arrived since the last one.
+pub struct PassthroughFramer {
+    data_so_far: Vec<u8>
+}
+
+impl Framer for PassthroughFramer {
+    fn add_data(&mut self, data: &[u8]) {
+        self.data_so_far.extend (data);
+    }
+
+    fn take_frame(&mut self) -> Option<FramedChunk> {
+        if self.data_so_far.is_empty () {return None}
+        let chunk = self.data_so_far.drain (..).collect ();
+        Some (FramedChunk {chunk, last_chunk: false})
+    }
+}
+
+impl PassthroughFramer {
+    pub fn new () -> PassthroughFramer {
+        PassthroughFramer {
+            data_so_far: vec! ()
+        }
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passthrough_framer_frames_nothing_when_empty () {
+        let mut subject = PassthroughFramer::new ();
+
+        let result = subject.take_frame ();
+
+        assert_eq! (result, None);
+    }
+
+    #[test]
+    fn passthrough_framer_frames_everything_added_so_far () {
+        let mut subject = PassthroughFramer::new ();
+        subject.add_data (&[0x00, 0x01]);
+        subject.add_data (&[0x02]);
+
+        let first = subject.take_frame ();
+        let second = subject.take_frame ();
+
+        assert_eq! (first, Some (FramedChunk {chunk: vec! (0x00, 0x01, 0x02), last_chunk: false}));
+        assert_eq! (second, None);
+    }
+}
@@ -6,11 +6,33 @@ use cryptde::StreamKey;
 use hopper::ExpiredCoresPackage;
 use peer_actors::BindMessage;
 
+/// Why a stream ended before the server had anything to say
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum StreamFailure {
+    MissingHostname,
+    DnsFailure,
+    ConnectionRefused,
+    Timeout,
+    ConnectionFailed,
+    PolicyBlock
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct ClientResponsePayload {
     pub stream_key: StreamKey,
+    // Counts this stream's responses from zero, so the originating Node can put them back in order
+    pub sequence_number: u64,
+    // How many of the stream's requests have arrived in order; the originating Node needn't resend any of those
+    pub ack: u64,
+    // Which of the stream's routes this payload took
+    pub path_id: u8,
+    // The server has finished sending; the originating Node stops writing to the client once it has everything before this
     pub last_response: bool,
-    pub data: PlainData
+    // The server won't read any more, so the originating Node stops reading from the client. Only ever set on the last response.
+    pub stopped_reading: bool,
+    pub data: PlainData,
+    // Present only on the last response of a stream that failed before any data came back
+    pub failure: Option<StreamFailure>
 }
 
 #[derive(Clone)]
@@ -1,4 +1,6 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::HashMap;
+use std::time::Duration;
 use actix::Recipient;
 use actix::Syn;
 use cryptde::Key;
@@ -7,17 +9,93 @@ use cryptde::StreamKey;
 use dispatcher::InboundClientData;
 use hopper::ExpiredCoresPackage;
 use peer_actors::BindMessage;
+use stream_handler_pool::WriteBacklogMsg;
 
 #[derive (Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum ProxyProtocol {
     HTTP,
-    TLS
+    TLS,
+    // Opaque bytes on a stream opened with HTTP CONNECT or SOCKS
+    Tunnel
+}
+
+/// Which protocol each ordinary listener speaks, unless the operator says otherwise
+pub fn default_port_protocols () -> HashMap<u16, ProxyProtocol> {
+    let mut port_protocols = HashMap::new ();
+    port_protocols.insert (80, ProxyProtocol::HTTP);
+    port_protocols.insert (443, ProxyProtocol::TLS);
+    port_protocols
+}
+
+/// These headers tell the server where the request ends and which site it's for, so no rule may touch them
+pub const PROTECTED_HEADERS: &[&str] = &["Host", "Content-Length", "Transfer-Encoding"];
+
+/// One way of rewriting the headers of plain HTTP requests before they leave the Node
+#[derive (Clone, Debug, PartialEq)]
+pub enum HeaderRule {
+    Remove {name: String},
+    // Replaces the value of the header if the request has it; doesn't add it if not
+    Rewrite {name: String, value: String},
+    // Removes Referer unless it names the host the request is going to
+    StripCrossOriginReferer
+}
+
+/// How many other exits a new stream may try after the first one fails to set it up
+pub const DEFAULT_ROUTE_RETRIES: usize = 2;
+
+/// How long a stream's circuit may go without traffic in either direction before it's torn down
+pub const DEFAULT_CIRCUIT_IDLE_TIMEOUT_MS: u64 = 300000;
+
+/// How many disjoint routes to its exit each stream is spread across
+pub const DEFAULT_STREAM_PATHS: usize = 1;
+
+/// How the ProxyServer should treat traffic arriving on each of the Node's listening ports
+#[derive (Clone, Debug, PartialEq)]
+pub struct ProxyServerConfig {
+    pub port_protocols: HashMap<u16, ProxyProtocol>,
+    pub http_proxy_port: Option<u16>,
+    pub socks_port: Option<u16>,
+    // If present, SOCKS clients must log in with this username and password
+    pub socks_credentials: Option<(String, String)>,
+    // Traffic on these ports is tunneled to whatever hostname the client looked up
+    pub tcp_forward_ports: Vec<u16>,
+    // Applied in order to plain HTTP requests; the first rule that matches a header decides its fate
+    pub header_rules: Vec<HeaderRule>,
+    pub route_retries: usize,
+    pub circuit_idle_timeout: Duration,
+    pub stream_paths: usize
+}
+
+impl ProxyServerConfig {
+    pub fn new () -> ProxyServerConfig {
+        ProxyServerConfig {
+            port_protocols: default_port_protocols (),
+            http_proxy_port: None,
+            socks_port: None,
+            socks_credentials: None,
+            tcp_forward_ports: vec! (),
+            header_rules: vec! (),
+            route_retries: DEFAULT_ROUTE_RETRIES,
+            circuit_idle_timeout: Duration::from_millis (DEFAULT_CIRCUIT_IDLE_TIMEOUT_MS),
+            stream_paths: DEFAULT_STREAM_PATHS
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct ClientRequestPayload {
     pub stream_key: StreamKey,
+    // Counts this stream's requests from zero, so the exit Node can put them back in order
+    pub sequence_number: u64,
+    // How many of the stream's responses have arrived in order; the exit Node needn't resend any of those
+    pub ack: u64,
+    // Which of the stream's routes this payload took, and which ones responses may still come back on
+    pub path_id: u8,
+    pub open_paths: Vec<u8>,
+    // The client has finished sending; the exit Node stops writing to the server once it has everything before this
     pub last_data: bool,
+    // The client won't read any more, so the exit Node stops reading from the server
+    pub stopped_reading: bool,
     pub data: PlainData,
     pub target_hostname: Option<String>,
     pub target_port: u16,
@@ -30,4 +108,5 @@ pub struct ProxyServerSubs { // ProxyServer will handle these messages:
     pub bind: Recipient<Syn, BindMessage>,
     pub from_dispatcher: Recipient<Syn, InboundClientData>,
     pub from_hopper: Recipient<Syn, ExpiredCoresPackage>,
+    pub write_backlog: Recipient<Syn, WriteBacklogMsg>,
 }
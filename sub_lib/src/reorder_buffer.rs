@@ -0,0 +1,191 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::BTreeMap;
+use std::time::Duration;
+use std::time::Instant;
+use send_window::retransmission_budget;
+
+/// How many bytes of a stream's early payloads may wait for the payloads that should precede them
+pub const DEFAULT_MAX_REORDER_BYTES: usize = 0x100000;
+
+/// How long a stream may wait for a missing payload before it's given up as broken: as long as the
+/// payload's sender may go on sending it again
+pub fn default_reorder_gap_timeout () -> Duration {
+    retransmission_budget ()
+}
+
+/// Puts one stream's numbered payloads back in the order they were sent. Payloads that arrive early
+/// are held until the ones before them show up; the stream can't be put back together if a gap
+/// stays open longer than gap_timeout, or if the payloads held behind it would exceed max_bytes.
+pub struct ReorderBuffer<T> {
+    next_sequence_number: u64,
+    pending: BTreeMap<u64, (T, usize)>,
+    buffered_bytes: usize,
+    gap_opened: Option<Instant>,
+    max_bytes: usize,
+    gap_timeout: Duration
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new (max_bytes: usize, gap_timeout: Duration) -> ReorderBuffer<T> {
+        ReorderBuffer {
+            next_sequence_number: 0,
+            pending: BTreeMap::new (),
+            buffered_bytes: 0,
+            gap_opened: None,
+            max_bytes,
+            gap_timeout
+        }
+    }
+
+    /// Returns the payloads that are now ready, in order: none if this one has to wait or has been
+    /// seen before, or an explanation if the stream can no longer be put back together.
+    pub fn add (&mut self, sequence_number: u64, item: T, size: usize, now: Instant) -> Result<Vec<T>, String> {
+        self.expire (now)?;
+        if (sequence_number < self.next_sequence_number) || self.pending.contains_key (&sequence_number) {
+            return Ok (vec! ())
+        }
+        if sequence_number > self.next_sequence_number {
+            if self.buffered_bytes + size > self.max_bytes {
+                return Err (format! ("Payload {} is still missing, and holding {} more bytes behind it would exceed {}",
+                    self.next_sequence_number, size, self.max_bytes))
+            }
+            self.buffered_bytes += size;
+            self.pending.insert (sequence_number, (item, size));
+            if self.gap_opened.is_none () {self.gap_opened = Some (now)}
+            return Ok (vec! ())
+        }
+        let mut ready = vec! (item);
+        self.next_sequence_number += 1;
+        while let Some ((item, size)) = self.pending.remove (&self.next_sequence_number) {
+            self.buffered_bytes -= size;
+            ready.push (item);
+            self.next_sequence_number += 1;
+        }
+        self.gap_opened = if self.pending.is_empty () {None} else {Some (now)};
+        Ok (ready)
+    }
+
+    /// An explanation if a gap has been open longer than gap_timeout. Nothing else may ever arrive
+    /// to show that it has, so whoever holds the buffer should ask now and then.
+    pub fn expire (&self, now: Instant) -> Result<(), String> {
+        match self.gap_opened {
+            Some (gap_opened) if now.duration_since (gap_opened) > self.gap_timeout => {
+                let waited = now.duration_since (gap_opened);
+                Err (format! ("Payload {} is still missing after {}ms", self.next_sequence_number,
+                    waited.as_secs () * 1000 + u64::from (waited.subsec_nanos () / 1000000)))
+            },
+            _ => Ok (())
+        }
+    }
+
+    /// The first payload that hasn't been handed back yet; everything before it has been, in order
+    pub fn next_sequence_number (&self) -> u64 {
+        self.next_sequence_number
+    }
+
+    pub fn buffered_bytes (&self) -> usize {
+        self.buffered_bytes
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+
+    fn make_subject () -> ReorderBuffer<&'static str> {
+        ReorderBuffer::new (10, Duration::from_millis (1000))
+    }
+
+    #[test]
+    fn payloads_in_order_are_ready_immediately () {
+        let mut subject = make_subject ();
+        let now = Instant::now ();
+
+        assert_eq! (subject.add (0, "zero", 4, now), Ok (vec! ("zero")));
+        assert_eq! (subject.add (1, "one", 3, now), Ok (vec! ("one")));
+        assert_eq! (subject.buffered_bytes (), 0);
+    }
+
+    #[test]
+    fn early_payloads_wait_for_the_ones_before_them () {
+        let mut subject = make_subject ();
+        let now = Instant::now ();
+
+        assert_eq! (subject.add (2, "two", 3, now), Ok (vec! ()));
+        assert_eq! (subject.add (1, "one", 3, now), Ok (vec! ()));
+        assert_eq! (subject.buffered_bytes (), 6);
+        assert_eq! (subject.add (0, "zero", 4, now), Ok (vec! ("zero", "one", "two")));
+        assert_eq! (subject.buffered_bytes (), 0);
+        assert_eq! (subject.next_sequence_number (), 3);
+    }
+
+    #[test]
+    fn payloads_seen_before_are_ignored () {
+        let mut subject = make_subject ();
+        let now = Instant::now ();
+        subject.add (0, "zero", 4, now).unwrap ();
+        subject.add (2, "two", 3, now).unwrap ();
+
+        assert_eq! (subject.add (0, "zero again", 4, now), Ok (vec! ()));
+        assert_eq! (subject.add (2, "two again", 3, now), Ok (vec! ()));
+        assert_eq! (subject.buffered_bytes (), 3);
+        assert_eq! (subject.add (1, "one", 3, now), Ok (vec! ("one", "two")));
+    }
+
+    #[test]
+    fn holding_too_much_behind_a_gap_breaks_the_stream () {
+        let mut subject = make_subject ();
+        let now = Instant::now ();
+        subject.add (1, "one", 6, now).unwrap ();
+
+        let result = subject.add (2, "two", 5, now);
+
+        assert_eq! (result, Err (String::from ("Payload 0 is still missing, and holding 5 more bytes behind it would exceed 10")));
+    }
+
+    #[test]
+    fn a_gap_left_open_too_long_breaks_the_stream () {
+        let mut subject = make_subject ();
+        let now = Instant::now ();
+        subject.add (1, "one", 3, now).unwrap ();
+
+        let result = subject.add (0, "zero", 4, now + Duration::from_millis (1001));
+
+        assert_eq! (result, Err (String::from ("Payload 0 is still missing after 1001ms")));
+    }
+
+    #[test]
+    fn a_gap_that_remains_after_another_closes_gets_its_own_time () {
+        let mut subject = make_subject ();
+        let now = Instant::now ();
+        subject.add (1, "one", 3, now).unwrap ();
+        subject.add (3, "three", 5, now).unwrap ();
+        subject.add (0, "zero", 4, now + Duration::from_millis (900)).unwrap ();
+
+        let result = subject.add (2, "two", 3, now + Duration::from_millis (1800));
+
+        assert_eq! (result, Ok (vec! ("two", "three")));
+    }
+
+    #[test]
+    fn a_gap_left_open_too_long_expires_without_anything_else_arriving () {
+        let mut subject = make_subject ();
+        let now = Instant::now ();
+        subject.add (1, "one", 3, now).unwrap ();
+
+        let early = subject.expire (now + Duration::from_millis (1000));
+        let late = subject.expire (now + Duration::from_millis (1001));
+
+        assert_eq! (early, Ok (()));
+        assert_eq! (late, Err (String::from ("Payload 0 is still missing after 1001ms")));
+    }
+
+    #[test]
+    fn a_buffer_without_a_gap_never_expires () {
+        let mut subject = make_subject ();
+        let now = Instant::now ();
+        subject.add (0, "zero", 4, now).unwrap ();
+
+        assert_eq! (subject.expire (now + Duration::from_secs (3600)), Ok (()));
+    }
+}
@@ -0,0 +1,311 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::cmp::max;
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often each end of a stream looks for payloads to resend and acknowledgements it owes
+pub const RELIABILITY_TICK_MS: u64 = 100;
+
+/// How many times a payload may be sent without being acknowledged before its stream is given up
+pub const MAX_TRANSMISSIONS: u32 = 6;
+
+/// How many payloads may wait for room in the window before whatever's producing them should stop
+pub const MAX_WAITING: usize = 32;
+
+const INITIAL_WINDOW: usize = 4;
+const MAX_WINDOW: usize = 256;
+const INITIAL_RETRANSMIT_TIMEOUT_MS: u64 = 1000;
+const MIN_RETRANSMIT_TIMEOUT_MS: u64 = 200;
+const MAX_RETRANSMIT_TIMEOUT_MS: u64 = 30000;
+
+struct InFlight<T> {
+    item: T,
+    sent_at: Instant,
+    transmissions: u32
+}
+
+/// Keeps one stream's numbered payloads until the other end acknowledges them. Acknowledgements are
+/// cumulative: each one names the first payload that hasn't arrived in order yet. Only a window's
+/// worth of payloads is in flight at once; the window grows as acknowledgements come back and
+/// collapses when one doesn't, as TCP's does. When the oldest payload in flight goes unacknowledged
+/// for longer than the round trip times seen so far suggest, it's due to be sent again.
+pub struct SendWindow<T> {
+    unacked: BTreeMap<u64, InFlight<T>>,
+    waiting: VecDeque<(u64, T)>,
+    acked_up_to: u64,
+    window: usize,
+    slow_start_threshold: usize,
+    acked_toward_growth: usize,
+    smoothed_rtt_ms: Option<u64>,
+    rtt_variance_ms: u64,
+    retransmit_timeout_ms: u64,
+    timer_started: Option<Instant>
+}
+
+impl<T: Clone> SendWindow<T> {
+    pub fn new () -> SendWindow<T> {
+        SendWindow {
+            unacked: BTreeMap::new (),
+            waiting: VecDeque::new (),
+            acked_up_to: 0,
+            window: INITIAL_WINDOW,
+            slow_start_threshold: MAX_WINDOW,
+            acked_toward_growth: 0,
+            smoothed_rtt_ms: None,
+            rtt_variance_ms: 0,
+            retransmit_timeout_ms: INITIAL_RETRANSMIT_TIMEOUT_MS,
+            timer_started: None
+        }
+    }
+
+    /// Returns the payloads that may go out now: this one, unless the window is full
+    pub fn send (&mut self, sequence_number: u64, item: T, now: Instant) -> Vec<T> {
+        self.waiting.push_back ((sequence_number, item));
+        self.release (now)
+    }
+
+    /// Forgets every payload before the acknowledged one, and returns the payloads that may go out
+    /// now that there's room for them
+    pub fn acknowledge (&mut self, ack: u64, now: Instant) -> Vec<T> {
+        if ack <= self.acked_up_to {return vec! ()}
+        self.acked_up_to = ack;
+        let still_unacked = self.unacked.split_off (&ack);
+        let acked = ::std::mem::replace (&mut self.unacked, still_unacked);
+        // Round trips of payloads that were sent more than once can't be measured: it's not clear
+        // which transmission is being acknowledged
+        if let Some ((_, newest)) = acked.iter ().next_back () {
+            if newest.transmissions == 1 {self.measure_round_trip (millis (now.duration_since (newest.sent_at)))}
+        }
+        for _ in 0..acked.len () {self.grow ()}
+        self.timer_started = if self.unacked.is_empty () {None} else {Some (now)};
+        self.release (now)
+    }
+
+    /// Returns the oldest payload in flight, and how many times it's now been sent, if it's gone
+    /// unacknowledged too long. Everything else waits for it: the window shrinks to one payload.
+    pub fn due (&mut self, now: Instant) -> Option<(T, u32)> {
+        let timer_started = match self.timer_started {
+            Some (timer_started) => timer_started,
+            None => return None
+        };
+        if millis (now.duration_since (timer_started)) < self.retransmit_timeout_ms {return None}
+        self.slow_start_threshold = max (self.unacked.len () / 2, 2);
+        self.window = 1;
+        self.acked_toward_growth = 0;
+        self.retransmit_timeout_ms = min (self.retransmit_timeout_ms * 2, MAX_RETRANSMIT_TIMEOUT_MS);
+        self.timer_started = Some (now);
+        let oldest = self.unacked.values_mut ().next ().expect ("Retransmission timer running with nothing in flight");
+        oldest.sent_at = now;
+        oldest.transmissions += 1;
+        Some ((oldest.item.clone (), oldest.transmissions))
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.unacked.is_empty () && self.waiting.is_empty ()
+    }
+
+    /// Too many payloads are waiting for room in the window; no more should be sent until some
+    /// are acknowledged
+    pub fn is_full (&self) -> bool {
+        self.waiting.len () >= MAX_WAITING
+    }
+
+    pub fn window (&self) -> usize {
+        self.window
+    }
+
+    pub fn retransmit_timeout (&self) -> Duration {
+        Duration::from_millis (self.retransmit_timeout_ms)
+    }
+
+    fn release (&mut self, now: Instant) -> Vec<T> {
+        let mut released = vec! ();
+        while self.unacked.len () < self.window {
+            let (sequence_number, item) = match self.waiting.pop_front () {
+                Some (waiting) => waiting,
+                None => break
+            };
+            released.push (item.clone ());
+            self.unacked.insert (sequence_number, InFlight {item, sent_at: now, transmissions: 1});
+            if self.timer_started.is_none () {self.timer_started = Some (now)}
+        }
+        released
+    }
+
+    // Slow start until the window reaches the threshold a timeout last set, then one more payload
+    // per window's worth of acknowledgements
+    fn grow (&mut self) {
+        if self.window >= MAX_WINDOW {return}
+        if self.window < self.slow_start_threshold {
+            self.window += 1;
+        }
+        else {
+            self.acked_toward_growth += 1;
+            if self.acked_toward_growth >= self.window {
+                self.acked_toward_growth -= self.window;
+                self.window += 1;
+            }
+        }
+    }
+
+    // As RFC 6298 has it
+    fn measure_round_trip (&mut self, rtt_ms: u64) {
+        match self.smoothed_rtt_ms {
+            None => {
+                self.smoothed_rtt_ms = Some (rtt_ms);
+                self.rtt_variance_ms = rtt_ms / 2;
+            },
+            Some (smoothed_rtt_ms) => {
+                let deviation = if smoothed_rtt_ms > rtt_ms {smoothed_rtt_ms - rtt_ms} else {rtt_ms - smoothed_rtt_ms};
+                self.rtt_variance_ms = (self.rtt_variance_ms * 3 + deviation) / 4;
+                self.smoothed_rtt_ms = Some ((smoothed_rtt_ms * 7 + rtt_ms) / 8);
+            }
+        }
+        let smoothed_rtt_ms = self.smoothed_rtt_ms.expect ("Round trip time disappeared");
+        self.retransmit_timeout_ms = min (max (smoothed_rtt_ms + max (RELIABILITY_TICK_MS, self.rtt_variance_ms * 4),
+            MIN_RETRANSMIT_TIMEOUT_MS), MAX_RETRANSMIT_TIMEOUT_MS);
+    }
+}
+
+/// How long a payload can go on being sent before its stream is given up, if acknowledgements
+/// start out taking as long as a new SendWindow expects. The other end shouldn't give up on a
+/// missing payload any sooner.
+pub fn retransmission_budget () -> Duration {
+    let (budget_ms, _) = (0..MAX_TRANSMISSIONS).fold ((0, INITIAL_RETRANSMIT_TIMEOUT_MS), |(budget_ms, timeout_ms), _| {
+        // A payload falls due on the first tick after its timer runs out
+        (budget_ms + timeout_ms + RELIABILITY_TICK_MS, min (timeout_ms * 2, MAX_RETRANSMIT_TIMEOUT_MS))
+    });
+    Duration::from_millis (budget_ms)
+}
+
+fn millis (duration: Duration) -> u64 {
+    duration.as_secs () * 1000 + u64::from (duration.subsec_nanos () / 1000000)
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+
+    fn after (start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis (ms)
+    }
+
+    #[test]
+    fn only_a_window_of_payloads_goes_out_until_some_are_acknowledged () {
+        let mut subject = SendWindow::new ();
+        let now = Instant::now ();
+
+        let sent: Vec<u64> = (0..6).flat_map (|n| subject.send (n, n, now)).collect ();
+        let released = subject.acknowledge (2, now);
+
+        assert_eq! (sent, vec! (0, 1, 2, 3));
+        assert_eq! (released, vec! (4, 5));
+        assert_eq! (subject.window (), 6);
+    }
+
+    #[test]
+    fn old_acknowledgements_change_nothing () {
+        let mut subject = SendWindow::new ();
+        let now = Instant::now ();
+        for n in 0..3 {subject.send (n, n, now);}
+        subject.acknowledge (2, now);
+
+        let released = subject.acknowledge (1, now);
+
+        assert_eq! (released, Vec::<u64>::new ());
+        assert_eq! (subject.window (), 6);
+        assert_eq! (subject.is_empty (), false);
+        subject.acknowledge (3, now);
+        assert_eq! (subject.is_empty (), true);
+    }
+
+    #[test]
+    fn the_window_is_full_once_enough_payloads_are_waiting_for_room () {
+        let mut subject = SendWindow::new ();
+        let now = Instant::now ();
+        for n in 0..(INITIAL_WINDOW + MAX_WAITING - 1) {subject.send (n as u64, n, now);}
+        assert_eq! (subject.is_full (), false);
+        subject.send ((INITIAL_WINDOW + MAX_WAITING - 1) as u64, 0, now);
+        assert_eq! (subject.is_full (), true);
+
+        subject.acknowledge (1, now);
+
+        assert_eq! (subject.is_full (), false);
+    }
+
+    #[test]
+    fn the_retransmission_budget_covers_every_doubled_timeout_and_the_tick_that_notices_it () {
+        let result = retransmission_budget ();
+
+        assert_eq! (result, Duration::from_millis (1000 + 2000 + 4000 + 8000 + 16000 + 30000 + 6 * RELIABILITY_TICK_MS));
+    }
+
+    #[test]
+    fn the_oldest_payload_in_flight_is_due_again_when_its_timer_runs_out () {
+        let mut subject = SendWindow::new ();
+        let start = Instant::now ();
+        subject.send (0, "zero", start);
+        subject.send (1, "one", start);
+
+        assert_eq! (subject.due (after (start, 999)), None);
+        assert_eq! (subject.due (after (start, 1000)), Some (("zero", 2)));
+        assert_eq! (subject.window (), 1);
+        assert_eq! (subject.send (2, "two", after (start, 1000)), Vec::<&str>::new ());
+    }
+
+    #[test]
+    fn each_timeout_doubles_the_wait_for_the_next () {
+        let mut subject = SendWindow::new ();
+        let start = Instant::now ();
+        subject.send (0, "zero", start);
+        subject.due (after (start, 1000));
+
+        assert_eq! (subject.due (after (start, 2999)), None);
+        assert_eq! (subject.due (after (start, 3000)), Some (("zero", 3)));
+        assert_eq! (subject.retransmit_timeout (), Duration::from_millis (4000));
+    }
+
+    #[test]
+    fn round_trip_times_set_the_retransmission_timeout () {
+        let mut subject = SendWindow::new ();
+        let start = Instant::now ();
+        subject.send (0, "zero", start);
+        subject.acknowledge (1, after (start, 300));
+        assert_eq! (subject.retransmit_timeout (), Duration::from_millis (900));
+        subject.send (1, "one", after (start, 300));
+        subject.acknowledge (2, after (start, 400));
+
+        assert_eq! (subject.retransmit_timeout (), Duration::from_millis (923));
+    }
+
+    #[test]
+    fn payloads_sent_more_than_once_do_not_measure_the_round_trip () {
+        let mut subject = SendWindow::new ();
+        let start = Instant::now ();
+        subject.send (0, "zero", start);
+        subject.due (after (start, 1000));
+
+        subject.acknowledge (1, after (start, 1010));
+
+        assert_eq! (subject.retransmit_timeout (), Duration::from_millis (2000));
+        assert_eq! (subject.due (after (start, 5000)), None);
+    }
+
+    #[test]
+    fn the_window_grows_slowly_once_it_passes_the_threshold_a_timeout_set () {
+        let mut subject = SendWindow::new ();
+        let start = Instant::now ();
+        for n in 0..8 {subject.send (n, n, start);}
+        subject.acknowledge (4, start);
+        subject.due (after (start, 1000));
+
+        subject.acknowledge (6, after (start, 1100));
+        assert_eq! (subject.window (), 2);
+        subject.acknowledge (8, after (start, 1200));
+
+        assert_eq! (subject.window (), 3);
+    }
+}
@@ -1,9 +1,30 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::net::SocketAddr;
+use bytes::Bytes;
 use dispatcher::Endpoint;
 
 #[derive (PartialEq, Debug, Message)]
 pub struct TransmitDataMsg {
     pub endpoint: Endpoint,
+    // Shut down the writing side of the stream after this data
     pub last_data: bool,
-    pub data: Vec<u8>
+    // Shut down the reading side of the stream too
+    pub stopped_reading: bool,
+    pub data: Bytes
+}
+
+/// Stops the StreamHandlerPool reading from a client's stream, or starts it again, so that data the
+/// network isn't ready for waits in the client's socket
+#[derive (PartialEq, Debug, Message)]
+pub struct PauseReadingMsg {
+    pub socket_addr: SocketAddr,
+    pub paused: bool
+}
+
+/// Tells the ProxyServer that data written to a client's stream is waiting for the client to take it,
+/// or that the client has caught up, so that responses are acknowledged only as fast as they're read
+#[derive (PartialEq, Debug, Message)]
+pub struct WriteBacklogMsg {
+    pub socket_addr: SocketAddr,
+    pub backed_up: bool
 }
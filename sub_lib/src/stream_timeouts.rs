@@ -0,0 +1,237 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::cmp::min;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use futures::Async;
+use futures::Stream;
+use tokio_core::reactor::Handle;
+use tokio_core::reactor::Interval;
+
+pub const DEFAULT_STREAM_IDLE_TIMEOUT_MS: u64 = 300000;
+pub const DEFAULT_STREAM_LIFETIME_MS: u64 = 0; // 0 for unlimited
+
+/// The longest a StreamReader goes without looking at its stream's clock
+const MAX_EXPIRY_CHECK_MS: u64 = 1000;
+
+/// How long a stream may go without traffic either way, and how long it may stay open at all,
+/// before it's closed. None means there's no limit.
+#[derive (Clone, Copy, PartialEq, Debug)]
+pub struct StreamTimeouts {
+    pub idle: Option<Duration>,
+    pub lifetime: Option<Duration>
+}
+
+impl StreamTimeouts {
+    pub fn new () -> StreamTimeouts {
+        StreamTimeouts {
+            idle: StreamTimeouts::limit (DEFAULT_STREAM_IDLE_TIMEOUT_MS),
+            lifetime: StreamTimeouts::limit (DEFAULT_STREAM_LIFETIME_MS)
+        }
+    }
+
+    pub fn none () -> StreamTimeouts {
+        StreamTimeouts {idle: None, lifetime: None}
+    }
+
+    pub fn limit (ms: u64) -> Option<Duration> {
+        if ms == 0 {None} else {Some (Duration::from_millis (ms))}
+    }
+
+    /// How often a StreamReader should look at its stream's clock so that it notices promptly when
+    /// the stream expires, or None if the stream never does
+    pub fn check_interval (&self) -> Option<Duration> {
+        let check = Duration::from_millis (MAX_EXPIRY_CHECK_MS);
+        match (self.idle, self.lifetime) {
+            (None, None) => None,
+            (Some (idle), None) => Some (min (idle, check)),
+            (None, Some (lifetime)) => Some (min (lifetime, check)),
+            (Some (idle), Some (lifetime)) => Some (min (min (idle, lifetime), check))
+        }
+    }
+}
+
+#[derive (Clone, Copy, PartialEq, Debug)]
+pub enum Expiry {
+    Idle (Duration),
+    Lifetime (Duration)
+}
+
+impl fmt::Display for Expiry {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expiry::Idle (limit) => write! (f, "idle for more than {}ms", millis (limit)),
+            Expiry::Lifetime (limit) => write! (f, "open for more than {}ms", millis (limit))
+        }
+    }
+}
+
+/// Keeps track of when a stream opened and when it last carried traffic. The StreamReader and the
+/// StreamWriter on a stream share one, so that traffic in either direction keeps the stream alive.
+#[derive (Clone)]
+pub struct StreamClock {
+    timeouts: StreamTimeouts,
+    opened: Instant,
+    last_traffic: Arc<Mutex<Instant>>
+}
+
+impl StreamClock {
+    pub fn new (timeouts: StreamTimeouts) -> StreamClock {
+        StreamClock::starting_at (timeouts, Instant::now ())
+    }
+
+    pub fn starting_at (timeouts: StreamTimeouts, now: Instant) -> StreamClock {
+        StreamClock {
+            timeouts,
+            opened: now,
+            last_traffic: Arc::new (Mutex::new (now))
+        }
+    }
+
+    pub fn timeouts (&self) -> StreamTimeouts {
+        self.timeouts
+    }
+
+    pub fn traffic (&self, now: Instant) {
+        *self.last_traffic.lock ().expect ("StreamClock poisoned") = now;
+    }
+
+    /// Says why the stream should be closed, if it should
+    pub fn expiry (&self, now: Instant) -> Option<Expiry> {
+        if let Some (lifetime) = self.timeouts.lifetime {
+            if now.duration_since (self.opened) >= lifetime {return Some (Expiry::Lifetime (lifetime))}
+        }
+        if let Some (idle) = self.timeouts.idle {
+            let last_traffic = *self.last_traffic.lock ().expect ("StreamClock poisoned");
+            if now.duration_since (last_traffic) >= idle {return Some (Expiry::Idle (idle))}
+        }
+        None
+    }
+}
+
+/// Wakes the task that polls it often enough to notice promptly when a stream expires, so that a
+/// StreamReader waiting on a quiet stream still gets the chance to close it
+pub struct ExpiryTimer {
+    interval: Option<Interval>
+}
+
+impl ExpiryTimer {
+    pub fn new (timeouts: StreamTimeouts, handle: &Handle) -> io::Result<ExpiryTimer> {
+        let interval = match timeouts.check_interval () {
+            None => None,
+            Some (check_interval) => Some (Interval::new (check_interval, handle)?)
+        };
+        Ok (ExpiryTimer {interval})
+    }
+
+    pub fn none () -> ExpiryTimer {
+        ExpiryTimer {interval: None}
+    }
+
+    /// Says why the stream should be closed, if it should be by now; otherwise, the current task
+    /// will be woken in time for the next check
+    pub fn poll (&mut self, clock: &StreamClock) -> Option<Expiry> {
+        let stopped = match self.interval {
+            None => false,
+            Some (ref mut interval) => loop {
+                match interval.poll () {
+                    Ok (Async::Ready (Some (()))) => continue,
+                    Ok (Async::NotReady) => break false,
+                    Ok (Async::Ready (None)) | Err (_) => break true
+                }
+            }
+        };
+        if stopped {self.interval = None}
+        clock.expiry (Instant::now ())
+    }
+}
+
+fn millis (duration: Duration) -> u64 {
+    duration.as_secs () * 1000 + u64::from (duration.subsec_nanos () / 1000000)
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use futures::Poll;
+    use tokio_core::reactor::Core;
+
+    fn timeouts (idle_ms: u64, lifetime_ms: u64) -> StreamTimeouts {
+        StreamTimeouts {idle: StreamTimeouts::limit (idle_ms), lifetime: StreamTimeouts::limit (lifetime_ms)}
+    }
+
+    #[test]
+    fn default_timeouts_close_idle_streams_but_let_busy_ones_live () {
+        let subject = StreamTimeouts::new ();
+
+        assert_eq! (subject, timeouts (300000, 0));
+    }
+
+    #[test]
+    fn check_interval_is_short_enough_to_notice_expiry_promptly () {
+        assert_eq! (timeouts (0, 0).check_interval (), None);
+        assert_eq! (timeouts (300000, 0).check_interval (), Some (Duration::from_millis (1000)));
+        assert_eq! (timeouts (0, 250).check_interval (), Some (Duration::from_millis (250)));
+        assert_eq! (timeouts (400, 300).check_interval (), Some (Duration::from_millis (300)));
+    }
+
+    #[test]
+    fn traffic_in_either_direction_keeps_a_stream_from_going_idle () {
+        let start = Instant::now ();
+        let subject = StreamClock::starting_at (timeouts (1000, 0), start);
+        let writer_copy = subject.clone ();
+
+        assert_eq! (subject.expiry (start + Duration::from_millis (999)), None);
+        writer_copy.traffic (start + Duration::from_millis (900));
+
+        assert_eq! (subject.expiry (start + Duration::from_millis (1899)), None);
+        assert_eq! (subject.expiry (start + Duration::from_millis (1900)), Some (Expiry::Idle (Duration::from_millis (1000))));
+    }
+
+    #[test]
+    fn traffic_does_not_extend_a_streams_lifetime () {
+        let start = Instant::now ();
+        let subject = StreamClock::starting_at (timeouts (1000, 1500), start);
+
+        subject.traffic (start + Duration::from_millis (1400));
+
+        assert_eq! (subject.expiry (start + Duration::from_millis (1499)), None);
+        assert_eq! (subject.expiry (start + Duration::from_millis (1500)), Some (Expiry::Lifetime (Duration::from_millis (1500))));
+    }
+
+    #[test]
+    fn streams_without_limits_never_expire () {
+        let start = Instant::now ();
+        let subject = StreamClock::starting_at (timeouts (0, 0), start);
+
+        assert_eq! (subject.expiry (start + Duration::from_secs (86400)), None);
+    }
+
+    #[test]
+    fn expiry_timer_wakes_a_waiting_task_when_its_stream_goes_idle () {
+        let mut core = Core::new ().unwrap ();
+        let clock = StreamClock::new (timeouts (50, 0));
+        let mut subject = ExpiryTimer::new (clock.timeouts (), &core.handle ()).unwrap ();
+        let started = Instant::now ();
+
+        let result = core.run (future::poll_fn (|| -> Poll<Expiry, ()> {
+            match subject.poll (&clock) {
+                Some (expiry) => Ok (Async::Ready (expiry)),
+                None => Ok (Async::NotReady)
+            }
+        }));
+
+        assert_eq! (result, Ok (Expiry::Idle (Duration::from_millis (50))));
+        assert! (started.elapsed () >= Duration::from_millis (50));
+    }
+
+    #[test]
+    fn expiries_explain_themselves () {
+        assert_eq! (format! ("{}", Expiry::Idle (Duration::from_millis (300000))), "idle for more than 300000ms");
+        assert_eq! (format! ("{}", Expiry::Lifetime (Duration::from_millis (1500))), "open for more than 1500ms");
+    }
+}
@@ -1,5 +1,6 @@
 // Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
 use std::io;
+use std::io::ErrorKind;
 use std::io::Read;
 use std::io::Write;
 use std::net::TcpListener;
@@ -9,6 +10,10 @@ use std::net::Shutdown;
 use std::net::SocketAddr;
 use std::marker::Send;
 use std::time::Duration;
+use futures::Async;
+use futures::Poll;
+use tokio_core::net::TcpStream as ReactorTcpStream;
+use tokio_core::reactor::Handle;
 
 pub trait TcpListenerWrapper: Send {
     fn bind (&mut self, addr: SocketAddr) -> io::Result<()>;
@@ -38,6 +43,12 @@ pub trait TcpStreamWrapper: Send + Read + Write {
     fn take_error(&self) -> io::Result<Option<io::Error>>;
     fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
     fn try_clone(&self) -> io::Result<Box<TcpStreamWrapper>>;
+    /// Hands the stream to the reactor behind the Handle. From then on it doesn't block: poll_read
+    /// and poll_write return NotReady instead, and arrange for the current task to be woken when
+    /// it's worth trying again. Clones made afterward have to be registered separately.
+    fn register (&mut self, handle: &Handle) -> io::Result<()>;
+    fn poll_read (&mut self, buf: &mut [u8]) -> Poll<usize, io::Error>;
+    fn poll_write (&mut self, buf: &[u8]) -> Poll<usize, io::Error>;
 }
 
 pub trait TcpListenerWrapperFactory {
@@ -54,7 +65,9 @@ pub struct TcpListenerWrapperReal {
 }
 
 pub struct TcpStreamWrapperReal {
-    delegate: Option<TcpStream>
+    delegate: Option<TcpStream>,
+    // The same socket as the reactor sees it, once the stream is registered
+    registered: Option<ReactorTcpStream>
 }
 
 pub struct TcpListenerWrapperFactoryReal {}
@@ -172,10 +185,24 @@ impl TcpStreamWrapper for TcpStreamWrapperReal {
 
     fn try_clone(&self) -> io::Result<Box<TcpStreamWrapper>> {
         match self.delegate ().try_clone () {
-            Ok (c) => Ok (Box::new (TcpStreamWrapperReal {delegate: Some (c)})),
+            Ok (c) => Ok (Box::new (TcpStreamWrapperReal {delegate: Some (c), registered: None})),
             Err (e) => Err (e)
         }
     }
+
+    fn register (&mut self, handle: &Handle) -> io::Result<()> {
+        let stream = self.delegate ().try_clone ()?;
+        self.registered = Some (ReactorTcpStream::from_stream (stream, handle)?);
+        Ok (())
+    }
+
+    fn poll_read (&mut self, buf: &mut [u8]) -> Poll<usize, io::Error> {
+        TcpStreamWrapperReal::poll (self.registered_mut ().read (buf))
+    }
+
+    fn poll_write (&mut self, buf: &[u8]) -> Poll<usize, io::Error> {
+        TcpStreamWrapperReal::poll (self.registered_mut ().write (buf))
+    }
 }
 
 impl Read for TcpStreamWrapperReal {
@@ -204,7 +231,7 @@ impl TcpListenerWrapperFactory for TcpListenerWrapperFactoryReal {
 
 impl TcpStreamWrapperFactory for TcpStreamWrapperFactoryReal {
     fn make(&self) -> Box<TcpStreamWrapper> {
-        Box::new (TcpStreamWrapperReal {delegate: None})
+        Box::new (TcpStreamWrapperReal::new ())
     }
     fn dup(&self) -> Box<TcpStreamWrapperFactory> {Box::new (self.clone ())}
 }
@@ -221,7 +248,7 @@ impl TcpListenerWrapperReal {
 
 impl TcpStreamWrapperReal {
     pub fn new () -> TcpStreamWrapperReal {
-        TcpStreamWrapperReal {delegate: None}
+        TcpStreamWrapperReal {delegate: None, registered: None}
     }
 
     fn delegate (&self) -> &TcpStream {
@@ -231,13 +258,44 @@ impl TcpStreamWrapperReal {
     fn delegate_mut (&mut self) -> &mut TcpStream {
         self.delegate.as_mut().expect ("TcpStream not initialized - connect to a SocketAddr")
     }
+
+    fn registered_mut (&mut self) -> &mut ReactorTcpStream {
+        self.registered.as_mut ().expect ("TcpStream not registered - register it with a reactor")
+    }
+
+    // The reactor's streams say WouldBlock when they're not ready, and arrange for the current task to be woken
+    fn poll (result: io::Result<usize>) -> Poll<usize, io::Error> {
+        match result {
+            Ok (length) => Ok (Async::Ready (length)),
+            Err (ref e) if e.kind () == ErrorKind::WouldBlock => Ok (Async::NotReady),
+            Err (e) => Err (e)
+        }
+    }
 }
 
 #[cfg (test)]
 mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use futures::future;
+    use tokio_core::reactor::Core;
 
     #[test]
-    fn nothing () {
-
+    fn registered_stream_waits_for_data_instead_of_blocking () {
+        let listener = TcpListener::bind ("127.0.0.1:0").unwrap ();
+        let mut subject = TcpStreamWrapperReal::new ();
+        subject.connect (listener.local_addr ().unwrap ()).unwrap ();
+        let (mut peer, _) = listener.accept ().unwrap ();
+        let mut core = Core::new ().unwrap ();
+        subject.register (&core.handle ()).unwrap ();
+        let mut buf = [0u8; 16];
+
+        let before = core.run (future::lazy (|| subject.poll_read (&mut buf))).unwrap ();
+        peer.write_all (b"booga").unwrap ();
+        let after = core.run (future::poll_fn (|| subject.poll_read (&mut buf))).unwrap ();
+
+        assert_eq! (before, Async::NotReady);
+        assert_eq! (after, 5);
+        assert_eq! (&buf[0..5], b"booga");
     }
-}
\ No newline at end of file
+}
@@ -6,7 +6,15 @@ static DEAD_STREAM_ERRORS: [ErrorKind; 5] = [
     ErrorKind::ConnectionRefused, ErrorKind::TimedOut
 ];
 
-pub static NODE_MAILBOX_CAPACITY: usize = 0; // 0 for unbound
+// How many messages the Dispatcher, Hopper, ProxyServer and ProxyClient hold before whatever's
+// sending them data has to wait
+pub static NODE_MAILBOX_CAPACITY: usize = 64;
+
+// How many reads a StreamReader makes before letting the other streams on its thread have a turn
+pub static MAX_READS_PER_POLL: usize = 16;
+
+// How many read errors in a row a StreamReader puts up with before it gives its stream up as dead
+pub static MAX_READ_ERRORS_IN_A_ROW: usize = 3;
 
 pub fn indicates_dead_stream (kind: ErrorKind) -> bool {
     DEAD_STREAM_ERRORS.contains (&kind)
@@ -178,7 +186,7 @@ mod tests {
     }
 
     #[test]
-    fn node_mailbox_capacity_is_unbound() {
-        assert_eq!(NODE_MAILBOX_CAPACITY, 0)
+    fn node_mailbox_capacity_is_bounded() {
+        assert_eq!(NODE_MAILBOX_CAPACITY, 64)
     }
 }
\ No newline at end of file
@@ -0,0 +1,345 @@
+// Copyright (c) 2017-2018, Substratum LLC (https://substratum.net) and/or its affiliates. All rights reserved.
+use std::collections::VecDeque;
+use std::io;
+use std::io::ErrorKind;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use tokio_core::reactor::Remote;
+use tcp_wrappers::TcpStreamWrapper;
+
+/// How much data may wait in a WriteQueue before it's backed up
+pub const MAX_PENDING_BYTES: usize = 0x40000;
+
+/// Writes to a stream that's registered with a reactor without ever making the writer wait.
+/// Whatever the stream won't take right away stays here, in order, and a task on the reactor
+/// writes it as the stream makes room; a shutdown waits until everything written before it has
+/// gone. Once a write fails, the stream is shut down and every later write fails too. Clones share
+/// the same queue. Writes are never refused, so whoever is writing should stop taking on more data
+/// for the stream while the queue is backed up: at most a window's worth more will arrive.
+#[derive (Clone)]
+pub struct WriteQueue {
+    inner: Arc<Mutex<WriteQueueInner>>,
+    remote: Remote
+}
+
+struct WriteQueueInner {
+    stream: Box<TcpStreamWrapper>,
+    // Chunks waiting to be written, and how much of the first one already has been
+    pending: VecDeque<Vec<u8>>,
+    written: usize,
+    pending_bytes: usize,
+    shutdown: Option<Shutdown>,
+    flushing: bool,
+    failure: Option<ErrorKind>
+}
+
+impl WriteQueue {
+    pub fn new (stream: Box<TcpStreamWrapper>, remote: Remote) -> WriteQueue {
+        WriteQueue {
+            inner: Arc::new (Mutex::new (WriteQueueInner {
+                stream,
+                pending: VecDeque::new (),
+                written: 0,
+                pending_bytes: 0,
+                shutdown: None,
+                flushing: false,
+                failure: None
+            })),
+            remote
+        }
+    }
+
+    pub fn peer_addr (&self) -> io::Result<SocketAddr> {
+        self.lock ().stream.peer_addr ()
+    }
+
+    /// Accepts all the data or fails; there are no partial writes
+    pub fn write (&self, data: &[u8]) -> io::Result<usize> {
+        let mut inner = self.lock ();
+        if let Some (kind) = inner.failure {return Err (io::Error::from (kind))}
+        // Nothing can go ahead of data that's already waiting
+        let written = if inner.pending.is_empty () {inner.write_now (data)?} else {0};
+        if written < data.len () {
+            inner.pending.push_back (data[written..].to_vec ());
+            inner.pending_bytes += data.len () - written;
+            // Only one task at a time can wait on the stream, and while the flusher's waiting it does all the writing
+            if !inner.flushing {self.start_flusher (&mut inner)}
+        }
+        Ok (data.len ())
+    }
+
+    /// More than MAX_PENDING_BYTES are waiting for the stream to make room
+    pub fn is_backed_up (&self) -> bool {
+        self.lock ().pending_bytes >= MAX_PENDING_BYTES
+    }
+
+    pub fn shutdown (&self, how: Shutdown) -> io::Result<()> {
+        let mut inner = self.lock ();
+        if let Some (kind) = inner.failure {return Err (io::Error::from (kind))}
+        if inner.pending.is_empty () {return inner.stream.shutdown (how)}
+        inner.shutdown = match inner.shutdown {
+            Some (earlier) if earlier != how => Some (Shutdown::Both),
+            _ => Some (how)
+        };
+        Ok (())
+    }
+
+    fn start_flusher (&self, inner: &mut WriteQueueInner) {
+        inner.flushing = true;
+        let flusher = Flusher {inner: self.inner.clone ()};
+        self.remote.spawn (move |_| flusher);
+    }
+
+    fn lock (&self) -> MutexGuard<WriteQueueInner> {
+        self.inner.lock ().expect ("WriteQueue poisoned")
+    }
+}
+
+impl WriteQueueInner {
+    // Writes as much as the stream will take without waiting
+    fn write_now (&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < data.len () {
+            match self.stream.poll_write (&data[written..]) {
+                Ok (Async::Ready (0)) => return Err (self.fail (io::Error::from (ErrorKind::WriteZero))),
+                Ok (Async::Ready (length)) => written += length,
+                Ok (Async::NotReady) => break,
+                Err (e) => return Err (self.fail (e))
+            }
+        }
+        Ok (written)
+    }
+
+    fn flush (&mut self) -> Poll<(), io::Error> {
+        while let Some (chunk) = self.pending.pop_front () {
+            let written = self.written + self.write_now (&chunk[self.written..])?;
+            self.pending_bytes -= written - self.written;
+            if written < chunk.len () {
+                self.pending.push_front (chunk);
+                self.written = written;
+                return Ok (Async::NotReady)
+            }
+            self.written = 0;
+        }
+        if let Some (how) = self.shutdown.take () {
+            self.stream.shutdown (how).ok (); // nobody's left to tell about failure
+        }
+        Ok (Async::Ready (()))
+    }
+
+    // Shutting the stream down wakes its StreamReader, which ends the stream
+    fn fail (&mut self, error: io::Error) -> io::Error {
+        self.failure = Some (error.kind ());
+        self.pending.clear ();
+        self.pending_bytes = 0;
+        self.shutdown = None;
+        self.stream.shutdown (Shutdown::Both).ok (); // can't do anything about failure
+        error
+    }
+}
+
+struct Flusher {
+    inner: Arc<Mutex<WriteQueueInner>>
+}
+
+impl Future for Flusher {
+    type Item = ();
+    type Error = ();
+
+    fn poll (&mut self) -> Poll<(), ()> {
+        let mut inner = self.inner.lock ().expect ("WriteQueue poisoned");
+        match inner.flush () {
+            Ok (Async::NotReady) => Ok (Async::NotReady),
+            Ok (Async::Ready (())) | Err (_) => {
+                inner.flushing = false;
+                Ok (Async::Ready (()))
+            }
+        }
+    }
+}
+
+#[cfg (test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::io::Write;
+    use std::time::Duration;
+    use futures::future;
+    use futures::task;
+    use tokio_core::reactor::Core;
+    use tokio_core::reactor::Handle;
+
+    struct TcpStreamWrapperMock {
+        poll_write_results: Vec<Poll<usize, io::Error>>,
+        log: Arc<Mutex<Vec<String>>>
+    }
+
+    impl TcpStreamWrapper for TcpStreamWrapperMock {
+        fn poll_write (&mut self, buf: &[u8]) -> Poll<usize, io::Error> {
+            self.log.lock ().unwrap ().push (format! ("poll_write ({})", String::from_utf8 (buf.to_vec ()).unwrap ()));
+            let result = self.poll_write_results.remove (0);
+            // Pretend the stream makes room right away
+            if let Ok (Async::NotReady) = result {task::current ().notify ()}
+            result
+        }
+
+        fn shutdown (&self, how: Shutdown) -> io::Result<()> {
+            self.log.lock ().unwrap ().push (format! ("shutdown ({:?})", how));
+            Ok (())
+        }
+
+        fn peer_addr (&self) -> io::Result<SocketAddr> {unimplemented! ()}
+        fn connect (&mut self, _addr: SocketAddr) -> io::Result<()> {unimplemented! ()}
+        fn local_addr (&self) -> io::Result<SocketAddr> {unimplemented! ()}
+        fn set_read_timeout (&self, _dur: Option<Duration>) -> io::Result<()> {unimplemented! ()}
+        fn set_write_timeout (&self, _dur: Option<Duration>) -> io::Result<()> {unimplemented! ()}
+        fn read_timeout (&self) -> io::Result<Option<Duration>> {unimplemented! ()}
+        fn write_timeout (&self) -> io::Result<Option<Duration>> {unimplemented! ()}
+        fn peek (&self, _buf: &mut [u8]) -> io::Result<usize> {unimplemented! ()}
+        fn set_nodelay (&self, _nodelay: bool) -> io::Result<()> {unimplemented! ()}
+        fn nodelay (&self) -> io::Result<bool> {unimplemented! ()}
+        fn set_ttl (&self, _ttl: u32) -> io::Result<()> {unimplemented! ()}
+        fn ttl (&self) -> io::Result<u32> {unimplemented! ()}
+        fn take_error (&self) -> io::Result<Option<io::Error>> {unimplemented! ()}
+        fn set_nonblocking (&self, _nonblocking: bool) -> io::Result<()> {unimplemented! ()}
+        fn try_clone (&self) -> io::Result<Box<TcpStreamWrapper>> {unimplemented! ()}
+        fn register (&mut self, _handle: &Handle) -> io::Result<()> {unimplemented! ()}
+        fn poll_read (&mut self, _buf: &mut [u8]) -> Poll<usize, io::Error> {unimplemented! ()}
+    }
+
+    impl Read for TcpStreamWrapperMock {
+        fn read (&mut self, _buf: &mut [u8]) -> io::Result<usize> {unimplemented! ()}
+    }
+
+    impl Write for TcpStreamWrapperMock {
+        fn write (&mut self, _buf: &[u8]) -> io::Result<usize> {unimplemented! ()}
+        fn flush (&mut self) -> io::Result<()> {unimplemented! ()}
+    }
+
+    fn make_subject (core: &Core, poll_write_results: Vec<Poll<usize, io::Error>>) -> (WriteQueue, Arc<Mutex<Vec<String>>>) {
+        let log = Arc::new (Mutex::new (vec! ()));
+        let stream = TcpStreamWrapperMock {poll_write_results, log: log.clone ()};
+        (WriteQueue::new (Box::new (stream), core.remote ()), log)
+    }
+
+    // Writers call the WriteQueue from tasks of their own
+    fn in_task<F, R> (core: &mut Core, f: F) -> R where F: FnOnce () -> R {
+        core.run (future::lazy (|| -> Result<R, ()> {Ok (f ())})).unwrap ()
+    }
+
+    fn run_until<F> (core: &mut Core, check: F) where F: Fn () -> bool {
+        for _ in 0..100 {
+            if check () {return}
+            core.turn (Some (Duration::from_millis (10)));
+        }
+        panic! ("WriteQueue never finished")
+    }
+
+    #[test]
+    fn data_the_stream_takes_right_away_is_written_right_away () {
+        let core = Core::new ().unwrap ();
+        let (subject, log) = make_subject (&core, vec! (Ok (Async::Ready (5))));
+
+        let result = subject.write (b"booga");
+
+        assert_eq! (result.unwrap (), 5);
+        assert_eq! (*log.lock ().unwrap (), vec! (String::from ("poll_write (booga)")));
+    }
+
+    #[test]
+    fn data_the_stream_has_no_room_for_is_written_later_in_order () {
+        let mut core = Core::new ().unwrap ();
+        let (subject, log) = make_subject (&core, vec! (
+            Ok (Async::Ready (2)),
+            Ok (Async::NotReady),
+            Ok (Async::Ready (3)),
+            Ok (Async::Ready (3))
+        ));
+
+        let first = in_task (&mut core, || subject.write (b"booga"));
+        let second = in_task (&mut core, || subject.write (b"wah"));
+        run_until (&mut core, || log.lock ().unwrap ().len () == 4);
+
+        assert_eq! (first.unwrap (), 5);
+        assert_eq! (second.unwrap (), 3);
+        assert_eq! (*log.lock ().unwrap (), vec! (
+            String::from ("poll_write (booga)"),
+            String::from ("poll_write (oga)"),
+            String::from ("poll_write (oga)"),
+            String::from ("poll_write (wah)")
+        ));
+    }
+
+    #[test]
+    fn the_queue_is_backed_up_while_too_much_data_waits_for_the_stream () {
+        let mut core = Core::new ().unwrap ();
+        let (subject, log) = make_subject (&core, vec! (
+            Ok (Async::Ready (1)),
+            Ok (Async::NotReady),
+            Ok (Async::NotReady),
+            Ok (Async::Ready (MAX_PENDING_BYTES - 1)),
+            Ok (Async::Ready (1))
+        ));
+        let data = vec! (b'x'; MAX_PENDING_BYTES);
+
+        let (backed_up_short_of_the_limit, backed_up_at_the_limit) = in_task (&mut core, || {
+            subject.write (&data[..]).unwrap ();
+            let short_of_the_limit = subject.is_backed_up ();
+            subject.write (b"x").unwrap ();
+            (short_of_the_limit, subject.is_backed_up ())
+        });
+        run_until (&mut core, || log.lock ().unwrap ().len () == 5);
+
+        assert_eq! (backed_up_short_of_the_limit, false);
+        assert_eq! (backed_up_at_the_limit, true);
+        assert_eq! (subject.is_backed_up (), false);
+    }
+
+    #[test]
+    fn shutdown_waits_for_everything_written_before_it () {
+        let mut core = Core::new ().unwrap ();
+        let (subject, log) = make_subject (&core, vec! (
+            Ok (Async::NotReady),
+            Ok (Async::Ready (5))
+        ));
+
+        in_task (&mut core, || subject.write (b"booga")).unwrap ();
+        subject.shutdown (Shutdown::Write).unwrap ();
+        subject.shutdown (Shutdown::Read).unwrap ();
+        run_until (&mut core, || log.lock ().unwrap ().len () == 3);
+
+        assert_eq! (*log.lock ().unwrap (), vec! (
+            String::from ("poll_write (booga)"),
+            String::from ("poll_write (booga)"),
+            String::from ("shutdown (Both)")
+        ));
+    }
+
+    #[test]
+    fn a_failed_write_shuts_the_stream_down_and_fails_everything_after_it () {
+        let mut core = Core::new ().unwrap ();
+        let (subject, log) = make_subject (&core, vec! (
+            Ok (Async::NotReady),
+            Err (io::Error::from (ErrorKind::BrokenPipe))
+        ));
+
+        in_task (&mut core, || subject.write (b"booga")).unwrap ();
+        run_until (&mut core, || log.lock ().unwrap ().len () == 3);
+        let write_result = subject.write (b"wah");
+        let shutdown_result = subject.shutdown (Shutdown::Write);
+
+        assert_eq! (write_result.err ().unwrap ().kind (), ErrorKind::BrokenPipe);
+        assert_eq! (shutdown_result.err ().unwrap ().kind (), ErrorKind::BrokenPipe);
+        assert_eq! (*log.lock ().unwrap (), vec! (
+            String::from ("poll_write (booga)"),
+            String::from ("poll_write (booga)"),
+            String::from ("shutdown (Both)")
+        ));
+    }
+}
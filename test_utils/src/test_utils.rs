@@ -45,8 +45,12 @@ use sub_lib::proxy_client::ProxyClientSubs;
 use sub_lib::proxy_server::ProxyServerSubs;
 use sub_lib::route::Route;
 use sub_lib::route::RouteSegment;
+use sub_lib::stream_handler_pool::PauseReadingMsg;
+use sub_lib::stream_handler_pool::WriteBacklogMsg;
 use sub_lib::stream_handler_pool::TransmitDataMsg;
 use sub_lib::neighborhood::NodeQueryMessage;
+use sub_lib::neighborhood::RouteQueryMessage;
+use sub_lib::neighborhood::RouteQueryResponse;
 
 lazy_static! {
     static ref CRYPT_DE_NULL: CryptDENull = CryptDENull::new ();
@@ -417,6 +421,7 @@ pub fn make_proxy_server_subs_from(addr: &Addr<Syn, Recorder>) -> ProxyServerSub
         bind: addr.clone ().recipient::<BindMessage>(),
         from_dispatcher: addr.clone ().recipient::<InboundClientData>(),
         from_hopper: addr.clone ().recipient::<ExpiredCoresPackage>(),
+        write_backlog: addr.clone ().recipient::<WriteBacklogMsg>(),
     }
 }
 
@@ -425,6 +430,8 @@ pub fn make_dispatcher_subs_from(addr: &Addr<Syn, Recorder>) -> DispatcherSubs {
         ibcd_sub: addr.clone ().recipient::<InboundClientData>(),
         bind: addr.clone ().recipient::<BindMessage>(),
         from_proxy_server: addr.clone ().recipient::<TransmitDataMsg>(),
+        pause_reading: addr.clone ().recipient::<PauseReadingMsg>(),
+        write_backlog: addr.clone ().recipient::<WriteBacklogMsg>(),
         from_hopper: addr.clone ().recipient::<HopperTemporaryTransmitDataMsg>(),
     }
 }
@@ -447,6 +454,7 @@ pub fn make_proxy_client_subs_from(addr: &Addr<Syn, Recorder>) -> ProxyClientSub
 pub fn make_neighborhood_subs_from(addr: &Addr<Syn, Recorder>) -> NeighborhoodSubs {
     NeighborhoodSubs {
         bind: addr.clone ().recipient::<BindMessage>(),
+        route_query: addr.clone ().recipient::<RouteQueryMessage>(),
     }
 }
 
@@ -511,6 +519,7 @@ pub fn make_meaningless_route () -> Route {
 
 pub struct Recorder {
     recording: Arc<Mutex<Recording>>,
+    route_query_responses: Vec<Option<RouteQueryResponse>>,
 }
 
 pub struct Recording {
@@ -533,6 +542,22 @@ impl Handler<TransmitDataMsg> for Recorder {
     }
 }
 
+impl Handler<PauseReadingMsg> for Recorder {
+    type Result = ();
+
+    fn handle(&mut self, msg: PauseReadingMsg, _ctx: &mut Self::Context) {
+        self.record (msg);
+    }
+}
+
+impl Handler<WriteBacklogMsg> for Recorder {
+    type Result = ();
+
+    fn handle(&mut self, msg: WriteBacklogMsg, _ctx: &mut Self::Context) {
+        self.record (msg);
+    }
+}
+
 impl Handler<BindMessage> for Recorder {
     type Result = ();
 
@@ -583,13 +608,30 @@ impl Handler<NodeQueryMessage> for Recorder {
     }
 }
 
+impl Handler<RouteQueryMessage> for Recorder {
+    type Result = MessageResult<RouteQueryMessage>;
+
+    fn handle(&mut self, msg: RouteQueryMessage, _ctx: &mut Self::Context) -> <Self as Handler<RouteQueryMessage>>::Result {
+        self.record (msg);
+        if self.route_query_responses.is_empty () {return MessageResult(None)}
+        MessageResult(self.route_query_responses.remove (0))
+    }
+}
+
 impl Recorder {
     pub fn new () -> Recorder {
         Recorder {
             recording: Arc::new (Mutex::new (Recording {messages: vec! ()})),
+            route_query_responses: vec! (),
         }
     }
 
+    // Answers to RouteQueryMessages, in order; once they run out, the answer is None
+    pub fn route_query_response (mut self, response: Option<RouteQueryResponse>) -> Recorder {
+        self.route_query_responses.push (response);
+        self
+    }
+
     pub fn record<T> (&mut self, item: T) where T: Any + Send {
         let mut recording = self.recording.lock ().unwrap ();
         let messages: &mut Vec<Box<Any + Send>> = &mut recording.messages;